@@ -0,0 +1,411 @@
+//! Module for clipping shapes
+//!
+//! Shapes are cut to a rectangle by Liang-Barsky for polylines and Sutherland-Hodgman for
+//! polygons, or to any polygon by cutting both shapes where they cross, keeping the pieces inside
+//! the other shape, and linking them into rings again. New vertices get Z and M values
+//! interpolated along the edge they lie on.
+//!
+//! Clipping a concave polygon to a rectangle may leave edges along the rectangle which connect
+//! the separate pieces, as Sutherland-Hodgman does. Multipatches are kept whole if their bounding
+//! box meets the clip area.
+
+use std::collections::HashMap;
+use std::io::Error;
+use std::path::Path;
+
+use super::Shapefile;
+use super::geometry::{self, key, Key, SegmentIntersection, Vertex};
+use super::shape::{Shape, BoundingBox, Point};
+
+/// The offset of the test point beside an edge, relative to the length of the edge.
+const SIDE_OFFSET: f64 = 1e-6;
+
+type Edge = (Vertex, Vertex);
+
+/// A side of a box: the coordinate it limits, the limit, and whether values below it are inside.
+type Side = (fn(Point) -> f64, f64, bool);
+
+fn inside_box(p: Point, b: &BoundingBox) -> bool {
+    p.x >= b.x_min && p.x <= b.x_max && p.y >= b.y_min && p.y <= b.y_max
+}
+
+/// Liang-Barsky: The range of t for which the segment from a to b lies in the box, if any.
+fn clip_segment(a: Point, b: Point, bounds: &BoundingBox) -> Option<(f64, f64)> {
+    let (dx, dy) = (b.x - a.x, b.y - a.y);
+    let (mut t0, mut t1) = (0f64, 1f64);
+    for &(p, q) in &[(-dx, a.x - bounds.x_min), (dx, bounds.x_max - a.x), (-dy, a.y - bounds.y_min), (dy, bounds.y_max - a.y)] {
+        if p == 0f64 {
+            if q < 0f64 {
+                return None;
+            }
+        } else {
+            let r = q / p;
+            if p < 0f64 {
+                t0 = t0.max(r);
+            } else {
+                t1 = t1.min(r);
+            }
+        }
+    }
+    if t0 > t1 { None } else { Some((t0, t1)) }
+}
+
+/// Cuts lines into pieces, given the ranges of t to keep per segment.
+fn cut_lines<F>(parts: Vec<Vec<Vertex>>, mut ranges: F) -> Vec<Vec<Vertex>>
+where F: FnMut(Vertex, Vertex) -> Vec<(f64, f64)>
+{
+    let mut result: Vec<Vec<Vertex>> = vec![];
+    for part in parts {
+        let mut current: Vec<Vertex> = vec![];
+        for w in part.windows(2) {
+            for (t0, t1) in ranges(w[0], w[1]) {
+                let start = if t0 == 0f64 { w[0] } else { geometry::interpolate(w[0], w[1], t0) };
+                let end = if t1 == 1f64 { w[1] } else { geometry::interpolate(w[0], w[1], t1) };
+                if current.last().is_some_and(|l| l.point != start.point) {
+                    result.push(::std::mem::take(&mut current));
+                }
+                if current.is_empty() {
+                    current.push(start);
+                }
+                if end.point != start.point {
+                    current.push(end);
+                }
+            }
+        }
+        result.push(current);
+    }
+    result.into_iter().filter(|p| p.len() >= 2).collect()
+}
+
+/// Sutherland-Hodgman: Clips a closed ring to the box, one side after the other.
+fn clip_ring_to_box(ring: &[Vertex], bounds: &BoundingBox) -> Vec<Vertex> {
+    let sides: [Side; 4] = [
+        (|p| p.x, bounds.x_min, false),
+        (|p| p.x, bounds.x_max, true),
+        (|p| p.y, bounds.y_min, false),
+        (|p| p.y, bounds.y_max, true),
+    ];
+
+    let mut output: Vec<Vertex> = ring[..ring.len().saturating_sub(1)].to_vec();
+    for &(coordinate, limit, below) in &sides {
+        let input = ::std::mem::take(&mut output);
+        let inside = |v: &Vertex| if below { coordinate(v.point) <= limit } else { coordinate(v.point) >= limit };
+        for i in 0..input.len() {
+            let (current, previous) = (input[i], input[(i + input.len() - 1) % input.len()]);
+            let crossing = || {
+                let (c, p) = (coordinate(current.point), coordinate(previous.point));
+                geometry::interpolate(previous, current, (limit - p) / (c - p))
+            };
+            match (inside(&previous), inside(&current)) {
+                (true, true) => output.push(current),
+                (true, false) => output.push(crossing()),
+                (false, true) => {
+                    output.push(crossing());
+                    output.push(current);
+                },
+                (false, false) => (),
+            }
+        }
+    }
+
+    output.dedup_by(|a, b| a.point == b.point);
+    if output.len() > 1 && output[0].point == output[output.len() - 1].point {
+        output.pop();
+    }
+    if let Some(&first) = output.first() {
+        output.push(first);
+    }
+    output
+}
+
+/// The edges of the rings of a shape.
+fn edges_of(parts: &[Vec<Vertex>]) -> Vec<Edge> {
+    parts.iter().flat_map(|p| p.windows(2).filter(|w| w[0].point != w[1].point).map(|w| (w[0], w[1]))).collect()
+}
+
+/// Cuts the edges of both sides where they meet. The points where they meet are computed once, so
+/// that the pieces of both sides share them exactly.
+fn split_edges(first: &[Edge], second: &[Edge]) -> (Vec<Edge>, Vec<Edge>) {
+    let mut first_cuts: Vec<Vec<(f64, Vertex)>> = vec![vec![]; first.len()];
+    let mut second_cuts: Vec<Vec<(f64, Vertex)>> = vec![vec![]; second.len()];
+
+    for (i, &(a, b)) in first.iter().enumerate() {
+        for (j, &(c, d)) in second.iter().enumerate() {
+            match geometry::segment_intersection(a.point, b.point, c.point, d.point) {
+                SegmentIntersection::None => (),
+                SegmentIntersection::Proper => {
+                    let (da, db) = (geometry::cross(c.point, d.point, a.point), geometry::cross(c.point, d.point, b.point));
+                    let t = da / (da - db);
+                    let on_first = geometry::interpolate(a, b, t);
+                    let s = geometry::parameter(on_first.point, c.point, d.point);
+                    let on_second = Vertex {point: on_first.point, ..geometry::interpolate(c, d, s)};
+                    first_cuts[i].push((t, on_first));
+                    second_cuts[j].push((s, on_second));
+                },
+                SegmentIntersection::Touching => {
+                    for &v in &[c, d] {
+                        if geometry::on_segment(v.point, a.point, b.point) {
+                            let t = geometry::parameter(v.point, a.point, b.point);
+                            first_cuts[i].push((t, Vertex {point: v.point, ..geometry::interpolate(a, b, t)}));
+                        }
+                    }
+                    for &v in &[a, b] {
+                        if geometry::on_segment(v.point, c.point, d.point) {
+                            let s = geometry::parameter(v.point, c.point, d.point);
+                            second_cuts[j].push((s, Vertex {point: v.point, ..geometry::interpolate(c, d, s)}));
+                        }
+                    }
+                },
+            }
+        }
+    }
+
+    let apply = |edges: &[Edge], cuts: Vec<Vec<(f64, Vertex)>>| -> Vec<Edge> {
+        let mut result: Vec<Edge> = vec![];
+        for (&(a, b), mut cuts) in edges.iter().zip(cuts) {
+            cuts.retain(|c| c.0 > 0f64 && c.0 < 1f64);
+            cuts.sort_by(|x, y| x.0.partial_cmp(&y.0).unwrap_or(::std::cmp::Ordering::Equal));
+            let mut previous = a;
+            for (_, v) in cuts.into_iter().chain(Some((1f64, b))) {
+                if v.point != previous.point {
+                    result.push((previous, v));
+                    previous = v;
+                }
+            }
+        }
+        result
+    };
+    (apply(first, first_cuts), apply(second, second_cuts))
+}
+
+fn midpoint(a: Point, b: Point) -> Point {
+    Point {x: (a.x + b.x) / 2f64, y: (a.y + b.y) / 2f64}
+}
+
+/// Links edges into closed rings. Where several edges go on from a vertex, the one turning most to
+/// the right is taken, so that the interior stays on the right.
+fn link_rings(edges: Vec<Edge>) -> Vec<Vec<Vertex>> {
+    let mut outgoing: HashMap<Key, Vec<usize>> = HashMap::new();
+    for (i, e) in edges.iter().enumerate() {
+        outgoing.entry(key(e.0.point)).or_default().push(i);
+    }
+    let mut used = vec![false; edges.len()];
+    let mut result: Vec<Vec<Vertex>> = vec![];
+
+    for start in 0..edges.len() {
+        if used[start] {
+            continue;
+        }
+        used[start] = true;
+        let mut ring: Vec<Vertex> = vec![edges[start].0, edges[start].1];
+        let mut current = start;
+
+        while ring[ring.len() - 1].point != ring[0].point {
+            let (a, b) = (edges[current].0.point, edges[current].1.point);
+            let turn = |i: &usize| {
+                let c = edges[*i].1.point;
+                let (dx, dy) = (b.x - a.x, b.y - a.y);
+                let (ex, ey) = (c.x - b.x, c.y - b.y);
+                (dx * ey - dy * ex).atan2(dx * ex + dy * ey)
+            };
+            let next = outgoing.get(&key(b)).and_then(|candidates| {
+                candidates.iter().filter(|&&i| !used[i])
+                          .min_by(|x, y| turn(x).partial_cmp(&turn(y)).unwrap_or(::std::cmp::Ordering::Equal))
+                          .cloned()
+            });
+            match next {
+                Some(i) => {
+                    used[i] = true;
+                    ring.push(edges[i].1);
+                    current = i;
+                },
+                None => break,
+            }
+        }
+
+        if ring.len() >= 4 && ring[ring.len() - 1].point == ring[0].point {
+            result.push(ring);
+        }
+    }
+    result
+}
+
+/// The rings of a polygon, repaired so that the interior is on the right of every edge.
+fn oriented_rings(shape: &Shape) -> Vec<Vec<Vertex>> {
+    geometry::vertex_parts(&shape.repaired())
+}
+
+impl Shape {
+    /// Returns the part of the shape inside the box, or the null shape if nothing is left. Points
+    /// on the border of the box count as inside.
+    pub fn clipped_to_box(&self, bounds: &BoundingBox) -> Shape {
+        let extent = match self.computed_bounding_box() {
+            Some(b) => b,
+            None => return Shape::NullShape,
+        };
+        if !geometry::boxes_overlap(&extent, bounds) {
+            return Shape::NullShape;
+        }
+        if inside_box(Point {x: extent.x_min, y: extent.y_min}, bounds) && inside_box(Point {x: extent.x_max, y: extent.y_max}, bounds) {
+            return self.clone();
+        }
+
+        let parts = geometry::vertex_parts(self);
+        let parts = if geometry::dimension(self) == Some(2) {
+            parts.iter().map(|r| clip_ring_to_box(r, bounds))
+                 .filter(|r| geometry::signed_area(&r.iter().map(|v| v.point).collect::<Vec<Point>>()) != 0f64)
+                 .collect()
+        } else if geometry::dimension(self) == Some(1) {
+            cut_lines(parts, |a, b| clip_segment(a.point, b.point, bounds).into_iter().collect())
+        } else if let Shape::MultiPatch {..} = *self {
+            return self.clone();
+        } else {
+            parts.into_iter().map(|p| p.into_iter().filter(|v| inside_box(v.point, bounds)).collect()).collect()
+        };
+        geometry::from_vertex_parts(self, parts)
+    }
+
+    /// Returns the part of the shape inside the polygon, or the null shape if nothing is left.
+    /// Points and lines on the boundary of the polygon count as inside.
+    pub fn clipped_to_polygon(&self, polygon: &Shape) -> Shape {
+        let (extent, clip_extent) = match (self.computed_bounding_box(), polygon.computed_bounding_box()) {
+            (Some(a), Some(b)) if geometry::dimension(polygon) == Some(2) => (a, b),
+            _ => return Shape::NullShape,
+        };
+        if !geometry::boxes_overlap(&extent, &clip_extent) {
+            return Shape::NullShape;
+        }
+
+        let clip_rings = oriented_rings(polygon);
+        let clip_points: Vec<Vec<Point>> = clip_rings.iter().map(|r| r.iter().map(|v| v.point).collect()).collect();
+        let clip_edges = edges_of(&clip_rings);
+        let covered = |p: Point| geometry::point_in_rings(p, &clip_points) != Some(false);
+
+        let parts = if geometry::dimension(self) == Some(2) {
+            let rings = oriented_rings(self);
+            let points: Vec<Vec<Point>> = rings.iter().map(|r| r.iter().map(|v| v.point).collect()).collect();
+            let (own, other) = split_edges(&edges_of(&rings), &clip_edges);
+
+            // Edges inside the other polygon stay. Of the edges shared by both, those with both
+            // interiors on the same side stay once.
+            let mut kept: Vec<Edge> = own.into_iter().filter(|&(a, b)| {
+                match geometry::point_in_rings(midpoint(a.point, b.point), &clip_points) {
+                    Some(inside) => inside,
+                    None => {
+                        let m = midpoint(a.point, b.point);
+                        let beside = Point {x: m.x + (b.point.y - a.point.y) * SIDE_OFFSET, y: m.y - (b.point.x - a.point.x) * SIDE_OFFSET};
+                        geometry::point_in_rings(beside, &clip_points) == Some(true)
+                    },
+                }
+            }).collect();
+            kept.extend(other.into_iter().filter(|&(a, b)| geometry::point_in_rings(midpoint(a.point, b.point), &points) == Some(true)));
+            link_rings(kept)
+        } else if geometry::dimension(self) == Some(1) {
+            let parts = geometry::vertex_parts(self);
+            let (pieces, _) = split_edges(&edges_of(&parts), &clip_edges);
+            let pieces: Vec<Vec<Vertex>> = pieces.into_iter()
+                                                 .filter(|&(a, b)| covered(midpoint(a.point, b.point)))
+                                                 .map(|(a, b)| vec![a, b])
+                                                 .collect();
+            cut_lines(pieces, |_, _| vec![(0f64, 1f64)])
+        } else if let Shape::MultiPatch {..} = *self {
+            return self.clone();
+        } else {
+            geometry::vertex_parts(self).into_iter().map(|p| p.into_iter().filter(|v| covered(v.point)).collect()).collect()
+        };
+        geometry::from_vertex_parts(self, parts)
+    }
+}
+
+impl Shapefile {
+    /// Writes the parts of the shapes inside the box into a new shapefile, with all attributes.
+    /// Records with nothing left inside are left out. Returns the number of records written.
+    pub fn clip_to_box_into(&mut self, shp_path: &Path, shx_path: &Path, dbf_path: &Path, bounds: &BoundingBox) -> Result<u64, Error> {
+        self.rewrite(shp_path, shx_path, dbf_path, |shape| {
+            let clipped = shape.clipped_to_box(bounds);
+            Ok(if clipped == Shape::NullShape { None } else { Some(clipped) })
+        })
+    }
+
+    /// Writes the parts of the shapes inside the polygon into a new shapefile, with all attributes.
+    /// Records with nothing left inside are left out. Returns the number of records written.
+    pub fn clip_to_polygon_into(&mut self, shp_path: &Path, shx_path: &Path, dbf_path: &Path, polygon: &Shape) -> Result<u64, Error> {
+        self.rewrite(shp_path, shx_path, dbf_path, |shape| {
+            let clipped = shape.clipped_to_polygon(polygon);
+            Ok(if clipped == Shape::NullShape { None } else { Some(clipped) })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::shape::{Shape, BoundingBox, Point, ZRange, MRange};
+    use super::super::geometry::polygon;
+
+    #[test]
+    fn test_clip_line_to_box() {
+        let line = Shape::PolyLineZ {
+            bounding_box: BoundingBox::new(),
+            parts: vec![0],
+            points: vec![Point {x: -5., y: 5.}, Point {x: 5., y: 5.}, Point {x: 5., y: 15.}, Point {x: 8., y: 5.}],
+            z_range: ZRange {min: 0., max: 0.},
+            z: vec![0., 100., 200., 300.],
+            m_range: MRange {min: 0., max: 0.},
+            m: vec![0., 1., 2., 3.],
+        };
+        let clipped = line.clipped_to_box(&BoundingBox {x_min: 0., y_min: 0., x_max: 10., y_max: 10.});
+
+        // In at x = 0, out at y = 10, in again on the way down
+        if let Shape::PolyLineZ {ref parts, ref points, ref z, ref m, ..} = clipped {
+            if *parts != vec![0, 3] || points[0] != (Point {x: 0., y: 5.}) || points[2] != (Point {x: 5., y: 10.}) {
+                panic!("{:?}", clipped)
+            }
+            if z[0] != 50. || m[0] != 0.5 || z[2] != 150. || points[4] != (Point {x: 8., y: 5.}) {
+                panic!("{:?}", clipped)
+            }
+        } else {
+            panic!()
+        }
+    }
+
+    #[test]
+    fn test_clip_polygon_to_box() {
+        let square = Shape::from(BoundingBox {x_min: 0., y_min: 0., x_max: 10., y_max: 10.});
+        let clipped = square.clipped_to_box(&BoundingBox {x_min: 5., y_min: -5., x_max: 15., y_max: 5.});
+        if clipped.area() != 25. || !clipped.is_valid() {
+            panic!("{:?}", clipped)
+        }
+        if square.clipped_to_box(&BoundingBox {x_min: 20., y_min: 20., x_max: 30., y_max: 30.}) != Shape::NullShape {
+            panic!()
+        }
+    }
+
+    #[test]
+    fn test_clip_to_polygon() {
+        // A square with a hole, cut by a diamond which reaches into the hole
+        let square = polygon(&[
+            &[(0., 0.), (0., 10.), (10., 10.), (10., 0.), (0., 0.)],
+            &[(4., 4.), (6., 4.), (6., 6.), (4., 6.), (4., 4.)],
+        ]);
+        let diamond = polygon(&[&[(5., 0.), (0., 5.), (5., 10.), (10., 5.), (5., 0.)]]);
+        let clipped = square.clipped_to_polygon(&diamond);
+        if clipped.area() != 46. || !clipped.is_valid() {
+            panic!("{} {:?}", clipped.area(), clipped)
+        }
+
+        // The same polygon is its own clip, a polygon inside keeps all of it
+        if square.clipped_to_polygon(&square).area() != 96. || diamond.clipped_to_polygon(&Shape::from(BoundingBox {x_min: -1., y_min: -1., x_max: 11., y_max: 11.})).area() != 50. {
+            panic!()
+        }
+
+        let line = Shape::PolyLine {bounding_box: BoundingBox::new(), parts: vec![0], points: vec![Point {x: -5., y: 5.}, Point {x: 15., y: 5.}]};
+        let clipped = line.clipped_to_polygon(&square);
+        if let Shape::PolyLine {ref parts, ref points, ..} = clipped {
+            if *parts != vec![0, 2] || points[1] != (Point {x: 4., y: 5.}) || points[2] != (Point {x: 6., y: 5.}) {
+                panic!("{:?}", clipped)
+            }
+        } else {
+            panic!()
+        }
+    }
+}
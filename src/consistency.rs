@@ -0,0 +1,251 @@
+//! Module for checking the consistency of a Shapefile
+//!
+//! The three files of a Shapefile repeat a lot of information: The SHX file copies the SHP header
+//! and the offset and length of every SHP record, the DBF file has one row per record, and the SHP
+//! header holds the extent of all records, which in turn hold their own extent.
+//! `Shapefile::validate` checks that all of this agrees, and reports every finding instead of
+//! stopping at the first one.
+
+use std::fmt;
+use std::io::{Error, Seek, SeekFrom};
+
+use super::{Shapefile, ShpFile};
+use super::shpfile::Record;
+use super::shape::{BoundingBox, ShapeType};
+
+/// One of the three files of a Shapefile.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum FileKind {
+    Shp,
+    Shx,
+    Dbf,
+}
+
+/// A violation of the spec found by `Shapefile::validate`. Record ids start at 1, offsets and
+/// lengths are in bytes.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Issue {
+    /// There is no SHX file, so the index was built from the SHP file.
+    MissingShx,
+    /// The shape types in the SHP and SHX headers differ.
+    HeaderShapeTypeMismatch {shp: ShapeType, shx: ShapeType},
+    /// The bounding boxes in the SHP and SHX headers differ.
+    HeaderBoundingBoxMismatch,
+    /// The file length in the header doesn't match the size of the file.
+    FileLengthMismatch {file: FileKind, header: u64, actual: u64},
+    /// The number of records differs between the files.
+    RecordCountMismatch {shp: u64, shx: u64, dbf: u64},
+    /// A SHP record couldn't be read. Nothing after it is checked.
+    UnreadableRecord {record: u64, offset: u64, message: String},
+    /// The record number in the record header isn't the position of the record.
+    RecordNumberMismatch {record: u64, found: i32},
+    /// The content length in the record header doesn't match the length of the shape.
+    ContentLengthMismatch {record: u64, content_length: u64, parsed: u64},
+    /// An SHX entry couldn't be read, like at the end of a truncated SHX file. No SHX entries after
+    /// it are checked.
+    UnreadableIndexEntry {record: u64, message: String},
+    /// The SHX entry doesn't point to the SHP record at this position.
+    IndexMismatch {record: u64, shx_offset: u64, shx_length: u64, shp_offset: u64, shp_length: u64},
+    /// The shape is neither of the type in the header nor a null shape.
+    ShapeTypeMismatch {record: u64, header: ShapeType, found: ShapeType},
+    /// The bounding box in the SHP header doesn't contain the bounding box of the record.
+    RecordOutsideExtent {record: u64},
+    /// The bounding box or the Z and M ranges stored in the record don't match its points.
+    RecordExtentMismatch {record: u64},
+    /// The bounding box in the SHP header isn't the one of all points in the file.
+    HeaderExtentMismatch,
+}
+
+impl fmt::Display for FileKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FileKind::Shp => write!(f, "SHP"),
+            FileKind::Shx => write!(f, "SHX"),
+            FileKind::Dbf => write!(f, "DBF"),
+        }
+    }
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Issue::MissingShx => write!(f, "SHX file is missing"),
+            Issue::HeaderShapeTypeMismatch {shp, shx} => {
+                write!(f, "SHP header has shape type {:?}, SHX header has {:?}", shp, shx)
+            },
+            Issue::HeaderBoundingBoxMismatch => write!(f, "SHP and SHX headers have different bounding boxes"),
+            Issue::FileLengthMismatch {file, header, actual} => {
+                write!(f, "{} header says the file has {} bytes, but it has {}", file, header, actual)
+            },
+            Issue::RecordCountMismatch {shp, shx, dbf} => {
+                write!(f, "Record counts differ: {} in SHP, {} in SHX, {} in DBF", shp, shx, dbf)
+            },
+            Issue::UnreadableRecord {record, offset, ref message} => {
+                write!(f, "SHP record {} at offset {} can't be read: {}", record, offset, message)
+            },
+            Issue::RecordNumberMismatch {record, found} => {
+                write!(f, "SHP record {} has record number {}", record, found)
+            },
+            Issue::ContentLengthMismatch {record, content_length, parsed} => {
+                write!(f, "SHP record {} has content length {}, but its shape has {} bytes", record, content_length, parsed)
+            },
+            Issue::UnreadableIndexEntry {record, ref message} => {
+                write!(f, "SHX entry {} can't be read: {}", record, message)
+            },
+            Issue::IndexMismatch {record, shx_offset, shx_length, shp_offset, shp_length} => {
+                write!(f, "SHX entry {} points to offset {} with length {}, but the SHP record is at {} with length {}",
+                       record, shx_offset, shx_length, shp_offset, shp_length)
+            },
+            Issue::ShapeTypeMismatch {record, header, found} => {
+                write!(f, "SHP record {} has shape type {:?}, but the header says {:?}", record, found, header)
+            },
+            Issue::RecordOutsideExtent {record} => {
+                write!(f, "SHP record {} is outside of the extent in the header", record)
+            },
+            Issue::RecordExtentMismatch {record} => {
+                write!(f, "SHP record {} has a bounding box or ranges which don't match its points", record)
+            },
+            Issue::HeaderExtentMismatch => write!(f, "SHP header has a bounding box which doesn't match the points"),
+        }
+    }
+}
+
+impl Shapefile {
+    /// Checks that the SHP, SHX and DBF files agree with each other and with the spec, and returns
+    /// everything that doesn't. An empty list means the Shapefile is consistent.
+    ///
+    /// Only I/O errors which prevent the check from running at all are returned as `Err`.
+    pub fn validate(&mut self) -> Result<Vec<Issue>, Error> {
+        let mut issues: Vec<Issue> = vec![];
+        let has_shx = self.has_shx();
+
+        // Headers
+        let shp_header = &self.shp_file.header;
+        let shp_size = self.shp_file.file.get_ref().metadata()?.len();
+        let shp_length = shp_header.file_length.max(0) as u64 * 2u64;
+        if shp_length != shp_size {
+            issues.push(Issue::FileLengthMismatch {file: FileKind::Shp, header: shp_length, actual: shp_size});
+        }
+
+        // An SHX file length shorter than the 50 words of the header gives no record count, so the
+        // index entries aren't compared with the records
+        let shx_usable = has_shx && self.shx_file.header.file_length >= 50;
+        if has_shx {
+            let shx_header = &self.shx_file.header;
+            if shx_header.shape_type != shp_header.shape_type {
+                issues.push(Issue::HeaderShapeTypeMismatch {shp: shp_header.shape_type, shx: shx_header.shape_type});
+            }
+            if shx_header.bounding_box != shp_header.bounding_box {
+                issues.push(Issue::HeaderBoundingBoxMismatch);
+            }
+
+            let shx_length = shx_header.file_length.max(0) as u64 * 2u64;
+            let shx_size = match self.shx_file.file {
+                Some(ref f) => f.get_ref().metadata()?.len(),
+                None => shx_length,
+            };
+            if shx_length != shx_size || !shx_usable {
+                issues.push(Issue::FileLengthMismatch {file: FileKind::Shx, header: shx_length, actual: shx_size});
+            }
+        } else {
+            issues.push(Issue::MissingShx);
+        }
+
+        // Records
+        let header_type = shp_header.shape_type;
+        let extent = shp_header.bounding_box;
+        let end = self.shp_file.records_end()?;
+        let shx_count = if shx_usable { self.shx_file.num_records() } else { 0 };
+        let mut position = ShpFile::FIRST_RECORD_OFFSET;
+        let mut id = 0u64;
+        let mut count = 0u64;
+        let mut computed: Option<BoundingBox> = None;
+        let mut shx_readable = shx_usable;
+
+        self.shx_file.seek_first_record()?;
+        while position + 8u64 <= end {
+            id += 1;
+
+            self.shp_file.file.seek(SeekFrom::Start(position))?;
+            let (record, parsed) = match Record::parse(&mut self.shp_file.file) {
+                Ok(r) => r,
+                Err(e) => {
+                    issues.push(Issue::UnreadableRecord {record: id, offset: position, message: e.to_string()});
+                    break;
+                },
+            };
+
+            if record.content_length < 0 {
+                issues.push(Issue::UnreadableRecord {record: id, offset: position, message: String::from("Negative content length!")});
+                break;
+            }
+            let length = 8u64 + record.content_length as u64 * 2u64;
+
+            if record.record_number as u64 != id {
+                issues.push(Issue::RecordNumberMismatch {record: id, found: record.record_number});
+            }
+
+            if parsed as u64 != length {
+                issues.push(Issue::ContentLengthMismatch {record: id, content_length: length - 8u64, parsed: parsed as u64 - 8u64});
+            }
+
+            if shx_readable && id <= shx_count {
+                match self.shx_file.read_next_record() {
+                    Ok(entry) => {
+                        let shx_offset = entry.offset as u64 * 2u64;
+                        let shx_length = entry.length as u64 * 2u64;
+                        if shx_offset != position || shx_length != length - 8u64 {
+                            issues.push(Issue::IndexMismatch {record: id, shx_offset, shx_length, shp_offset: position, shp_length: length - 8u64});
+                        }
+                    },
+                    Err(e) => {
+                        issues.push(Issue::UnreadableIndexEntry {record: id, message: e.to_string()});
+                        shx_readable = false;
+                    },
+                }
+            }
+
+            let found = record.shape.shape_type();
+            if found != ShapeType::NullShape && found != header_type {
+                issues.push(Issue::ShapeTypeMismatch {record: id, header: header_type, found});
+            }
+
+            if !record.shape.extents_match() {
+                issues.push(Issue::RecordExtentMismatch {record: id});
+            }
+
+            if let Some(b) = record.shape.computed_bounding_box() {
+                computed = Some(match computed {
+                    None => b,
+                    Some(c) => BoundingBox {x_min: c.x_min.min(b.x_min), y_min: c.y_min.min(b.y_min), x_max: c.x_max.max(b.x_max), y_max: c.y_max.max(b.y_max)},
+                });
+            }
+
+            if let Some(e) = record.shape.stored_extent() {
+                let mut outside = e.x_min < extent.x_min || e.y_min < extent.y_min
+                    || e.x_max > extent.x_max || e.y_max > extent.y_max;
+                if header_type.has_z() && found.has_z() {
+                    outside = outside || e.z_min < extent.z_min || e.z_max > extent.z_max;
+                }
+                if outside {
+                    issues.push(Issue::RecordOutsideExtent {record: id});
+                }
+            }
+
+            position += length;
+            count = id;
+        }
+
+        let header_box = BoundingBox {x_min: extent.x_min, y_min: extent.y_min, x_max: extent.x_max, y_max: extent.y_max};
+        if computed.is_some_and(|c| c != header_box) {
+            issues.push(Issue::HeaderExtentMismatch);
+        }
+
+        let dbf_count = self.dbf_file.num_records as u64;
+        if (shx_usable && count != shx_count) || count != dbf_count {
+            issues.push(Issue::RecordCountMismatch {shp: count, shx: if shx_usable { shx_count } else { count }, dbf: dbf_count});
+        }
+
+        Ok(issues)
+    }
+}
@@ -1,22 +1,864 @@
 //! Module for DBF files
 //!
-//! Internally uses the implementation from the `dbf` crate.
+//! The structure of dBASE tables is described in http://www.dbase.com/Knowledgebase/INT/db7_file_fmt.htm.
+//! The values are handed out as `dbf::Field`s, so the record type of the `dbf` crate is kept. The
+//! parsing happens here, because memo fields need the DBT or FPT file next to the table.
 
-use std::path::Path;
-use std::io::Error;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Error, ErrorKind, BufReader, BufWriter, Read, Write, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 
 use dbf;
-use super::DbfFile;
+use super::{Date, DbfFile, DbfField, DbfValue, DbfWriter, MemoFile, MemoFormat, MemoWriter};
+
+impl DbfField {
+    /// Length of a field descriptor in the header.
+    const DESCRIPTOR_LENGTH: usize = 32;
+    /// The longest column name that fits into a field descriptor.
+    const MAX_NAME_LENGTH: usize = 10;
+    /// The longest character column.
+    const MAX_CHARACTER_LENGTH: u8 = 254;
+
+    /// Parses a field descriptor from its 32 bytes.
+    fn parse(bytes: &[u8]) -> Self {
+        let name_end = bytes[0..11].iter().position(|&b| b == 0).unwrap_or(11);
+
+        DbfField {
+            name: String::from_utf8_lossy(&bytes[0..name_end]).trim().to_string(),
+            field_type: bytes[11],
+            length: bytes[16],
+            decimal_count: bytes[17],
+        }
+    }
+
+    /// A text column of the given length in bytes.
+    pub fn character(name: &str, length: u8) -> Self {
+        DbfField {name: name.to_string(), field_type: b'C', length, decimal_count: 0}
+    }
+
+    /// A number column with the given total length in bytes, including the decimal places.
+    pub fn numeric(name: &str, length: u8, decimal_count: u8) -> Self {
+        DbfField {name: name.to_string(), field_type: b'N', length, decimal_count}
+    }
+
+    /// A date column, written as `YYYYMMDD`.
+    pub fn date(name: &str) -> Self {
+        DbfField {name: name.to_string(), field_type: b'D', length: 8, decimal_count: 0}
+    }
+
+    /// A memo column, whose text goes into a DBT file next to the DBF file.
+    pub fn memo(name: &str) -> Self {
+        DbfField {name: name.to_string(), field_type: b'M', length: 10, decimal_count: 0}
+    }
+
+    /// A logical column, written as `T` or `F`.
+    pub fn logical(name: &str) -> Self {
+        DbfField {name: name.to_string(), field_type: b'L', length: 1, decimal_count: 0}
+    }
+
+    /// Derives a column definition from the first value written to it. Text and numbers get a
+    /// column just wide enough for them. Null values get a placeholder of one character, until
+    /// the first value tells the type.
+    fn for_value(name: &str, value: &DbfValue) -> Self {
+        match *value {
+            DbfValue::Null => Self::character(name, 1),
+            DbfValue::Character(ref s) => Self::character(name, s.len().clamp(1, Self::MAX_CHARACTER_LENGTH as usize) as u8),
+            DbfValue::Integer(_) | DbfValue::Float(_) => {
+                let (integer, decimals) = Self::number_size(value).unwrap_or((1, 0));
+                Self::sized_numeric(name, integer, decimals)
+            },
+            DbfValue::Logical(_) => Self::logical(name),
+            DbfValue::Date(_) => Self::date(name),
+        }
+    }
+
+    /// The characters before the decimal point, sign included, and the decimal places a number
+    /// needs. None for values which aren't numbers. At most 15 decimal places are kept.
+    fn number_size(value: &DbfValue) -> Option<(usize, usize)> {
+        let text = match *value {
+            DbfValue::Integer(i) => return Some((i.to_string().len(), 0)),
+            DbfValue::Float(f) if f.is_finite() => f.to_string(),
+            DbfValue::Character(ref s) => match s.trim().parse::<f64>() {
+                Ok(f) if f.is_finite() => f.to_string(),
+                _ => return None,
+            },
+            _ => return None,
+        };
+
+        let mut halves = text.splitn(2, '.');
+        let integer = halves.next().map_or(1, |i| i.len());
+        let decimals = halves.next().map_or(0, |d| d.len().min(15));
+        Some((integer, decimals))
+    }
+
+    /// A number column with room for the given characters before the decimal point and decimal
+    /// places.
+    fn sized_numeric(name: &str, integer: usize, decimals: usize) -> Self {
+        let length = integer.max(1) + if decimals > 0 { decimals + 1 } else { 0 };
+        Self::numeric(name, length.min(u8::MAX as usize - 1) as u8, decimals as u8)
+    }
+
+    /// A wider column if the value doesn't fit into this derived one, None if it does or this is
+    /// neither a character nor a number column. Character columns grow up to 254 bytes.
+    fn widened_for(&self, value: &DbfValue) -> Option<Self> {
+        if self.field_type == b'C' {
+            let length = match *value {
+                DbfValue::Character(ref s) => s.len(),
+                DbfValue::Integer(i) => i.to_string().len(),
+                DbfValue::Float(f) => f.to_string().len(),
+                DbfValue::Date(_) => 8,
+                DbfValue::Logical(_) | DbfValue::Null => 1,
+            };
+            let length = length.min(Self::MAX_CHARACTER_LENGTH as usize);
+            return if length > self.length as usize { Some(Self::character(&self.name, length as u8)) } else { None };
+        }
+        if self.field_type != b'N' {
+            return None;
+        }
+        let (integer, decimals) = Self::number_size(value)?;
+        let current_decimals = self.decimal_count as usize;
+        let current_integer = self.length as usize - if current_decimals > 0 { current_decimals + 1 } else { 0 };
+        if integer <= current_integer && decimals <= current_decimals {
+            return None;
+        }
+        Some(Self::sized_numeric(&self.name, integer.max(current_integer), decimals.max(current_decimals)))
+    }
+
+    /// The column definition for copying values of this column into a new table. Memo columns
+    /// stay memo columns, with room for the block numbers of dBASE III.
+    pub(crate) fn for_copy(&self) -> Self {
+        match self.field_type {
+            b'M' => Self::memo(&self.name),
+            _ => self.clone(),
+        }
+    }
+
+    /// Writes the 32 byte field descriptor.
+    fn write<T: Write>(&self, file: &mut T) -> Result<(), Error> {
+        if self.name.is_empty() || self.name.len() > Self::MAX_NAME_LENGTH {
+            return Err(Error::new(ErrorKind::Other, format!("DBF column name `{}` must have 1 to 10 bytes!", self.name)));
+        }
+
+        let mut descriptor = [0u8; 32];
+        descriptor[..self.name.len()].copy_from_slice(self.name.as_bytes());
+        descriptor[11] = self.field_type;
+        descriptor[16] = self.length;
+        descriptor[17] = self.decimal_count;
+
+        file.write_all(&descriptor)
+    }
+
+    /// Formats a value for this column, padded to the column length.
+    fn encode(&self, value: &DbfValue) -> Result<Vec<u8>, Error> {
+        let mismatch = || Error::new(ErrorKind::Other, format!("Value {:?} does not fit into DBF column `{}`!", value, self.name));
+        let length = self.length as usize;
+
+        let text = match (self.field_type, value) {
+            (b'M', _) => return Err(Error::new(ErrorKind::Other, "Memo fields can only be written with a DBT file!")),
+            (b'L', DbfValue::Null) => String::from("?"),
+            (_, DbfValue::Null) => String::new(),
+            (b'C', DbfValue::Character(s)) => s.clone(),
+            (b'C', DbfValue::Integer(i)) => i.to_string(),
+            (b'C', DbfValue::Float(f)) => f.to_string(),
+            (b'C', DbfValue::Logical(b)) => String::from(if *b { "T" } else { "F" }),
+            (b'C', DbfValue::Date(d)) | (b'D', DbfValue::Date(d)) => {
+                format!("{:04}{:02}{:02}", d.year, d.month, d.day)
+            },
+            (b'D', DbfValue::Character(s)) => {
+                let d = Date::parse(s).ok_or_else(mismatch)?;
+                format!("{:04}{:02}{:02}", d.year, d.month, d.day)
+            },
+            (b'N', _) | (b'F', _) => {
+                let v = match *value {
+                    DbfValue::Integer(i) if self.decimal_count == 0 => {
+                        return Self::pad_left(i.to_string(), length).ok_or_else(mismatch);
+                    },
+                    DbfValue::Integer(i) => i as f64,
+                    DbfValue::Float(f) => f,
+                    DbfValue::Character(ref s) => s.trim().parse().map_err(|_| mismatch())?,
+                    _ => return Err(mismatch()),
+                };
+                if !v.is_finite() {
+                    return Err(mismatch());
+                }
+                return Self::pad_left(format!("{:.*}", self.decimal_count as usize, v), length).ok_or_else(mismatch);
+            },
+            (b'L', DbfValue::Logical(b)) => String::from(if *b { "T" } else { "F" }),
+            (b'L', DbfValue::Character(s)) => {
+                match s.trim() {
+                    "T" | "t" | "Y" | "y" => String::from("T"),
+                    "F" | "f" | "N" | "n" => String::from("F"),
+                    _ => return Err(mismatch()),
+                }
+            },
+            _ => return Err(mismatch()),
+        };
+
+        // Text is cut off at the column length, at a character boundary
+        let mut end = text.len().min(length);
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        let mut result = text.as_bytes()[..end].to_vec();
+        result.resize(length, b' ');
+        Ok(result)
+    }
+
+    /// Right-aligns a number in a field of the given length. Returns None if it doesn't fit.
+    fn pad_left(text: String, length: usize) -> Option<Vec<u8>> {
+        if text.len() > length {
+            return None;
+        }
+        Some(format!("{:>1$}", text, length).into_bytes())
+    }
+}
+
+impl<'a> From<&'a dbf::Field> for DbfValue {
+    fn from(field: &'a dbf::Field) -> Self {
+        match *field {
+            dbf::Field::Character(ref s) => DbfValue::Character(s.clone()),
+            dbf::Field::Numeric(n) => DbfValue::Float(n),
+            dbf::Field::Null => DbfValue::Null,
+        }
+    }
+}
 
 impl DbfFile {
+    /// Marks the end of the field descriptors in the header.
+    const HEADER_TERMINATOR: u8 = 0x0D;
+    /// Version bytes of the files which store their memos in the dBASE IV format.
+    const DBASE_IV_MEMO_VERSIONS: [u8; 2] = [0x8B, 0xCB];
+
     /// Given a file name, parses the DBF file and returns the result.
+    ///
+    /// If there is a DBT or FPT file with the same name, it is used for the contents of memo fields.
     pub fn parse_file(path: &Path) -> Result<DbfFile, Error> {
-        let result = DbfFile{ file: dbf::DbfFile::open_file(path) };
-        Ok(result)
+        let mut file = BufReader::new(File::open(path)?);
+
+        let mut header = [0u8; 32];
+        file.read_exact(&mut header)?;
+
+        let version = header[0];
+        let num_records = (&header[4..8]).read_u32::<LittleEndian>()?;
+        let header_length = (&header[8..10]).read_u16::<LittleEndian>()?;
+        let record_length = (&header[10..12]).read_u16::<LittleEndian>()?;
+
+        if (header_length as usize) < header.len() + 1 || record_length < 1 {
+            return Err(Error::new(ErrorKind::Other, "DBF header is invalid!"));
+        }
+
+        // The field descriptors follow until the terminator byte
+        let mut descriptors = vec![0u8; header_length as usize - header.len()];
+        file.read_exact(&mut descriptors)?;
+
+        let fields: Vec<DbfField> = descriptors.chunks(DbfField::DESCRIPTOR_LENGTH)
+                                               .take_while(|c| c[0] != Self::HEADER_TERMINATOR && c.len() == DbfField::DESCRIPTOR_LENGTH)
+                                               .map(DbfField::parse)
+                                               .collect();
+
+        let has_memo_fields = fields.iter().any(|f| f.field_type == b'M');
+        let memo_file = if has_memo_fields {
+            Self::find_memo_file(path, version)?
+        } else {
+            None
+        };
+
+        Ok(DbfFile {
+            file,
+            position: header_length as u64,
+            num_records,
+            header_length,
+            record_length,
+            fields,
+            memo_file,
+        })
+    }
+
+    /// Looks for the DBT or FPT file next to the DBF file and opens it.
+    fn find_memo_file(path: &Path, version: u8) -> Result<Option<MemoFile>, Error> {
+        let dbt_format = if Self::DBASE_IV_MEMO_VERSIONS.contains(&version) {
+            MemoFormat::DBaseIV
+        } else {
+            MemoFormat::DBaseIII
+        };
+
+        let candidates = [
+            ("fpt", MemoFormat::FoxPro),
+            ("FPT", MemoFormat::FoxPro),
+            ("dbt", dbt_format),
+            ("DBT", dbt_format),
+        ];
+
+        for &(extension, format) in candidates.iter() {
+            let memo_path: PathBuf = path.with_extension(extension);
+            if memo_path.is_file() {
+                return MemoFile::parse_file(&memo_path, format).map(Some);
+            }
+        }
+
+        Ok(None)
     }
 
     /// Get the record with the given ID.
     pub fn record(&mut self, id: u32) -> Option<dbf::Record> {
-        self.file.record(id)
+        self.record_columns(id, None)
+    }
+
+    /// Looks up the indices of the columns with the given names. Unknown names are left out.
+    pub fn column_indices(&self, names: &[&str]) -> Vec<usize> {
+        names.iter()
+             .filter_map(|n| self.fields.iter().position(|f| f.name == *n))
+             .collect()
+    }
+
+    /// Get the record with the given ID, with only the given columns if there are any. The other
+    /// columns are not decoded, and their memos are not read.
+    pub fn record_columns(&mut self, id: u32, columns: Option<&[usize]>) -> Option<dbf::Record> {
+        if id >= self.num_records {
+            return None;
+        }
+
+        // Seeking discards the buffer, so only do it if the record doesn't follow the last one
+        let position = self.header_length as u64 + id as u64 * self.record_length as u64;
+        if position != self.position {
+            if self.file.seek(SeekFrom::Start(position)).is_err() {
+                self.position = u64::MAX;
+                return None;
+            }
+            self.position = position;
+        }
+
+        let mut bytes = vec![0u8; self.record_length as usize];
+        if self.file.read_exact(&mut bytes).is_err() {
+            self.position = u64::MAX;
+            return None;
+        }
+        self.position += bytes.len() as u64;
+
+        // The first byte is the deletion flag
+        let mut offset = 1usize;
+        let mut result = HashMap::with_capacity(columns.map_or(self.fields.len(), |c| c.len()));
+
+        for (i, field) in self.fields.iter().enumerate() {
+            let end = offset + field.length as usize;
+            if end > bytes.len() {
+                return None;
+            }
+
+            if columns.is_none_or(|c| c.contains(&i)) {
+                let value = Self::field_value(field, &bytes[offset..end], &mut self.memo_file);
+                result.insert(field.name.clone(), value);
+            }
+            offset = end;
+        }
+
+        Some(result)
+    }
+
+    /// Turns the raw bytes of a field into a value, depending on its type.
+    fn field_value(field: &DbfField, bytes: &[u8], memo_file: &mut Option<MemoFile>) -> dbf::Field {
+        // Binary block numbers can look like blanks or '*', so memos come before the null check
+        if field.field_type == b'M' {
+            return Self::memo_value(bytes, memo_file);
+        }
+
+        let text = String::from_utf8_lossy(bytes);
+        let text = text.trim_matches(|c: char| c == ' ' || c == '\0');
+
+        // Spec says that a string '*' means NULL, but empty strings are also viewed as null by
+        // some software
+        if text.is_empty() || text.starts_with('*') {
+            return dbf::Field::Null;
+        }
+
+        match field.field_type {
+            b'N' | b'F' => {
+                match text.parse() {
+                    Ok(v) => dbf::Field::Numeric(v),
+                    Err(_) => dbf::Field::Null,
+                }
+            },
+            b'L' => {
+                if text == "?" {
+                    dbf::Field::Null
+                } else {
+                    dbf::Field::Character(text.to_string())
+                }
+            },
+            _ => dbf::Field::Character(text.to_string()),
+        }
+    }
+
+    /// Reads the memo a memo field points to. Visual FoxPro stores the block number as a 4 byte
+    /// binary integer, dBASE as text. Memos which aren't text are null.
+    fn memo_value(bytes: &[u8], memo_file: &mut Option<MemoFile>) -> dbf::Field {
+        let block = if bytes.len() == 4 {
+            (&bytes[..]).read_u32::<LittleEndian>().ok()
+        } else {
+            let text = String::from_utf8_lossy(bytes);
+            text.trim_matches(|c: char| c == ' ' || c == '\0').parse::<u32>().ok()
+        };
+
+        match (block, memo_file.as_mut()) {
+            (Some(0), _) => dbf::Field::Null,
+            (Some(b), Some(m)) => {
+                match m.memo(b) {
+                    Ok(Some(s)) => dbf::Field::Character(s),
+                    Ok(None) | Err(_) => dbf::Field::Null,
+                }
+            },
+            _ => dbf::Field::Null,
+        }
+    }
+}
+
+impl DbfWriter {
+    /// The version byte of a dBASE III file without memo.
+    const VERSION: u8 = 0x03;
+    /// The version byte of a dBASE III file with a DBT file.
+    const VERSION_WITH_MEMO: u8 = 0x83;
+    /// Marks the end of the field descriptors in the header.
+    const HEADER_TERMINATOR: u8 = 0x0D;
+    /// Marks the end of the file.
+    const END_OF_FILE: u8 = 0x1A;
+
+    /// Creates the DBF file. If the columns are not given, they are derived from the first record.
+    /// Memo columns get a DBT file next to the DBF file.
+    pub fn create_file(path: &Path, fields: Option<Vec<DbfField>>) -> Result<Self, Error> {
+        let memo_file = match fields {
+            Some(ref f) if f.iter().any(|f| f.field_type == b'M') => Some(MemoWriter::create_file(&path.with_extension("dbt"))?),
+            _ => None,
+        };
+
+        // Readable too, since derived columns which get wider are rewritten
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path)?;
+        let mut result = DbfWriter {
+            fields: None,
+            derived: false,
+            untyped: vec![],
+            memo_file,
+            num_records: 0,
+            file: BufWriter::new(file),
+        };
+
+        if let Some(f) = fields {
+            result.set_fields(f)?;
+        }
+
+        Ok(result)
+    }
+
+    /// Fixes the columns and writes the header.
+    fn set_fields(&mut self, fields: Vec<DbfField>) -> Result<(), Error> {
+        self.fields = Some(fields);
+        self.write_header()
+    }
+
+    /// Writes the header with the current number of records at the start of the file.
+    fn write_header(&mut self) -> Result<(), Error> {
+        let empty = vec![];
+        let fields = self.fields.as_ref().unwrap_or(&empty);
+
+        let header_length = 32 + 32 * fields.len() + 1;
+        let record_length = 1 + fields.iter().map(|f| f.length as usize).sum::<usize>();
+        if header_length > u16::MAX as usize || record_length > u16::MAX as usize {
+            return Err(Error::new(ErrorKind::Other, "Too many DBF columns!"));
+        }
+
+        let today = today();
+
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_u8(if self.memo_file.is_some() { Self::VERSION_WITH_MEMO } else { Self::VERSION })?;
+        self.file.write_all(&[(today.year - 1900) as u8, today.month as u8, today.day as u8])?;
+        self.file.write_u32::<LittleEndian>(self.num_records)?;
+        self.file.write_u16::<LittleEndian>(header_length as u16)?;
+        self.file.write_u16::<LittleEndian>(record_length as u16)?;
+        self.file.write_all(&[0u8; 20])?;
+
+        for f in fields {
+            f.write(&mut self.file)?;
+        }
+        self.file.write_u8(Self::HEADER_TERMINATOR)?;
+
+        Ok(())
+    }
+
+    /// Formats a record. The columns are derived from it if this is the first one. Derived
+    /// columns get their type from their first value which isn't null, and get wider if the values
+    /// don't fit.
+    pub fn encode_record(&mut self, values: &[(String, DbfValue)]) -> Result<Vec<u8>, Error> {
+        if self.fields.is_none() {
+            let fields = values.iter().map(|(name, value)| DbfField::for_value(name, value)).collect();
+            self.untyped = values.iter().filter(|(_, value)| *value == DbfValue::Null).map(|(name, _)| name.clone()).collect();
+            self.set_fields(fields)?;
+            self.derived = true;
+        }
+        if self.derived {
+            for (name, value) in values {
+                let typed = *value != DbfValue::Null && self.untyped.contains(name);
+                let changed = self.fields.as_ref().unwrap().iter().enumerate()
+                                  .find(|(_, f)| &f.name == name)
+                                  .and_then(|(i, f)| {
+                                      if typed { Some((i, DbfField::for_value(name, value))) } else { f.widened_for(value).map(|w| (i, w)) }
+                                  });
+                if typed {
+                    self.untyped.retain(|n| n != name);
+                }
+                if let Some((index, field)) = changed {
+                    self.repack(index, field)?;
+                }
+            }
+        }
+
+        let fields = self.fields.as_ref().unwrap();
+        for (name, _) in values {
+            if !fields.iter().any(|f| &f.name == name) {
+                return Err(Error::new(ErrorKind::Other, format!("There is no DBF column `{}`!", name)));
+            }
+        }
+
+        // Memos come last, so that nothing gets into the DBT file if a value doesn't fit
+        let value_of = |f: &DbfField| values.iter().find(|(name, _)| name == &f.name).map_or(&DbfValue::Null, |(_, v)| v);
+        let mut encoded: Vec<Option<Vec<u8>>> = vec![];
+        for f in fields {
+            encoded.push(if f.field_type == b'M' { None } else { Some(f.encode(value_of(f))?) });
+        }
+
+        // Not deleted
+        let mut result = vec![b' '];
+        for (f, e) in fields.iter().zip(encoded) {
+            match (e, self.memo_file.as_mut()) {
+                (Some(bytes), _) => result.extend(bytes),
+                (None, Some(memo_file)) => result.extend(Self::encode_memo(f, value_of(f), memo_file)?),
+                (None, None) => result.extend(f.encode(value_of(f))?),
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Writes the text of a memo column into the DBT file, and formats its block number.
+    fn encode_memo(field: &DbfField, value: &DbfValue, memo_file: &mut MemoWriter) -> Result<Vec<u8>, Error> {
+        let block = match *value {
+            DbfValue::Null => return Ok(vec![b' '; field.length as usize]),
+            DbfValue::Character(ref s) if s.is_empty() => return Ok(vec![b' '; field.length as usize]),
+            DbfValue::Character(ref s) => memo_file.write(s)?,
+            _ => return Err(Error::new(ErrorKind::Other, format!("Value {:?} does not fit into DBF column `{}`!", value, field.name))),
+        };
+        DbfField::pad_left(block.to_string(), field.length as usize)
+            .ok_or_else(|| Error::new(ErrorKind::Other, format!("DBT file is too long for DBF column `{}`!", field.name)))
+    }
+
+    /// Replaces a column by a wider one, or gives a column of null values its type, rewriting the
+    /// records written so far.
+    fn repack(&mut self, index: usize, field: DbfField) -> Result<(), Error> {
+        let old_fields = self.fields.clone().unwrap_or_default();
+        let header_length = 32 + 32 * old_fields.len() as u64 + 1;
+        let record_length = 1 + old_fields.iter().map(|f| f.length as usize).sum::<usize>();
+        let start = 1 + old_fields[..index].iter().map(|f| f.length as usize).sum::<usize>();
+        let end = start + old_fields[index].length as usize;
+
+        let mut records = vec![0u8; record_length * self.num_records as usize];
+        self.file.flush()?;
+        self.file.get_mut().seek(SeekFrom::Start(header_length))?;
+        self.file.get_mut().read_exact(&mut records)?;
+
+        let mut repacked: Vec<u8> = Vec::with_capacity(records.len());
+        for record in records.chunks(record_length) {
+            let text = String::from_utf8_lossy(&record[start..end]);
+            let value = match old_fields[index].field_type {
+                b'N' | b'F' => text.trim().parse::<f64>().map_or(DbfValue::Null, DbfValue::Float),
+                _ if text.trim().is_empty() => DbfValue::Null,
+                _ => DbfValue::Character(text.trim_end().to_string()),
+            };
+            repacked.extend_from_slice(&record[..start]);
+            repacked.extend(field.encode(&value)?);
+            repacked.extend_from_slice(&record[end..]);
+        }
+
+        if let Some(fields) = self.fields.as_mut() {
+            fields[index] = field;
+        }
+        self.write_header()?;
+        self.file.write_all(&repacked)
+    }
+
+    /// Appends a record formatted by `encode_record`.
+    pub fn write_encoded(&mut self, record: &[u8]) -> Result<(), Error> {
+        self.num_records += 1;
+        self.file.write_all(record)
+    }
+
+    /// Writes the end of file marker, updates the header and flushes the file.
+    pub fn finish(&mut self) -> Result<(), Error> {
+        if self.fields.is_none() {
+            self.set_fields(vec![])?;
+        }
+
+        if let Some(ref mut memo_file) = self.memo_file {
+            memo_file.finish()?;
+        }
+
+        self.file.seek(SeekFrom::End(0))?;
+        self.file.write_u8(Self::END_OF_FILE)?;
+        self.write_header()?;
+        self.file.flush()
+    }
+}
+
+/// The current date in UTC.
+fn today() -> Date {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    civil_from_days((seconds / 86400) as i64)
+}
+
+/// Converts days since 1970-01-01 into a civil date, see
+/// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+pub(crate) fn civil_from_days(days: i64) -> Date {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = (yoe + era * 400 + if month <= 2 { 1 } else { 0 }) as i32;
+
+    Date {year, month, day}
+}
+
+/// Converts a civil date into days since 1970-01-01, the inverse of `civil_from_days`.
+pub(crate) fn days_from_civil(date: Date) -> i64 {
+    let year = i64::from(date.year) - if date.month <= 2 { 1 } else { 0 };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let month = i64::from(date.month);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(date.day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{DbfField, DbfFile, DbfValue, DbfWriter};
+    use std::env;
+    use std::fs::File;
+    use std::io::Write;
+    use std::path::PathBuf;
+    use byteorder::{BigEndian, LittleEndian, WriteBytesExt};
+    use dbf;
+
+    /// Writes a DBF file with a character column `name` and a memo column `notes`.
+    fn write_dbf(path: &PathBuf, version: u8, memo_refs: &[&str]) {
+        let mut out: Vec<u8> = vec![];
+        out.push(version);
+        out.extend_from_slice(&[117, 1, 1]);
+        out.write_u32::<LittleEndian>(memo_refs.len() as u32).unwrap();
+        out.write_u16::<LittleEndian>(32 + 2 * 32 + 1).unwrap();
+        out.write_u16::<LittleEndian>(1 + 8 + 10).unwrap();
+        out.extend_from_slice(&[0u8; 20]);
+
+        for &(name, ty, len) in [("name", b'C', 8u8), ("notes", b'M', 10u8)].iter() {
+            let mut descriptor = [0u8; 32];
+            descriptor[..name.len()].copy_from_slice(name.as_bytes());
+            descriptor[11] = ty;
+            descriptor[16] = len;
+            out.extend_from_slice(&descriptor);
+        }
+        out.push(0x0D);
+
+        for (i, r) in memo_refs.iter().enumerate() {
+            out.push(b' ');
+            out.extend_from_slice(format!("{:<8}", format!("road{}", i)).as_bytes());
+            out.extend_from_slice(format!("{:>10}", r).as_bytes());
+        }
+        out.push(0x1A);
+
+        File::create(path).unwrap().write_all(&out).unwrap();
+    }
+
+    /// Writes a Visual FoxPro DBF file with a 4 byte binary memo column `notes`.
+    fn write_vfp_dbf(path: &PathBuf, blocks: &[u32]) {
+        let mut out: Vec<u8> = vec![];
+        out.push(0x30);
+        out.extend_from_slice(&[117, 1, 1]);
+        out.write_u32::<LittleEndian>(blocks.len() as u32).unwrap();
+        out.write_u16::<LittleEndian>(32 + 32 + 1).unwrap();
+        out.write_u16::<LittleEndian>(1 + 4).unwrap();
+        out.extend_from_slice(&[0u8; 20]);
+
+        let mut descriptor = [0u8; 32];
+        descriptor[..5].copy_from_slice(b"notes");
+        descriptor[11] = b'M';
+        descriptor[16] = 4;
+        out.extend_from_slice(&descriptor);
+        out.push(0x0D);
+
+        for &block in blocks {
+            out.push(b' ');
+            out.write_u32::<LittleEndian>(block).unwrap();
+        }
+        out.push(0x1A);
+
+        File::create(path).unwrap().write_all(&out).unwrap();
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("shapefile-utils-test-{}", name))
+    }
+
+    fn memo_of(file: &mut DbfFile, id: u32) -> Option<String> {
+        match file.record(id).unwrap().remove("notes").unwrap() {
+            dbf::Field::Character(s) => Some(s),
+            dbf::Field::Null => None,
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn test_dbase3_memo() {
+        let dbf_path = temp_path("memo3.dbf");
+        write_dbf(&dbf_path, 0x83, &["1", "", "2"]);
+
+        let mut dbt: Vec<u8> = vec![];
+        dbt.write_u32::<LittleEndian>(3).unwrap();
+        dbt.resize(512, 0);
+        dbt.extend_from_slice(b"Built 1871\x1A\x1A");
+        dbt.resize(1024, 0);
+        dbt.extend_from_slice(&[b'x'; 600]);
+        dbt.extend_from_slice(b"\x1A\x1A");
+        File::create(dbf_path.with_extension("dbt")).unwrap().write_all(&dbt).unwrap();
+
+        let mut file = DbfFile::parse_file(&dbf_path).unwrap();
+        if memo_of(&mut file, 0) != Some(String::from("Built 1871")) {
+            panic!()
+        }
+        if memo_of(&mut file, 1).is_some() {
+            panic!()
+        }
+        // Spans more than one block
+        if memo_of(&mut file, 2).unwrap().len() != 600 {
+            panic!()
+        }
+    }
+
+    #[test]
+    fn test_dbase4_memo() {
+        let dbf_path = temp_path("memo4.dbf");
+        write_dbf(&dbf_path, 0x8B, &["1"]);
+
+        let mut dbt: Vec<u8> = vec![];
+        dbt.write_u32::<LittleEndian>(2).unwrap();
+        dbt.resize(20, 0);
+        dbt.write_u16::<LittleEndian>(64).unwrap();
+        dbt.resize(64, 0);
+        dbt.extend_from_slice(&[0xFF, 0xFF, 0x08, 0x00]);
+        dbt.write_u32::<LittleEndian>(8 + 5).unwrap();
+        dbt.extend_from_slice(b"Ferry");
+        File::create(dbf_path.with_extension("dbt")).unwrap().write_all(&dbt).unwrap();
+
+        let mut file = DbfFile::parse_file(&dbf_path).unwrap();
+        if memo_of(&mut file, 0) != Some(String::from("Ferry")) {
+            panic!()
+        }
+    }
+
+    #[test]
+    fn test_foxpro_memo() {
+        let dbf_path = temp_path("memofox.dbf");
+        write_dbf(&dbf_path, 0xF5, &["8", "9"]);
+
+        let mut fpt: Vec<u8> = vec![];
+        fpt.write_u32::<BigEndian>(10).unwrap();
+        fpt.resize(6, 0);
+        fpt.write_u16::<BigEndian>(64).unwrap();
+        fpt.resize(512, 0);
+        fpt.write_u32::<BigEndian>(1).unwrap();
+        fpt.write_u32::<BigEndian>(7).unwrap();
+        fpt.extend_from_slice(b"Toll rd");
+        // A picture, which has no text
+        fpt.resize(9 * 64, 0);
+        fpt.write_u32::<BigEndian>(0).unwrap();
+        fpt.write_u32::<BigEndian>(4).unwrap();
+        fpt.extend_from_slice(&[0x89, b'P', b'N', b'G']);
+        File::create(dbf_path.with_extension("fpt")).unwrap().write_all(&fpt).unwrap();
+
+        let mut file = DbfFile::parse_file(&dbf_path).unwrap();
+        if memo_of(&mut file, 0) != Some(String::from("Toll rd")) || memo_of(&mut file, 1).is_some() {
+            panic!()
+        }
+        if let dbf::Field::Character(ref s) = file.record(0).unwrap()["name"] {
+            if s != "road0" {
+                panic!()
+            }
+        } else {
+            panic!()
+        }
+    }
+
+    #[test]
+    fn test_binary_memo_blocks() {
+        let dbf_path = temp_path("memovfp.dbf");
+        // Block 32 is stored as a blank and 42 as '*', and block 2 claims a huge length
+        write_vfp_dbf(&dbf_path, &[32, 42, 2]);
+
+        let mut fpt: Vec<u8> = vec![];
+        fpt.write_u32::<BigEndian>(43).unwrap();
+        fpt.resize(6, 0);
+        fpt.write_u16::<BigEndian>(64).unwrap();
+        fpt.resize(128, 0);
+        fpt.write_u32::<BigEndian>(1).unwrap();
+        fpt.write_u32::<BigEndian>(0xFFFF_FFF0).unwrap();
+        fpt.resize(32 * 64, 0);
+        fpt.write_u32::<BigEndian>(1).unwrap();
+        fpt.write_u32::<BigEndian>(6).unwrap();
+        fpt.extend_from_slice(b"Bridge");
+        fpt.resize(42 * 64, 0);
+        fpt.write_u32::<BigEndian>(1).unwrap();
+        fpt.write_u32::<BigEndian>(6).unwrap();
+        fpt.extend_from_slice(b"Tunnel");
+        File::create(dbf_path.with_extension("fpt")).unwrap().write_all(&fpt).unwrap();
+
+        let mut file = DbfFile::parse_file(&dbf_path).unwrap();
+        if memo_of(&mut file, 0) != Some(String::from("Bridge")) || memo_of(&mut file, 1) != Some(String::from("Tunnel")) {
+            panic!()
+        }
+        if memo_of(&mut file, 2).is_some() {
+            panic!()
+        }
+    }
+
+    #[test]
+    fn test_missing_memo_file() {
+        let dbf_path = temp_path("memonone.dbf");
+        write_dbf(&dbf_path, 0x83, &["1"]);
+
+        let mut file = DbfFile::parse_file(&dbf_path).unwrap();
+        if memo_of(&mut file, 0).is_some() {
+            panic!()
+        }
+    }
+
+    #[test]
+    fn test_write_memo() {
+        let dbf_path = temp_path("memowrite.dbf");
+        let long = "Rebuilt after the flood. ".repeat(30);
+        let mut writer = DbfWriter::create_file(&dbf_path, Some(vec![DbfField::character("name", 8), DbfField::memo("notes")])).unwrap();
+        for (i, notes) in [DbfValue::Character(long.clone()), DbfValue::Null, DbfValue::Character(String::from("Toll"))].iter().enumerate() {
+            let record = writer.encode_record(&[(String::from("name"), DbfValue::Character(format!("road{}", i))),
+                                                (String::from("notes"), notes.clone())]).unwrap();
+            writer.write_encoded(&record).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let mut file = DbfFile::parse_file(&dbf_path).unwrap();
+        if memo_of(&mut file, 0) != Some(long) || memo_of(&mut file, 1).is_some() || memo_of(&mut file, 2) != Some(String::from("Toll")) {
+            panic!()
+        }
     }
 }
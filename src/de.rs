@@ -0,0 +1,569 @@
+//! Deserialization of records into user defined structs.
+//!
+//! Every column of the DBF file becomes a struct field of the same name, so `#[serde(rename)]`
+//! works as usual. The values are coerced into the requested type where possible: numeric strings
+//! can be read as numbers, numbers as strings, dBASE dates (`YYYYMMDD`) as `Date`, and logical
+//! fields (`T`, `F`, `Y`, `N`) as `bool`. Null values need an `Option`.
+//!
+//! The shape is available as a field called `geometry`, which can be a `Shape` or an
+//! `Option<Shape>`. It is left out if the struct does not have such a field. Another name can be
+//! chosen with `Shapefile::set_geometry_field`; a DBF column with the name of the geometry field
+//! is left out, since the shape takes its place.
+//!
+//! # Example
+//!
+//! ```ignore
+//! #[derive(Deserialize)]
+//! struct Road {
+//!     #[serde(rename = "osm_id")]
+//!     id: u64,
+//!     name: Option<String>,
+//!     geometry: Shape,
+//! }
+//!
+//! for road in my_shapefile.iter_as::<Road>() {
+//!     let road = road.unwrap();
+//! }
+//! ```
+
+use std::collections::hash_map;
+use std::error;
+use std::fmt;
+use std::io::Cursor;
+
+use dbf;
+use serde::de::{self, Deserialize, DeserializeOwned, DeserializeSeed, IntoDeserializer, MapAccess, SeqAccess, Visitor};
+
+use super::{Date, ShapefileRecord};
+use super::shape::Shape;
+
+/// The name of the struct field that receives the shape.
+pub const GEOMETRY_FIELD: &str = "geometry";
+
+/// Marker used by `Shape`'s `Deserialize` implementation to recognize our deserializer.
+const SHAPE_TOKEN: &str = "$shapefile_utils::Shape";
+
+/// An error that occurred while deserializing a record.
+#[derive(Debug, PartialEq)]
+pub struct Error {
+    /// A description of the error
+    message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl error::Error for Error {}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error {message: msg.to_string()}
+    }
+}
+
+/// Deserializes a record into an instance of `T`.
+pub fn from_record<T: DeserializeOwned>(record: ShapefileRecord) -> Result<T, Error> {
+    from_record_with(record, GEOMETRY_FIELD)
+}
+
+/// Deserializes a record into an instance of `T`, with the shape in the given field.
+pub fn from_record_with<T: DeserializeOwned>(record: ShapefileRecord, geometry_field: &str) -> Result<T, Error> {
+    T::deserialize(RecordDeserializer::with_geometry_field(record, geometry_field))
+}
+
+/// A deserializer over the attributes and the shape of a record. Acts like a map.
+pub struct RecordDeserializer {
+    /// The shape, which is taken out once the `geometry` field is visited.
+    shape: Option<Shape>,
+    /// The name of the field receiving the shape
+    geometry_field: String,
+    /// The DBF values of the record
+    attributes: hash_map::IntoIter<String, dbf::Field>,
+    /// The value belonging to the most recently visited key
+    value: Option<FieldValue>,
+}
+
+/// A value in the map of a record.
+enum FieldValue {
+    Attribute(dbf::Field),
+    Geometry(Shape),
+}
+
+impl RecordDeserializer {
+    /// Creates a deserializer for the given record.
+    pub fn new(record: ShapefileRecord) -> Self {
+        Self::with_geometry_field(record, GEOMETRY_FIELD)
+    }
+
+    /// Creates a deserializer for the given record, with the shape in the given field.
+    pub fn with_geometry_field(record: ShapefileRecord, geometry_field: &str) -> Self {
+        RecordDeserializer {
+            shape: Some(record.shape),
+            geometry_field: geometry_field.to_string(),
+            attributes: record.metadata.into_iter(),
+            value: None,
+        }
+    }
+}
+
+impl<'de> de::Deserializer<'de> for RecordDeserializer {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_map(self)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf option
+        unit unit_struct newtype_struct seq tuple tuple_struct map struct enum identifier
+        ignored_any
+    }
+}
+
+impl<'de> MapAccess<'de> for RecordDeserializer {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, Error> {
+        // An attribute named like the geometry field would be a duplicate key
+        let geometry_field = &self.geometry_field;
+        let (key, value) = match self.attributes.find(|a| a.0 != *geometry_field) {
+            Some((k, v)) => (k, FieldValue::Attribute(v)),
+            None => {
+                match self.shape.take() {
+                    Some(s) => (geometry_field.clone(), FieldValue::Geometry(s)),
+                    None => return Ok(None),
+                }
+            },
+        };
+
+        self.value = Some(value);
+        seed.deserialize(key.into_deserializer()).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        match self.value.take() {
+            Some(FieldValue::Attribute(f)) => seed.deserialize(FieldDeserializer {field: f}),
+            Some(FieldValue::Geometry(s)) => seed.deserialize(GeometryDeserializer {shape: s}),
+            None => Err(de::Error::custom("value requested before key")),
+        }
+    }
+}
+
+/// A deserializer for a single DBF value, which coerces it into the requested type.
+struct FieldDeserializer {
+    field: dbf::Field,
+}
+
+impl FieldDeserializer {
+    /// Interprets the value as a float, parsing strings if necessary.
+    fn to_f64(&self) -> Result<f64, Error> {
+        match self.field {
+            dbf::Field::Numeric(n) => Ok(n),
+            dbf::Field::Character(ref s) => {
+                s.trim().parse().map_err(|_| de::Error::custom(format!("`{}` is not a number", s)))
+            },
+            dbf::Field::Null => Err(de::Error::custom("null value for a non-optional number")),
+        }
+    }
+
+    /// Interprets the value as an integer, allowing floats without fraction.
+    fn to_i64(&self) -> Result<i64, Error> {
+        if let dbf::Field::Character(ref s) = self.field {
+            if let Ok(v) = s.trim().parse::<i64>() {
+                return Ok(v);
+            }
+        }
+
+        let v = self.to_f64()?;
+        if v.fract() != 0f64 || v < i64::MIN as f64 || v > i64::MAX as f64 {
+            return Err(de::Error::custom(format!("{} is not an integer", v)));
+        }
+        Ok(v as i64)
+    }
+
+    /// Interprets the value as an unsigned integer, allowing floats without fraction.
+    fn to_u64(&self) -> Result<u64, Error> {
+        if let dbf::Field::Character(ref s) = self.field {
+            if let Ok(v) = s.trim().parse::<u64>() {
+                return Ok(v);
+            }
+        }
+
+        let v = self.to_f64()?;
+        if v.fract() != 0f64 || v < 0f64 || v > u64::MAX as f64 {
+            return Err(de::Error::custom(format!("{} is not an unsigned integer", v)));
+        }
+        Ok(v as u64)
+    }
+}
+
+impl<'de> de::Deserializer<'de> for FieldDeserializer {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.field {
+            dbf::Field::Character(s) => visitor.visit_string(s),
+            dbf::Field::Numeric(n) => visitor.visit_f64(n),
+            dbf::Field::Null => visitor.visit_none(),
+        }
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.field {
+            dbf::Field::Character(ref s) => {
+                match s.trim() {
+                    "T" | "t" | "Y" | "y" | "true" | "1" => visitor.visit_bool(true),
+                    "F" | "f" | "N" | "n" | "false" | "0" => visitor.visit_bool(false),
+                    _ => Err(de::Error::custom(format!("`{}` is not a logical value", s))),
+                }
+            },
+            dbf::Field::Numeric(n) => visitor.visit_bool(n != 0f64),
+            dbf::Field::Null => Err(de::Error::custom("null value for a non-optional bool")),
+        }
+    }
+
+    fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_i64(self.to_i64()?)
+    }
+
+    fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_i64(self.to_i64()?)
+    }
+
+    fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_i64(self.to_i64()?)
+    }
+
+    fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_i64(self.to_i64()?)
+    }
+
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_u64(self.to_u64()?)
+    }
+
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_u64(self.to_u64()?)
+    }
+
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_u64(self.to_u64()?)
+    }
+
+    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_u64(self.to_u64()?)
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_f64(self.to_f64()?)
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_f64(self.to_f64()?)
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_string(visitor)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.field {
+            dbf::Field::Character(s) => visitor.visit_string(s),
+            // Numeric columns often hold identifiers, so don't print a fraction for whole numbers
+            dbf::Field::Numeric(n) => visitor.visit_string(format!("{}", n)),
+            dbf::Field::Null => Err(de::Error::custom("null value for a non-optional string")),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.field {
+            dbf::Field::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str, _variants: &'static [&'static str], visitor: V) -> Result<V::Value, Error> {
+        match self.field {
+            dbf::Field::Character(s) => visitor.visit_enum(s.into_deserializer()),
+            _ => Err(de::Error::custom("only character values can be read as enums")),
+        }
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    forward_to_deserialize_any! {
+        i128 u128 char bytes byte_buf unit_struct seq tuple tuple_struct map struct identifier
+    }
+}
+
+/// A deserializer for the `geometry` field, which can only be read as a `Shape`.
+struct GeometryDeserializer {
+    shape: Shape,
+}
+
+impl<'de> de::Deserializer<'de> for GeometryDeserializer {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Error> {
+        Err(de::Error::custom("the geometry can only be deserialized into a `Shape`"))
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.shape {
+            Shape::NullShape => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, name: &'static str, visitor: V) -> Result<V::Value, Error> {
+        if name != SHAPE_TOKEN {
+            return Err(de::Error::custom("the geometry can only be deserialized into a `Shape`"));
+        }
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let mut bytes: Vec<u8> = vec![];
+        self.shape.write(&mut bytes).map_err(de::Error::custom)?;
+        visitor.visit_byte_buf(bytes)
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string unit unit_struct seq
+        tuple tuple_struct map struct enum identifier
+    }
+}
+
+/// Parses a shape from its binary SHP encoding, the mirror of how it is serialized.
+struct ShapeVisitor;
+
+impl<'de> Visitor<'de> for ShapeVisitor {
+    type Value = Shape;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("the binary encoding of a shape")
+    }
+
+    fn visit_newtype_struct<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<Shape, D::Error> {
+        deserializer.deserialize_bytes(self)
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Shape, E> {
+        match Shape::parse(&mut Cursor::new(v)) {
+            Ok((shape, _)) => Ok(shape),
+            Err(e) => Err(E::custom(e)),
+        }
+    }
+
+    /// Formats without a byte type, like JSON, write the bytes as a sequence of numbers.
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Shape, A::Error> {
+        let mut bytes: Vec<u8> = vec![];
+        while let Some(b) = seq.next_element()? {
+            bytes.push(b);
+        }
+        self.visit_bytes(&bytes)
+    }
+}
+
+/// A `Shape` is deserialized from its binary encoding in the SHP file, the way it is serialized.
+impl<'de> Deserialize<'de> for Shape {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_newtype_struct(SHAPE_TOKEN, ShapeVisitor)
+    }
+}
+
+impl Date {
+    /// Parses a date in the dBASE format `YYYYMMDD`, or in the ISO format `YYYY-MM-DD`.
+    pub fn parse(s: &str) -> Option<Date> {
+        let digits: String = s.trim().chars().filter(|&c| c != '-').collect();
+        if digits.len() != 8 || !digits.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+
+        let result = Date {
+            year: digits[0..4].parse().ok()?,
+            month: digits[4..6].parse().ok()?,
+            day: digits[6..8].parse().ok()?,
+        };
+
+        if result.month < 1 || result.month > 12 || result.day < 1 || result.day > 31 {
+            return None;
+        }
+        Some(result)
+    }
+}
+
+/// Reads the date from a string.
+struct DateVisitor;
+
+impl<'de> Visitor<'de> for DateVisitor {
+    type Value = Date;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a date formatted as YYYYMMDD or YYYY-MM-DD")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Date, E> {
+        Date::parse(v).ok_or_else(|| E::custom(format!("`{}` is not a date", v)))
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<Date, E> {
+        self.visit_str(&format!("{}", v))
+    }
+}
+
+impl<'de> Deserialize<'de> for Date {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(DateVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_record, from_record_with};
+    use super::super::{Date, ShapefileRecord};
+    use super::super::shape::{Shape, BoundingBox, Point};
+    use std::collections::HashMap;
+    use dbf;
+
+    #[derive(Deserialize, Debug)]
+    struct Road {
+        #[serde(rename = "osm_id")]
+        id: u64,
+        name: Option<String>,
+        lanes: u8,
+        width: f64,
+        oneway: bool,
+        opened: Date,
+        geometry: Shape,
+    }
+
+    #[derive(Deserialize, Debug)]
+    struct RoadName {
+        name: String,
+        ref_code: String,
+        geometry: Option<Shape>,
+    }
+
+    fn record(shape: Shape) -> ShapefileRecord {
+        let mut metadata = HashMap::new();
+        metadata.insert(String::from("osm_id"), dbf::Field::Character(String::from("464787242")));
+        metadata.insert(String::from("name"), dbf::Field::Character(String::from("Dock 10")));
+        metadata.insert(String::from("lanes"), dbf::Field::Numeric(2.0));
+        metadata.insert(String::from("width"), dbf::Field::Character(String::from("7.5")));
+        metadata.insert(String::from("oneway"), dbf::Field::Character(String::from("F")));
+        metadata.insert(String::from("opened"), dbf::Field::Character(String::from("19710401")));
+        metadata.insert(String::from("ref_code"), dbf::Field::Numeric(42.0));
+        ShapefileRecord {shape, metadata}
+    }
+
+    #[test]
+    fn test_deserialize_struct() {
+        let road: Road = from_record(record(Shape::Point {point: Point {x: 1.0, y: 2.0}})).unwrap();
+
+        if road.id != 464787242 || road.name != Some(String::from("Dock 10")) || road.lanes != 2
+        || road.width != 7.5 || road.oneway || road.opened != (Date {year: 1971, month: 4, day: 1}) {
+            panic!()
+        }
+
+        if road.geometry != (Shape::Point {point: Point {x: 1.0, y: 2.0}}) {
+            panic!()
+        }
+    }
+
+    #[test]
+    fn test_deserialize_coercion_and_null_shape() {
+        let road: RoadName = from_record(record(Shape::NullShape)).unwrap();
+
+        if road.name != "Dock 10" || road.ref_code != "42" || road.geometry.is_some() {
+            panic!()
+        }
+    }
+
+    #[derive(Deserialize, Debug)]
+    struct Parcel {
+        geometry: Option<String>,
+        shape: Shape,
+    }
+
+    #[test]
+    fn test_geometry_column() {
+        // A column named like the geometry field gives way to the shape
+        let surveyed = || {
+            let mut r = record(Shape::Point {point: Point {x: 1.0, y: 2.0}});
+            r.metadata.insert(String::from("geometry"), dbf::Field::Character(String::from("surveyed")));
+            r
+        };
+        let road: RoadName = from_record(surveyed()).unwrap();
+        if road.geometry != Some(Shape::Point {point: Point {x: 1.0, y: 2.0}}) {
+            panic!("{:?}", road)
+        }
+
+        // With another geometry field, it can be read
+        let parcel: Parcel = from_record_with(surveyed(), "shape").unwrap();
+        if parcel.geometry != Some(String::from("surveyed")) || parcel.shape != (Shape::Point {point: Point {x: 1.0, y: 2.0}}) {
+            panic!("{:?}", parcel)
+        }
+    }
+
+    #[test]
+    fn test_deserialize_errors() {
+        let mut r = record(Shape::NullShape);
+        r.metadata.insert(String::from("name"), dbf::Field::Null);
+        r.metadata.insert(String::from("lanes"), dbf::Field::Numeric(2.5));
+
+        // Fractional lanes don't fit into an u8
+        if from_record::<Road>(r).is_ok() {
+            panic!()
+        }
+
+        // A null name doesn't fit into a String
+        let mut r = record(Shape::NullShape);
+        r.metadata.insert(String::from("name"), dbf::Field::Null);
+        if from_record::<RoadName>(r).is_ok() {
+            panic!()
+        }
+    }
+
+    #[test]
+    fn test_deserialize_from_bytes() {
+        use serde::Deserialize;
+        use serde::de::value::{BytesDeserializer, Error};
+
+        // Any deserializer which hands out the binary encoding works, not just our own
+        let shape = Shape::PolyLine {
+            bounding_box: BoundingBox {x_min: 0.0, y_min: 0.0, x_max: 3.0, y_max: 4.0},
+            parts: vec![0],
+            points: vec![Point {x: 0.0, y: 0.0}, Point {x: 3.0, y: 4.0}],
+        };
+        let mut bytes: Vec<u8> = vec![];
+        shape.write(&mut bytes).unwrap();
+        if Shape::deserialize(BytesDeserializer::<Error>::new(&bytes)) != Ok(shape) {
+            panic!()
+        }
+        if Shape::deserialize(BytesDeserializer::<Error>::new(&[1, 2, 3])).is_ok() {
+            panic!()
+        }
+    }
+}
@@ -0,0 +1,487 @@
+//! Module for planar geometry helpers
+//!
+//! These are the building blocks shared by the validity checks and the other operations on shapes:
+//! access to the parts of a shape, ring areas, point in ring tests and segment intersections.
+
+use std::ops::Range;
+
+use super::shape::{Shape, ShapeType, BoundingBox, PatchType, Point, PointM, PointZ, Range as ValueRange};
+
+/// Measures below this value mean "no data", see page 2 of the spec.
+pub(crate) const NO_DATA: f64 = -1e38;
+/// The measure written where there is none.
+pub(crate) const MISSING_MEASURE: f64 = -1e39;
+
+/// A point as a hashable and ordered key, equal for points with the same coordinates.
+pub(crate) type Key = (u64, u64);
+
+/// The key of a point.
+pub(crate) fn key(p: Point) -> Key {
+    (p.x.to_bits(), p.y.to_bits())
+}
+
+/// The parts and points of a shape with parts, None for all other shapes.
+pub(crate) fn parts_and_points(shape: &Shape) -> Option<(&[i32], &[Point])> {
+    match *shape {
+        Shape::PolyLine {ref parts, ref points, ..}
+        | Shape::PolyLineM {ref parts, ref points, ..}
+        | Shape::PolyLineZ {ref parts, ref points, ..}
+        | Shape::Polygon {ref parts, ref points, ..}
+        | Shape::PolygonM {ref parts, ref points, ..}
+        | Shape::PolygonZ {ref parts, ref points, ..}
+        | Shape::MultiPatch {ref parts, ref points, ..} => Some((parts, points)),
+        _ => None,
+    }
+}
+
+/// The ranges of point indices of the parts. Offsets which are out of order or out of range are
+/// clamped, so the ranges are always valid.
+pub(crate) fn part_ranges(parts: &[i32], num_points: usize) -> Vec<Range<usize>> {
+    let mut result = Vec::with_capacity(parts.len());
+    for (i, &start) in parts.iter().enumerate() {
+        let end = match parts.get(i + 1) {
+            Some(&e) => e.max(0) as usize,
+            None => num_points,
+        };
+        let start = (start.max(0) as usize).min(num_points);
+        result.push(start..end.max(start).min(num_points));
+    }
+    result
+}
+
+/// The points of every part of a shape with parts, None for all other shapes.
+pub(crate) fn parts_of(shape: &Shape) -> Option<Vec<&[Point]>> {
+    parts_and_points(shape).map(|(parts, points)| {
+        part_ranges(parts, points.len()).into_iter().map(|r| &points[r]).collect()
+    })
+}
+
+/// The dimension of a shape for planar operations: 0 for points, 1 for polylines and 2 for
+/// polygons. None for the NullShape and multipatches, whose surfaces lie in 3D.
+pub(crate) fn dimension(shape: &Shape) -> Option<u32> {
+    match shape.shape_type() {
+        ShapeType::MultiPatch => None,
+        shape_type => shape_type.dimension(),
+    }
+}
+
+/// The signed area of a ring, positive if the ring is counterclockwise. The ring doesn't need to
+/// be closed.
+pub(crate) fn signed_area(ring: &[Point]) -> f64 {
+    if ring.len() < 3 {
+        return 0f64;
+    }
+
+    let mut sum = 0f64;
+    for i in 0..ring.len() {
+        let a = ring[i];
+        let b = ring[(i + 1) % ring.len()];
+        sum += a.x * b.y - b.x * a.y;
+    }
+    sum / 2f64
+}
+
+/// The cross product of (b - a) and (c - a). Positive if c is left of the line from a to b.
+pub(crate) fn cross(a: Point, b: Point, c: Point) -> f64 {
+    (b.x - a.x) * (c.y - a.y) - (b.y - a.y) * (c.x - a.x)
+}
+
+/// Whether p lies on the segment from a to b.
+pub(crate) fn on_segment(p: Point, a: Point, b: Point) -> bool {
+    cross(a, b, p) == 0f64
+        && p.x >= a.x.min(b.x) && p.x <= a.x.max(b.x)
+        && p.y >= a.y.min(b.y) && p.y <= a.y.max(b.y)
+}
+
+/// Whether a point is inside a ring, using the even-odd rule. Returns None if the point is on the
+/// boundary of the ring.
+pub(crate) fn point_in_ring(p: Point, ring: &[Point]) -> Option<bool> {
+    let mut inside = false;
+    let n = ring.len();
+    if n == 0 {
+        return Some(false);
+    }
+
+    let mut j = n - 1;
+    for i in 0..n {
+        let a = ring[i];
+        let b = ring[j];
+        if on_segment(p, a, b) {
+            return None;
+        }
+        if (a.y > p.y) != (b.y > p.y) && p.x < (b.x - a.x) * (p.y - a.y) / (b.y - a.y) + a.x {
+            inside = !inside;
+        }
+        j = i;
+    }
+    Some(inside)
+}
+
+/// Whether a point is inside the rings of a polygon, using the even-odd rule. Returns None if the
+/// point is on the boundary of one of the rings.
+pub(crate) fn point_in_rings<R: AsRef<[Point]>>(p: Point, rings: &[R]) -> Option<bool> {
+    let mut inside = false;
+    for ring in rings {
+        match point_in_ring(p, ring.as_ref()) {
+            None => return None,
+            Some(true) => inside = !inside,
+            Some(false) => (),
+        }
+    }
+    Some(inside)
+}
+
+/// Whether the first ring lies inside the second one, judged by the first vertex which isn't on the
+/// boundary of the second ring.
+pub(crate) fn ring_in_ring(inner: &[Point], outer: &[Point]) -> bool {
+    for &p in inner {
+        if let Some(inside) = point_in_ring(p, outer) {
+            return inside;
+        }
+    }
+    false
+}
+
+/// Groups the rings of a repaired polygon into polygons: every outer ring, which runs clockwise,
+/// with the holes inside it. Every hole belongs to the smallest outer ring around it, and holes
+/// outside of all outer rings are left out.
+pub(crate) fn assemble_rings(rings: &[Vec<Point>]) -> Vec<(usize, Vec<usize>)> {
+    let areas: Vec<f64> = rings.iter().map(|r| signed_area(r)).collect();
+
+    let mut holes: Vec<Vec<usize>> = vec![vec![]; rings.len()];
+    for i in (0..rings.len()).filter(|&i| areas[i] > 0f64) {
+        let outer = (0..rings.len()).filter(|&j| areas[j] < 0f64 && ring_in_ring(&rings[i], &rings[j]))
+                                    .min_by(|&a, &b| areas[b].partial_cmp(&areas[a]).unwrap_or(::std::cmp::Ordering::Equal));
+        if let Some(j) = outer {
+            holes[j].push(i);
+        }
+    }
+
+    holes.into_iter().enumerate().filter(|&(j, _)| areas[j] < 0f64).collect()
+}
+
+/// How two segments meet.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum SegmentIntersection {
+    /// They have no point in common.
+    None,
+    /// They share only points on the end of at least one segment, or they overlap collinearly.
+    Touching,
+    /// They cross at a point inside both segments.
+    Proper,
+}
+
+/// How the segments from a to b and from c to d meet.
+pub(crate) fn segment_intersection(a: Point, b: Point, c: Point, d: Point) -> SegmentIntersection {
+    let d1 = cross(c, d, a);
+    let d2 = cross(c, d, b);
+    let d3 = cross(a, b, c);
+    let d4 = cross(a, b, d);
+
+    if ((d1 > 0f64 && d2 < 0f64) || (d1 < 0f64 && d2 > 0f64))
+        && ((d3 > 0f64 && d4 < 0f64) || (d3 < 0f64 && d4 > 0f64)) {
+        return SegmentIntersection::Proper;
+    }
+
+    if on_segment(a, c, d) || on_segment(b, c, d) || on_segment(c, a, b) || on_segment(d, a, b) {
+        return SegmentIntersection::Touching;
+    }
+
+    SegmentIntersection::None
+}
+
+/// A segment of a part, for finding intersections.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Segment {
+    /// The index of the part
+    pub part: usize,
+    /// The index of the segment within the part
+    pub index: usize,
+    /// The number of segments of the part
+    pub count: usize,
+    pub a: Point,
+    pub b: Point,
+}
+
+/// The segments of the given parts, leaving out repeated vertices.
+pub(crate) fn segments(parts: &[&[Point]]) -> Vec<Segment> {
+    let mut result: Vec<Segment> = vec![];
+    for (part, points) in parts.iter().enumerate() {
+        let mut vertices: Vec<Point> = Vec::with_capacity(points.len());
+        for &p in points.iter() {
+            if vertices.last() != Some(&p) {
+                vertices.push(p);
+            }
+        }
+
+        let count = vertices.len().saturating_sub(1);
+        for index in 0..count {
+            result.push(Segment {part, index, count, a: vertices[index], b: vertices[index + 1]});
+        }
+    }
+    result
+}
+
+/// Calls f for every pair of segments whose bounding boxes overlap, sweeping along the x axis.
+pub(crate) fn for_each_candidate_pair<F: FnMut(&Segment, &Segment)>(segments: &mut [Segment], mut f: F) {
+    segments.sort_by(|s, t| s.a.x.min(s.b.x).partial_cmp(&t.a.x.min(t.b.x)).unwrap_or(::std::cmp::Ordering::Equal));
+
+    for i in 0..segments.len() {
+        let s = segments[i];
+        let x_max = s.a.x.max(s.b.x);
+        let (y_min, y_max) = (s.a.y.min(s.b.y), s.a.y.max(s.b.y));
+
+        for t in &segments[i + 1..] {
+            if t.a.x.min(t.b.x) > x_max {
+                break;
+            }
+            if t.a.y.max(t.b.y) < y_min || t.a.y.min(t.b.y) > y_max {
+                continue;
+            }
+            f(&s, t);
+        }
+    }
+}
+
+/// Whether two segments of the same part follow each other, so they share an end point.
+pub(crate) fn adjacent(s: &Segment, t: &Segment, closed: bool) -> bool {
+    let (first, second) = if s.index < t.index { (s, t) } else { (t, s) };
+    second.index == first.index + 1 || (closed && first.index == 0 && second.index + 1 == second.count)
+}
+
+/// A point with its Z and M values, for operations which need to keep them together.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) struct Vertex {
+    pub point: Point,
+    pub z: f64,
+    pub m: f64,
+}
+
+/// The position of p on the segment from a to b, 0 at a and 1 at b.
+pub(crate) fn parameter(p: Point, a: Point, b: Point) -> f64 {
+    let (dx, dy) = (b.x - a.x, b.y - a.y);
+    ((p.x - a.x) * dx + (p.y - a.y) * dy) / (dx * dx + dy * dy)
+}
+
+/// The distance of p from the segment from a to b.
+pub(crate) fn point_segment_distance(p: Point, a: Point, b: Point) -> f64 {
+    if a == b {
+        return (p.x - a.x).hypot(p.y - a.y);
+    }
+    let t = parameter(p, a, b).clamp(0f64, 1f64);
+    (p.x - a.x - t * (b.x - a.x)).hypot(p.y - a.y - t * (b.y - a.y))
+}
+
+/// Whether two bounding boxes share at least one point.
+pub(crate) fn boxes_overlap(a: &BoundingBox, b: &BoundingBox) -> bool {
+    a.x_min <= b.x_max && b.x_min <= a.x_max && a.y_min <= b.y_max && b.y_min <= a.y_max
+}
+
+/// The vertex at t along the segment from a to b, with interpolated Z and M values. If one of the
+/// measures is no data, so is the result.
+pub(crate) fn interpolate(a: Vertex, b: Vertex, t: f64) -> Vertex {
+    let m = if a.m < NO_DATA || b.m < NO_DATA { MISSING_MEASURE } else { a.m + t * (b.m - a.m) };
+    Vertex {
+        point: Point {x: a.point.x + t * (b.point.x - a.point.x), y: a.point.y + t * (b.point.y - a.point.y)},
+        z: a.z + t * (b.z - a.z),
+        m,
+    }
+}
+
+/// The vertices of a part read from a format in which Z values are optional, like KML and GPX,
+/// with the missing ones filled in: Between two given values they are interpolated by the distance
+/// along the part, and before the first or after the last given value that one is repeated. None
+/// if the part has no Z value at all, since there is nothing to fill them in from.
+pub(crate) fn fill_missing_z(part: &[(Vertex, Option<f64>)]) -> Option<Vec<Vertex>> {
+    let given: Vec<usize> = (0..part.len()).filter(|&i| part[i].1.is_some()).collect();
+    let (first, last) = (*given.first()?, *given.last()?);
+
+    let mut result: Vec<Vertex> = part.iter().map(|&(v, z)| Vertex {z: z.unwrap_or(0f64), ..v}).collect();
+    let mut distance = vec![0f64; part.len()];
+    for i in 1..part.len() {
+        let (a, b) = (part[i - 1].0.point, part[i].0.point);
+        distance[i] = distance[i - 1] + (b.x - a.x).hypot(b.y - a.y);
+    }
+    for i in 0..first {
+        result[i].z = result[first].z;
+    }
+    for i in last + 1..part.len() {
+        result[i].z = result[last].z;
+    }
+    for w in given.windows(2) {
+        let (a, b) = (w[0], w[1]);
+        let span = distance[b] - distance[a];
+        for i in a + 1..b {
+            let t = if span > 0f64 { (distance[i] - distance[a]) / span } else { 0f64 };
+            result[i].z = result[a].z + t * (result[b].z - result[a].z);
+        }
+    }
+    Some(result)
+}
+
+/// The vertices of a shape, split into its parts. Shapes without parts have one part with all
+/// their points. Missing Z values are 0 and missing M values are no data.
+pub(crate) fn vertex_parts(shape: &Shape) -> Vec<Vec<Vertex>> {
+    let vertex = |points: &[Point], z: &[f64], m: &[f64], i: usize| Vertex {
+        point: points[i],
+        z: z.get(i).cloned().unwrap_or(0f64),
+        m: m.get(i).cloned().unwrap_or(MISSING_MEASURE),
+    };
+
+    let (points, z, m): (&[Point], &[f64], &[f64]) = match *shape {
+        Shape::NullShape => return vec![],
+        Shape::Point {point} => return vec![vec![Vertex {point, z: 0f64, m: MISSING_MEASURE}]],
+        Shape::PointM {point} => return vec![vec![Vertex {point: Point {x: point.x, y: point.y}, z: 0f64, m: point.m}]],
+        Shape::PointZ {point} => return vec![vec![Vertex {point: Point {x: point.x, y: point.y}, z: point.z, m: point.m}]],
+        Shape::MultiPoint {ref points, ..}
+        | Shape::PolyLine {ref points, ..}
+        | Shape::Polygon {ref points, ..} => (points, &[], &[]),
+        Shape::MultiPointM {ref points, ref m, ..}
+        | Shape::PolyLineM {ref points, ref m, ..}
+        | Shape::PolygonM {ref points, ref m, ..} => (points, &[], m),
+        Shape::MultiPointZ {ref points, ref z, ref m, ..}
+        | Shape::PolyLineZ {ref points, ref z, ref m, ..}
+        | Shape::PolygonZ {ref points, ref z, ref m, ..}
+        | Shape::MultiPatch {ref points, ref z, ref m, ..} => (points, z, m),
+    };
+
+    match parts_and_points(shape) {
+        Some((parts, _)) => {
+            part_ranges(parts, points.len()).into_iter()
+                                            .map(|r| r.map(|i| vertex(points, z, m, i)).collect())
+                                            .collect()
+        },
+        None => vec![(0..points.len()).map(|i| vertex(points, z, m, i)).collect()],
+    }
+}
+
+/// Builds a shape of the same type as the given one from vertices split into parts, with the
+/// bounding box and the ranges computed from the vertices. Shapes without parts take the vertices
+/// of all parts. Empty parts are left out, together with their patch types for multipatches. If
+/// there are no vertices left, the result is a null shape.
+pub(crate) fn from_vertex_parts(shape: &Shape, vertex_parts: Vec<Vec<Vertex>>) -> Shape {
+    let vertex_parts: Vec<(usize, Vec<Vertex>)> = vertex_parts.into_iter().enumerate().filter(|(_, p)| !p.is_empty()).collect();
+    if vertex_parts.is_empty() {
+        return Shape::NullShape;
+    }
+
+    let mut parts: Vec<i32> = vec![];
+    let mut kept: Vec<usize> = vec![];
+    let mut vertices: Vec<Vertex> = vec![];
+    for (index, part) in vertex_parts {
+        parts.push(vertices.len() as i32);
+        kept.push(index);
+        vertices.extend(part);
+    }
+
+    let points: Vec<Point> = vertices.iter().map(|v| v.point).collect();
+    let z: Vec<f64> = vertices.iter().map(|v| v.z).collect();
+    let has_m = match *shape {
+        Shape::PolyLineZ {ref m, ..} | Shape::PolygonZ {ref m, ..}
+        | Shape::MultiPointZ {ref m, ..} | Shape::MultiPatch {ref m, ..} => !m.is_empty(),
+        _ => true,
+    };
+    let m: Vec<f64> = if has_m { vertices.iter().map(|v| v.m).collect() } else { vec![] };
+
+    let bounding_box = bounding_box_of(&points);
+    let z_range = range_of(&z);
+    let m_range = measure_range_of(&m);
+    let first = vertices[0];
+
+    match *shape {
+        Shape::NullShape => Shape::NullShape,
+        Shape::Point {..} => Shape::Point {point: first.point},
+        Shape::PointM {..} => Shape::PointM {point: PointM {x: first.point.x, y: first.point.y, m: first.m}},
+        Shape::PointZ {..} => Shape::PointZ {point: PointZ {x: first.point.x, y: first.point.y, z: first.z, m: first.m}},
+        Shape::MultiPoint {..} => Shape::MultiPoint {bounding_box, points},
+        Shape::MultiPointM {..} => Shape::MultiPointM {bounding_box, points, m_range, m},
+        Shape::MultiPointZ {..} => Shape::MultiPointZ {bounding_box, points, z_range, z, m_range, m},
+        Shape::PolyLine {..} => Shape::PolyLine {bounding_box, parts, points},
+        Shape::PolyLineM {..} => Shape::PolyLineM {bounding_box, parts, points, m_range, m},
+        Shape::PolyLineZ {..} => Shape::PolyLineZ {bounding_box, parts, points, z_range, z, m_range, m},
+        Shape::Polygon {..} => Shape::Polygon {bounding_box, parts, points},
+        Shape::PolygonM {..} => Shape::PolygonM {bounding_box, parts, points, m_range, m},
+        Shape::PolygonZ {..} => Shape::PolygonZ {bounding_box, parts, points, z_range, z, m_range, m},
+        Shape::MultiPatch {ref part_types, ..} => {
+            let part_types = kept.iter().map(|&i| part_types.get(i).cloned().unwrap_or(PatchType::Ring)).collect();
+            Shape::MultiPatch {bounding_box, parts, part_types, points, z_range, z, m_range, m}
+        },
+    }
+}
+
+/// The bounding box of the points, all zeros if there are none.
+pub(crate) fn bounding_box_of(points: &[Point]) -> BoundingBox {
+    if points.is_empty() {
+        return BoundingBox::new();
+    }
+
+    let mut result = BoundingBox {x_min: points[0].x, y_min: points[0].y, x_max: points[0].x, y_max: points[0].y};
+    for p in &points[1..] {
+        result.x_min = result.x_min.min(p.x);
+        result.y_min = result.y_min.min(p.y);
+        result.x_max = result.x_max.max(p.x);
+        result.y_max = result.y_max.max(p.y);
+    }
+    result
+}
+
+/// The range of the values, leaving out NaN, zero if there are none.
+pub(crate) fn range_of(values: &[f64]) -> ValueRange<f64> {
+    let mut iter = values.iter().filter(|v| !v.is_nan());
+    let first = match iter.next() {
+        Some(&v) => v,
+        None => return ValueRange {min: 0f64, max: 0f64},
+    };
+    iter.fold(ValueRange {min: first, max: first}, |r, &v| ValueRange {min: r.min.min(v), max: r.max.max(v)})
+}
+
+/// The range of the measures, leaving out no data values.
+pub(crate) fn measure_range_of(values: &[f64]) -> ValueRange<f64> {
+    let measures: Vec<f64> = values.iter().cloned().filter(|&v| v >= NO_DATA).collect();
+    range_of(&measures)
+}
+
+/// A polygon with the given rings, for the tests of the modules which work on shapes.
+#[cfg(test)]
+pub(crate) fn polygon(rings: &[&[(f64, f64)]]) -> Shape {
+    let mut parts: Vec<i32> = vec![];
+    let mut points: Vec<Point> = vec![];
+    for ring in rings {
+        parts.push(points.len() as i32);
+        points.extend(ring.iter().map(|&(x, y)| Point {x, y}));
+    }
+    Shape::Polygon {bounding_box: BoundingBox::new(), parts, points}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_vertex_parts, vertex_parts};
+    use super::super::shape::{Shape, BoundingBox, PatchType, Point, Range};
+
+    #[test]
+    fn test_from_vertex_parts() {
+        let square = [(0., 0.), (0., 1.), (1., 1.), (1., 0.), (0., 0.)];
+        let patch = Shape::MultiPatch {
+            bounding_box: BoundingBox::new(),
+            parts: vec![0, 5, 10],
+            part_types: vec![PatchType::OuterRing, PatchType::InnerRing, PatchType::TriangleFan],
+            points: square.iter().chain(square.iter()).chain(square.iter()).map(|&(x, y)| Point {x, y}).collect(),
+            z_range: Range {min: 0., max: 0.},
+            z: vec![0.; 15],
+            m_range: Range {min: 0., max: 0.},
+            m: vec![],
+        };
+
+        // The patch types of the parts which are left stay with them
+        let mut parts = vertex_parts(&patch);
+        parts[1].clear();
+        match from_vertex_parts(&patch, parts) {
+            Shape::MultiPatch {ref parts, ref part_types, ..} => {
+                if *parts != [0, 5] || *part_types != [PatchType::OuterRing, PatchType::TriangleFan] {
+                    panic!("{:?} {:?}", parts, part_types)
+                }
+            },
+            other => panic!("{:?}", other),
+        }
+    }
+}
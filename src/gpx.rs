@@ -0,0 +1,394 @@
+//! Module for GPX, as written by GPS devices
+//!
+//! Polyline layers are written as tracks, one per record, with a track segment per part. Point
+//! and multipoint layers are written as waypoints. Z values become elevations, and M values can be
+//! taken as times, in seconds since 1970-01-01 UTC. The coordinates are written in WGS84, see
+//! `Shapefile::transformer_to`.
+//!
+//! Reading goes the other way: the tracks of a GPX file become a polyline layer and its waypoints
+//! a point layer, with the elevations as Z values and the times as M values. Elevations missing
+//! within a track segment are filled in from the others along it. If a waypoint or a whole segment
+//! has no elevation, the layer gets no Z values, rather than made up ones. Points without time get
+//! no measure.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+use dbf;
+
+use super::{Date, DbfField, Projection, Shapefile, ShapefileWriter};
+use super::dbffile::{civil_from_days, days_from_civil};
+use super::geometry::{self, Vertex, MISSING_MEASURE, NO_DATA};
+use super::shape::{Shape, ShapeType, Point, PointM, PointZ};
+use super::xml::{self, Element};
+
+/// Options for `Shapefile::to_gpx`.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct GpxOptions {
+    /// The field holding the names of the tracks and waypoints
+    pub name_field: Option<String>,
+    /// Whether the M values are times, in seconds since 1970-01-01 UTC
+    pub measure_as_time: bool,
+}
+
+impl GpxOptions {
+    /// Unnamed tracks and waypoints, without times.
+    pub fn new() -> Self {
+        GpxOptions {name_field: None, measure_as_time: false}
+    }
+
+    /// Names the tracks and waypoints by the values of a field.
+    pub fn name_field(mut self, field: &str) -> Self {
+        self.name_field = Some(field.to_string());
+        self
+    }
+
+    /// Writes the M values as times.
+    pub fn measure_as_time(mut self) -> Self {
+        self.measure_as_time = true;
+        self
+    }
+}
+
+/// Formats seconds since 1970-01-01 UTC as an ISO 8601 time, with milliseconds if there are any.
+fn format_time(seconds: f64) -> String {
+    let mut whole = seconds.floor() as i64;
+    let mut millis = ((seconds - seconds.floor()) * 1000f64).round() as i64;
+    if millis == 1000 {
+        whole += 1;
+        millis = 0;
+    }
+    let date = civil_from_days(whole.div_euclid(86400));
+    let time = whole.rem_euclid(86400);
+    let mut result = format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}", date.year, date.month, date.day, time / 3600, time / 60 % 60, time % 60);
+    if millis > 0 {
+        let _ = write!(result, ".{:03}", millis);
+    }
+    result.push('Z');
+    result
+}
+
+/// Parses an ISO 8601 time like `2024-05-01T10:00:00Z` into seconds since 1970-01-01 UTC. Times
+/// without a time zone are taken to be in UTC.
+fn parse_time(text: &str) -> Option<f64> {
+    let text = text.trim();
+    if text.len() < 19 || !text.is_ascii() || &text[10..11] != "T" || &text[13..14] != ":" || &text[16..17] != ":" {
+        return None;
+    }
+    let date = Date::parse(&text[0..10])?;
+    let hours: i64 = text[11..13].parse().ok()?;
+    let minutes: i64 = text[14..16].parse().ok()?;
+    let mut seconds: f64 = text[17..19].parse().ok()?;
+
+    let mut rest = &text[19..];
+    if rest.starts_with('.') {
+        let digits = rest[1..].find(|c: char| !c.is_ascii_digit()).map_or(rest.len(), |i| i + 1);
+        seconds += rest[..digits].parse::<f64>().ok()?;
+        rest = &rest[digits..];
+    }
+    let offset = match rest {
+        "" | "Z" => 0,
+        _ if rest.len() == 6 && (rest.starts_with('+') || rest.starts_with('-')) && &rest[3..4] == ":" => {
+            let minutes = rest[1..3].parse::<i64>().ok()? * 60 + rest[4..6].parse::<i64>().ok()?;
+            if rest.starts_with('-') { -minutes } else { minutes }
+        },
+        _ => return None,
+    };
+
+    let whole = days_from_civil(date) * 86400 + hours * 3600 + (minutes - offset) * 60;
+    Some(whole as f64 + seconds)
+}
+
+/// The elevation and time of a point, in the order GPX wants them.
+fn point_details(vertex: &Vertex, z: bool, options: &GpxOptions) -> String {
+    let mut result = String::new();
+    if z && !vertex.z.is_nan() {
+        let _ = write!(result, "<ele>{}</ele>", vertex.z);
+    }
+    if options.measure_as_time && vertex.m >= NO_DATA {
+        let _ = write!(result, "<time>{}</time>", format_time(vertex.m));
+    }
+    result
+}
+
+impl Shapefile {
+    /// Writes a polyline layer as GPX tracks, or a point or multipoint layer as GPX waypoints.
+    pub fn to_gpx(&mut self, options: &GpxOptions) -> Result<String, Error> {
+        let tracks = match self.shape_type() {
+            ShapeType::PolyLine | ShapeType::PolyLineM | ShapeType::PolyLineZ => true,
+            ShapeType::Point | ShapeType::PointM | ShapeType::PointZ |
+            ShapeType::MultiPoint | ShapeType::MultiPointM | ShapeType::MultiPointZ => false,
+            _ => return Err(Error::new(ErrorKind::Other, "Only polyline and point layers can be written as GPX!")),
+        };
+        let transformer = self.transformer_to(&Projection::wgs84())?;
+
+        let mut result = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                                       <gpx version=\"1.1\" creator=\"shapefile-utils\" xmlns=\"http://www.topografix.com/GPX/1/1\">\n");
+        for record in self.iter_sequential() {
+            let record = record?;
+            let name = match options.name_field.as_ref().and_then(|f| record.metadata.get(f)).and_then(xml::text_of) {
+                Some(n) => format!("<name>{}</name>", xml::escape(&n)),
+                None => String::new(),
+            };
+            let shape = match transformer {
+                Some(ref t) => record.shape.transformed(t),
+                None => record.shape,
+            };
+            let z = shape.shape_type().has_z();
+
+            let parts: Vec<Vec<Vertex>> = geometry::vertex_parts(&shape).into_iter().filter(|p| !p.is_empty()).collect();
+            if parts.is_empty() {
+                continue;
+            }
+            if tracks {
+                let _ = write!(result, "<trk>{}", name);
+                for part in parts {
+                    result.push_str("<trkseg>");
+                    for v in part {
+                        let _ = write!(result, "<trkpt lat=\"{}\" lon=\"{}\">{}</trkpt>", v.point.y, v.point.x, point_details(&v, z, options));
+                    }
+                    result.push_str("</trkseg>");
+                }
+                result.push_str("</trk>\n");
+            } else {
+                for v in parts.iter().flat_map(|p| p.iter()) {
+                    let _ = writeln!(result, "<wpt lat=\"{}\" lon=\"{}\">{}{}</wpt>", v.point.y, v.point.x, point_details(v, z, options), name);
+                }
+            }
+        }
+        result.push_str("</gpx>\n");
+        Ok(result)
+    }
+
+    /// Writes the layer into a GPX file.
+    pub fn write_gpx(&mut self, path: &Path, options: &GpxOptions) -> Result<(), Error> {
+        let gpx = self.to_gpx(options)?;
+        fs::write(path, gpx)
+    }
+}
+
+/// The position and time of a `wpt` or `trkpt` element, and its elevation if it has one.
+fn parse_point(element: &Element) -> Result<(Vertex, Option<f64>), Error> {
+    let coordinate = |name: &str| element.attribute(name).and_then(|v| v.trim().parse::<f64>().ok());
+    let (lat, lon) = match (coordinate("lat"), coordinate("lon")) {
+        (Some(lat), Some(lon)) => (lat, lon),
+        _ => return Err(Error::new(ErrorKind::Other, "GPX point without valid lat and lon!")),
+    };
+    let z = match element.child_text("ele") {
+        Some(e) => Some(e.parse::<f64>().map_err(|_| Error::new(ErrorKind::Other, format!("Invalid GPX elevation `{}`!", e)))?),
+        None => None,
+    };
+    let m = match element.child_text("time") {
+        Some(t) => parse_time(t).ok_or_else(|| Error::new(ErrorKind::Other, format!("Invalid GPX time `{}`!", t)))?,
+        None => MISSING_MEASURE,
+    };
+    Ok((Vertex {point: Point {x: lon, y: lat}, z: 0f64, m}, z))
+}
+
+/// The points of a track segment, with their elevations if they have one.
+type Segment = Vec<(Vertex, Option<f64>)>;
+
+/// Reads a GPX file into its root element.
+fn read_gpx(path: &Path) -> Result<Element, Error> {
+    let root = xml::parse(&fs::read_to_string(path)?)?;
+    if root.name != "gpx" {
+        return Err(Error::new(ErrorKind::Other, "Not a GPX document!"));
+    }
+    Ok(root)
+}
+
+/// Writes shapes with their names into a new shapefile in WGS84, with a PRJ file next to the main
+/// file.
+fn write_named(shapes: Vec<(Shape, Option<String>)>, shp_path: &Path, shx_path: &Path, dbf_path: &Path) -> Result<u64, Error> {
+    let length = shapes.iter().filter_map(|s| s.1.as_ref()).map(|n| n.len()).max().unwrap_or(1);
+    let mut writer = ShapefileWriter::with_schema(shp_path, shx_path, dbf_path, vec![DbfField::character("name", length.clamp(1, 254) as u8)])?;
+    for (shape, name) in &shapes {
+        let mut metadata = HashMap::new();
+        metadata.insert(String::from("name"), name.as_ref().map_or(dbf::Field::Null, |n| dbf::Field::Character(n.clone())));
+        writer.write(shape, &metadata)?;
+    }
+    writer.finish()?;
+    Projection::wgs84().write_file(&shp_path.with_extension("prj"))?;
+
+    Ok(shapes.len() as u64)
+}
+
+/// A track as a polyline with its segments as parts, with Z values if it is elevated and M values
+/// if it is timed.
+fn track_shape(segments: Vec<Vec<Vertex>>, elevated: bool, timed: bool) -> Shape {
+    let mut parts: Vec<i32> = vec![];
+    let mut vertices: Vec<Vertex> = vec![];
+    for segment in segments {
+        parts.push(vertices.len() as i32);
+        vertices.extend(segment);
+    }
+
+    let points: Vec<Point> = vertices.iter().map(|v| v.point).collect();
+    let bounding_box = geometry::bounding_box_of(&points);
+    let z: Vec<f64> = if elevated { vertices.iter().map(|v| v.z).collect() } else { vec![] };
+    let m: Vec<f64> = if timed { vertices.iter().map(|v| v.m).collect() } else { vec![] };
+    let (z_range, m_range) = (geometry::range_of(&z), geometry::measure_range_of(&m));
+    match (elevated, timed) {
+        (true, _) => Shape::PolyLineZ {bounding_box, parts, points, z_range, z, m_range, m},
+        (false, true) => Shape::PolyLineM {bounding_box, parts, points, m_range, m},
+        (false, false) => Shape::PolyLine {bounding_box, parts, points},
+    }
+}
+
+/// Reads the tracks of a GPX file into a new polyline shapefile, one record per track with its
+/// segments as parts and its name in a `name` column. The layer has Z values if every segment has
+/// an elevation, and M values if any point has a time. Returns the number of records written.
+pub fn tracks_to_shapefile(gpx_path: &Path, shp_path: &Path, shx_path: &Path, dbf_path: &Path) -> Result<u64, Error> {
+    let root = read_gpx(gpx_path)?;
+
+    let mut tracks: Vec<(Vec<Segment>, Option<String>)> = vec![];
+    for track in root.descendants("trk") {
+        let mut parts = vec![];
+        for segment in track.children.iter().filter(|e| e.name == "trkseg") {
+            let part = segment.children.iter().filter(|e| e.name == "trkpt").map(parse_point).collect::<Result<Segment, Error>>()?;
+            if part.len() > 1 {
+                parts.push(part);
+            }
+        }
+        if !parts.is_empty() {
+            tracks.push((parts, track.child_text("name").map(|n| n.to_string())));
+        }
+    }
+
+    let elevated = tracks.iter().all(|t| t.0.iter().all(|p| p.iter().any(|v| v.1.is_some())));
+    let timed = tracks.iter().any(|t| t.0.iter().any(|p| p.iter().any(|v| v.0.m >= NO_DATA)));
+    let shapes = tracks.into_iter().map(|(parts, name)| {
+        let segments = parts.iter().map(|p| geometry::fill_missing_z(p).unwrap_or_else(|| p.iter().map(|v| v.0).collect())).collect();
+        (track_shape(segments, elevated, timed), name)
+    }).collect();
+    write_named(shapes, shp_path, shx_path, dbf_path)
+}
+
+/// Reads the waypoints of a GPX file into a new point shapefile, with their names in a `name`
+/// column. The layer has Z values if every waypoint has an elevation, and M values if any has a
+/// time. Returns the number of records written.
+pub fn waypoints_to_shapefile(gpx_path: &Path, shp_path: &Path, shx_path: &Path, dbf_path: &Path) -> Result<u64, Error> {
+    let root = read_gpx(gpx_path)?;
+
+    let mut waypoints = vec![];
+    for waypoint in root.children.iter().filter(|e| e.name == "wpt") {
+        waypoints.push((parse_point(waypoint)?, waypoint.child_text("name").map(|n| n.to_string())));
+    }
+
+    let elevated = waypoints.iter().all(|w| (w.0).1.is_some());
+    let timed = waypoints.iter().any(|w| (w.0).0.m >= NO_DATA);
+    let shapes = waypoints.into_iter().map(|((v, z), name)| {
+        let shape = if elevated {
+            Shape::PointZ {point: PointZ {x: v.point.x, y: v.point.y, z: z.unwrap_or(0f64), m: v.m}}
+        } else if timed {
+            Shape::PointM {point: PointM {x: v.point.x, y: v.point.y, m: v.m}}
+        } else {
+            Shape::Point {point: v.point}
+        };
+        (shape, name)
+    }).collect();
+    write_named(shapes, shp_path, shx_path, dbf_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use dbf;
+    use super::{format_time, parse_time, tracks_to_shapefile, waypoints_to_shapefile, GpxOptions};
+    use super::super::Shapefile;
+    use super::super::shape::Shape;
+
+    #[test]
+    fn test_time() {
+        if format_time(0.) != "1970-01-01T00:00:00Z" || format_time(1714557600.25) != "2024-05-01T10:00:00.250Z" || format_time(951782400.) != "2000-02-29T00:00:00Z" {
+            panic!("{} {}", format_time(1714557600.25), format_time(951782400.))
+        }
+        if parse_time("2024-05-01T10:00:00Z") != Some(1714557600.) || parse_time("2024-05-01T12:00:00.5+02:00") != Some(1714557600.5) {
+            panic!("{:?}", parse_time("2024-05-01T12:00:00.5+02:00"))
+        }
+        if parse_time("1969-12-31T23:59:59") != Some(-1.) || parse_time("2024-05-01 10:00").is_some() || parse_time("yesterday").is_some() {
+            panic!()
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let directory = env::temp_dir().join("shapefile-utils-test-gpx");
+        fs::create_dir_all(&directory).unwrap();
+        let gpx_path = directory.join("hike.gpx");
+        fs::write(&gpx_path, "<?xml version=\"1.0\"?>\n<gpx version=\"1.1\" creator=\"test\" xmlns=\"http://www.topografix.com/GPX/1/1\">\
+                              <wpt lat=\"47.25\" lon=\"8.5\"><ele>410</ele><name>Start &amp; finish</name></wpt>\
+                              <trk><name>Hike</name>\
+                              <trkseg><trkpt lat=\"47.25\" lon=\"8.5\"><ele>410</ele><time>2024-05-01T10:00:00Z</time></trkpt>\
+                              <trkpt lat=\"47.26\" lon=\"8.51\"><ele>455.5</ele><time>2024-05-01T10:10:00Z</time></trkpt></trkseg>\
+                              <trkseg><trkpt lat=\"47.27\" lon=\"8.52\"></trkpt><trkpt lat=\"47.28\" lon=\"8.53\"><ele>500</ele></trkpt></trkseg>\
+                              </trk></gpx>").unwrap();
+
+        let (shp, shx, dbf) = (directory.join("hike.shp"), directory.join("hike.shx"), directory.join("hike.dbf"));
+        if tracks_to_shapefile(&gpx_path, &shp, &shx, &dbf).unwrap() != 1 {
+            panic!()
+        }
+        let mut tracks = Shapefile::new(&shp, &shx, &dbf).unwrap();
+        let record = tracks.iter_sequential().next().unwrap().unwrap();
+        match record.shape {
+            Shape::PolyLineZ {ref parts, ref z, ref m, ..} if *parts == vec![0, 2] && *z == [410., 455.5, 500., 500.]
+                                                               && m[0] == 1714557600. && m[1] == 1714558200. && m[2] < -1e38 => (),
+            ref s => panic!("{:?}", s),
+        }
+        if !matches!(record.metadata.get("name"), Some(dbf::Field::Character(n)) if n == "Hike") {
+            panic!("{:?}", record.metadata)
+        }
+
+        let gpx = tracks.to_gpx(&GpxOptions::new().name_field("name").measure_as_time()).unwrap();
+        if !gpx.contains("<trk><name>Hike</name><trkseg><trkpt lat=\"47.25\" lon=\"8.5\"><ele>410</ele><time>2024-05-01T10:00:00Z</time></trkpt>") ||
+           !gpx.contains("<trkseg><trkpt lat=\"47.27\" lon=\"8.52\"><ele>500</ele></trkpt><trkpt lat=\"47.28\" lon=\"8.53\"><ele>500</ele></trkpt>") {
+            panic!("{}", gpx)
+        }
+        if tracks.to_gpx(&GpxOptions::new()).unwrap().contains("<time>") {
+            panic!()
+        }
+
+        let (shp, shx, dbf) = (directory.join("huts.shp"), directory.join("huts.shx"), directory.join("huts.dbf"));
+        if waypoints_to_shapefile(&gpx_path, &shp, &shx, &dbf).unwrap() != 1 {
+            panic!()
+        }
+        let mut waypoints = Shapefile::new(&shp, &shx, &dbf).unwrap();
+        let gpx = waypoints.to_gpx(&GpxOptions::new().name_field("name")).unwrap();
+        if !gpx.contains("<wpt lat=\"47.25\" lon=\"8.5\"><ele>410</ele><name>Start &amp; finish</name></wpt>") {
+            panic!("{}", gpx)
+        }
+    }
+
+    #[test]
+    fn test_without_elevation() {
+        let directory = env::temp_dir().join("shapefile-utils-test-gpx-flat");
+        fs::create_dir_all(&directory).unwrap();
+        let gpx_path = directory.join("ride.gpx");
+        fs::write(&gpx_path, "<gpx version=\"1.1\" creator=\"test\"><wpt lat=\"47.25\" lon=\"8.5\"/><wpt lat=\"47.3\" lon=\"8.6\"><ele>500</ele></wpt>\
+                              <trk><trkseg><trkpt lat=\"47.25\" lon=\"8.5\"><time>2024-05-01T10:00:00Z</time></trkpt>\
+                              <trkpt lat=\"47.26\" lon=\"8.51\"/></trkseg></trk></gpx>").unwrap();
+
+        let (shp, shx, dbf) = (directory.join("ride.shp"), directory.join("ride.shx"), directory.join("ride.dbf"));
+        tracks_to_shapefile(&gpx_path, &shp, &shx, &dbf).unwrap();
+        let mut tracks = Shapefile::new(&shp, &shx, &dbf).unwrap();
+        match tracks.iter_sequential().next().unwrap().unwrap().shape {
+            Shape::PolyLineM {ref m, ..} if m[0] == 1714557600. && m[1] < -1e38 => (),
+            ref s => panic!("{:?}", s),
+        }
+        if tracks.to_gpx(&GpxOptions::new().measure_as_time()).unwrap().contains("<ele>") {
+            panic!()
+        }
+
+        let (shp, shx, dbf) = (directory.join("stops.shp"), directory.join("stops.shx"), directory.join("stops.dbf"));
+        waypoints_to_shapefile(&gpx_path, &shp, &shx, &dbf).unwrap();
+        // One waypoint has no elevation, so none of them gets one
+        let mut waypoints = Shapefile::new(&shp, &shx, &dbf).unwrap();
+        if !matches!(waypoints.iter_sequential().next().unwrap().unwrap().shape, Shape::Point {..}) {
+            panic!()
+        }
+    }
+}
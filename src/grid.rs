@@ -0,0 +1,464 @@
+//! Module for burning polygons into grids
+//!
+//! Every row of the grid is crossed by a scanline through the centers of its cells. The edges of
+//! a polygon crossing it give the spans inside the polygon by the even-odd rule, and the cells
+//! whose center lies in a span get the value of the polygon. With the all-touched rule, the cells
+//! through whose inside an edge of the polygon passes get it too, so that small or thin polygons
+//! leave a trace. Touching a cell only at its border doesn't count, so that polygons along the
+//! lines of the grid don't spill into the neighboring cells. Where polygons overlap, the later
+//! record wins.
+//!
+//! Cells without data are kept apart from the values, and only get a value for them when the grid
+//! is written as ESRI ASCII Grid, with a PRJ file if the coordinate system is known.
+
+use std::fmt::Write as FmtWrite;
+use std::fs::File;
+use std::io::{Error, ErrorKind, Write};
+use std::mem;
+use std::path::Path;
+
+use dbf;
+
+use super::{Grid, Shapefile};
+use super::geometry;
+use super::shape::{Shape, ShapeType, BoundingBox, Point};
+
+/// The value written for cells without data, unless the grid has another one.
+pub const DEFAULT_NO_DATA: f64 = -9999f64;
+
+/// The position, size and resolution of a grid. The cells are square.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct GridDefinition {
+    /// The x of the lower left corner
+    pub x_origin: f64,
+    /// The y of the lower left corner
+    pub y_origin: f64,
+    /// The width and height of a cell
+    pub cell_size: f64,
+    /// The number of columns
+    pub width: usize,
+    /// The number of rows
+    pub height: usize,
+}
+
+impl GridDefinition {
+    /// A grid with the given lower left corner, cell size and number of columns and rows.
+    pub fn new(x_origin: f64, y_origin: f64, cell_size: f64, width: usize, height: usize) -> Self {
+        GridDefinition {x_origin, y_origin, cell_size, width, height}
+    }
+
+    /// The smallest grid with the given cell size which covers the box, starting at its lower left
+    /// corner. Fails if the grid isn't valid, see `validate`.
+    pub fn covering(bounding_box: &BoundingBox, cell_size: f64) -> Result<Self, Error> {
+        if !(cell_size.is_finite() && cell_size > 0f64) {
+            return Err(Error::new(ErrorKind::Other, format!("Grid cell size {} isn't positive!", cell_size)));
+        }
+        let cells = |length: f64| -> Result<usize, Error> {
+            let cells = (length / cell_size).ceil().max(1f64);
+            if cells.is_nan() || cells >= usize::MAX as f64 {
+                return Err(Error::new(ErrorKind::Other, "Grid has too many cells!"));
+            }
+            Ok(cells as usize)
+        };
+        let result = GridDefinition {
+            x_origin: bounding_box.x_min,
+            y_origin: bounding_box.y_min,
+            cell_size,
+            width: cells(bounding_box.x_max - bounding_box.x_min)?,
+            height: cells(bounding_box.y_max - bounding_box.y_min)?,
+        };
+        result.validate()?;
+        Ok(result)
+    }
+
+    /// Checks that the grid can be built: The origin has to be finite, the cell size positive,
+    /// and there has to be at least one column and one row, but not more cells than fit into
+    /// memory.
+    pub fn validate(&self) -> Result<(), Error> {
+        if !self.x_origin.is_finite() || !self.y_origin.is_finite() {
+            return Err(Error::new(ErrorKind::Other, "Grid origin isn't finite!"));
+        }
+        if !(self.cell_size.is_finite() && self.cell_size > 0f64) {
+            return Err(Error::new(ErrorKind::Other, format!("Grid cell size {} isn't positive!", self.cell_size)));
+        }
+        if self.width == 0 || self.height == 0 {
+            return Err(Error::new(ErrorKind::Other, "Grid has no cells!"));
+        }
+        match self.width.checked_mul(self.height) {
+            Some(cells) if cells <= isize::MAX as usize / mem::size_of::<Option<f64>>() => Ok(()),
+            _ => Err(Error::new(ErrorKind::Other, "Grid has too many cells!")),
+        }
+    }
+
+    /// The area covered by the grid.
+    pub fn bounding_box(&self) -> BoundingBox {
+        BoundingBox {
+            x_min: self.x_origin,
+            y_min: self.y_origin,
+            x_max: self.x_origin + self.width as f64 * self.cell_size,
+            y_max: self.y_origin + self.height as f64 * self.cell_size,
+        }
+    }
+
+    /// The center of a cell, with rows counted from the north. None outside the grid.
+    pub fn cell_center(&self, column: usize, row: usize) -> Option<Point> {
+        if column >= self.width || row >= self.height {
+            return None;
+        }
+        Some(Point {
+            x: self.x_origin + (column as f64 + 0.5f64) * self.cell_size,
+            y: self.y_origin + (self.height - row) as f64 * self.cell_size - 0.5f64 * self.cell_size,
+        })
+    }
+
+    /// A point in cell units, from the north west corner of the grid.
+    fn to_cells(self, p: Point) -> (f64, f64) {
+        ((p.x - self.x_origin) / self.cell_size, (self.y_origin + self.height as f64 * self.cell_size - p.y) / self.cell_size)
+    }
+}
+
+/// Which cells a polygon is burned into.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum BurnRule {
+    /// The cells whose center is inside the polygon
+    CenterPoint,
+    /// The cells which the polygon touches at all
+    AllTouched,
+}
+
+/// The cells whose inside meets the open range from a to b, both in cell units, clamped to the
+/// grid. A range without length meets a cell unless it's on the border between two.
+fn cell_range(a: f64, b: f64, count: usize) -> Option<(usize, usize)> {
+    let (first, last) = if a == b {
+        if a.fract() == 0f64 {
+            return None;
+        }
+        (a.floor(), a.floor())
+    } else {
+        (a.floor(), b.ceil() - 1f64)
+    };
+    if last < 0f64 || first >= count as f64 {
+        return None;
+    }
+    Some((first.max(0f64) as usize, (last as usize).min(count - 1)))
+}
+
+/// Sets the cells inside the rings, which are given in cell units.
+fn burn(values: &mut [Option<f64>], definition: &GridDefinition, rings: &[Vec<(f64, f64)>], value: f64, rule: BurnRule) {
+    let (width, height) = (definition.width, definition.height);
+    let mut crossings: Vec<f64> = vec![];
+    let value = Some(value);
+
+    // Only the rows whose scanline lies within the extent of the rings can be crossed
+    let (y_min, y_max) = rings.iter().flatten().fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), p| (min.min(p.1), max.max(p.1)));
+    let first_row = (y_min - 0.5f64).ceil().max(0f64).min(height as f64) as usize;
+    let end_row = ((y_max - 0.5f64).floor() + 1f64).max(0f64).min(height as f64) as usize;
+
+    for row in first_row..end_row.max(first_row) {
+        let y = row as f64 + 0.5f64;
+        crossings.clear();
+        for ring in rings {
+            for w in ring.windows(2) {
+                let (a, b) = (w[0], w[1]);
+                if (a.1 <= y) != (b.1 <= y) {
+                    crossings.push(a.0 + (y - a.1) / (b.1 - a.1) * (b.0 - a.0));
+                }
+            }
+        }
+        crossings.sort_by(|a, b| a.partial_cmp(b).unwrap_or(::std::cmp::Ordering::Equal));
+
+        // Cell i has its center at i + 0.5, so the span from a to b holds the cells from
+        // ceil(a - 0.5) up to, but not including, ceil(b - 0.5)
+        for span in crossings.chunks(2).filter(|s| s.len() == 2) {
+            let first = (span[0] - 0.5f64).ceil().max(0f64) as usize;
+            let end = ((span[1] - 0.5f64).ceil().max(0f64) as usize).min(width);
+            for cell in &mut values[row * width..][first.min(end)..end] {
+                *cell = value;
+            }
+        }
+    }
+
+    if rule == BurnRule::AllTouched {
+        for ring in rings {
+            for w in ring.windows(2) {
+                let (a, b) = (w[0], w[1]);
+                let (y_min, y_max) = (a.1.min(b.1), a.1.max(b.1));
+                let (rows_from, rows_to) = match cell_range(y_min, y_max, height) {
+                    Some(r) => r,
+                    None => continue,
+                };
+                for row in rows_from..=rows_to {
+                    // The part of the edge inside the row
+                    let (left, right) = if y_min == y_max {
+                        (a.0.min(b.0), a.0.max(b.0))
+                    } else {
+                        let x_at = |y: f64| a.0 + (y - a.1) / (b.1 - a.1) * (b.0 - a.0);
+                        let (p, q) = (x_at((row as f64).max(y_min)), x_at(((row + 1) as f64).min(y_max)));
+                        (p.min(q), p.max(q))
+                    };
+                    if let Some((first, last)) = cell_range(left, right, width) {
+                        for cell in &mut values[row * width + first..=row * width + last] {
+                            *cell = value;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Grid {
+    /// A grid without any data. Fails if the definition isn't valid, see
+    /// `GridDefinition::validate`.
+    pub fn new(definition: GridDefinition) -> Result<Self, Error> {
+        definition.validate()?;
+        Ok(Grid {definition, values: vec![None; definition.width * definition.height], no_data: DEFAULT_NO_DATA, projection: None})
+    }
+
+    /// Sets the value written for cells without data. It should be one which none of the cells
+    /// has, or they can't be told apart in the written grid.
+    pub fn no_data(mut self, no_data: f64) -> Self {
+        self.no_data = no_data;
+        self
+    }
+
+    /// The value written for cells without data.
+    pub fn no_data_value(&self) -> f64 {
+        self.no_data
+    }
+
+    /// The position, size and resolution.
+    pub fn definition(&self) -> &GridDefinition {
+        &self.definition
+    }
+
+    /// The values of all cells, row by row from the north, None where there is no data.
+    pub fn values(&self) -> &[Option<f64>] {
+        &self.values
+    }
+
+    /// The value of a cell, with rows counted from the north. None outside the grid or without
+    /// data.
+    pub fn value(&self, column: usize, row: usize) -> Option<f64> {
+        if column >= self.definition.width || row >= self.definition.height {
+            return None;
+        }
+        self.values[row * self.definition.width + column]
+    }
+
+    /// The coordinate system, if known.
+    pub fn projection(&self) -> Option<&super::Projection> {
+        self.projection.as_ref()
+    }
+
+    /// Sets the value of all cells inside the polygon. Shapes which aren't polygons are ignored.
+    pub fn burn(&mut self, polygon: &Shape, value: f64, rule: BurnRule) {
+        let definition = self.definition;
+        if geometry::dimension(polygon) != Some(2) {
+            return;
+        }
+        let rings: Vec<Vec<(f64, f64)>> = geometry::parts_of(polygon).unwrap_or_default().iter()
+                                                  .map(|r| r.iter().map(|&p| definition.to_cells(p)).collect())
+                                                  .collect();
+        burn(&mut self.values, &definition, &rings, value, rule);
+    }
+
+    /// The grid as ESRI ASCII Grid, with the no data value in the cells without data.
+    pub fn to_ascii_grid(&self) -> String {
+        let d = &self.definition;
+        let mut result = format!("ncols {}\nnrows {}\nxllcorner {}\nyllcorner {}\ncellsize {}\nNODATA_value {}\n",
+                                 d.width, d.height, d.x_origin, d.y_origin, d.cell_size, self.no_data);
+        for row in self.values.chunks(d.width.max(1)) {
+            let line: Vec<String> = row.iter().map(|v| v.unwrap_or(self.no_data).to_string()).collect();
+            let _ = writeln!(result, "{}", line.join(" "));
+        }
+        result
+    }
+
+    /// Writes the grid as ESRI ASCII Grid, with a PRJ file next to it if the coordinate system is
+    /// known.
+    pub fn write_ascii_grid(&self, path: &Path) -> Result<(), Error> {
+        let mut file = File::create(path)?;
+        file.write_all(self.to_ascii_grid().as_bytes())?;
+        if let Some(ref projection) = self.projection {
+            projection.write_file(&path.with_extension("prj"))?;
+        }
+        Ok(())
+    }
+}
+
+impl Shapefile {
+    /// Burns the values of a numeric field of a polygon layer into a grid. Records without a value
+    /// are left out. Fails for layers of other shape types, if the field doesn't exist or isn't
+    /// a number column (N or F), and if the grid isn't valid.
+    pub fn rasterize(&mut self, definition: &GridDefinition, field: &str, rule: BurnRule) -> Result<Grid, Error> {
+        match self.shape_type() {
+            ShapeType::Polygon | ShapeType::PolygonM | ShapeType::PolygonZ => (),
+            t => return Err(Error::new(ErrorKind::Other, format!("Rasterizing needs a polygon layer, not {:?}!", t))),
+        }
+        match self.fields().iter().find(|f| f.name == field) {
+            Some(f) if matches!(f.field_type, b'N' | b'F') => (),
+            Some(_) => return Err(Error::new(ErrorKind::Other, format!("Field {} isn't numeric!", field))),
+            None => return Err(Error::new(ErrorKind::Other, format!("Field {} doesn't exist!", field))),
+        }
+
+        let mut grid = Grid::new(*definition)?;
+        grid.projection = self.projection().cloned();
+        let extent = definition.bounding_box();
+        for record in self.iter_sequential() {
+            let record = record?;
+            let value = match record.metadata.get(field) {
+                Some(&dbf::Field::Numeric(v)) => v,
+                _ => continue,
+            };
+            match record.shape.computed_bounding_box() {
+                Some(b) if b.x_min <= extent.x_max && b.x_max >= extent.x_min && b.y_min <= extent.y_max && b.y_max >= extent.y_min => {
+                    grid.burn(&record.shape, value, rule);
+                },
+                _ => (),
+            }
+        }
+        Ok(grid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::env;
+    use dbf;
+    use super::{BurnRule, GridDefinition};
+    use super::super::{Grid, Shapefile, ShapefileWriter};
+    use super::super::shape::{Shape, BoundingBox, Point};
+
+    fn triangle() -> Shape {
+        Shape::Polygon {
+            bounding_box: BoundingBox::new(),
+            parts: vec![0],
+            points: vec![Point {x: 0., y: 0.}, Point {x: 0., y: 4.}, Point {x: 4., y: 0.}, Point {x: 0., y: 0.}],
+        }
+    }
+
+    /// The cells with data as a picture, rows from the north.
+    fn picture(grid: &Grid) -> Vec<String> {
+        grid.values().chunks(grid.definition().width).map(|row| {
+            row.iter().map(|v| if v.is_none() { '.' } else { '#' }).collect()
+        }).collect()
+    }
+
+    #[test]
+    fn test_burn() {
+        let definition = GridDefinition::new(0., 0., 1., 5, 5);
+        let mut grid = Grid::new(definition).unwrap();
+        grid.burn(&triangle(), 7., BurnRule::CenterPoint);
+        if picture(&grid) != vec![".....", ".....", "#....", "##...", "###.."] || grid.value(0, 4) != Some(7.) || grid.value(3, 4).is_some() {
+            panic!("{:?}", picture(&grid))
+        }
+
+        // The diagonal cuts through the cells whose centers are on it, and only meets the cells
+        // above those at their corners, which doesn't count as touching
+        let mut grid = Grid::new(definition).unwrap();
+        grid.burn(&triangle(), 7., BurnRule::AllTouched);
+        if picture(&grid) != vec![".....", "#....", "##...", "###..", "####."] {
+            panic!("{:?}", picture(&grid))
+        }
+
+        // Along the lines of the grid, no neighboring cell is touched
+        let mut grid = Grid::new(definition).unwrap();
+        grid.burn(&Shape::from(BoundingBox {x_min: 1., y_min: 1., x_max: 3., y_max: 3.}), 7., BurnRule::AllTouched);
+        if picture(&grid) != vec![".....", ".....", ".##..", ".##..", "....."] {
+            panic!("{:?}", picture(&grid))
+        }
+
+        // A thin sliver touches every cell it passes
+        let sliver = Shape::Polygon {
+            bounding_box: BoundingBox::new(),
+            parts: vec![0],
+            points: vec![Point {x: 0.2, y: 4.9}, Point {x: 4.8, y: 0.4}, Point {x: 4.8, y: 0.2}, Point {x: 0.2, y: 4.9}],
+        };
+        let mut grid = Grid::new(definition).unwrap();
+        grid.burn(&sliver, 7., BurnRule::CenterPoint);
+        if grid.values().iter().any(|v| v.is_some()) {
+            panic!("{:?}", picture(&grid))
+        }
+        grid.burn(&sliver, 7., BurnRule::AllTouched);
+        if picture(&grid) != vec!["##...", ".##..", "..##.", "...##", "....#"] {
+            panic!("{:?}", picture(&grid))
+        }
+
+        // A polygon smaller than a cell only shows up with all-touched
+        let small = Shape::from(BoundingBox {x_min: 2.2, y_min: 2.2, x_max: 2.4, y_max: 2.4});
+        let mut grid = Grid::new(definition).unwrap();
+        grid.burn(&small, 1., BurnRule::CenterPoint);
+        if grid.values().iter().any(|v| v.is_some()) {
+            panic!()
+        }
+        grid.burn(&small, 1., BurnRule::AllTouched);
+        if grid.value(2, 2) != Some(1.) || grid.values().iter().filter(|v| v.is_some()).count() != 1 {
+            panic!("{:?}", picture(&grid))
+        }
+    }
+
+    #[test]
+    fn test_ascii_grid() {
+        let mut grid = Grid::new(GridDefinition::covering(&BoundingBox {x_min: 100., y_min: 200., x_max: 130., y_max: 215.}, 10.).unwrap()).unwrap();
+        grid.burn(&Shape::from(BoundingBox {x_min: 100., y_min: 200., x_max: 120., y_max: 210.}), 2.5, BurnRule::CenterPoint);
+        let text = grid.to_ascii_grid();
+        if text != "ncols 3\nnrows 2\nxllcorner 100\nyllcorner 200\ncellsize 10\nNODATA_value -9999\n-9999 -9999 -9999\n2.5 2.5 -9999\n" {
+            panic!("{}", text)
+        }
+
+        // A real -9999 stays a value, and another no data value keeps it apart
+        grid.burn(&Shape::from(BoundingBox {x_min: 120., y_min: 200., x_max: 130., y_max: 210.}), -9999., BurnRule::CenterPoint);
+        let grid = grid.no_data(-1.);
+        if grid.value(2, 1) != Some(-9999.) || grid.value(2, 0).is_some() {
+            panic!("{:?}", grid.values())
+        }
+        let text = grid.to_ascii_grid();
+        if text != "ncols 3\nnrows 2\nxllcorner 100\nyllcorner 200\ncellsize 10\nNODATA_value -1\n-1 -1 -1\n2.5 2.5 -9999\n" {
+            panic!("{}", text)
+        }
+    }
+
+    #[test]
+    fn test_rasterize() {
+        // Two overlapping squares, the later one wins
+        let base = env::temp_dir().join("shapefile-utils-test-rasterize");
+        let (shp, shx, dbf) = (base.with_extension("shp"), base.with_extension("shx"), base.with_extension("dbf"));
+        let mut writer = ShapefileWriter::new(&shp, &shx, &dbf).unwrap();
+        for &(x, height) in &[(0., 10.), (1., 20.)] {
+            let mut metadata = HashMap::new();
+            metadata.insert(String::from("height"), dbf::Field::Numeric(height));
+            metadata.insert(String::from("name"), dbf::Field::Character(String::from("block")));
+            writer.write(&Shape::from(BoundingBox {x_min: x, y_min: 0., x_max: x + 2., y_max: 1.}), &metadata).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let mut layer = Shapefile::new(&shp, &shx, &dbf).unwrap();
+        let grid = layer.rasterize(&GridDefinition::new(0., 0., 1., 4, 1), "height", BurnRule::CenterPoint).unwrap();
+        if grid.values() != [Some(10.), Some(20.), Some(20.), None] {
+            panic!("{:?}", grid.values())
+        }
+        if layer.rasterize(grid.definition(), "name", BurnRule::CenterPoint).is_ok() || layer.rasterize(grid.definition(), "width", BurnRule::CenterPoint).is_ok() {
+            panic!()
+        }
+    }
+
+    #[test]
+    fn test_invalid_definitions() {
+        let extent = BoundingBox {x_min: 0., y_min: 0., x_max: 10., y_max: 10.};
+        if GridDefinition::covering(&extent, 0.).is_ok() || GridDefinition::covering(&extent, f64::NAN).is_ok() || GridDefinition::covering(&extent, 1e-300).is_ok() {
+            panic!()
+        }
+        let empty = GridDefinition::new(0., 0., 1., 0, 3);
+        let huge = GridDefinition::new(0., 0., 1., usize::MAX, 2);
+        if Grid::new(empty).is_ok() || Grid::new(huge).is_ok() || Grid::new(GridDefinition::new(f64::INFINITY, 0., 1., 1, 1)).is_ok() {
+            panic!()
+        }
+
+        let definition = GridDefinition::covering(&extent, 5.).unwrap();
+        if definition.cell_center(1, 0) != Some(Point {x: 7.5, y: 7.5}) || definition.cell_center(0, 2).is_some() {
+            panic!("{:?}", definition.cell_center(1, 0))
+        }
+    }
+}
@@ -0,0 +1,550 @@
+//! Module for KML, as read by Google Earth
+//!
+//! Layers are written as one placemark per record, with the DBF attributes as `ExtendedData`.
+//! Points become `Point`s, polylines `LineString`s and polygons `Polygon`s, with their rings
+//! grouped into outer rings and holes. Shapes with several of them become a `MultiGeometry`. Z
+//! values are written as absolute altitudes, M values are dropped, and so are null shapes and
+//! multipatches. KML is always in WGS84 longitude and latitude, so layers with a PRJ file are
+//! reprojected first.
+//!
+//! The reader takes the placemarks of a KML file with their names, `ExtendedData` and geometry,
+//! wherever they are in folders. Placemarks with a `MultiGeometry` of different kinds of geometry
+//! keep only those of the first kind. Altitudes are optional in KML: Within a line or ring, the
+//! missing ones are filled in from the others along it. A geometry with a point, line or ring
+//! without any altitude gets no Z values at all, so that no altitude is made up from nothing.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+use dbf;
+
+use super::{DbfField, Projection, Shapefile, ShapefileWriter};
+use super::geometry::{self, Vertex, MISSING_MEASURE};
+use super::shape::{Shape, BoundingBox, Point, PointZ, ZRange, MRange};
+use super::xml::{self, Element};
+
+/// Options for `Shapefile::to_kml`.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct KmlOptions {
+    /// The name of the document, shown above the placemarks
+    pub document_name: Option<String>,
+    /// The field holding the names of the placemarks
+    pub name_field: Option<String>,
+}
+
+impl KmlOptions {
+    /// A document without name, with unnamed placemarks.
+    pub fn new() -> Self {
+        KmlOptions {document_name: None, name_field: None}
+    }
+
+    /// Sets the name of the document.
+    pub fn document_name(mut self, name: &str) -> Self {
+        self.document_name = Some(name.to_string());
+        self
+    }
+
+    /// Names the placemarks by the values of a field.
+    pub fn name_field(mut self, field: &str) -> Self {
+        self.name_field = Some(field.to_string());
+        self
+    }
+}
+
+/// A placemark read from a KML file.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Placemark {
+    /// The name, if it has one
+    pub name: Option<String>,
+    /// The names and values of the `ExtendedData`, in the order of the file
+    pub data: Vec<(String, String)>,
+    /// The geometry: a point, multipoint, polyline or polygon, with Z values if every point, line
+    /// and ring has altitudes, or a null shape for placemarks without geometry
+    pub shape: Shape,
+}
+
+/// The kinds of geometry, which can't be mixed in a shapefile.
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Kind {
+    Point,
+    Line,
+    Polygon,
+}
+
+/// The vertices of a geometry, with their altitudes if they have one.
+type Coordinates = Vec<(Vertex, Option<f64>)>;
+
+/// The text of a `coordinates` element.
+fn coordinates<'a, I>(vertices: I, z: bool) -> String
+where I: IntoIterator<Item = &'a Vertex>
+{
+    let mut result = String::new();
+    for (i, v) in vertices.into_iter().enumerate() {
+        if i > 0 {
+            result.push(' ');
+        }
+        let _ = write!(result, "{},{}", v.point.x, v.point.y);
+        if z {
+            let _ = write!(result, ",{}", v.z);
+        }
+    }
+    result
+}
+
+/// The KML geometry of a shape, None for shapes without one.
+fn geometry_of(shape: &Shape) -> Option<String> {
+    let z = shape.shape_type().has_z();
+    let altitude = if z { "<altitudeMode>absolute</altitudeMode>" } else { "" };
+
+    let geometries: Vec<String> = match *shape {
+        Shape::Point {..} | Shape::PointM {..} | Shape::PointZ {..} |
+        Shape::MultiPoint {..} | Shape::MultiPointM {..} | Shape::MultiPointZ {..} => {
+            geometry::vertex_parts(shape).iter().flat_map(|p| p.iter()).map(|v| {
+                format!("<Point>{}<coordinates>{}</coordinates></Point>", altitude, coordinates(Some(v), z))
+            }).collect()
+        },
+        Shape::PolyLine {..} | Shape::PolyLineM {..} | Shape::PolyLineZ {..} => {
+            geometry::vertex_parts(shape).iter().filter(|p| p.len() > 1).map(|p| {
+                format!("<LineString>{}<coordinates>{}</coordinates></LineString>", altitude, coordinates(p, z))
+            }).collect()
+        },
+        Shape::Polygon {..} | Shape::PolygonM {..} | Shape::PolygonZ {..} => {
+            // KML wants outer rings counterclockwise, the other way around than shapefiles
+            let rings = geometry::vertex_parts(&shape.repaired());
+            let points: Vec<Vec<Point>> = rings.iter().map(|r| r.iter().map(|v| v.point).collect()).collect();
+            let ring = |i: usize| format!("<LinearRing><coordinates>{}</coordinates></LinearRing>", coordinates(rings[i].iter().rev(), z));
+            geometry::assemble_rings(&points).into_iter().map(|(outer, holes)| {
+                let mut polygon = format!("<Polygon>{}<outerBoundaryIs>{}</outerBoundaryIs>", altitude, ring(outer));
+                for i in holes {
+                    let _ = write!(polygon, "<innerBoundaryIs>{}</innerBoundaryIs>", ring(i));
+                }
+                polygon.push_str("</Polygon>");
+                polygon
+            }).collect()
+        },
+        Shape::MultiPatch {..} | Shape::NullShape => vec![],
+    };
+
+    match geometries.len() {
+        0 => None,
+        1 => geometries.into_iter().next(),
+        _ => Some(format!("<MultiGeometry>{}</MultiGeometry>", geometries.concat())),
+    }
+}
+
+/// A placemark for a record, None if the shape has no KML geometry.
+fn placemark_of(shape: &Shape, attributes: &HashMap<String, dbf::Field>, fields: &[DbfField], options: &KmlOptions) -> Option<String> {
+    let geometry = geometry_of(shape)?;
+
+    let mut result = String::from("<Placemark>");
+    if let Some(name) = options.name_field.as_ref().and_then(|f| attributes.get(f)).and_then(xml::text_of) {
+        let _ = write!(result, "<name>{}</name>", xml::escape(&name));
+    }
+    let data: Vec<String> = fields.iter().filter_map(|f| {
+        let value = xml::text_of(attributes.get(&f.name)?)?;
+        Some(format!("<Data name=\"{}\"><value>{}</value></Data>", xml::escape(&f.name), xml::escape(&value)))
+    }).collect();
+    if !data.is_empty() {
+        let _ = write!(result, "<ExtendedData>{}</ExtendedData>", data.concat());
+    }
+    result.push_str(&geometry);
+    result.push_str("</Placemark>");
+    Some(result)
+}
+
+impl Shapefile {
+    /// Writes the layer as a KML document, one placemark per record.
+    pub fn to_kml(&mut self, options: &KmlOptions) -> Result<String, Error> {
+        let transformer = self.transformer_to(&Projection::wgs84())?;
+        let fields = self.fields().to_vec();
+
+        let mut result = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                                       <kml xmlns=\"http://www.opengis.net/kml/2.2\">\n<Document>\n");
+        if let Some(ref name) = options.document_name {
+            let _ = writeln!(result, "<name>{}</name>", xml::escape(name));
+        }
+        for record in self.iter_sequential() {
+            let record = record?;
+            let shape = match transformer {
+                Some(ref t) => record.shape.transformed(t),
+                None => record.shape,
+            };
+            if let Some(placemark) = placemark_of(&shape, &record.metadata, &fields, options) {
+                result.push_str(&placemark);
+                result.push('\n');
+            }
+        }
+        result.push_str("</Document>\n</kml>\n");
+        Ok(result)
+    }
+
+    /// Writes the layer into a KML file.
+    pub fn write_kml(&mut self, path: &Path, options: &KmlOptions) -> Result<(), Error> {
+        let kml = self.to_kml(options)?;
+        fs::write(path, kml)
+    }
+}
+
+/// The vertices in the text of a `coordinates` element, with their altitudes if they have one.
+fn parse_coordinates(element: Option<&Element>) -> Result<Coordinates, Error> {
+    let mut vertices = vec![];
+    for tuple in element.map(|e| e.text.as_str()).unwrap_or("").split_whitespace() {
+        let values: Vec<f64> = match tuple.split(',').map(|v| v.parse::<f64>()).collect() {
+            Ok(v) => v,
+            Err(_) => return Err(Error::new(ErrorKind::Other, format!("Invalid KML coordinates `{}`!", tuple))),
+        };
+        if values.len() < 2 || values.len() > 3 {
+            return Err(Error::new(ErrorKind::Other, format!("Invalid KML coordinates `{}`!", tuple)));
+        }
+        vertices.push((Vertex {point: Point {x: values[0], y: values[1]}, z: 0f64, m: MISSING_MEASURE}, values.get(2).cloned()));
+    }
+    Ok(vertices)
+}
+
+/// A ring of a KML polygon, closed and running clockwise for outer rings and counterclockwise for
+/// holes, as in shapefiles.
+fn parse_ring(boundary: &Element, outer: bool) -> Result<Coordinates, Error> {
+    let mut ring = parse_coordinates(boundary.child("LinearRing").and_then(|r| r.child("coordinates")))?;
+    if ring.first().map(|v| v.0.point) != ring.last().map(|v| v.0.point) {
+        let first = ring[0];
+        ring.push(first);
+    }
+    let points: Vec<Point> = ring.iter().map(|v| v.0.point).collect();
+    if (geometry::signed_area(&points) > 0f64) == outer {
+        ring.reverse();
+    }
+    Ok(ring)
+}
+
+/// Collects the parts of the geometries in or below an element, with their kinds.
+fn collect_geometries(element: &Element, parts: &mut Vec<(Kind, Coordinates)>) -> Result<(), Error> {
+    match element.name.as_str() {
+        "Point" | "LineString" => {
+            let vertices = parse_coordinates(element.child("coordinates"))?;
+            if !vertices.is_empty() {
+                parts.push((if element.name == "Point" { Kind::Point } else { Kind::Line }, vertices));
+            }
+        },
+        "Polygon" => {
+            if let Some(boundary) = element.child("outerBoundaryIs") {
+                let outer = parse_ring(boundary, true)?;
+                if outer.len() > 3 {
+                    parts.push((Kind::Polygon, outer));
+                    for boundary in element.children.iter().filter(|e| e.name == "innerBoundaryIs") {
+                        let hole = parse_ring(boundary, false)?;
+                        if hole.len() > 3 {
+                            parts.push((Kind::Polygon, hole));
+                        }
+                    }
+                }
+            }
+        },
+        "MultiGeometry" => {
+            for child in &element.children {
+                collect_geometries(child, parts)?;
+            }
+        },
+        _ => (),
+    }
+    Ok(())
+}
+
+/// A shape of the given kind without vertices, to build shapes of the same type from.
+fn template(kind: Kind, multipoint: bool, z: bool) -> Shape {
+    let bounding_box = BoundingBox::new();
+    let z_range = ZRange {min: 0f64, max: 0f64};
+    let m_range = MRange {min: 0f64, max: 0f64};
+    match (kind, multipoint, z) {
+        (Kind::Point, false, false) => Shape::Point {point: Point {x: 0f64, y: 0f64}},
+        (Kind::Point, false, true) => Shape::PointZ {point: PointZ {x: 0f64, y: 0f64, z: 0f64, m: MISSING_MEASURE}},
+        (Kind::Point, true, false) => Shape::MultiPoint {bounding_box, points: vec![]},
+        (Kind::Point, true, true) => Shape::MultiPointZ {bounding_box, points: vec![], z_range, z: vec![], m_range, m: vec![]},
+        (Kind::Line, _, false) => Shape::PolyLine {bounding_box, parts: vec![], points: vec![]},
+        (Kind::Line, _, true) => Shape::PolyLineZ {bounding_box, parts: vec![], points: vec![], z_range, z: vec![], m_range, m: vec![]},
+        (Kind::Polygon, _, false) => Shape::Polygon {bounding_box, parts: vec![], points: vec![]},
+        (Kind::Polygon, _, true) => Shape::PolygonZ {bounding_box, parts: vec![], points: vec![], z_range, z: vec![], m_range, m: vec![]},
+    }
+}
+
+/// The kind of a shape read from KML, None for null shapes.
+fn kind_of(shape: &Shape) -> Option<Kind> {
+    match *shape {
+        Shape::Point {..} | Shape::PointZ {..} | Shape::MultiPoint {..} | Shape::MultiPointZ {..} => Some(Kind::Point),
+        Shape::PolyLine {..} | Shape::PolyLineZ {..} => Some(Kind::Line),
+        Shape::Polygon {..} | Shape::PolygonZ {..} => Some(Kind::Polygon),
+        _ => None,
+    }
+}
+
+/// Reads the placemarks of a KML document.
+pub fn parse_placemarks(text: &str) -> Result<Vec<Placemark>, Error> {
+    let root = xml::parse(text)?;
+    if root.name != "kml" {
+        return Err(Error::new(ErrorKind::Other, "Not a KML document!"));
+    }
+
+    let mut result = vec![];
+    for element in root.descendants("Placemark") {
+        let name = element.child_text("name").map(|s| s.to_string());
+
+        let mut data = vec![];
+        if let Some(extended) = element.child("ExtendedData") {
+            for d in extended.descendants("Data") {
+                if let Some(n) = d.attribute("name") {
+                    data.push((n.to_string(), d.child_text("value").unwrap_or("").to_string()));
+                }
+            }
+            for d in extended.descendants("SimpleData") {
+                if let Some(n) = d.attribute("name") {
+                    data.push((n.to_string(), d.text.trim().to_string()));
+                }
+            }
+        }
+
+        let mut parts = vec![];
+        for child in &element.children {
+            collect_geometries(child, &mut parts)?;
+        }
+        let shape = match parts.first().map(|p| p.0) {
+            None => Shape::NullShape,
+            Some(kind) => {
+                let parts: Vec<Coordinates> = parts.into_iter().filter(|p| p.0 == kind).map(|p| p.1).collect();
+                let multipoint = parts.len() > 1;
+                match parts.iter().map(|p| geometry::fill_missing_z(p)).collect::<Option<Vec<Vec<Vertex>>>>() {
+                    Some(filled) => geometry::from_vertex_parts(&template(kind, multipoint, true), filled),
+                    None => {
+                        let flat = parts.into_iter().map(|p| p.into_iter().map(|v| v.0).collect()).collect();
+                        geometry::from_vertex_parts(&template(kind, multipoint, false), flat)
+                    },
+                }
+            },
+        };
+
+        result.push(Placemark {name, data, shape});
+    }
+    Ok(result)
+}
+
+/// Reads the placemarks of a KML file.
+pub fn read_placemarks(path: &Path) -> Result<Vec<Placemark>, Error> {
+    parse_placemarks(&fs::read_to_string(path)?)
+}
+
+/// The longest prefix of a text which fits into a DBF column name.
+fn column_name(key: &str) -> String {
+    let mut result = String::new();
+    for c in key.chars() {
+        if result.len() + c.len_utf8() > 10 {
+            break;
+        }
+        result.push(c);
+    }
+    result
+}
+
+/// The column for the values: a number column wide enough for all of them if they are all numbers,
+/// or else a character column.
+fn column_for(name: &str, values: &[&str]) -> DbfField {
+    let numbers: Option<Vec<f64>> = values.iter().map(|v| v.parse::<f64>().ok().filter(|n| n.is_finite())).collect();
+    match numbers {
+        Some(ref numbers) if !numbers.is_empty() => {
+            let decimal_count = values.iter().map(|v| v.split('.').nth(1).map_or(0, |d| d.chars().take_while(|c| c.is_ascii_digit()).count()))
+                                      .max().unwrap_or(0).min(15);
+            let length = numbers.iter().map(|n| format!("{:.*}", decimal_count, n).len()).max().unwrap_or(1);
+            DbfField::numeric(name, length.clamp(1, 254) as u8, decimal_count as u8)
+        },
+        _ => {
+            let length = values.iter().map(|v| v.len()).max().unwrap_or(1);
+            DbfField::character(name, length.clamp(1, 254) as u8)
+        },
+    }
+}
+
+/// Reads the placemarks of a KML file into a new shapefile in WGS84, with a PRJ file next to the
+/// main file. The layer takes the kind of geometry of the first placemark: points, polylines or
+/// polygons, with Z values if all of the placemarks have altitudes. Placemarks of other kinds and
+/// those without geometry are left out. The names of the placemarks go into a `name` column, and
+/// their `ExtendedData` into columns of the same names, cut to the ten characters DBF allows.
+/// Columns whose values are all numbers become number columns. Returns the number of records
+/// written.
+pub fn kml_to_shapefile(kml_path: &Path, shp_path: &Path, shx_path: &Path, dbf_path: &Path) -> Result<u64, Error> {
+    let placemarks = read_placemarks(kml_path)?;
+    let kind = match placemarks.iter().filter_map(|p| kind_of(&p.shape)).next() {
+        Some(k) => k,
+        None => return Err(Error::new(ErrorKind::Other, "The KML file has no placemarks with geometry!")),
+    };
+    let placemarks: Vec<Placemark> = placemarks.into_iter().filter(|p| kind_of(&p.shape) == Some(kind)).collect();
+    let z = placemarks.iter().all(|p| p.shape.shape_type().has_z());
+    let multipoint = placemarks.iter().any(|p| matches!(p.shape, Shape::MultiPoint {..} | Shape::MultiPointZ {..}));
+    let template = template(kind, multipoint, z);
+
+    // The values of every record by column, the first key wins where several share a column
+    let mut columns: Vec<String> = vec![];
+    let mut records: Vec<HashMap<String, String>> = vec![];
+    for p in &placemarks {
+        let mut values = HashMap::new();
+        let named = p.name.iter().map(|n| ("name", n.as_str()));
+        for (key, value) in named.chain(p.data.iter().map(|d| (d.0.as_str(), d.1.as_str()))) {
+            let column = column_name(key);
+            if column.is_empty() || values.contains_key(&column) {
+                continue;
+            }
+            if !columns.contains(&column) {
+                columns.push(column.clone());
+            }
+            values.insert(column, value.to_string());
+        }
+        records.push(values);
+    }
+    let fields: Vec<DbfField> = columns.iter().map(|c| {
+        let values: Vec<&str> = records.iter().filter_map(|r| r.get(c)).map(|v| v.as_str()).filter(|v| !v.is_empty()).collect();
+        column_for(c, &values)
+    }).collect();
+
+    let mut writer = ShapefileWriter::with_schema(shp_path, shx_path, dbf_path, fields.clone())?;
+    for (p, values) in placemarks.iter().zip(&records) {
+        let shape = geometry::from_vertex_parts(&template, geometry::vertex_parts(&p.shape));
+        let metadata: HashMap<String, dbf::Field> = fields.iter().map(|f| {
+            let value = match values.get(&f.name).map(|v| v.as_str()) {
+                None | Some("") => dbf::Field::Null,
+                Some(v) if f.field_type == b'N' => dbf::Field::Numeric(v.parse().unwrap_or(0f64)),
+                Some(v) => dbf::Field::Character(v.to_string()),
+            };
+            (f.name.clone(), value)
+        }).collect();
+        writer.write(&shape, &metadata)?;
+    }
+    writer.finish()?;
+    Projection::wgs84().write_file(&shp_path.with_extension("prj"))?;
+
+    Ok(placemarks.len() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::env;
+    use std::fs;
+    use dbf;
+    use super::{geometry_of, parse_placemarks, kml_to_shapefile, Placemark};
+    use super::super::Shapefile;
+    use super::super::shape::{Shape, BoundingBox, Point, PointZ};
+
+    #[test]
+    fn test_geometry() {
+        // A square with a hole, written counterclockwise with the hole clockwise
+        let mut square = Shape::from(BoundingBox {x_min: 0., y_min: 0., x_max: 10., y_max: 10.});
+        if let Shape::Polygon {ref mut parts, ref mut points, ..} = square {
+            parts.push(points.len() as i32);
+            points.extend([(4., 4.), (6., 4.), (6., 6.), (4., 6.), (4., 4.)].iter().map(|&(x, y)| Point {x, y}));
+        }
+        let kml = geometry_of(&square).unwrap();
+        if kml != "<Polygon><outerBoundaryIs><LinearRing><coordinates>0,0 10,0 10,10 0,10 0,0</coordinates></LinearRing></outerBoundaryIs>\
+                   <innerBoundaryIs><LinearRing><coordinates>4,4 4,6 6,6 6,4 4,4</coordinates></LinearRing></innerBoundaryIs></Polygon>" {
+            panic!("{}", kml)
+        }
+
+        let point = Shape::PointZ {point: PointZ {x: 8.5, y: 47.25, z: 410., m: 0.}};
+        if geometry_of(&point) != Some(String::from("<Point><altitudeMode>absolute</altitudeMode><coordinates>8.5,47.25,410</coordinates></Point>")) {
+            panic!("{:?}", geometry_of(&point))
+        }
+
+        let lines = Shape::PolyLine {bounding_box: BoundingBox::new(), parts: vec![0, 2], points: vec![Point {x: 0., y: 0.}, Point {x: 1., y: 1.}, Point {x: 2., y: 2.}, Point {x: 3., y: 3.}]};
+        if geometry_of(&lines) != Some(String::from("<MultiGeometry><LineString><coordinates>0,0 1,1</coordinates></LineString>\
+                                                     <LineString><coordinates>2,2 3,3</coordinates></LineString></MultiGeometry>")) {
+            panic!("{:?}", geometry_of(&lines))
+        }
+        if geometry_of(&Shape::NullShape).is_some() {
+            panic!()
+        }
+    }
+
+    #[test]
+    fn test_parse_placemarks() {
+        let kml = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                   <kml xmlns=\"http://www.opengis.net/kml/2.2\"><Document><Folder>\
+                   <Placemark><name>Hut</name><ExtendedData><Data name=\"beds\"><value>12</value></Data></ExtendedData>\
+                   <Point><coordinates>8.5,47.25,410</coordinates></Point></Placemark>\
+                   <Placemark><name>Field</name><Polygon><outerBoundaryIs><LinearRing><coordinates>\n  0,0 10,0 10,10 0,10\n</coordinates></LinearRing></outerBoundaryIs></Polygon></Placemark>\
+                   <Placemark><name>Note</name></Placemark>\
+                   </Folder></Document></kml>";
+        let placemarks = parse_placemarks(kml).unwrap();
+        if placemarks.len() != 3 {
+            panic!("{:?}", placemarks)
+        }
+        if placemarks[0] != (Placemark {name: Some(String::from("Hut")), data: vec![(String::from("beds"), String::from("12"))],
+                                        shape: Shape::PointZ {point: PointZ {x: 8.5, y: 47.25, z: 410., m: -1e39}}}) {
+            panic!("{:?}", placemarks[0])
+        }
+        // The ring is closed and turned clockwise
+        match placemarks[1].shape {
+            Shape::Polygon {ref points, ..} if points.len() == 5 && points[1] == Point {x: 0., y: 10.} => (),
+            ref s => panic!("{:?}", s),
+        }
+        if placemarks[2].shape != Shape::NullShape {
+            panic!("{:?}", placemarks[2])
+        }
+
+        // Missing altitudes along a line are filled in, but a point without one has none to take
+        let kml = "<kml><Placemark><LineString><coordinates>0,0,100 1,0 3,0 4,0,400 5,0</coordinates></LineString></Placemark>\
+                   <Placemark><MultiGeometry><Point><coordinates>0,0,100</coordinates></Point><Point><coordinates>1,0</coordinates></Point></MultiGeometry></Placemark></kml>";
+        let placemarks = parse_placemarks(kml).unwrap();
+        match placemarks[0].shape {
+            Shape::PolyLineZ {ref z, ..} if *z == [100., 175., 325., 400., 400.] => (),
+            ref s => panic!("{:?}", s),
+        }
+        if !matches!(placemarks[1].shape, Shape::MultiPoint {..}) {
+            panic!("{:?}", placemarks[1])
+        }
+        if parse_placemarks("<gpx/>").is_ok() || parse_placemarks("<kml><Placemark><Point><coordinates>1</coordinates></Point></Placemark></kml>").is_ok() {
+            panic!()
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let directory = env::temp_dir().join("shapefile-utils-test-kml");
+        fs::create_dir_all(&directory).unwrap();
+        let kml_path = directory.join("huts.kml");
+        fs::write(&kml_path, "<kml xmlns=\"http://www.opengis.net/kml/2.2\"><Document>\
+                              <Placemark><name>Hut &amp; Co</name><ExtendedData><Data name=\"elevation_m\"><value>410.5</value></Data></ExtendedData>\
+                              <Point><coordinates>8.5,47.25</coordinates></Point></Placemark>\
+                              <Placemark><name>Lake</name><LineString><coordinates>8,47 9,48</coordinates></LineString></Placemark>\
+                              <Placemark><ExtendedData><Data name=\"elevation_m\"><value>2</value></Data></ExtendedData>\
+                              <Point><coordinates>9,48,1200</coordinates></Point></Placemark>\
+                              </Document></kml>").unwrap();
+
+        let (shp, shx, dbf) = (directory.join("huts.shp"), directory.join("huts.shx"), directory.join("huts.dbf"));
+        // The line is left out, and the points get no Z values, since the first one has no altitude
+        if kml_to_shapefile(&kml_path, &shp, &shx, &dbf).unwrap() != 2 {
+            panic!()
+        }
+        let mut shapefile = Shapefile::new(&shp, &shx, &dbf).unwrap();
+        let names: Vec<(String, u8)> = shapefile.fields().iter().map(|f| (f.name.clone(), f.field_type)).collect();
+        if names != vec![(String::from("name"), b'C'), (String::from("elevation_"), b'N')] || shapefile.projection().is_none() {
+            panic!("{:?}", names)
+        }
+        let records: Vec<(Shape, HashMap<String, dbf::Field>)> = shapefile.iter_sequential().map(|r| r.unwrap()).map(|r| (r.shape, r.metadata)).collect();
+        match records[1].0 {
+            Shape::Point {point} if point.x == 9. => (),
+            ref s => panic!("{:?}", s),
+        }
+        if !matches!(records[0].1.get("elevation_"), Some(&dbf::Field::Numeric(n)) if n == 410.5) || !matches!(records[1].1.get("name"), Some(&dbf::Field::Null)) {
+            panic!("{:?}", records)
+        }
+
+        let kml = shapefile.to_kml(&super::KmlOptions::new().document_name("Huts").name_field("name")).unwrap();
+        if !kml.contains("<Document>\n<name>Huts</name>\n<Placemark><name>Hut &amp; Co</name>\
+                          <ExtendedData><Data name=\"name\"><value>Hut &amp; Co</value></Data><Data name=\"elevation_\"><value>410.5</value></Data></ExtendedData>\
+                          <Point><coordinates>8.5,47.25</coordinates></Point></Placemark>") {
+            panic!("{}", kml)
+        }
+        let placemarks = parse_placemarks(&kml).unwrap();
+        if placemarks.len() != 2 || placemarks[1].name.is_some() || placemarks[1].data != vec![(String::from("elevation_"), String::from("2"))] {
+            panic!("{:?}", placemarks)
+        }
+    }
+}
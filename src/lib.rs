@@ -15,9 +15,13 @@
 //!
 //!     The metadata associated with the geographic shapes from the main file.
 //!
+//! Memo fields of the dBASE table are read from a DBT (dBASE III and IV) or FPT (FoxPro) file next
+//! to the DBF file, if there is one.
+//!
+//! The coordinate system is read from the PRJ file next to the main file, if there is one.
 //!
 //! There are a couple of other formats on my to-do list, which are mostly sidecar files like CPG for
-//! the dBASE table encoding, and PRJ for the projection used in the main file.
+//! the dBASE table encoding.
 //!
 //! This file mostly defines the data structures for interchange. The function implementations reside
 //! inside the respective submodules.
@@ -44,42 +48,82 @@
 
 extern crate byteorder;
 extern crate dbf;
+extern crate miniz_oxide;
+#[macro_use]
+extern crate serde;
+#[cfg(feature = "mbtiles")]
+extern crate rusqlite;
+#[cfg(feature = "mbtiles")]
+extern crate flate2;
+
+#[cfg(test)]
+#[macro_use]
+extern crate serde_derive;
 
 pub mod shape;
+pub mod de;
+pub mod ser;
+pub mod consistency;
+pub mod validity;
+pub mod repair;
+pub mod measure;
+pub mod predicates;
+pub mod transform;
+pub mod simplify;
+pub mod clip;
+pub mod mvt;
+pub mod style;
+pub mod svg;
+pub mod raster;
+pub mod grid;
+pub mod kml;
+pub mod gpx;
 mod shapefile;
 mod shpfile;
 mod shxfile;
 mod dbffile;
+mod memofile;
+mod prjfile;
+mod geometry;
+mod spatialindex;
+mod lookup;
+mod pngfile;
+mod xml;
+
+pub use shxfile::rebuild_shx;
 
 use std::collections::HashMap;
-use std::io::BufReader;
+use std::io::{BufReader, BufWriter};
 use std::fs::File;
+use std::marker::PhantomData;
+
+use shape::ShapeType;
 
 /// A bounding box specifying minimum and maximum values on X, Y, Z and M axes.
 /// The x is for latitude, y is for longitude.
 /// The z is for altitude and optional.
 /// The m is a "measure" axis for scalar maps, and optional.
-#[derive(Debug, PartialEq)]
-struct BoundingBoxZ {
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct BoundingBoxZ {
     /// The minimum latitude.
-    x_min: f64,
+    pub x_min: f64,
     /// The minimum longitude.
-    y_min: f64,
+    pub y_min: f64,
 
     /// The maximum latitude.
-    x_max: f64,
+    pub x_max: f64,
     /// The maximum longitude.
-    y_max: f64,
+    pub y_max: f64,
 
     /// The minimum altitude.
-    z_min: f64,
+    pub z_min: f64,
     /// The maximum altitude.
-    z_max: f64,
+    pub z_max: f64,
 
     /// The minimum measure.
-    m_min: f64,
+    pub m_min: f64,
     /// The maximum measure.
-    m_max: f64,
+    pub m_max: f64,
 }
 
 /// The header of a SHP file, as defined in the spec.
@@ -87,9 +131,8 @@ struct BoundingBoxZ {
 struct FileHeader {
     /// The length of the file in 16-bit words.
     file_length: i32,
-    /// One of the shape type constants with STY_*. All shapes in the file must be of the specified
-    /// type, or of type 0.
-    shape_type: i32,
+    /// All shapes in the file must be of the specified type, or null shapes.
+    shape_type: ShapeType,
     /// The bounding box of the data contained in the shape file.
     bounding_box: BoundingBoxZ,
 }
@@ -104,7 +147,7 @@ struct ShpFile {
 }
 
 /// An index record.
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 struct ShxRecord {
     /// Offset of the SHP file record measured in 16-bit words
     pub offset: i32,
@@ -112,18 +155,125 @@ struct ShxRecord {
     pub length: i32,
 }
 
-/// An SHX file
+/// An SHX file, or an index built in memory from the SHP file if there is none.
 struct ShxFile {
     /// The SHX file header
     header: FileHeader,
+    /// The file handle, or None if the index lives in memory
+    file: Option<BufReader<File>>,
+    /// The index records, if the index lives in memory
+    records: Vec<ShxRecord>,
+    /// The next record for `read_next_record`, if the index lives in memory
+    cursor: usize,
+}
+
+/// A column definition of a DBF file, as found in the field descriptors of its header.
+#[derive(Debug, PartialEq, Clone)]
+pub struct DbfField {
+    /// The column name, at most 10 characters long
+    pub name: String,
+    /// The dBASE type character (C, N, F, D, L, M, ...)
+    pub field_type: u8,
+    /// Length of the field inside a record, in bytes
+    pub length: u8,
+    /// Number of decimal places
+    pub decimal_count: u8,
+}
+
+/// A DBF value, with the type information needed for deriving a column definition.
+#[derive(Debug, PartialEq, Clone)]
+enum DbfValue {
+    Null,
+    Character(String),
+    Integer(i64),
+    Float(f64),
+    Logical(bool),
+    Date(Date),
+}
+
+/// A DBF file. The values are handed out as `dbf::Field`s.
+struct DbfFile {
     /// The file handle
     file: BufReader<File>,
+    /// The current position in the file, so that consecutive records are read without seeking
+    position: u64,
+    /// Number of records, as specified in the header
+    num_records: u32,
+    /// Length of the header in bytes, which is where the first record starts
+    header_length: u16,
+    /// Length of a single record in bytes, including the deletion flag
+    record_length: u16,
+    /// The column definitions
+    fields: Vec<DbfField>,
+    /// The DBT or FPT file holding the contents of memo fields
+    memo_file: Option<MemoFile>,
 }
 
-/// A DBF file, implemented by the `dbf` crate
-struct DbfFile {
-    /// The `dbf` file handle
-    file: dbf::DbfFile<File>,
+/// The different block layouts of memo files.
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum MemoFormat {
+    /// dBASE III DBT: 512 byte blocks, text terminated by 0x1A.
+    DBaseIII,
+    /// dBASE IV DBT: Configurable block size, every memo starts with a length header.
+    DBaseIV,
+    /// FoxPro FPT: Big endian header, every memo starts with a type and length header.
+    FoxPro,
+}
+
+/// A memo file (DBT or FPT) belonging to a DBF file.
+struct MemoFile {
+    /// The file handle
+    file: BufReader<File>,
+    /// The layout of the blocks
+    format: MemoFormat,
+    /// The size of a block in bytes
+    block_size: u32,
+    /// The size of the whole file in bytes
+    file_size: u64,
+}
+
+/// A DBT file being written next to a DBF file, for its memo columns.
+struct MemoWriter {
+    /// The file handle
+    file: BufWriter<File>,
+    /// The block the next memo starts at
+    next_block: u32,
+}
+
+/// A SHP file being written.
+struct ShpWriter {
+    /// The header, which is updated with every record and written when finishing
+    header: FileHeader,
+    /// Whether the bounding box in the header contains any shape yet
+    has_extent: bool,
+    /// The number of records written so far
+    num_records: i32,
+    /// The file handle
+    file: BufWriter<File>,
+}
+
+/// An SHX file being written.
+struct ShxWriter {
+    /// The number of records written so far
+    num_records: i32,
+    /// The file handle
+    file: BufWriter<File>,
+}
+
+/// A DBF file being written.
+struct DbfWriter {
+    /// The column definitions. Unknown until the first record if they are derived from it.
+    fields: Option<Vec<DbfField>>,
+    /// Whether the columns were derived from the first record, so that they may get wider
+    derived: bool,
+    /// The derived columns which only had null values so far, so that they don't have a type yet
+    untyped: Vec<String>,
+    /// The DBT file, if there are memo columns
+    memo_file: Option<MemoWriter>,
+    /// The number of records written so far
+    num_records: u32,
+    /// The file handle
+    file: BufWriter<File>,
 }
 
 /// Represents a record in the shapefile - has shape and metadata.
@@ -135,6 +285,46 @@ pub struct ShapefileRecord {
     pub metadata: HashMap<String, dbf::Field>
 }
 
+/// A calendar date, as stored in dBASE date fields.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub struct Date {
+    /// The year
+    pub year: i32,
+    /// The month, starting at 1
+    pub month: u32,
+    /// The day of the month, starting at 1
+    pub day: u32,
+}
+
+/// A node of a well-known text (WKT) definition, like `SPHEROID["WGS_1984",6378137,298.257223563]`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct WktNode {
+    /// The keyword in front of the brackets
+    pub keyword: String,
+    /// The values inside the brackets
+    pub values: Vec<WktValue>,
+}
+
+/// A value inside the brackets of a WKT node.
+#[derive(Debug, PartialEq, Clone)]
+pub enum WktValue {
+    /// A quoted string
+    Text(String),
+    /// A number
+    Number(f64),
+    /// An unquoted word, like `NORTH`
+    Keyword(String),
+    /// A nested node
+    Node(WktNode),
+}
+
+/// The coordinate system of a shapefile, as defined in its PRJ file.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Projection {
+    /// The parsed WKT, with `GEOGCS` or `PROJCS` at the root
+    pub wkt: WktNode,
+}
+
 /// The joint struct which makes the API of all of this.
 pub struct Shapefile {
     /// SHP file handle
@@ -143,6 +333,92 @@ pub struct Shapefile {
     shx_file: ShxFile,
     /// DBF file handle
     dbf_file: DbfFile,
+    /// The coordinate system from the PRJ file next to the SHP file, if there is one
+    projection: Option<Projection>,
+    /// The struct field receiving the shape, for `iter_as` and `record_as`
+    geometry_field: String,
+}
+
+/// Writes a new shapefile, record by record.
+///
+/// The headers are written when calling `finish`, or when the writer is dropped.
+pub struct ShapefileWriter {
+    /// SHP file handle
+    shp_file: ShpWriter,
+    /// SHX file handle
+    shx_file: ShxWriter,
+    /// DBF file handle
+    dbf_file: DbfWriter,
+    /// The struct field holding the shape, for `write_record`
+    geometry_field: String,
+    /// Whether the headers have been written already
+    finished: bool,
+}
+
+/// A packed R-tree over bounding boxes, for finding the items near a point or inside an area
+/// without looking at all of them.
+///
+/// The tree is built once from all items, with the sort-tile-recursive method, and can't be
+/// changed afterwards.
+#[derive(Debug, Clone)]
+pub struct SpatialIndex {
+    /// The number of children of every node
+    node_size: usize,
+    /// The boxes of every level, the items first and the root last. The children of node i are the
+    /// nodes i * node_size up to (i + 1) * node_size of the level below.
+    levels: Vec<Vec<shape::BoundingBox>>,
+    /// The ids of the items, in the order of the first level
+    ids: Vec<u64>,
+}
+
+/// A polygon layer held in memory, for finding the polygons at a point.
+pub struct PolygonLookup {
+    /// The index over the bounding boxes of the polygons, by position in `ids`
+    index: SpatialIndex,
+    /// The record ids, ascending
+    ids: Vec<u64>,
+    /// The polygons
+    shapes: Vec<shape::Shape>,
+    /// The attributes of the polygons
+    attributes: Vec<HashMap<String, dbf::Field>>,
+}
+
+/// A layer held in memory in Web Mercator, for cutting it into Mapbox Vector Tiles.
+pub struct Tiler {
+    /// The zoom levels, tile size and layer name
+    options: mvt::TileOptions,
+    /// The index over the bounding boxes of the shapes, by position in `ids`
+    index: SpatialIndex,
+    /// The record ids, ascending
+    ids: Vec<u64>,
+    /// The shapes, in Web Mercator
+    shapes: Vec<shape::Shape>,
+    /// The attributes of the shapes
+    attributes: Vec<HashMap<String, dbf::Field>>,
+}
+
+/// An RGBA picture with 8 bits per channel, for drawing layers into.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Canvas {
+    /// The width in pixels
+    width: u32,
+    /// The height in pixels
+    height: u32,
+    /// The pixels row by row from the top, four bytes each, not premultiplied
+    pixels: Vec<u8>,
+}
+
+/// A grid of values, like the attributes of polygons burned into cells.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Grid {
+    /// The position, size and resolution
+    definition: grid::GridDefinition,
+    /// The values of the cells, row by row from the north, None where there is no data
+    values: Vec<Option<f64>>,
+    /// The value written for cells without data
+    no_data: f64,
+    /// The coordinate system, if known
+    projection: Option<Projection>,
 }
 
 /// An iterator over record-organized structures.
@@ -153,13 +429,89 @@ pub struct ShapefileRecordIterator<'a> {
     id: u64,
 }
 
+/// An iterator over the shapes only, which doesn't touch the DBF file.
+pub struct ShapeIterator<'a> {
+    /// The reference to the instance
+    instance: &'a mut Shapefile,
+    /// Current ID for the iterator
+    id: u64,
+}
+
+/// An iterator over the attributes only, which doesn't touch the SHP and SHX files.
+pub struct AttributeIterator<'a> {
+    /// The reference to the instance
+    instance: &'a mut Shapefile,
+    /// Current ID for the iterator, starting at 0 like the DBF records
+    id: u32,
+    /// Indices of the columns to read, or None for all of them
+    columns: Option<Vec<usize>>,
+}
+
+/// An iterator over records, which reads the SHP file from front to back instead of looking up
+/// every record in the SHX file.
+pub struct ShapefileSequentialIterator<'a> {
+    /// The reference to the instance
+    instance: &'a mut Shapefile,
+    /// Current ID for the iterator
+    id: u64,
+    /// Offset of the next record in the SHP file, in bytes
+    position: u64,
+    /// Offset of the end of the SHP file, in bytes
+    end: u64,
+    /// Whether every record is checked against its SHX entry
+    validate_index: bool,
+    /// Set after an error, which ends the iteration
+    failed: bool,
+}
+
+/// An iterator over records, which deserializes them into a user defined type.
+pub struct ShapefileRecordAsIterator<'a, T> {
+    /// The iterator over the raw records
+    records: ShapefileRecordIterator<'a>,
+    /// The struct field receiving the shape
+    geometry_field: String,
+    /// The type to deserialize into
+    marker: PhantomData<T>,
+}
+
 
 #[cfg(test)]
 mod tests {
-    use super::{Shapefile, ShpFile, ShxFile, DbfFile};
-    use std::path::Path;
+    use super::{rebuild_shx, Shapefile, ShapefileWriter, ShpFile, ShxFile, DbfFile, DbfField, Date, Projection};
+    use super::shape::{Shape, ShapeType, BoundingBox, Point};
+    use super::consistency::{FileKind, Issue};
+    use super::repair::RepairOptions;
+    use byteorder::{BigEndian, ReadBytesExt};
+    use std::collections::HashMap;
+    use std::env;
+    use std::fs;
+    use std::path::{Path, PathBuf};
     use dbf;
 
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Road {
+        #[serde(rename = "osm_id")]
+        id: u64,
+        name: Option<String>,
+        width: f64,
+        opened: Date,
+        geometry: Shape,
+    }
+
+    /// Paths for a temporary shapefile with the given name.
+    fn temp_paths(name: &str) -> (PathBuf, PathBuf, PathBuf) {
+        let base = env::temp_dir().join(format!("shapefile-utils-test-{}", name));
+        (base.with_extension("shp"), base.with_extension("shx"), base.with_extension("dbf"))
+    }
+
+    fn line(x: f64) -> Shape {
+        Shape::PolyLine {
+            bounding_box: BoundingBox {x_min: x, y_min: 0.0, x_max: x + 1.0, y_max: 2.0},
+            parts: vec![0],
+            points: vec![Point {x, y: 0.0}, Point {x: x + 1.0, y: 2.0}],
+        }
+    }
+
     #[test]
     fn test_shp_file_parse() {
         match ShpFile::parse_file(&Path::new("assets/test.shp")) {
@@ -239,4 +591,449 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_write_and_read_structs() {
+        let (shp, shx, dbf) = temp_paths("roads");
+        let roads = vec![
+            Road {id: 464787242, name: Some(String::from("Dock 10")), width: 7.5, opened: Date {year: 1971, month: 4, day: 1}, geometry: line(1.0)},
+            Road {id: 2, name: None, width: 3.25, opened: Date {year: 2001, month: 12, day: 31}, geometry: line(-4.0)},
+        ];
+
+        let mut writer = ShapefileWriter::new(&shp, &shx, &dbf).unwrap();
+        for road in roads.iter() {
+            writer.write_record(road).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let mut sf = Shapefile::new(&shp, &shx, &dbf).unwrap();
+        if sf.num_records() != 2 {
+            panic!()
+        }
+
+        let read: Vec<Road> = sf.iter_as::<Road>().map(|r| r.unwrap()).collect();
+        if read != roads {
+            panic!()
+        }
+
+        // The header covers both shapes
+        if sf.shp_file.header.shape_type != ShapeType::PolyLine || sf.shp_file.header.bounding_box.x_min != -4.0
+        || sf.shp_file.header.bounding_box.x_max != 2.0 || sf.shx_file.header.file_length != 50 + 2 * 4 {
+            panic!()
+        }
+    }
+
+    #[test]
+    fn test_write_with_schema() {
+        let (shp, shx, dbf) = temp_paths("schema");
+        let fields = vec![DbfField::character("name", 4), DbfField::numeric("lanes", 2, 0)];
+
+        let mut writer = ShapefileWriter::with_schema(&shp, &shx, &dbf, fields).unwrap();
+        let mut metadata = HashMap::new();
+        metadata.insert(String::from("name"), dbf::Field::Character(String::from("Long Road")));
+        metadata.insert(String::from("lanes"), dbf::Field::Numeric(2.0));
+        writer.write(&Shape::NullShape, &metadata).unwrap();
+
+        // Doesn't fit, so nothing gets written
+        metadata.insert(String::from("lanes"), dbf::Field::Numeric(200.0));
+        if writer.write(&line(0.0), &metadata).is_ok() {
+            panic!()
+        }
+
+        // Unknown columns are rejected as well
+        metadata.insert(String::from("lanes"), dbf::Field::Numeric(4.0));
+        metadata.insert(String::from("oneway"), dbf::Field::Character(String::from("T")));
+        if writer.write(&line(0.0), &metadata).is_ok() {
+            panic!()
+        }
+        drop(writer);
+
+        let mut sf = Shapefile::new(&shp, &shx, &dbf).unwrap();
+        let record = sf.record(1).unwrap();
+        if record.shape != Shape::NullShape || sf.record(2).is_some() {
+            panic!()
+        }
+
+        // Text gets cut off at the column length
+        if let Some(dbf::Field::Character(s)) = record.metadata.get("name") {
+            if s != "Long" {
+                panic!()
+            }
+        } else {
+            panic!()
+        }
+    }
+
+    #[test]
+    fn test_derived_number_columns() {
+        let (shp, shx, dbf) = temp_paths("numbers");
+        let values = [Some(3.0), Some(464787242.0), Some(2.5), None, Some(-0.125)];
+
+        let mut writer = ShapefileWriter::new(&shp, &shx, &dbf).unwrap();
+        for v in values.iter() {
+            let mut metadata = HashMap::new();
+            metadata.insert(String::from("value"), v.map_or(dbf::Field::Null, dbf::Field::Numeric));
+            writer.write(&line(0.0), &metadata).unwrap();
+        }
+        writer.finish().unwrap();
+
+        // The column grew to 9 digits before the point and 3 decimal places
+        let mut sf = Shapefile::new(&shp, &shx, &dbf).unwrap();
+        if sf.fields() != [DbfField::numeric("value", 13, 3)] {
+            panic!("{:?}", sf.fields())
+        }
+        let read: Vec<Option<f64>> = sf.iter_sequential().map(|r| match r.unwrap().metadata.remove("value") {
+            Some(dbf::Field::Numeric(n)) => Some(n),
+            _ => None,
+        }).collect();
+        if read != values {
+            panic!("{:?}", read)
+        }
+    }
+
+    #[test]
+    fn test_derived_columns_from_data() {
+        let (shp, shx, dbf) = temp_paths("derived");
+        let rows = [(None, "ab"), (Some(4.0), "abcde"), (Some(12.5), "abc")];
+
+        let mut writer = ShapefileWriter::new(&shp, &shx, &dbf).unwrap();
+        for (value, name) in rows.iter() {
+            let mut metadata = HashMap::new();
+            metadata.insert(String::from("value"), value.map_or(dbf::Field::Null, dbf::Field::Numeric));
+            metadata.insert(String::from("name"), dbf::Field::Character(name.to_string()));
+            writer.write(&line(0.0), &metadata).unwrap();
+        }
+        writer.finish().unwrap();
+
+        // The number column got its type from the second record, the text column fits the longest name
+        let mut sf = Shapefile::new(&shp, &shx, &dbf).unwrap();
+        let mut fields = sf.fields().to_vec();
+        fields.sort_by(|a, b| a.name.cmp(&b.name));
+        if fields != [DbfField::character("name", 5), DbfField::numeric("value", 4, 1)] {
+            panic!("{:?}", fields)
+        }
+        let read: Vec<(Option<f64>, String)> = sf.iter_sequential().map(|r| {
+            let mut metadata = r.unwrap().metadata;
+            let value = match metadata.remove("value") {
+                Some(dbf::Field::Numeric(n)) => Some(n),
+                _ => None,
+            };
+            match metadata.remove("name") {
+                Some(dbf::Field::Character(s)) => (value, s),
+                other => panic!("{:?}", other),
+            }
+        }).collect();
+        if read != rows.iter().map(|(v, n)| (*v, n.to_string())).collect::<Vec<_>>() {
+            panic!("{:?}", read)
+        }
+    }
+
+    #[test]
+    fn test_shapes_and_attributes() {
+        let (shp, shx, dbf) = temp_paths("projection");
+        let mut writer = ShapefileWriter::new(&shp, &shx, &dbf).unwrap();
+        for i in 0..3 {
+            let mut metadata = HashMap::new();
+            metadata.insert(String::from("name"), dbf::Field::Character(format!("road{}", i)));
+            metadata.insert(String::from("width"), dbf::Field::Numeric(i as f64));
+            writer.write(&line(i as f64), &metadata).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let mut sf = Shapefile::new(&shp, &shx, &dbf).unwrap();
+
+        let shapes: Vec<Shape> = sf.shapes().collect();
+        if shapes.len() != 3 || shapes[2] != line(2.0) {
+            panic!()
+        }
+
+        if sf.attributes().count() != 3 {
+            panic!()
+        }
+
+        for (i, record) in sf.attributes_projected(&["width", "nonexistent"]).enumerate() {
+            if record.len() != 1 {
+                panic!()
+            }
+            if let Some(&dbf::Field::Numeric(w)) = record.get("width") {
+                if w != i as f64 {
+                    panic!()
+                }
+            } else {
+                panic!()
+            }
+        }
+    }
+
+    #[test]
+    fn test_sequential_iterator() {
+        let (shp, shx, dbf) = temp_paths("sequential");
+        let mut writer = ShapefileWriter::new(&shp, &shx, &dbf).unwrap();
+        for i in 0..5 {
+            let mut metadata = HashMap::new();
+            metadata.insert(String::from("name"), dbf::Field::Character(format!("road{}", i)));
+            let shape = if i == 2 { Shape::NullShape } else { line(i as f64) };
+            writer.write(&shape, &metadata).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let mut sf = Shapefile::new(&shp, &shx, &dbf).unwrap();
+        let random: Vec<Shape> = sf.iter().map(|r| r.shape).collect();
+        let sequential: Vec<Shape> = sf.iter_sequential().map(|r| r.unwrap().shape).collect();
+        let validated: Vec<Shape> = sf.iter_sequential().with_index_validation().map(|r| r.unwrap().shape).collect();
+
+        if random.len() != 5 || random != sequential || random != validated {
+            panic!()
+        }
+        drop(sf);
+
+        // Break the length of the second SHX entry
+        let mut bytes = fs::read(&shx).unwrap();
+        bytes[100 + 8 + 7] += 1;
+        fs::write(&shx, &bytes).unwrap();
+
+        let mut sf = Shapefile::new(&shp, &shx, &dbf).unwrap();
+        if sf.iter_sequential().filter(|r| r.is_ok()).count() != 5 {
+            panic!()
+        }
+
+        let results: Vec<_> = sf.iter_sequential().with_index_validation().collect();
+        if results.len() != 2 || results[0].is_err() || results[1].is_ok() {
+            panic!()
+        }
+    }
+
+    #[test]
+    fn test_rebuild_shx() {
+        let (shp, shx, dbf) = temp_paths("rebuild-shx");
+        let mut writer = ShapefileWriter::new(&shp, &shx, &dbf).unwrap();
+        for i in 0..3 {
+            let shape = if i == 1 { Shape::NullShape } else { line(i as f64) };
+            writer.write(&shape, &HashMap::new()).unwrap();
+        }
+        writer.finish().unwrap();
+
+        // Truncate the index in the middle of the second entry
+        let original = fs::read(&shx).unwrap();
+        fs::write(&shx, &original[..112]).unwrap();
+
+        if rebuild_shx(&shp, &shx).unwrap() != 3 || fs::read(&shx).unwrap() != original {
+            panic!()
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let (shp, shx, dbf) = temp_paths("validate");
+        let mut writer = ShapefileWriter::new(&shp, &shx, &dbf).unwrap();
+        for i in 0..3 {
+            let mut metadata = HashMap::new();
+            metadata.insert(String::from("name"), dbf::Field::Character(format!("road{}", i)));
+            writer.write(&line(i as f64), &metadata).unwrap();
+        }
+        writer.finish().unwrap();
+
+        if !Shapefile::new(&shp, &shx, &dbf).unwrap().validate().unwrap().is_empty() {
+            panic!()
+        }
+
+        // Break the record number of the third SHP record, and the offset of the second SHX entry
+        let mut shx_bytes = fs::read(&shx).unwrap();
+        let offset = 2 * (&shx_bytes[116..120]).read_i32::<BigEndian>().unwrap() as usize;
+        shx_bytes[108 + 3] += 2;
+        fs::write(&shx, &shx_bytes).unwrap();
+
+        let mut shp_bytes = fs::read(&shp).unwrap();
+        shp_bytes[offset + 3] = 7;
+        fs::write(&shp, &shp_bytes).unwrap();
+
+        let issues = Shapefile::new(&shp, &shx, &dbf).unwrap().validate().unwrap();
+        if issues.len() != 2 {
+            panic!()
+        }
+        if let Issue::IndexMismatch {record: 2, ..} = issues[0] {} else {
+            panic!()
+        }
+        if issues[1] != (Issue::RecordNumberMismatch {record: 3, found: 7}) {
+            panic!()
+        }
+
+        // A truncated SHX is reported along with everything else
+        shx_bytes.truncate(100 + 8 + 4);
+        fs::write(&shx, &shx_bytes).unwrap();
+        let issues = Shapefile::new(&shp, &shx, &dbf).unwrap().validate().unwrap();
+        if !issues.iter().any(|i| matches!(*i, Issue::FileLengthMismatch {file: FileKind::Shx, ..})) {
+            panic!("{:?}", issues)
+        }
+        if !matches!(issues[issues.len() - 2], Issue::UnreadableIndexEntry {record: 2, ..}) || issues[issues.len() - 1] != (Issue::RecordNumberMismatch {record: 3, found: 7}) {
+            panic!("{:?}", issues)
+        }
+
+        // A negative SHX file length is reported, and the index isn't compared with the records
+        shx_bytes[24..28].copy_from_slice(&(-1i32).to_be_bytes());
+        fs::write(&shx, &shx_bytes).unwrap();
+        let issues = Shapefile::new(&shp, &shx, &dbf).unwrap().validate().unwrap();
+        if issues != vec![Issue::FileLengthMismatch {file: FileKind::Shx, header: 0, actual: 112}, Issue::RecordNumberMismatch {record: 3, found: 7}] {
+            panic!("{:?}", issues)
+        }
+
+        fs::remove_file(&shx).unwrap();
+        let issues = Shapefile::new(&shp, &shx, &dbf).unwrap().validate().unwrap();
+        if issues != vec![Issue::MissingShx, Issue::RecordNumberMismatch {record: 3, found: 7}] {
+            panic!()
+        }
+    }
+
+    #[test]
+    fn test_repair_into() {
+        let (shp, shx, dbf) = temp_paths("repair-source");
+        let (out_shp, out_shx, out_dbf) = temp_paths("repair-target");
+
+        // A counterclockwise ring without the closing point
+        let square = Shape::Polygon {
+            bounding_box: BoundingBox::new(),
+            parts: vec![0],
+            points: vec![Point {x: 0.0, y: 0.0}, Point {x: 1.0, y: 0.0}, Point {x: 1.0, y: 1.0}, Point {x: 0.0, y: 1.0}],
+        };
+        let mut writer = ShapefileWriter::new(&shp, &shx, &dbf).unwrap();
+        let mut metadata = HashMap::new();
+        metadata.insert(String::from("name"), dbf::Field::Character(String::from("square")));
+        writer.write(&square, &metadata).unwrap();
+        writer.write(&square.repaired(), &metadata).unwrap();
+        writer.finish().unwrap();
+
+        let mut sf = Shapefile::new(&shp, &shx, &dbf).unwrap();
+        if sf.repair_into(&out_shp, &out_shx, &out_dbf, &RepairOptions::new()).unwrap() != 1 {
+            panic!()
+        }
+
+        let mut repaired = Shapefile::new(&out_shp, &out_shx, &out_dbf).unwrap();
+        for record in repaired.iter() {
+            if !record.shape.is_valid() {
+                panic!()
+            }
+            match record.metadata.get("name") {
+                Some(dbf::Field::Character(s)) if s == "square" => (),
+                _ => panic!(),
+            }
+        }
+        if !repaired.validate().unwrap().is_empty() {
+            panic!()
+        }
+    }
+
+    #[test]
+    fn test_reproject_into() {
+        let (shp, shx, dbf) = temp_paths("reproject-source");
+        let (out_shp, out_shx, out_dbf) = temp_paths("reproject-target");
+
+        let mut writer = ShapefileWriter::new(&shp, &shx, &dbf).unwrap();
+        let mut metadata = HashMap::new();
+        metadata.insert(String::from("name"), dbf::Field::Character(String::from("meridian")));
+        let meridian = Shape::PolyLine {
+            bounding_box: BoundingBox::new(),
+            parts: vec![0],
+            points: vec![Point {x: 9.0, y: 0.0}, Point {x: 9.0, y: 10.0}],
+        };
+        writer.write(&meridian, &metadata).unwrap();
+        writer.finish().unwrap();
+        Projection::wgs84().write_file(&shp.with_extension("prj")).unwrap();
+
+        let utm = Projection::utm(32, true).unwrap();
+        let mut sf = Shapefile::new(&shp, &shx, &dbf).unwrap();
+        if sf.reproject_into(&out_shp, &out_shx, &out_dbf, &utm).unwrap() != 1 {
+            panic!()
+        }
+
+        let mut projected = Shapefile::new(&out_shp, &out_shx, &out_dbf).unwrap();
+        if projected.projection() != Some(&utm) {
+            panic!()
+        }
+        let extent = projected.extent();
+        if (extent.x_min - 500000.0).abs() > 1e-6 || (extent.x_max - 500000.0).abs() > 1e-6 || (extent.y_max - 1105412.49).abs() > 0.01 {
+            panic!("{:?}", extent)
+        }
+        match projected.record(1).unwrap().metadata.get("name") {
+            Some(dbf::Field::Character(s)) if s == "meridian" => (),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn test_writer_computes_extents() {
+        let (shp, shx, dbf) = temp_paths("extents");
+        let mut wrong = line(1.0);
+        if let Shape::PolyLine {ref mut bounding_box, ..} = wrong {
+            bounding_box.x_max = 100.0;
+        }
+
+        let mut writer = ShapefileWriter::new(&shp, &shx, &dbf).unwrap();
+        writer.write(&wrong, &HashMap::new()).unwrap();
+        writer.finish().unwrap();
+
+        let mut sf = Shapefile::new(&shp, &shx, &dbf).unwrap();
+        if sf.record(1).unwrap().shape != line(1.0) || !sf.validate().unwrap().is_empty() {
+            panic!()
+        }
+    }
+
+    #[test]
+    fn test_layer_metadata() {
+        let (shp, shx, dbf) = temp_paths("metadata");
+        let mut writer = ShapefileWriter::new(&shp, &shx, &dbf).unwrap();
+        writer.write(&Shape::NullShape, &HashMap::new()).unwrap();
+        writer.write(&line(1.0), &HashMap::new()).unwrap();
+        writer.write(&line(4.0), &HashMap::new()).unwrap();
+        writer.finish().unwrap();
+
+        let sf = Shapefile::new(&shp, &shx, &dbf).unwrap();
+        let extent = sf.extent();
+        if sf.shape_type() != ShapeType::PolyLine || sf.num_records() != 3 {
+            panic!()
+        }
+        if extent.x_min != 1.0 || extent.x_max != 5.0 || extent.y_min != 0.0 || extent.y_max != 2.0 {
+            panic!()
+        }
+
+        // One DBF record less than in the SHX file
+        let mut bytes = fs::read(&dbf).unwrap();
+        bytes[4] = 2;
+        fs::write(&dbf, &bytes).unwrap();
+        if Shapefile::new(&shp, &shx, &dbf).unwrap().num_records() != 2 {
+            panic!()
+        }
+    }
+
+    #[test]
+    fn test_missing_shx() {
+        let (shp, shx, dbf) = temp_paths("missing-shx");
+        let mut writer = ShapefileWriter::new(&shp, &shx, &dbf).unwrap();
+        for i in 0..4 {
+            let mut metadata = HashMap::new();
+            metadata.insert(String::from("name"), dbf::Field::Character(format!("road{}", i)));
+            writer.write(&line(i as f64), &metadata).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let expected: Vec<Shape> = Shapefile::new(&shp, &shx, &dbf).unwrap().iter().map(|r| r.shape).collect();
+        fs::remove_file(&shx).unwrap();
+
+        let mut sf = Shapefile::new(&shp, &shx, &dbf).unwrap();
+        if sf.has_shx() || sf.num_records() != 4 {
+            panic!()
+        }
+        if sf.record(3).unwrap().shape != expected[2] || sf.record(5).is_some() {
+            panic!()
+        }
+        let sequential: Vec<Shape> = sf.iter_sequential().with_index_validation().map(|r| r.unwrap().shape).collect();
+        if sequential != expected {
+            panic!()
+        }
+
+        let mut sf = Shapefile::without_shx(&shp, &dbf).unwrap();
+        let shapes: Vec<Shape> = sf.iter().map(|r| r.shape).collect();
+        if shapes != expected {
+            panic!()
+        }
+    }
 }
@@ -0,0 +1,148 @@
+//! Module for looking up polygons by point
+//!
+//! `PolygonLookup` reads a polygon layer into memory once. A query asks the spatial index for the
+//! polygons whose bounding box holds the point, and tests only those.
+
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+
+use dbf;
+
+use super::{PolygonLookup, Shapefile, SpatialIndex};
+use super::geometry;
+use super::shape::{Shape, ShapeType, Point};
+
+impl PolygonLookup {
+    /// Reads all polygons of a layer with their attributes. Fails for layers of other shape types.
+    pub fn new(shapefile: &mut Shapefile) -> Result<Self, Error> {
+        match shapefile.shape_type() {
+            ShapeType::Polygon | ShapeType::PolygonM | ShapeType::PolygonZ => (),
+            t => return Err(Error::new(ErrorKind::Other, format!("Lookups need a polygon layer, not {:?}!", t))),
+        }
+
+        let mut records: Vec<(u64, Shape, HashMap<String, dbf::Field>)> = vec![];
+        for (i, record) in shapefile.iter_sequential().enumerate() {
+            let record = record?;
+            records.push((i as u64 + 1, record.shape, record.metadata));
+        }
+        Ok(Self::from_records(records))
+    }
+
+    /// Builds a lookup from polygons with their record ids and attributes. Shapes which aren't
+    /// polygons are left out.
+    pub fn from_records<I>(records: I) -> Self
+    where I: IntoIterator<Item = (u64, Shape, HashMap<String, dbf::Field>)>
+    {
+        let mut records: Vec<(u64, Shape, HashMap<String, dbf::Field>)> = records.into_iter()
+                                                                              .filter(|r| geometry::dimension(&r.1) == Some(2))
+                                                                              .collect();
+        records.sort_by_key(|r| r.0);
+
+        let mut items = Vec::with_capacity(records.len());
+        let mut ids = Vec::with_capacity(records.len());
+        let mut shapes = Vec::with_capacity(records.len());
+        let mut attributes = Vec::with_capacity(records.len());
+        for (id, shape, metadata) in records {
+            if let Some(b) = shape.computed_bounding_box() {
+                items.push((ids.len() as u64, b));
+                ids.push(id);
+                shapes.push(shape);
+                attributes.push(metadata);
+            }
+        }
+
+        PolygonLookup {index: SpatialIndex::new(items), ids, shapes, attributes}
+    }
+
+    /// The number of polygons.
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// Whether there are no polygons.
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// The record ids of the polygons which contain the point or have it on their boundary,
+    /// ascending. A point on the border between two polygons is in both.
+    pub fn ids_at(&self, point: Point) -> Vec<u64> {
+        let mut result: Vec<u64> = self.index.query_point(point).into_iter()
+                                             .map(|i| i as usize)
+                                             .filter(|&i| {
+                                                 let rings = geometry::parts_of(&self.shapes[i]).unwrap_or_default();
+                                                 geometry::point_in_rings(point, &rings) != Some(false)
+                                             })
+                                             .map(|i| self.ids[i])
+                                             .collect();
+        result.sort();
+        result
+    }
+
+    /// The record ids and attributes of the polygons which contain the point or have it on their
+    /// boundary, ordered by record id.
+    pub fn lookup(&self, point: Point) -> Vec<(u64, &HashMap<String, dbf::Field>)> {
+        self.ids_at(point).into_iter().filter_map(|id| self.attributes(id).map(|a| (id, a))).collect()
+    }
+
+    /// The polygon with the given record id.
+    pub fn shape(&self, id: u64) -> Option<&Shape> {
+        self.ids.binary_search(&id).ok().map(|i| &self.shapes[i])
+    }
+
+    /// The attributes of the polygon with the given record id.
+    pub fn attributes(&self, id: u64) -> Option<&HashMap<String, dbf::Field>> {
+        self.ids.binary_search(&id).ok().map(|i| &self.attributes[i])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use dbf;
+    use super::super::PolygonLookup;
+    use super::super::shape::{Shape, BoundingBox, Point};
+
+    fn district(name: &str, x_min: f64, x_max: f64) -> HashMap<String, dbf::Field> {
+        let mut result = HashMap::new();
+        result.insert(String::from("name"), dbf::Field::Character(String::from(name)));
+        result.insert(String::from("x_min"), dbf::Field::Numeric(x_min));
+        result.insert(String::from("x_max"), dbf::Field::Numeric(x_max));
+        result
+    }
+
+    #[test]
+    fn test_lookup() {
+        // Three districts side by side, the middle one with a lake
+        let mut records: Vec<(u64, Shape, HashMap<String, dbf::Field>)> = vec![];
+        for (i, &name) in ["west", "middle", "east"].iter().enumerate() {
+            let x = i as f64 * 10.;
+            records.push((i as u64 + 1, Shape::from(BoundingBox {x_min: x, y_min: 0., x_max: x + 10., y_max: 10.}), district(name, x, x + 10.)));
+        }
+        if let Shape::Polygon {ref mut parts, ref mut points, ..} = records[1].1 {
+            parts.push(points.len() as i32);
+            points.extend([(14., 4.), (16., 4.), (16., 6.), (14., 6.), (14., 4.)].iter().map(|&(x, y)| Point {x, y}));
+        }
+        records.push((4, Shape::NullShape, HashMap::new()));
+
+        let lookup = PolygonLookup::from_records(records);
+        if lookup.len() != 3 || lookup.shape(4).is_some() {
+            panic!()
+        }
+
+        let found = lookup.lookup(Point {x: 25., y: 5.});
+        if found.len() != 1 || found[0].0 != 3 {
+            panic!("{:?}", found)
+        }
+        match found[0].1.get("name") {
+            Some(dbf::Field::Character(s)) if s == "east" => (),
+            _ => panic!(),
+        }
+        if !lookup.ids_at(Point {x: 15., y: 5.}).is_empty() || lookup.ids_at(Point {x: 12., y: 5.}) != vec![2] {
+            panic!()
+        }
+        if lookup.ids_at(Point {x: 10., y: 5.}) != vec![1, 2] || !lookup.ids_at(Point {x: 35., y: 5.}).is_empty() {
+            panic!()
+        }
+    }
+}
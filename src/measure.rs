@@ -0,0 +1,442 @@
+//! Module for geometric measures
+//!
+//! The planar measures treat the coordinates as cartesian, which is right for projected layers.
+//! For layers in longitude and latitude, the geodesic measures compute lengths and areas in meters
+//! on an ellipsoid, WGS84 unless the PRJ file says otherwise. `Shapefile::area_of` and its siblings
+//! pick the right one for the layer.
+
+use std::f64::consts::PI;
+
+use super::Shapefile;
+use super::geometry;
+use super::shape::{Shape, Point};
+
+/// An ellipsoid of revolution, the model of the earth used by a geographic coordinate system.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Ellipsoid {
+    /// The semi-major axis in meters
+    pub a: f64,
+    /// The flattening
+    pub f: f64,
+}
+
+impl Ellipsoid {
+    /// The ellipsoid of the World Geodetic System 1984.
+    pub const WGS84: Ellipsoid = Ellipsoid {a: 6378137.0, f: 1.0 / 298.257223563};
+
+    /// Creates an ellipsoid from the semi-major axis and the inverse flattening, as given in WKT.
+    /// An inverse flattening of 0 means a sphere.
+    pub fn from_inverse_flattening(a: f64, inverse_flattening: f64) -> Self {
+        let f = if inverse_flattening == 0f64 { 0f64 } else { 1f64 / inverse_flattening };
+        Ellipsoid {a, f}
+    }
+
+    /// The semi-minor axis in meters.
+    pub fn b(&self) -> f64 {
+        self.a * (1f64 - self.f)
+    }
+
+    /// The square of the first eccentricity.
+    pub fn e2(&self) -> f64 {
+        self.f * (2f64 - self.f)
+    }
+
+    /// The length in meters of the geodesic between two points given as longitude (x) and latitude
+    /// (y) in degrees, by Vincenty's inverse formula.
+    pub fn distance(&self, from: Point, to: Point) -> f64 {
+        let (a, b, f) = (self.a, self.b(), self.f);
+        let l = (to.x - from.x).to_radians();
+        let u1 = ((1f64 - f) * from.y.to_radians().tan()).atan();
+        let u2 = ((1f64 - f) * to.y.to_radians().tan()).atan();
+        let (sin_u1, cos_u1) = u1.sin_cos();
+        let (sin_u2, cos_u2) = u2.sin_cos();
+
+        let mut lambda = l;
+        let (mut sin_sigma, mut cos_sigma, mut sigma, mut cos2_alpha, mut cos_2sigma_m) = (0f64, 0f64, 0f64, 0f64, 0f64);
+
+        // Near antipodal points don't converge, the last iteration is close enough for our purposes
+        for _ in 0..200 {
+            let (sin_lambda, cos_lambda) = lambda.sin_cos();
+            sin_sigma = ((cos_u2 * sin_lambda).powi(2) + (cos_u1 * sin_u2 - sin_u1 * cos_u2 * cos_lambda).powi(2)).sqrt();
+            if sin_sigma == 0f64 {
+                return 0f64;
+            }
+            cos_sigma = sin_u1 * sin_u2 + cos_u1 * cos_u2 * cos_lambda;
+            sigma = sin_sigma.atan2(cos_sigma);
+
+            let sin_alpha = cos_u1 * cos_u2 * sin_lambda / sin_sigma;
+            cos2_alpha = 1f64 - sin_alpha * sin_alpha;
+            cos_2sigma_m = if cos2_alpha == 0f64 { 0f64 } else { cos_sigma - 2f64 * sin_u1 * sin_u2 / cos2_alpha };
+
+            let c = f / 16f64 * cos2_alpha * (4f64 + f * (4f64 - 3f64 * cos2_alpha));
+            let previous = lambda;
+            lambda = l + (1f64 - c) * f * sin_alpha
+                * (sigma + c * sin_sigma * (cos_2sigma_m + c * cos_sigma * (-1f64 + 2f64 * cos_2sigma_m * cos_2sigma_m)));
+            if (lambda - previous).abs() < 1e-12 {
+                break;
+            }
+        }
+
+        let u_2 = cos2_alpha * (a * a - b * b) / (b * b);
+        let big_a = 1f64 + u_2 / 16384f64 * (4096f64 + u_2 * (-768f64 + u_2 * (320f64 - 175f64 * u_2)));
+        let big_b = u_2 / 1024f64 * (256f64 + u_2 * (-128f64 + u_2 * (74f64 - 47f64 * u_2)));
+        let delta_sigma = big_b * sin_sigma * (cos_2sigma_m + big_b / 4f64
+            * (cos_sigma * (-1f64 + 2f64 * cos_2sigma_m * cos_2sigma_m)
+               - big_b / 6f64 * cos_2sigma_m * (-3f64 + 4f64 * sin_sigma * sin_sigma) * (-3f64 + 4f64 * cos_2sigma_m * cos_2sigma_m)));
+
+        b * big_a * (sigma - delta_sigma)
+    }
+
+    /// The area in square meters enclosed by a ring given in longitude (x) and latitude (y) in
+    /// degrees. The ring is mapped onto the sphere of the same surface area by authalic latitudes,
+    /// where the area of a spherical polygon is exact.
+    pub fn ring_area(&self, ring: &[Point]) -> f64 {
+        if ring.len() < 3 {
+            return 0f64;
+        }
+
+        let e2 = self.e2();
+        let e = e2.sqrt();
+        let q = |sin_phi: f64| {
+            if e == 0f64 {
+                2f64 * sin_phi
+            } else {
+                (1f64 - e2) * (sin_phi / (1f64 - e2 * sin_phi * sin_phi)
+                               - 1f64 / (2f64 * e) * ((1f64 - e * sin_phi) / (1f64 + e * sin_phi)).ln())
+            }
+        };
+        let q_p = q(1f64);
+        let sin_beta = |lat: f64| (q(lat.to_radians().sin()) / q_p).clamp(-1f64, 1f64);
+
+        let mut sum = 0f64;
+        for i in 0..ring.len() {
+            let p1 = ring[i];
+            let p2 = ring[(i + 1) % ring.len()];
+            let mut d_lambda = (p2.x - p1.x).to_radians();
+            if d_lambda > PI {
+                d_lambda -= 2f64 * PI;
+            } else if d_lambda < -PI {
+                d_lambda += 2f64 * PI;
+            }
+            sum += d_lambda * (2f64 + sin_beta(p1.y) + sin_beta(p2.y));
+        }
+
+        (sum * self.a * self.a * q_p / 4f64).abs()
+    }
+}
+
+fn planar_length(line: &[Point]) -> f64 {
+    line.windows(2).map(|w| (w[1].x - w[0].x).hypot(w[1].y - w[0].y)).sum()
+}
+
+/// The mean of the points.
+fn mean(points: &[Point]) -> Option<Point> {
+    if points.is_empty() {
+        return None;
+    }
+    let n = points.len() as f64;
+    Some(Point {x: points.iter().map(|p| p.x).sum::<f64>() / n, y: points.iter().map(|p| p.y).sum::<f64>() / n})
+}
+
+/// The centroid of lines, weighting every segment by its length.
+fn line_centroid(lines: &[&[Point]]) -> Option<Point> {
+    let (mut x, mut y, mut total) = (0f64, 0f64, 0f64);
+    for line in lines {
+        for w in line.windows(2) {
+            let length = (w[1].x - w[0].x).hypot(w[1].y - w[0].y);
+            x += length * (w[0].x + w[1].x) / 2f64;
+            y += length * (w[0].y + w[1].y) / 2f64;
+            total += length;
+        }
+    }
+
+    if total == 0f64 {
+        let points: Vec<Point> = lines.iter().flat_map(|l| l.iter().cloned()).collect();
+        return mean(&points);
+    }
+    Some(Point {x: x / total, y: y / total})
+}
+
+/// The point in the middle of the widest span inside the rings, on one of a few horizontal lines
+/// between the vertices.
+fn widest_span_point(rings: &[&[Point]]) -> Option<Point> {
+    let mut ys: Vec<f64> = rings.iter().flat_map(|r| r.iter().map(|p| p.y)).collect();
+    ys.sort_by(|a, b| a.partial_cmp(b).unwrap_or(::std::cmp::Ordering::Equal));
+    ys.dedup();
+    if ys.len() < 2 {
+        return None;
+    }
+
+    // Lines halfway between neighboring vertex heights never run through a vertex
+    let step = ((ys.len() - 1) / 32).max(1);
+    let mut best: Option<(f64, Point)> = None;
+    for i in (0..ys.len() - 1).step_by(step) {
+        let y = (ys[i] + ys[i + 1]) / 2f64;
+        let mut xs: Vec<f64> = vec![];
+        for ring in rings {
+            for w in ring.windows(2) {
+                let (a, b) = (w[0], w[1]);
+                if (a.y > y) != (b.y > y) {
+                    xs.push(a.x + (y - a.y) / (b.y - a.y) * (b.x - a.x));
+                }
+            }
+        }
+        xs.sort_by(|a, b| a.partial_cmp(b).unwrap_or(::std::cmp::Ordering::Equal));
+
+        for pair in xs.chunks(2) {
+            if pair.len() == 2 && best.is_none_or(|(w, _)| pair[1] - pair[0] > w) {
+                best = Some((pair[1] - pair[0], Point {x: (pair[0] + pair[1]) / 2f64, y}));
+            }
+        }
+    }
+    best.map(|(_, p)| p)
+}
+
+/// The point halfway along a line.
+fn halfway(line: &[Point]) -> Option<Point> {
+    let mut remaining = planar_length(line) / 2f64;
+    for w in line.windows(2) {
+        let length = (w[1].x - w[0].x).hypot(w[1].y - w[0].y);
+        if length >= remaining && length > 0f64 {
+            let t = remaining / length;
+            return Some(Point {x: w[0].x + t * (w[1].x - w[0].x), y: w[0].y + t * (w[1].y - w[0].y)});
+        }
+        remaining -= length;
+    }
+    line.first().cloned()
+}
+
+impl Shape {
+    /// The planar area of a polygon, with the area of the holes taken away. Zero for all other shapes.
+    pub fn area(&self) -> f64 {
+        if geometry::dimension(self) != Some(2) {
+            return 0f64;
+        }
+
+        // Outer rings are clockwise with a negative signed area, holes counterclockwise
+        let rings = geometry::parts_of(self).unwrap_or_default();
+        rings.iter().map(|r| -geometry::signed_area(r)).sum::<f64>().abs()
+    }
+
+    /// The planar length of all parts of a polyline or polygon. Zero for all other shapes.
+    pub fn length(&self) -> f64 {
+        if !matches!(geometry::dimension(self), Some(1) | Some(2)) {
+            return 0f64;
+        }
+        geometry::parts_of(self).unwrap_or_default().iter().map(|p| planar_length(p)).sum()
+    }
+
+    /// The planar length of all rings of a polygon, including the holes. Zero for all other shapes.
+    pub fn perimeter(&self) -> f64 {
+        if geometry::dimension(self) != Some(2) {
+            return 0f64;
+        }
+        self.length()
+    }
+
+    /// The center of mass: Weighted by area for polygons, by length for polylines, and the mean of
+    /// the points for all other shapes. Returns None for the NullShape and shapes without points.
+    pub fn centroid(&self) -> Option<Point> {
+        match *self {
+            Shape::NullShape => None,
+            Shape::Point {point} => Some(point),
+            Shape::PointM {point} => Some(Point {x: point.x, y: point.y}),
+            Shape::PointZ {point} => Some(Point {x: point.x, y: point.y}),
+            Shape::MultiPoint {ref points, ..}
+            | Shape::MultiPointM {ref points, ..}
+            | Shape::MultiPointZ {ref points, ..}
+            | Shape::MultiPatch {ref points, ..} => mean(points),
+            _ => {
+                let parts = geometry::parts_of(self).unwrap_or_default();
+                if geometry::dimension(self) == Some(2) {
+                    let (mut x, mut y, mut total) = (0f64, 0f64, 0f64);
+                    for ring in &parts {
+                        for w in ring.windows(2) {
+                            let cross = w[0].x * w[1].y - w[1].x * w[0].y;
+                            x += (w[0].x + w[1].x) * cross;
+                            y += (w[0].y + w[1].y) * cross;
+                        }
+                        total += geometry::signed_area(ring);
+                    }
+                    if total != 0f64 {
+                        return Some(Point {x: x / (6f64 * total), y: y / (6f64 * total)});
+                    }
+                }
+                line_centroid(&parts)
+            },
+        }
+    }
+
+    /// A point which is guaranteed to be on the shape, for placing labels: Inside a polygon, on a
+    /// polyline, or one of the points. Returns None for the NullShape and shapes without points.
+    pub fn interior_point(&self) -> Option<Point> {
+        let centroid = self.centroid()?;
+
+        match *self {
+            Shape::MultiPoint {ref points, ..}
+            | Shape::MultiPointM {ref points, ..}
+            | Shape::MultiPointZ {ref points, ..}
+            | Shape::MultiPatch {ref points, ..} => {
+                let distance = |p: &&Point| (p.x - centroid.x).hypot(p.y - centroid.y);
+                points.iter().min_by(|a, b| distance(a).partial_cmp(&distance(b)).unwrap_or(::std::cmp::Ordering::Equal)).cloned()
+            },
+            _ if geometry::dimension(self) == Some(2) => {
+                let rings = geometry::parts_of(self).unwrap_or_default();
+                if geometry::point_in_rings(centroid, &rings) == Some(true) {
+                    return Some(centroid);
+                }
+                widest_span_point(&rings).or_else(|| rings.iter().flat_map(|r| r.first()).next().cloned())
+            },
+            _ if geometry::dimension(self) == Some(1) => {
+                let parts = geometry::parts_of(self).unwrap_or_default();
+                let longest = parts.iter().max_by(|a, b| {
+                    planar_length(a).partial_cmp(&planar_length(b)).unwrap_or(::std::cmp::Ordering::Equal)
+                });
+                longest.and_then(|l| halfway(l))
+            },
+            _ => Some(centroid),
+        }
+    }
+
+    /// The area of a polygon in square meters on the ellipsoid, with the area of the holes taken
+    /// away. The coordinates are longitude (x) and latitude (y) in degrees. Zero for all other shapes.
+    pub fn geodesic_area(&self, ellipsoid: &Ellipsoid) -> f64 {
+        if geometry::dimension(self) != Some(2) {
+            return 0f64;
+        }
+
+        let rings = geometry::parts_of(self).unwrap_or_default();
+        rings.iter().map(|r| {
+            let area = ellipsoid.ring_area(r);
+            if geometry::signed_area(r) < 0f64 { area } else { -area }
+        }).sum::<f64>().abs()
+    }
+
+    /// The length in meters of all parts of a polyline or polygon on the ellipsoid. The coordinates
+    /// are longitude (x) and latitude (y) in degrees. Zero for all other shapes.
+    pub fn geodesic_length(&self, ellipsoid: &Ellipsoid) -> f64 {
+        if !matches!(geometry::dimension(self), Some(1) | Some(2)) {
+            return 0f64;
+        }
+        geometry::parts_of(self).unwrap_or_default().iter()
+                      .flat_map(|p| p.windows(2))
+                      .map(|w| ellipsoid.distance(w[0], w[1]))
+                      .sum()
+    }
+
+    /// The length in meters of all rings of a polygon on the ellipsoid. Zero for all other shapes.
+    pub fn geodesic_perimeter(&self, ellipsoid: &Ellipsoid) -> f64 {
+        if geometry::dimension(self) != Some(2) {
+            return 0f64;
+        }
+        self.geodesic_length(ellipsoid)
+    }
+}
+
+impl Shapefile {
+    /// The ellipsoid for geodesic measures, if the layer is in longitude and latitude according to
+    /// its PRJ file. Falls back to WGS84 if the PRJ file doesn't name one.
+    pub fn geodesic_ellipsoid(&self) -> Option<Ellipsoid> {
+        match self.projection() {
+            Some(p) if p.is_geographic() => Some(p.ellipsoid().unwrap_or(Ellipsoid::WGS84)),
+            _ => None,
+        }
+    }
+
+    /// The area of a shape of this layer: Geodesic in square meters if the layer is geographic,
+    /// planar in the units of the layer otherwise.
+    pub fn area_of(&self, shape: &Shape) -> f64 {
+        match self.geodesic_ellipsoid() {
+            Some(e) => shape.geodesic_area(&e),
+            None => shape.area(),
+        }
+    }
+
+    /// The length of a shape of this layer: Geodesic in meters if the layer is geographic, planar
+    /// in the units of the layer otherwise.
+    pub fn length_of(&self, shape: &Shape) -> f64 {
+        match self.geodesic_ellipsoid() {
+            Some(e) => shape.geodesic_length(&e),
+            None => shape.length(),
+        }
+    }
+
+    /// The perimeter of a polygon of this layer: Geodesic in meters if the layer is geographic,
+    /// planar in the units of the layer otherwise.
+    pub fn perimeter_of(&self, shape: &Shape) -> f64 {
+        match self.geodesic_ellipsoid() {
+            Some(e) => shape.geodesic_perimeter(&e),
+            None => shape.perimeter(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Ellipsoid;
+    use super::super::shape::{Shape, BoundingBox, Point};
+    use super::super::geometry::polygon;
+
+    #[test]
+    fn test_planar_measures() {
+        let shell: &[(f64, f64)] = &[(0., 0.), (0., 10.), (10., 10.), (10., 0.), (0., 0.)];
+        let hole: &[(f64, f64)] = &[(0., 0.), (5., 0.), (5., 10.), (0., 10.), (0., 0.)];
+        let shape = polygon(&[shell, &[(1., 1.), (4., 1.), (4., 9.), (1., 9.), (1., 1.)]]);
+
+        if shape.area() != 76.0 || shape.perimeter() != 62.0 {
+            panic!()
+        }
+
+        // With the left half cut out, the centroid moves right
+        let halved = polygon(&[shell, hole]);
+        if halved.area() != 50.0 || halved.centroid() != Some(Point {x: 7.5, y: 5.0}) {
+            panic!()
+        }
+        if !halved.interior_point().is_some_and(|p| p.x > 5.0 && p.x < 10.0) {
+            panic!()
+        }
+    }
+
+    #[test]
+    fn test_interior_point_of_ring() {
+        // A U shape, whose centroid is in the gap
+        let u = polygon(&[&[(0., 0.), (0., 10.), (2., 10.), (2., 2.), (8., 2.), (8., 10.), (10., 10.), (10., 0.), (0., 0.)]]);
+        let centroid = u.centroid().unwrap();
+        let p = u.interior_point().unwrap();
+        if centroid.y < 2.0 || p == centroid {
+            panic!()
+        }
+        if !(p.y < 2.0 || p.x < 2.0 || p.x > 8.0) {
+            panic!()
+        }
+
+        let line = Shape::PolyLine {bounding_box: BoundingBox::new(), parts: vec![0], points: vec![Point {x: 0., y: 0.}, Point {x: 4., y: 0.}, Point {x: 4., y: 4.}]};
+        if line.length() != 8.0 || line.interior_point() != Some(Point {x: 4., y: 0.}) || line.area() != 0.0 {
+            panic!()
+        }
+    }
+
+    #[test]
+    fn test_geodesic_measures() {
+        let wgs84 = Ellipsoid::WGS84;
+
+        // One degree of longitude along the equator
+        let d = wgs84.distance(Point {x: 0., y: 0.}, Point {x: 1., y: 0.});
+        if (d - 111319.49).abs() > 0.01 {
+            panic!("{}", d)
+        }
+
+        // Flinders Peak to Buninyong, the classic example of Vincenty's formula
+        let d = wgs84.distance(Point {x: 144.42486788888888, y: -37.95103341666667}, Point {x: 143.92649552777777, y: -37.65282113888889});
+        if (d - 54972.271).abs() > 0.001 {
+            panic!("{}", d)
+        }
+
+        // A square degree at the equator has about 12308 square kilometers
+        let cell = polygon(&[&[(0., 0.), (0., 1.), (1., 1.), (1., 0.), (0., 0.)]]);
+        let area = cell.geodesic_area(&wgs84);
+        if (area / 1e6 - 12308.8).abs() > 1.0 {
+            panic!("{}", area)
+        }
+    }
+}
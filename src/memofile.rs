@@ -0,0 +1,160 @@
+//! Module for memo files
+//!
+//! Memo fields of a DBF file only contain a block number. The actual text is stored in a DBT
+//! (dBASE) or FPT (FoxPro) file, which is a sequence of fixed size blocks with a header block in
+//! front. Three layouts are supported:
+//!
+//! * dBASE III: 512 byte blocks, the text runs until a 0x1A byte.
+//! * dBASE IV: The block size is at offset 20 of the header, every memo starts with `FF FF 08 00`
+//!   and the length of the memo including this 8 byte header.
+//! * FoxPro: The block size is at offset 6 of the header, every memo starts with its type and
+//!   length, both in big endian. Only text memos are read; pictures and other binary memos have
+//!   no text, so they are left out rather than decoded into garbage.
+//!
+//! Memos are written in the dBASE III layout.
+
+use std::fs::File;
+use std::io::{Error, ErrorKind, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use byteorder::{BigEndian, LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use super::{MemoFile, MemoFormat, MemoWriter};
+
+impl MemoFile {
+    /// The block size of dBASE III files, and the default for the others.
+    const DEFAULT_BLOCK_SIZE: u32 = 512;
+    /// The marker at the beginning of a dBASE IV memo.
+    const DBASE_IV_MARKER: [u8; 4] = [0xFF, 0xFF, 0x08, 0x00];
+    /// The end of a dBASE III memo.
+    const DBASE_III_TERMINATOR: u8 = 0x1A;
+
+    /// Given a file name, opens the memo file and reads the block size from its header.
+    pub fn parse_file(path: &Path, format: MemoFormat) -> Result<Self, Error> {
+        let file = File::open(path)?;
+        let file_size = file.metadata()?.len();
+        let mut file = BufReader::new(file);
+
+        let mut header = [0u8; 22];
+        file.read_exact(&mut header)?;
+
+        let block_size = match format {
+            MemoFormat::DBaseIII => Self::DEFAULT_BLOCK_SIZE,
+            MemoFormat::DBaseIV => (&header[20..22]).read_u16::<LittleEndian>()? as u32,
+            MemoFormat::FoxPro => (&header[6..8]).read_u16::<BigEndian>()? as u32,
+        };
+
+        Ok(MemoFile {
+            file,
+            format,
+            block_size: if block_size == 0 { Self::DEFAULT_BLOCK_SIZE } else { block_size },
+            file_size,
+        })
+    }
+
+    /// The type of a FoxPro text memo.
+    const FOXPRO_TEXT: u32 = 1;
+
+    /// Reads the memo starting at the given block. Returns None for FoxPro memos which aren't text.
+    pub fn memo(&mut self, block: u32) -> Result<Option<String>, Error> {
+        let position = block as u64 * self.block_size as u64;
+        self.file.seek(SeekFrom::Start(position))?;
+
+        let bytes = match self.format {
+            MemoFormat::FoxPro => {
+                // Type of the memo (0 = picture, 1 = text, 2 = object), then the length of the data
+                let memo_type = self.file.read_u32::<BigEndian>()?;
+                if memo_type != Self::FOXPRO_TEXT {
+                    return Ok(None);
+                }
+                let length = self.file.read_u32::<BigEndian>()?;
+                self.read_exact_vec(length as usize)?
+            },
+            MemoFormat::DBaseIV | MemoFormat::DBaseIII => {
+                let mut marker = [0u8; 4];
+                self.file.read_exact(&mut marker)?;
+
+                if marker == Self::DBASE_IV_MARKER {
+                    // The length includes the 8 bytes of marker and length
+                    let length = self.file.read_u32::<LittleEndian>()?;
+                    if length < 8 {
+                        return Err(Error::new(ErrorKind::Other, "DBT memo length is invalid!"));
+                    }
+                    self.read_exact_vec(length as usize - 8)?
+                } else {
+                    // No marker, so this is dBASE III text which runs until the terminator
+                    self.file.seek(SeekFrom::Start(position))?;
+                    self.read_until_terminator()?
+                }
+            },
+        };
+
+        Ok(Some(String::from_utf8_lossy(&bytes).trim_end_matches('\0').to_string()))
+    }
+
+    /// Reads exactly n bytes into a new Vec. Lengths which run past the end of the file are
+    /// rejected before anything is allocated.
+    fn read_exact_vec(&mut self, n: usize) -> Result<Vec<u8>, Error> {
+        let position = self.file.stream_position()?;
+        if position.saturating_add(n as u64) > self.file_size {
+            return Err(Error::new(ErrorKind::Other, "Memo runs past the end of the memo file!"));
+        }
+        let mut result = vec![0u8; n];
+        self.file.read_exact(&mut result)?;
+        Ok(result)
+    }
+
+    /// Reads up to the next 0x1A byte, or the end of the file.
+    fn read_until_terminator(&mut self) -> Result<Vec<u8>, Error> {
+        let mut result: Vec<u8> = vec![];
+        let mut block = vec![0u8; self.block_size as usize];
+
+        loop {
+            let n = self.file.read(&mut block)?;
+            if n == 0 {
+                return Ok(result);
+            }
+
+            match block[..n].iter().position(|&b| b == Self::DBASE_III_TERMINATOR) {
+                Some(end) => {
+                    result.extend_from_slice(&block[..end]);
+                    return Ok(result);
+                },
+                None => result.extend_from_slice(&block[..n]),
+            }
+        }
+    }
+}
+
+impl MemoWriter {
+    /// The size of a block in dBASE III files.
+    const BLOCK_SIZE: usize = 512;
+
+    /// Creates the DBT file, starting with the header block.
+    pub fn create_file(path: &Path) -> Result<Self, Error> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(&[0u8; Self::BLOCK_SIZE])?;
+        Ok(MemoWriter {file, next_block: 1})
+    }
+
+    /// Appends a memo and returns the block it starts at. The terminator can't be part of the
+    /// text, so it is left out.
+    pub fn write(&mut self, text: &str) -> Result<u32, Error> {
+        let mut bytes: Vec<u8> = text.bytes().filter(|&b| b != MemoFile::DBASE_III_TERMINATOR).collect();
+        bytes.extend_from_slice(&[MemoFile::DBASE_III_TERMINATOR; 2]);
+        let blocks = bytes.len().div_ceil(Self::BLOCK_SIZE);
+        bytes.resize(blocks * Self::BLOCK_SIZE, 0);
+        self.file.write_all(&bytes)?;
+
+        let block = self.next_block;
+        self.next_block += blocks as u32;
+        Ok(block)
+    }
+
+    /// Writes the next free block into the header and flushes the file.
+    pub fn finish(&mut self) -> Result<(), Error> {
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_u32::<LittleEndian>(self.next_block)?;
+        self.file.seek(SeekFrom::End(0))?;
+        self.file.flush()
+    }
+}
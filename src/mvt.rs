@@ -0,0 +1,733 @@
+//! Module for Mapbox Vector Tiles
+//!
+//! A `Tiler` reads a layer once, reprojects it to Web Mercator if it has a PRJ file, and indexes
+//! the bounding boxes of the shapes. Every tile then only looks at the shapes near it, clips them
+//! to the tile plus a buffer, and rounds the coordinates to the grid of the tile. The tiles follow
+//! version 2.1 of the vector tile specification, with one layer per tile and the DBF attributes as
+//! tags.
+//!
+//! Tiles are numbered as on web maps, with y counting down from the north. They are written into
+//! a directory tree of z/x/y.pbf files, or, with the `mbtiles` feature, into an MBTiles file.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+use dbf;
+
+use super::{Projection, Shapefile, SpatialIndex, Tiler};
+use super::geometry::{self, Vertex};
+use super::shape::{Shape, BoundingBox, Point};
+
+/// Half the width of the Web Mercator world, in meters.
+const HALF_WORLD: f64 = 20037508.342789244;
+
+/// The highest supported zoom level.
+const MAX_ZOOM: u8 = 30;
+
+/// The geometry types of features.
+const POINT: u64 = 1;
+const LINESTRING: u64 = 2;
+const POLYGON: u64 = 3;
+
+/// The commands of geometries.
+const MOVE_TO: u32 = 1;
+const LINE_TO: u32 = 2;
+const CLOSE_PATH: u32 = 7;
+
+/// Options for a `Tiler`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TileOptions {
+    /// The name of the layer inside the tiles
+    pub layer_name: String,
+    /// The lowest zoom level written
+    pub min_zoom: u8,
+    /// The highest zoom level written
+    pub max_zoom: u8,
+    /// The width and height of a tile in its own coordinates
+    pub extent: u32,
+    /// How far shapes reach over the edge of a tile, in tile coordinates
+    pub buffer: u32,
+}
+
+impl TileOptions {
+    /// Zoom levels 0 to 14, with an extent of 4096 and a buffer of 64.
+    pub fn new(layer_name: &str) -> Self {
+        TileOptions {layer_name: String::from(layer_name), min_zoom: 0, max_zoom: 14, extent: 4096, buffer: 64}
+    }
+
+    /// Sets the lowest and the highest zoom level. Levels above 30 are lowered to 30.
+    pub fn zoom(mut self, min_zoom: u8, max_zoom: u8) -> Self {
+        self.min_zoom = min_zoom.min(MAX_ZOOM);
+        self.max_zoom = max_zoom.min(MAX_ZOOM);
+        self
+    }
+
+    /// Sets the extent of a tile.
+    pub fn extent(mut self, extent: u32) -> Self {
+        self.extent = extent;
+        self
+    }
+
+    /// Sets the buffer around a tile.
+    pub fn buffer(mut self, buffer: u32) -> Self {
+        self.buffer = buffer;
+        self
+    }
+}
+
+/// A protocol buffer message being written.
+struct Message(Vec<u8>);
+
+impl Message {
+    fn new() -> Self {
+        Message(vec![])
+    }
+
+    fn raw_varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.0.push((value as u8 & 0x7f) | 0x80);
+            value >>= 7;
+        }
+        self.0.push(value as u8);
+    }
+
+    fn varint(&mut self, field: u32, value: u64) {
+        self.raw_varint(u64::from(field) << 3);
+        self.raw_varint(value);
+    }
+
+    fn double(&mut self, field: u32, value: f64) {
+        self.raw_varint(u64::from(field) << 3 | 1);
+        self.0.extend_from_slice(&value.to_bits().to_le_bytes());
+    }
+
+    fn bytes(&mut self, field: u32, value: &[u8]) {
+        self.raw_varint(u64::from(field) << 3 | 2);
+        self.raw_varint(value.len() as u64);
+        self.0.extend_from_slice(value);
+    }
+
+    fn packed(&mut self, field: u32, values: &[u32]) {
+        let mut inner = Message::new();
+        for &v in values {
+            inner.raw_varint(u64::from(v));
+        }
+        self.bytes(field, &inner.0);
+    }
+}
+
+/// A tag value, comparable so that every value is stored once per layer.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+enum Value {
+    String(String),
+    Integer(i64),
+    Double(u64),
+}
+
+impl Value {
+    fn from_field(field: &dbf::Field) -> Option<Value> {
+        match *field {
+            dbf::Field::Character(ref s) => Some(Value::String(s.clone())),
+            dbf::Field::Numeric(n) if n.fract() == 0f64 && n.abs() < 9007199254740992f64 => Some(Value::Integer(n as i64)),
+            dbf::Field::Numeric(n) => Some(Value::Double(n.to_bits())),
+            dbf::Field::Null => None,
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut message = Message::new();
+        match *self {
+            Value::String(ref s) => message.bytes(1, s.as_bytes()),
+            Value::Double(bits) => message.double(3, f64::from_bits(bits)),
+            Value::Integer(i) => message.varint(6, ((i << 1) ^ (i >> 63)) as u64),
+        }
+        message.0
+    }
+}
+
+fn zigzag(value: i64) -> u32 {
+    ((value << 1) ^ (value >> 63)) as u32
+}
+
+fn command(id: u32, count: usize) -> u32 {
+    id | (count as u32) << 3
+}
+
+/// The bounds of a tile in Web Mercator.
+fn tile_bounds(z: u8, x: u32, y: u32) -> BoundingBox {
+    let size = 2f64 * HALF_WORLD / f64::from(1u32 << z);
+    BoundingBox {
+        x_min: -HALF_WORLD + f64::from(x) * size,
+        y_min: HALF_WORLD - f64::from(y + 1) * size,
+        x_max: -HALF_WORLD + f64::from(x + 1) * size,
+        y_max: HALF_WORLD - f64::from(y) * size,
+    }
+}
+
+fn grown(b: &BoundingBox, margin: f64) -> BoundingBox {
+    BoundingBox {x_min: b.x_min - margin, y_min: b.y_min - margin, x_max: b.x_max + margin, y_max: b.y_max + margin}
+}
+
+/// Rounds points to the grid of a tile, with y pointing down.
+struct Grid {
+    x_min: f64,
+    y_max: f64,
+    scale: f64,
+}
+
+impl Grid {
+    fn point(&self, p: Point) -> (i64, i64) {
+        (((p.x - self.x_min) * self.scale).round() as i64, ((self.y_max - p.y) * self.scale).round() as i64)
+    }
+
+    /// The points of a part on the grid, without repetitions.
+    fn part(&self, part: &[Vertex]) -> Vec<(i64, i64)> {
+        let mut result: Vec<(i64, i64)> = vec![];
+        for v in part {
+            let p = self.point(v.point);
+            if result.last() != Some(&p) {
+                result.push(p);
+            }
+        }
+        result
+    }
+}
+
+/// Twice the area of a ring on the grid. Positive for outer rings, which are clockwise with y
+/// pointing down.
+fn grid_area(ring: &[(i64, i64)]) -> i64 {
+    (0..ring.len()).map(|i| {
+        let (a, b) = (ring[i], ring[(i + 1) % ring.len()]);
+        a.0 * b.1 - b.0 * a.1
+    }).sum()
+}
+
+/// Writes the commands for the paths, moving on from where the previous path ended.
+fn encode_paths(paths: &[Vec<(i64, i64)>], closed: bool) -> Vec<u32> {
+    let mut result: Vec<u32> = vec![];
+    let mut cursor = (0i64, 0i64);
+    for path in paths {
+        for (i, &p) in path.iter().enumerate() {
+            if i == 0 {
+                result.push(command(MOVE_TO, 1));
+            } else if i == 1 {
+                result.push(command(LINE_TO, path.len() - 1));
+            }
+            result.push(zigzag(p.0 - cursor.0));
+            result.push(zigzag(p.1 - cursor.1));
+            cursor = p;
+        }
+        if closed {
+            result.push(command(CLOSE_PATH, 1));
+        }
+    }
+    result
+}
+
+/// The rings of a polygon on the grid, every outer ring followed by its holes. Rings which
+/// collapse on the grid are left out, and so are the holes of outer rings which collapse.
+fn grid_rings(shape: &Shape, grid: &Grid) -> Vec<Vec<(i64, i64)>> {
+    let rings = geometry::vertex_parts(&shape.repaired());
+    let points: Vec<Vec<Point>> = rings.iter().map(|r| r.iter().map(|v| v.point).collect()).collect();
+
+    let on_grid = |i: usize| {
+        let mut ring = grid.part(&rings[i]);
+        if ring.len() > 1 && ring[0] == ring[ring.len() - 1] {
+            ring.pop();
+        }
+        ring
+    };
+    let mut result: Vec<Vec<(i64, i64)>> = vec![];
+    for (j, holes) in geometry::assemble_rings(&points) {
+        let outer = on_grid(j);
+        if outer.len() < 3 || grid_area(&outer) <= 0 {
+            continue;
+        }
+        result.push(outer);
+        for i in holes {
+            let hole = on_grid(i);
+            if hole.len() >= 3 && grid_area(&hole) < 0 {
+                result.push(hole);
+            }
+        }
+    }
+    result
+}
+
+/// The geometry type and commands of a clipped shape, None if nothing is left on the grid.
+fn encode_geometry(shape: &Shape, grid: &Grid) -> Option<(u64, Vec<u32>)> {
+    let (kind, commands) = match *shape {
+        Shape::Point {..} | Shape::PointM {..} | Shape::PointZ {..} |
+        Shape::MultiPoint {..} | Shape::MultiPointM {..} | Shape::MultiPointZ {..} => {
+            let points: Vec<(i64, i64)> = geometry::vertex_parts(shape).iter().flat_map(|p| p.iter().map(|v| grid.point(v.point))).collect();
+            if points.is_empty() {
+                return None;
+            }
+            let mut commands = vec![command(MOVE_TO, points.len())];
+            let mut cursor = (0i64, 0i64);
+            for p in points {
+                commands.push(zigzag(p.0 - cursor.0));
+                commands.push(zigzag(p.1 - cursor.1));
+                cursor = p;
+            }
+            (POINT, commands)
+        },
+        Shape::PolyLine {..} | Shape::PolyLineM {..} | Shape::PolyLineZ {..} => {
+            let lines: Vec<Vec<(i64, i64)>> = geometry::vertex_parts(shape).iter().map(|p| grid.part(p)).filter(|p| p.len() >= 2).collect();
+            (LINESTRING, encode_paths(&lines, false))
+        },
+        Shape::Polygon {..} | Shape::PolygonM {..} | Shape::PolygonZ {..} => (POLYGON, encode_paths(&grid_rings(shape, grid), true)),
+        Shape::MultiPatch {..} | Shape::NullShape => return None,
+    };
+    if commands.is_empty() { None } else { Some((kind, commands)) }
+}
+
+impl Tiler {
+    /// Reads all shapes of a layer with their attributes, reprojected to Web Mercator.
+    pub fn new(shapefile: &mut Shapefile, options: TileOptions) -> Result<Self, Error> {
+        let transformer = shapefile.transformer_to(&Projection::web_mercator())?;
+
+        let mut records: Vec<(u64, Shape, HashMap<String, dbf::Field>)> = vec![];
+        for (i, record) in shapefile.iter_sequential().enumerate() {
+            let record = record?;
+            let shape = match transformer {
+                Some(ref t) => record.shape.transformed(t),
+                None => record.shape,
+            };
+            records.push((i as u64 + 1, shape, record.metadata));
+        }
+        Ok(Self::from_records(records, options))
+    }
+
+    /// Builds a tiler from shapes in Web Mercator with their record ids and attributes. Shapes
+    /// which can't be shown on a web map, like those at the poles, are left out.
+    pub fn from_records<I>(records: I, options: TileOptions) -> Self
+    where I: IntoIterator<Item = (u64, Shape, HashMap<String, dbf::Field>)>
+    {
+        let mut records: Vec<(u64, Shape, HashMap<String, dbf::Field>)> = records.into_iter().collect();
+        records.sort_by_key(|r| r.0);
+
+        let mut items = vec![];
+        let mut ids = vec![];
+        let mut shapes = vec![];
+        let mut attributes = vec![];
+        for (id, shape, metadata) in records {
+            match shape.computed_bounding_box() {
+                Some(b) if b.x_min.is_finite() && b.y_min.is_finite() && b.x_max.is_finite() && b.y_max.is_finite() => {
+                    items.push((ids.len() as u64, b));
+                    ids.push(id);
+                    shapes.push(shape);
+                    attributes.push(metadata);
+                },
+                _ => (),
+            }
+        }
+
+        Tiler {options, index: SpatialIndex::new(items), ids, shapes, attributes}
+    }
+
+    /// The options the tiler was built with.
+    pub fn options(&self) -> &TileOptions {
+        &self.options
+    }
+
+    /// The tiles of a zoom level which may hold any shapes, as ranges of x and y. None if there
+    /// are none, or for zoom levels above 30.
+    pub fn tile_range(&self, z: u8) -> Option<(RangeInclusive<u32>, RangeInclusive<u32>)> {
+        if z > MAX_ZOOM {
+            return None;
+        }
+
+        let tiles = 1u32 << z;
+        let size = 2f64 * HALF_WORLD / f64::from(tiles);
+        let bounds = grown(&self.index.bounding_box()?, size * f64::from(self.options.buffer) / f64::from(self.options.extent));
+        if bounds.x_max < -HALF_WORLD || bounds.x_min > HALF_WORLD || bounds.y_max < -HALF_WORLD || bounds.y_min > HALF_WORLD {
+            return None;
+        }
+
+        let column = |x: f64| (((x + HALF_WORLD) / size).floor().max(0f64) as u32).min(tiles - 1);
+        let row = |y: f64| (((HALF_WORLD - y) / size).floor().max(0f64) as u32).min(tiles - 1);
+        Some((column(bounds.x_min)..=column(bounds.x_max), row(bounds.y_max)..=row(bounds.y_min)))
+    }
+
+    /// Encodes a tile, None if no shape is in it. Zoom levels above 30 and tiles outside the world
+    /// have no shapes.
+    pub fn tile(&self, z: u8, x: u32, y: u32) -> Option<Vec<u8>> {
+        if z > MAX_ZOOM || x >= 1u32 << z || y >= 1u32 << z {
+            return None;
+        }
+
+        let bounds = tile_bounds(z, x, y);
+        let size = bounds.x_max - bounds.x_min;
+        let extent = f64::from(self.options.extent);
+        let clip_box = grown(&bounds, size * f64::from(self.options.buffer) / extent);
+        let grid = Grid {x_min: bounds.x_min, y_max: bounds.y_max, scale: extent / size};
+
+        let mut positions: Vec<usize> = self.index.query(&clip_box).into_iter().map(|i| i as usize).collect();
+        positions.sort();
+
+        let mut keys: Vec<String> = vec![];
+        let mut key_indices: HashMap<String, u32> = HashMap::new();
+        let mut values: Vec<Value> = vec![];
+        let mut value_indices: HashMap<Value, u32> = HashMap::new();
+        let mut layer = Message::new();
+        layer.varint(15, 2);
+        layer.bytes(1, self.options.layer_name.as_bytes());
+
+        let mut empty = true;
+        for i in positions {
+            let (kind, commands) = match encode_geometry(&self.shapes[i].clipped_to_box(&clip_box), &grid) {
+                Some(g) => g,
+                None => continue,
+            };
+
+            let mut names: Vec<&String> = self.attributes[i].keys().collect();
+            names.sort();
+            let mut tags: Vec<u32> = vec![];
+            for name in names {
+                let value = match Value::from_field(&self.attributes[i][name]) {
+                    Some(v) => v,
+                    None => continue,
+                };
+                let next_key = keys.len() as u32;
+                tags.push(*key_indices.entry(name.clone()).or_insert_with(|| {
+                    keys.push(name.clone());
+                    next_key
+                }));
+                let next_value = values.len() as u32;
+                tags.push(*value_indices.entry(value.clone()).or_insert_with(|| {
+                    values.push(value);
+                    next_value
+                }));
+            }
+
+            let mut feature = Message::new();
+            feature.varint(1, self.ids[i]);
+            if !tags.is_empty() {
+                feature.packed(2, &tags);
+            }
+            feature.varint(3, kind);
+            feature.packed(4, &commands);
+            layer.bytes(2, &feature.0);
+            empty = false;
+        }
+        if empty {
+            return None;
+        }
+
+        for key in &keys {
+            layer.bytes(3, key.as_bytes());
+        }
+        for value in &values {
+            layer.bytes(4, &value.encode());
+        }
+        layer.varint(5, u64::from(self.options.extent));
+
+        let mut tile = Message::new();
+        tile.bytes(3, &layer.0);
+        Some(tile.0)
+    }
+
+    /// Calls f with z, x, y and the data of every tile which holds any shapes, zoom level by zoom
+    /// level.
+    fn for_each_tile<F>(&self, mut f: F) -> Result<u64, Error>
+    where F: FnMut(u8, u32, u32, Vec<u8>) -> Result<(), Error>
+    {
+        if self.options.max_zoom > MAX_ZOOM {
+            return Err(Error::new(ErrorKind::Other, format!("Zoom levels above {} aren't supported!", MAX_ZOOM)));
+        }
+
+        let mut count = 0;
+        for z in self.options.min_zoom..=self.options.max_zoom {
+            let (columns, rows) = match self.tile_range(z) {
+                Some(r) => r,
+                None => continue,
+            };
+            for x in columns {
+                for y in rows.clone() {
+                    if let Some(data) = self.tile(z, x, y) {
+                        f(z, x, y, data)?;
+                        count += 1;
+                    }
+                }
+            }
+        }
+        Ok(count)
+    }
+
+    /// Writes every tile which holds any shapes to z/x/y.pbf inside the directory. Returns the
+    /// number of tiles written.
+    pub fn write_directory(&self, path: &Path) -> Result<u64, Error> {
+        self.for_each_tile(|z, x, y, data| {
+            let directory = path.join(z.to_string()).join(x.to_string());
+            fs::create_dir_all(&directory)?;
+            fs::write(directory.join(format!("{}.pbf", y)), data)
+        })
+    }
+
+    /// Writes every tile which holds any shapes into a new MBTiles file, gzipped, with the metadata
+    /// describing the layer and its fields. Returns the number of tiles written.
+    #[cfg(feature = "mbtiles")]
+    pub fn write_mbtiles(&self, path: &Path) -> Result<u64, Error> {
+        use std::io::Write;
+        use flate2::Compression;
+        use flate2::write::GzEncoder;
+        use rusqlite::Connection;
+
+        fn sql_error(e: ::rusqlite::Error) -> Error {
+            Error::new(ErrorKind::Other, format!("Can't write the MBTiles file: {}!", e))
+        }
+
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        let mut connection = Connection::open(path).map_err(sql_error)?;
+        let transaction = connection.transaction().map_err(sql_error)?;
+        transaction.execute_batch("CREATE TABLE metadata (name TEXT, value TEXT);\
+                                   CREATE TABLE tiles (zoom_level INTEGER, tile_column INTEGER, tile_row INTEGER, tile_data BLOB);\
+                                   CREATE UNIQUE INDEX tile_index ON tiles (zoom_level, tile_column, tile_row);").map_err(sql_error)?;
+
+        for (name, value) in self.metadata()? {
+            transaction.execute("INSERT INTO metadata (name, value) VALUES (?1, ?2)", (name, value)).map_err(sql_error)?;
+        }
+
+        let count = {
+            let mut insert = transaction.prepare("INSERT INTO tiles (zoom_level, tile_column, tile_row, tile_data) VALUES (?1, ?2, ?3, ?4)")
+                                        .map_err(sql_error)?;
+            self.for_each_tile(|z, x, y, data| {
+                let mut encoder = GzEncoder::new(vec![], Compression::default());
+                encoder.write_all(&data)?;
+                // MBTiles counts rows up from the south
+                let row = (1u32 << z) - 1 - y;
+                insert.execute((z, x, row, encoder.finish()?)).map_err(sql_error)?;
+                Ok(())
+            })?
+        };
+        transaction.commit().map_err(sql_error)?;
+        Ok(count)
+    }
+
+    /// The metadata of an MBTiles file: the name, format, zoom levels, bounds in degrees and the
+    /// fields of the layer.
+    #[cfg(feature = "mbtiles")]
+    fn metadata(&self) -> Result<Vec<(&'static str, String)>, Error> {
+        use super::transform::Transformer;
+
+        let mut result = vec![
+            ("name", self.options.layer_name.clone()),
+            ("format", String::from("pbf")),
+            ("minzoom", self.options.min_zoom.to_string()),
+            ("maxzoom", self.options.max_zoom.to_string()),
+        ];
+
+        if let Some(b) = self.index.bounding_box() {
+            let to_degrees = Transformer::between(&Projection::web_mercator(), &Projection::wgs84())?;
+            let clamp = |p: Point| Point {x: p.x.clamp(-HALF_WORLD, HALF_WORLD), y: p.y.clamp(-HALF_WORLD, HALF_WORLD)};
+            let south_west = to_degrees.transform(clamp(Point {x: b.x_min, y: b.y_min}));
+            let north_east = to_degrees.transform(clamp(Point {x: b.x_max, y: b.y_max}));
+            result.push(("bounds", format!("{},{},{},{}", south_west.x, south_west.y, north_east.x, north_east.y)));
+        }
+
+        // Fields with any numbers are numbers, the others strings
+        let mut fields: HashMap<&str, &str> = HashMap::new();
+        for attributes in &self.attributes {
+            for (name, value) in attributes {
+                match *value {
+                    dbf::Field::Numeric(_) => { fields.insert(name, "Number"); },
+                    _ => { fields.entry(name).or_insert("String"); },
+                }
+            }
+        }
+        let mut names: Vec<&&str> = fields.keys().collect();
+        names.sort();
+        let fields: Vec<String> = names.iter().map(|n| format!("{}:{}", json_string(n), json_string(fields[*n]))).collect();
+        result.push(("json", format!("{{\"vector_layers\":[{{\"id\":{},\"minzoom\":{},\"maxzoom\":{},\"fields\":{{{}}}}}]}}",
+                                     json_string(&self.options.layer_name), self.options.min_zoom, self.options.max_zoom, fields.join(","))));
+        Ok(result)
+    }
+}
+
+/// A string as a JSON literal.
+#[cfg(feature = "mbtiles")]
+fn json_string(s: &str) -> String {
+    let mut result = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::env;
+    use std::f64::consts::PI;
+    use std::fs;
+    use dbf;
+    use super::{HALF_WORLD, TileOptions};
+    use super::super::Tiler;
+    use super::super::shape::{Shape, BoundingBox, Point};
+
+    /// A point given in degrees in Web Mercator.
+    fn mercator(longitude: f64, latitude: f64) -> Point {
+        Point {x: longitude * HALF_WORLD / 180., y: (PI / 4. + latitude.to_radians() / 2.).tan().ln() * HALF_WORLD / PI}
+    }
+
+    /// The fields of a message, with the number or the bytes of their value.
+    fn fields(mut data: &[u8]) -> Vec<(u64, u64, Vec<u8>)> {
+        fn varint(data: &mut &[u8]) -> u64 {
+            let mut result = 0u64;
+            let mut shift = 0;
+            loop {
+                let byte = data[0];
+                *data = &data[1..];
+                result |= u64::from(byte & 0x7f) << shift;
+                shift += 7;
+                if byte < 0x80 {
+                    return result;
+                }
+            }
+        }
+
+        let mut result = vec![];
+        while !data.is_empty() {
+            let tag = varint(&mut data);
+            match tag & 7 {
+                0 => result.push((tag >> 3, varint(&mut data), vec![])),
+                1 => {
+                    result.push((tag >> 3, 0, data[..8].to_vec()));
+                    data = &data[8..];
+                },
+                2 => {
+                    let length = varint(&mut data) as usize;
+                    result.push((tag >> 3, 0, data[..length].to_vec()));
+                    data = &data[length..];
+                },
+                _ => panic!(),
+            }
+        }
+        result
+    }
+
+    fn packed(data: &[u8]) -> Vec<u64> {
+        let mut result = vec![];
+        let (mut value, mut shift) = (0u64, 0);
+        for &byte in data {
+            value |= u64::from(byte & 0x7f) << shift;
+            shift += 7;
+            if byte < 0x80 {
+                result.push(value);
+                value = 0;
+                shift = 0;
+            }
+        }
+        result
+    }
+
+    fn tiler() -> Tiler {
+        // A square from 10 to 20 degrees east and north, and a point at null island
+        let (a, b) = (mercator(10., 10.), mercator(20., 20.));
+        let mut attributes = HashMap::new();
+        attributes.insert(String::from("name"), dbf::Field::Character(String::from("square")));
+        attributes.insert(String::from("size"), dbf::Field::Numeric(100.));
+        attributes.insert(String::from("note"), dbf::Field::Null);
+        let records = vec![
+            (1, Shape::from(BoundingBox {x_min: a.x, y_min: a.y, x_max: b.x, y_max: b.y}), attributes),
+            (2, Shape::Point {point: Point {x: 0., y: 0.}}, HashMap::new()),
+        ];
+        Tiler::from_records(records, TileOptions::new("things").zoom(0, 4))
+    }
+
+    #[test]
+    fn test_encode_tile() {
+        let tiler = tiler();
+        let tile = tiler.tile(0, 0, 0).unwrap();
+        let layers = fields(&tile);
+        if layers.len() != 1 || layers[0].0 != 3 {
+            panic!("{:?}", layers)
+        }
+
+        let layer = fields(&layers[0].2);
+        if layer[0] != (15, 2, vec![]) || layer[1] != (1, 0, b"things".to_vec()) || layer[layer.len() - 1] != (5, 4096, vec![]) {
+            panic!("{:?}", layer)
+        }
+        let features: Vec<Vec<(u64, u64, Vec<u8>)>> = layer.iter().filter(|f| f.0 == 2).map(|f| fields(&f.2)).collect();
+        let keys: Vec<&[u8]> = layer.iter().filter(|f| f.0 == 3).map(|f| &f.2[..]).collect();
+        if features.len() != 2 || keys != vec![&b"name"[..], &b"size"[..]] {
+            panic!("{:?}", layer)
+        }
+
+        // The square: two tags, a polygon, one ring of four corners
+        let square = &features[0];
+        if square[0] != (1, 1, vec![]) || packed(&square[1].2) != vec![0, 0, 1, 1] || square[2] != (3, 3, vec![]) {
+            panic!("{:?}", square)
+        }
+        let commands = packed(&square[3].2);
+        if commands.len() != 11 || commands[0] != 9 || commands[3] != 3 << 3 | 2 || commands[10] != 15 {
+            panic!("{:?}", commands)
+        }
+
+        // The point, in the middle of the world
+        let point = &features[1];
+        if point[0] != (1, 2, vec![]) || point[1] != (3, 1, vec![]) || packed(&point[2].2) != vec![9, 4096, 4096] {
+            panic!("{:?}", point)
+        }
+
+        // The square is in the north east, the tile south west of the middle only holds the point,
+        // which touches it
+        if tiler.tile(1, 0, 1).map(|t| fields(&fields(&t)[0].2).iter().filter(|f| f.0 == 2).count()) != Some(1) {
+            panic!()
+        }
+        if tiler.tile(3, 0, 0).is_some() {
+            panic!()
+        }
+        if tiler.tile(32, 0, 0).is_some() || tiler.tile(1, 2, 0).is_some() || tiler.tile(0, 0, u32::MAX).is_some() {
+            panic!()
+        }
+    }
+
+    #[test]
+    fn test_write_directory() {
+        let tiler = tiler();
+        if tiler.tile_range(4) != Some((7..=8, 7..=8)) {
+            panic!("{:?}", tiler.tile_range(4))
+        }
+        if tiler.tile_range(40).is_some() || TileOptions::new("things").zoom(0, 40).max_zoom != 30 {
+            panic!()
+        }
+
+        let path = env::temp_dir().join("shapefile-utils-test-tiles");
+        let _ = fs::remove_dir_all(&path);
+        let count = tiler.write_directory(&path).unwrap();
+        if count < 5 || !path.join("0/0/0.pbf").exists() || !path.join("4/8/7.pbf").exists() || path.join("4/9/7.pbf").exists() {
+            panic!("{}", count)
+        }
+    }
+
+    #[cfg(feature = "mbtiles")]
+    #[test]
+    fn test_write_mbtiles() {
+        use rusqlite::Connection;
+
+        let path = env::temp_dir().join("shapefile-utils-test-tiles.mbtiles");
+        let count = tiler().write_mbtiles(&path).unwrap();
+
+        let connection = Connection::open(&path).unwrap();
+        let stored: u64 = connection.query_row("SELECT COUNT(*) FROM tiles", (), |r| r.get(0)).unwrap();
+        // Rows count up from the south, so tile 4/8/7 is row 8
+        let data: Vec<u8> = connection.query_row("SELECT tile_data FROM tiles WHERE zoom_level = 4 AND tile_column = 8 AND tile_row = 8", (), |r| r.get(0)).unwrap();
+        let json: String = connection.query_row("SELECT value FROM metadata WHERE name = 'json'", (), |r| r.get(0)).unwrap();
+        if stored != count || data[..2] != [0x1f, 0x8b] || !json.contains("\"fields\":{\"name\":\"String\",\"note\":\"String\",\"size\":\"Number\"}") {
+            panic!("{} {}", stored, json)
+        }
+    }
+}
@@ -0,0 +1,144 @@
+//! Module for writing PNG files
+//!
+//! Canvases are written as 8 bit RGBA without interlacing. Every row gets the filter which makes
+//! it smallest by the usual sum of absolute differences, and the rows are compressed by
+//! miniz_oxide.
+
+use std::fs;
+use std::io::Error;
+use std::path::Path;
+
+use miniz_oxide::deflate::compress_to_vec_zlib;
+
+use super::Canvas;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+
+/// The deflate level, from 0 for none to 10 for the smallest files.
+const COMPRESSION_LEVEL: u8 = 6;
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffffffffu32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = i16::from(a) + i16::from(b) - i16::from(c);
+    let (pa, pb, pc) = ((p - i16::from(a)).abs(), (p - i16::from(b)).abs(), (p - i16::from(c)).abs());
+    if pa <= pb && pa <= pc { a } else if pb <= pc { b } else { c }
+}
+
+/// The rows of the picture, each behind the number of its filter.
+fn filtered_rows(canvas: &Canvas) -> Vec<u8> {
+    let stride = canvas.width as usize * 4;
+    let mut result = Vec::with_capacity((stride + 1) * canvas.height as usize);
+    let zeros = vec![0u8; stride];
+
+    for (y, row) in canvas.pixels.chunks(stride.max(1)).enumerate().take(canvas.height as usize) {
+        let above = if y == 0 { &zeros[..] } else { &canvas.pixels[(y - 1) * stride..y * stride] };
+        let left = |i: usize| if i < 4 { 0 } else { row[i - 4] };
+        let upper_left = |i: usize| if i < 4 { 0 } else { above[i - 4] };
+
+        let candidates: Vec<Vec<u8>> = (0..5u8).map(|filter| {
+            let mut line = Vec::with_capacity(stride + 1);
+            line.push(filter);
+            line.extend((0..stride).map(|i| match filter {
+                0 => row[i],
+                1 => row[i].wrapping_sub(left(i)),
+                2 => row[i].wrapping_sub(above[i]),
+                3 => row[i].wrapping_sub(((u16::from(left(i)) + u16::from(above[i])) / 2) as u8),
+                _ => row[i].wrapping_sub(paeth(left(i), above[i], upper_left(i))),
+            }));
+            line
+        }).collect();
+        let cost = |line: &Vec<u8>| line[1..].iter().map(|&b| u64::from((b as i8).unsigned_abs())).sum::<u64>();
+        if let Some(best) = candidates.iter().min_by_key(|l| cost(l)) {
+            result.extend_from_slice(best);
+        }
+    }
+    result
+}
+
+fn chunk(result: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    result.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = result.len();
+    result.extend_from_slice(kind);
+    result.extend_from_slice(data);
+    let crc = crc32(&result[start..]);
+    result.extend_from_slice(&crc.to_be_bytes());
+}
+
+impl Canvas {
+    /// Encodes the picture as PNG.
+    pub fn to_png(&self) -> Vec<u8> {
+        let mut header = vec![];
+        header.extend_from_slice(&self.width.to_be_bytes());
+        header.extend_from_slice(&self.height.to_be_bytes());
+        // 8 bits per channel, RGBA, deflate, adaptive filters, no interlacing
+        header.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+        let mut result = SIGNATURE.to_vec();
+        chunk(&mut result, b"IHDR", &header);
+        chunk(&mut result, b"IDAT", &compress_to_vec_zlib(&filtered_rows(self), COMPRESSION_LEVEL));
+        chunk(&mut result, b"IEND", &[]);
+        result
+    }
+
+    /// Writes the picture into a PNG file.
+    pub fn write_png(&self, path: &Path) -> Result<(), Error> {
+        fs::write(path, self.to_png())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use miniz_oxide::inflate::decompress_to_vec_zlib;
+
+    use super::crc32;
+    use super::super::Canvas;
+    use super::super::style::Color;
+
+    #[test]
+    fn test_png() {
+        let mut canvas = Canvas::new(3, 2, Color::WHITE);
+        canvas.set_pixel(1, 0, Color::rgba(255, 0, 0, 128));
+        canvas.set_pixel(2, 1, Color::rgb(0, 0, 255));
+        let png = canvas.to_png();
+
+        if png[..8] != [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a] || png[12..16] != *b"IHDR" || png[16..24] != [0, 0, 0, 3, 0, 0, 0, 2] {
+            panic!("{:?}", png)
+        }
+        if crc32(b"IEND") != 0xae426082 || png[png.len() - 12..] != [0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82] {
+            panic!("{:?}", png)
+        }
+
+        // Undo the filters
+        let length = u32::from_be_bytes([png[33], png[34], png[35], png[36]]) as usize;
+        let rows = decompress_to_vec_zlib(&png[41..41 + length]).unwrap();
+        let mut pixels: Vec<u8> = vec![];
+        for (y, row) in rows.chunks(13).enumerate() {
+            for i in 0..12 {
+                let left = if i < 4 { 0 } else { pixels[y * 12 + i - 4] };
+                let above = if y == 0 { 0 } else { pixels[(y - 1) * 12 + i] };
+                let upper_left = if y == 0 || i < 4 { 0 } else { pixels[(y - 1) * 12 + i - 4] };
+                let predicted = match row[0] {
+                    0 => 0,
+                    1 => left,
+                    2 => above,
+                    3 => ((u16::from(left) + u16::from(above)) / 2) as u8,
+                    _ => super::paeth(left, above, upper_left),
+                };
+                pixels.push(row[i + 1].wrapping_add(predicted));
+            }
+        }
+        if pixels != canvas.pixels() {
+            panic!("{:?}", pixels)
+        }
+    }
+}
@@ -0,0 +1,408 @@
+//! Module for spatial predicates
+//!
+//! The predicates compare two shapes in the plane, following the meaning they have in the OGC
+//! simple features spec. A shape is seen as a set of points with an interior and a boundary:
+//!
+//! * Points have no boundary.
+//! * The boundary of a polyline is made of the end points of its parts which aren't closed, the
+//!   rest is interior.
+//! * The boundary of a polygon is made of its rings, the interior is what the rings enclose by the
+//!   even-odd rule, so holes are outside.
+//! * Multipatches are compared by their vertices only.
+//!
+//! Points and bounding boxes turn into shapes with `Shape::from`, to test them against a shape.
+
+use super::geometry::{self, SegmentIntersection};
+use super::shape::{Shape, BoundingBox, Point};
+
+/// A shape reduced to what matters in the plane.
+enum Geometry<'a> {
+    Empty,
+    Points(Vec<Point>),
+    Lines(Vec<&'a [Point]>),
+    Area(Vec<&'a [Point]>),
+}
+
+/// Where a point lies relative to a shape.
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Location {
+    Interior,
+    Boundary,
+    Exterior,
+}
+
+/// A shape prepared for the predicates.
+struct Prepared<'a> {
+    shape: &'a Shape,
+    geometry: Geometry<'a>,
+    /// The segments of lines and rings, and the points as segments of length zero
+    segments: Vec<(Point, Point)>,
+    /// The end points of polylines which are boundary points
+    boundary: Vec<Point>,
+}
+
+impl<'a> Prepared<'a> {
+    fn new(shape: &'a Shape) -> Self {
+        let geometry = match *shape {
+            Shape::NullShape => Geometry::Empty,
+            Shape::Point {point} => Geometry::Points(vec![point]),
+            Shape::PointM {point} => Geometry::Points(vec![Point {x: point.x, y: point.y}]),
+            Shape::PointZ {point} => Geometry::Points(vec![Point {x: point.x, y: point.y}]),
+            Shape::MultiPoint {ref points, ..}
+            | Shape::MultiPointM {ref points, ..}
+            | Shape::MultiPointZ {ref points, ..}
+            | Shape::MultiPatch {ref points, ..} => Geometry::Points(points.clone()),
+            Shape::PolyLine {..} | Shape::PolyLineM {..} | Shape::PolyLineZ {..} => {
+                Geometry::Lines(geometry::parts_of(shape).unwrap_or_default().into_iter().filter(|p| !p.is_empty()).collect())
+            },
+            Shape::Polygon {..} | Shape::PolygonM {..} | Shape::PolygonZ {..} => {
+                Geometry::Area(geometry::parts_of(shape).unwrap_or_default().into_iter().filter(|p| p.len() >= 3).collect())
+            },
+        };
+        let geometry = match geometry {
+            Geometry::Points(ref p) if p.is_empty() => Geometry::Empty,
+            Geometry::Lines(ref p) | Geometry::Area(ref p) if p.is_empty() => Geometry::Empty,
+            g => g,
+        };
+
+        let mut segments: Vec<(Point, Point)> = vec![];
+        let mut boundary: Vec<Point> = vec![];
+        match geometry {
+            Geometry::Empty => (),
+            Geometry::Points(ref points) => segments.extend(points.iter().map(|&p| (p, p))),
+            Geometry::Lines(ref lines) => {
+                for line in lines {
+                    if line.len() == 1 {
+                        segments.push((line[0], line[0]));
+                    }
+                    segments.extend(line.windows(2).filter(|w| w[0] != w[1]).map(|w| (w[0], w[1])));
+                }
+
+                // By the mod 2 rule, end points shared by an even number of parts are interior
+                let ends: Vec<Point> = lines.iter().filter(|l| l.len() >= 2 && l[0] != l[l.len() - 1])
+                                            .flat_map(|l| vec![l[0], l[l.len() - 1]]).collect();
+                for &p in &ends {
+                    if ends.iter().filter(|&&q| q == p).count() % 2 == 1 && !boundary.contains(&p) {
+                        boundary.push(p);
+                    }
+                }
+            },
+            Geometry::Area(ref rings) => {
+                for ring in rings {
+                    segments.extend(ring.windows(2).filter(|w| w[0] != w[1]).map(|w| (w[0], w[1])));
+                    let (first, last) = (ring[0], ring[ring.len() - 1]);
+                    if first != last {
+                        segments.push((last, first));
+                    }
+                }
+            },
+        }
+
+        Prepared {shape, geometry, segments, boundary}
+    }
+
+    fn is_empty(&self) -> bool {
+        matches!(self.geometry, Geometry::Empty)
+    }
+
+    fn is_area(&self) -> bool {
+        matches!(self.geometry, Geometry::Area(_))
+    }
+
+    fn vertices(&self) -> Vec<Point> {
+        match self.geometry {
+            Geometry::Empty => vec![],
+            Geometry::Points(ref points) => points.clone(),
+            Geometry::Lines(ref parts) | Geometry::Area(ref parts) => parts.iter().flat_map(|p| p.iter().cloned()).collect(),
+        }
+    }
+
+    fn locate(&self, p: Point) -> Location {
+        match self.geometry {
+            Geometry::Empty => Location::Exterior,
+            Geometry::Points(ref points) => if points.contains(&p) { Location::Interior } else { Location::Exterior },
+            Geometry::Lines(_) => {
+                if !self.segments.iter().any(|&(a, b)| geometry::on_segment(p, a, b)) {
+                    Location::Exterior
+                } else if self.boundary.contains(&p) {
+                    Location::Boundary
+                } else {
+                    Location::Interior
+                }
+            },
+            Geometry::Area(ref rings) => match geometry::point_in_rings(p, rings) {
+                None => Location::Boundary,
+                Some(true) => Location::Interior,
+                Some(false) => Location::Exterior,
+            },
+        }
+    }
+
+    /// Points of the shape which stand for all of it: The vertices, plus the middle of every piece
+    /// of the segments when they're cut where the other shape meets them. Between two cuts, a
+    /// segment lies entirely inside, outside or on the other shape. The flag tells whether the
+    /// point is in the interior of this shape.
+    fn samples(&self, other: &Prepared) -> Vec<(Point, bool)> {
+        let mut result: Vec<(Point, bool)> = self.vertices().into_iter().map(|p| {
+            (p, self.locate(p) == Location::Interior)
+        }).collect();
+
+        if let Geometry::Points(_) = self.geometry {
+            return result;
+        }
+        let interior = !self.is_area();
+
+        for &(a, b) in self.segments.iter().filter(|&&(a, b)| a != b) {
+            let mut cuts: Vec<f64> = vec![0f64, 1f64];
+            for &(c, d) in &other.segments {
+                match geometry::segment_intersection(a, b, c, d) {
+                    SegmentIntersection::None => (),
+                    SegmentIntersection::Proper => {
+                        let (da, db) = (geometry::cross(c, d, a), geometry::cross(c, d, b));
+                        cuts.push(da / (da - db));
+                    },
+                    SegmentIntersection::Touching => {
+                        for &p in &[c, d] {
+                            if geometry::on_segment(p, a, b) {
+                                cuts.push(geometry::parameter(p, a, b));
+                            }
+                        }
+                    },
+                }
+            }
+            cuts.sort_by(|s, t| s.partial_cmp(t).unwrap_or(::std::cmp::Ordering::Equal));
+
+            for w in cuts.windows(2) {
+                if w[1] > w[0] {
+                    let t = (w[0] + w[1]) / 2f64;
+                    result.push((Point {x: a.x + t * (b.x - a.x), y: a.y + t * (b.y - a.y)}, interior));
+                }
+            }
+        }
+        result
+    }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        self.shape.computed_bounding_box()
+    }
+}
+
+fn intersects(a: &Prepared, b: &Prepared) -> bool {
+    if a.is_empty() || b.is_empty() {
+        return false;
+    }
+    if let (Some(x), Some(y)) = (a.bounding_box(), b.bounding_box()) {
+        if !geometry::boxes_overlap(&x, &y) {
+            return false;
+        }
+    }
+
+    let meet = a.segments.iter().any(|&(p, q)| {
+        b.segments.iter().any(|&(r, s)| geometry::segment_intersection(p, q, r, s) != SegmentIntersection::None)
+    });
+
+    // Without meeting boundaries, one shape can still lie inside the other
+    meet || (a.is_area() && b.vertices().iter().any(|&p| a.locate(p) != Location::Exterior))
+         || (b.is_area() && a.vertices().iter().any(|&p| b.locate(p) != Location::Exterior))
+}
+
+fn interiors_intersect(a: &Prepared, b: &Prepared) -> bool {
+    if !intersects(a, b) {
+        return false;
+    }
+
+    // Lines and rings which cross each other share interior points on both sides
+    let crossing = a.segments.iter().any(|&(p, q)| {
+        b.segments.iter().any(|&(r, s)| geometry::segment_intersection(p, q, r, s) == SegmentIntersection::Proper)
+    });
+    if crossing {
+        return true;
+    }
+
+    // A point of a ring in the interior of another area has interior points of both around it
+    let inside = |x: &Prepared, y: &Prepared| {
+        let boundary_counts = x.is_area() && y.is_area();
+        x.samples(y).into_iter().any(|(p, interior)| (interior || boundary_counts) && y.locate(p) == Location::Interior)
+    };
+    if inside(a, b) || inside(b, a) {
+        return true;
+    }
+
+    // Areas with the same boundary
+    let interior_point_inside = |x: &Prepared, y: &Prepared| {
+        x.is_area() && x.shape.interior_point().is_some_and(|p| y.locate(p) == Location::Interior)
+    };
+    interior_point_inside(a, b) || interior_point_inside(b, a)
+}
+
+/// Whether no point of b is outside of a.
+fn covers(a: &Prepared, b: &Prepared) -> bool {
+    if a.is_empty() || b.is_empty() || (b.is_area() && !a.is_area()) {
+        return false;
+    }
+    if let (Some(x), Some(y)) = (a.bounding_box(), b.bounding_box()) {
+        if x.x_min > y.x_min || x.y_min > y.y_min || x.x_max < y.x_max || x.y_max < y.y_max {
+            return false;
+        }
+    }
+
+    if b.samples(a).into_iter().any(|(p, _)| a.locate(p) == Location::Exterior) {
+        return false;
+    }
+
+    // If a ring of a is inside b, b reaches beyond it, like over a hole
+    !(b.is_area() && a.samples(b).into_iter().any(|(p, _)| b.locate(p) == Location::Interior))
+}
+
+impl From<Point> for Shape {
+    fn from(point: Point) -> Self {
+        Shape::Point {point}
+    }
+}
+
+impl From<BoundingBox> for Shape {
+    /// Turns the bounding box into a polygon with a single, clockwise ring.
+    fn from(b: BoundingBox) -> Self {
+        let points = vec![
+            Point {x: b.x_min, y: b.y_min},
+            Point {x: b.x_min, y: b.y_max},
+            Point {x: b.x_max, y: b.y_max},
+            Point {x: b.x_max, y: b.y_min},
+            Point {x: b.x_min, y: b.y_min},
+        ];
+        Shape::Polygon {bounding_box: b, parts: vec![0], points}
+    }
+}
+
+impl Shape {
+    /// Whether the shapes have at least one point in common.
+    pub fn intersects(&self, other: &Shape) -> bool {
+        intersects(&Prepared::new(self), &Prepared::new(other))
+    }
+
+    /// Whether the other shape lies entirely inside this one, and not only on its boundary. A
+    /// polygon doesn't contain a point on one of its rings, and a polyline doesn't contain its
+    /// end points.
+    pub fn contains(&self, other: &Shape) -> bool {
+        let (a, b) = (Prepared::new(self), Prepared::new(other));
+        covers(&a, &b) && interiors_intersect(&a, &b)
+    }
+
+    /// Whether this shape lies entirely inside the other one, the reverse of `contains`.
+    pub fn within(&self, other: &Shape) -> bool {
+        other.contains(self)
+    }
+
+    /// Whether the shapes meet only at their boundaries, like neighboring polygons sharing an edge.
+    pub fn touches(&self, other: &Shape) -> bool {
+        let (a, b) = (Prepared::new(self), Prepared::new(other));
+        intersects(&a, &b) && !interiors_intersect(&a, &b)
+    }
+
+    /// The smallest distance between the shapes, zero if they intersect. None if one of them has no
+    /// points.
+    pub fn distance(&self, other: &Shape) -> Option<f64> {
+        let (a, b) = (Prepared::new(self), Prepared::new(other));
+        if a.is_empty() || b.is_empty() {
+            return None;
+        }
+        if intersects(&a, &b) {
+            return Some(0f64);
+        }
+
+        // Segments which don't meet are closest at one of their end points
+        let mut result = f64::INFINITY;
+        for &(p, q) in &a.segments {
+            for &(r, s) in &b.segments {
+                result = result.min(geometry::point_segment_distance(p, r, s))
+                               .min(geometry::point_segment_distance(q, r, s))
+                               .min(geometry::point_segment_distance(r, p, q))
+                               .min(geometry::point_segment_distance(s, p, q));
+            }
+        }
+        Some(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::shape::{Shape, BoundingBox, Point};
+    use super::super::geometry::polygon;
+
+    fn line(points: &[(f64, f64)]) -> Shape {
+        Shape::PolyLine {bounding_box: BoundingBox::new(), parts: vec![0], points: points.iter().map(|&(x, y)| Point {x, y}).collect()}
+    }
+
+    fn point(x: f64, y: f64) -> Shape {
+        Shape::from(Point {x, y})
+    }
+
+    #[test]
+    fn test_polygon_with_hole() {
+        let square = polygon(&[
+            &[(0., 0.), (0., 10.), (10., 10.), (10., 0.), (0., 0.)],
+            &[(4., 4.), (6., 4.), (6., 6.), (4., 6.), (4., 4.)],
+        ]);
+
+        if !square.contains(&point(2., 2.)) || square.contains(&point(5., 5.)) || square.contains(&point(0., 5.)) {
+            panic!()
+        }
+        if !square.touches(&point(0., 5.)) || !square.touches(&point(4., 5.)) || square.touches(&point(2., 2.)) {
+            panic!()
+        }
+        if square.intersects(&point(5., 5.)) || square.distance(&point(5., 5.)) != Some(1.0) {
+            panic!()
+        }
+
+        // A box around the hole reaches beyond the polygon, a box next to it doesn't
+        let around = Shape::from(BoundingBox {x_min: 3., y_min: 3., x_max: 7., y_max: 7.});
+        let beside = Shape::from(BoundingBox {x_min: 1., y_min: 1., x_max: 3., y_max: 3.});
+        if square.contains(&around) || !around.intersects(&square) || !square.contains(&beside) || !beside.within(&square) {
+            panic!()
+        }
+    }
+
+    #[test]
+    fn test_neighbors() {
+        let left = Shape::from(BoundingBox {x_min: 0., y_min: 0., x_max: 1., y_max: 1.});
+        let right = Shape::from(BoundingBox {x_min: 1., y_min: 0., x_max: 2., y_max: 1.});
+        let overlapping = Shape::from(BoundingBox {x_min: 0.5, y_min: 0., x_max: 2., y_max: 1.});
+        if !left.touches(&right) || left.contains(&right) || !left.intersects(&right) {
+            panic!()
+        }
+        if left.touches(&overlapping) || !left.intersects(&overlapping) || !left.contains(&left) {
+            panic!()
+        }
+        let far = Shape::from(BoundingBox {x_min: 4., y_min: 5., x_max: 6., y_max: 6.});
+        if left.distance(&far) != Some(5.0) || left.intersects(&far) {
+            panic!()
+        }
+    }
+
+    #[test]
+    fn test_lines_and_points() {
+        let l = line(&[(0., 0.), (10., 0.)]);
+        if !l.contains(&point(5., 0.)) || l.contains(&point(0., 0.)) || !l.touches(&point(0., 0.)) {
+            panic!()
+        }
+        if !l.intersects(&line(&[(5., -1.), (5., 1.)])) || l.touches(&line(&[(5., -1.), (5., 1.)])) {
+            panic!()
+        }
+        if !l.touches(&line(&[(10., 0.), (10., 5.)])) || !l.contains(&line(&[(2., 0.), (8., 0.)])) {
+            panic!()
+        }
+        if l.distance(&line(&[(3., 2.), (8., 4.)])) != Some(2.0) {
+            panic!()
+        }
+
+        let square = Shape::from(BoundingBox {x_min: 0., y_min: 0., x_max: 10., y_max: 10.});
+        if !l.touches(&square) || l.within(&square) || !line(&[(1., 1.), (9., 9.)]).within(&square) {
+            panic!()
+        }
+
+        let points = Shape::MultiPoint {bounding_box: BoundingBox::new(), points: vec![Point {x: 1., y: 1.}, Point {x: 20., y: 20.}]};
+        if !points.intersects(&square) || points.within(&square) || Shape::NullShape.distance(&square).is_some() {
+            panic!()
+        }
+    }
+}
@@ -0,0 +1,324 @@
+//! Module for PRJ files
+//!
+//! A PRJ file holds the coordinate system of the main file as a single well-known text (WKT)
+//! definition, in the ESRI flavour of the OGC 01-009 syntax:
+//!
+//! ```text
+//! GEOGCS["GCS_WGS_1984",DATUM["D_WGS_1984",SPHEROID["WGS_1984",6378137,298.257223563]],...]
+//! ```
+//!
+//! The WKT is parsed into a tree of `WktNode`s, which `Projection` interprets.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::path::Path;
+
+use super::{Projection, WktNode, WktValue};
+use super::measure::Ellipsoid;
+
+/// The geographic coordinate system of WGS84, as ESRI writes it.
+const GCS_WGS_1984: &str = "GEOGCS[\"GCS_WGS_1984\",DATUM[\"D_WGS_1984\",SPHEROID[\"WGS_1984\",6378137,298.257223563]],\
+                            PRIMEM[\"Greenwich\",0],UNIT[\"Degree\",0.017453292519943295]]";
+
+/// Reads WKT from a string, one node after the other.
+struct WktParser<'a> {
+    text: &'a [u8],
+    position: usize,
+}
+
+impl<'a> WktParser<'a> {
+    fn error(&self, message: &str) -> Error {
+        Error::new(ErrorKind::Other, format!("{} at position {} of the WKT!", message, self.position))
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.position < self.text.len() && (self.text[self.position] as char).is_whitespace() {
+            self.position += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.text.get(self.position).cloned()
+    }
+
+    fn word(&mut self) -> String {
+        self.skip_whitespace();
+        let start = self.position;
+        while self.position < self.text.len() {
+            let c = self.text[self.position];
+            if !(c.is_ascii_alphanumeric() || c == b'_' || c == b'.' || c == b'-' || c == b'+') {
+                break;
+            }
+            self.position += 1;
+        }
+        String::from_utf8_lossy(&self.text[start..self.position]).to_string()
+    }
+
+    fn quoted(&mut self) -> Result<String, Error> {
+        // Skip the opening quote, a doubled quote stands for a quote
+        self.position += 1;
+        let mut result: Vec<u8> = vec![];
+        loop {
+            match self.text.get(self.position) {
+                None => return Err(self.error("Unterminated string")),
+                Some(&b'"') if self.text.get(self.position + 1) == Some(&b'"') => {
+                    result.push(b'"');
+                    self.position += 2;
+                },
+                Some(&b'"') => {
+                    self.position += 1;
+                    return Ok(String::from_utf8_lossy(&result).to_string());
+                },
+                Some(&c) => {
+                    result.push(c);
+                    self.position += 1;
+                },
+            }
+        }
+    }
+
+    fn node(&mut self, keyword: String) -> Result<WktNode, Error> {
+        let close = match self.peek() {
+            Some(b'[') => b']',
+            Some(b'(') => b')',
+            _ => return Err(self.error("Expected an opening bracket")),
+        };
+        self.position += 1;
+
+        let mut values: Vec<WktValue> = vec![];
+        loop {
+            let value = match self.peek() {
+                None => return Err(self.error("Unexpected end")),
+                Some(b'"') => WktValue::Text(self.quoted()?),
+                Some(_) => {
+                    let word = self.word();
+                    if word.is_empty() {
+                        return Err(self.error("Unexpected character"));
+                    }
+                    match self.peek() {
+                        Some(b'[') | Some(b'(') => WktValue::Node(self.node(word)?),
+                        _ => match word.parse::<f64>() {
+                            Ok(n) => WktValue::Number(n),
+                            Err(_) => WktValue::Keyword(word),
+                        },
+                    }
+                },
+            };
+            values.push(value);
+
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(c) if c == close => {
+                    self.position += 1;
+                    return Ok(WktNode {keyword, values});
+                },
+                _ => return Err(self.error("Expected a comma or a closing bracket")),
+            }
+        }
+    }
+}
+
+impl WktNode {
+    /// Parses a single WKT node, like a whole PRJ file.
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut parser = WktParser {text: text.as_bytes(), position: 0};
+        let keyword = parser.word();
+        if keyword.is_empty() {
+            return Err(parser.error("Expected a keyword"));
+        }
+
+        let result = parser.node(keyword)?;
+        if parser.peek().is_some() {
+            return Err(parser.error("Unexpected text after the end"));
+        }
+        Ok(result)
+    }
+
+    /// The nodes directly inside this one.
+    pub fn children(&self) -> Vec<&WktNode> {
+        self.values.iter().filter_map(|v| match *v {
+            WktValue::Node(ref n) => Some(n),
+            _ => None,
+        }).collect()
+    }
+
+    /// The first node directly inside this one with the given keyword, ignoring case.
+    pub fn child(&self, keyword: &str) -> Option<&WktNode> {
+        self.children().into_iter().find(|n| n.keyword.eq_ignore_ascii_case(keyword))
+    }
+
+    /// The first node with the given keyword, searching this node and everything inside it.
+    pub fn find(&self, keyword: &str) -> Option<&WktNode> {
+        if self.keyword.eq_ignore_ascii_case(keyword) {
+            return Some(self);
+        }
+        self.children().into_iter().filter_map(|n| n.find(keyword)).next()
+    }
+
+    /// The text at the given position, like the name which comes first in most nodes.
+    pub fn text(&self, index: usize) -> Option<&str> {
+        match self.values.get(index) {
+            Some(&WktValue::Text(ref s)) | Some(&WktValue::Keyword(ref s)) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// The number at the given position.
+    pub fn number(&self, index: usize) -> Option<f64> {
+        match self.values.get(index) {
+            Some(&WktValue::Number(n)) => Some(n),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for WktNode {
+    /// Writes the node as WKT, with square brackets and without whitespace.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}[", self.keyword)?;
+        for (i, value) in self.values.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            match *value {
+                WktValue::Text(ref s) => write!(f, "\"{}\"", s.replace('"', "\"\""))?,
+                WktValue::Number(n) => write!(f, "{}", n)?,
+                WktValue::Keyword(ref s) => write!(f, "{}", s)?,
+                WktValue::Node(ref n) => write!(f, "{}", n)?,
+            }
+        }
+        write!(f, "]")
+    }
+}
+
+impl fmt::Display for Projection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.wkt)
+    }
+}
+
+impl Projection {
+    /// Longitude and latitude in degrees on WGS84.
+    pub fn wgs84() -> Self {
+        // The WKT is ours, so it always parses
+        Self::parse(GCS_WGS_1984).unwrap()
+    }
+
+    /// The spherical Mercator projection of web maps, in meters.
+    pub fn web_mercator() -> Self {
+        Self::parse(&format!("PROJCS[\"WGS_1984_Web_Mercator_Auxiliary_Sphere\",{},PROJECTION[\"Mercator_Auxiliary_Sphere\"],\
+                              PARAMETER[\"False_Easting\",0],PARAMETER[\"False_Northing\",0],PARAMETER[\"Central_Meridian\",0],\
+                              PARAMETER[\"Standard_Parallel_1\",0],PARAMETER[\"Auxiliary_Sphere_Type\",0],UNIT[\"Meter\",1]]",
+                             GCS_WGS_1984)).unwrap()
+    }
+
+    /// A zone of the Universal Transverse Mercator projection on WGS84, in meters. The zones are
+    /// numbered from 1 to 60, starting at 180 degrees west.
+    pub fn utm(zone: u32, north: bool) -> Result<Self, Error> {
+        if !(1..=60).contains(&zone) {
+            return Err(Error::new(ErrorKind::Other, "UTM zones are numbered from 1 to 60!"));
+        }
+
+        let central_meridian = zone as f64 * 6f64 - 183f64;
+        let (hemisphere, false_northing) = if north { ('N', 0) } else { ('S', 10000000) };
+        Self::parse(&format!("PROJCS[\"WGS_1984_UTM_Zone_{}{}\",{},PROJECTION[\"Transverse_Mercator\"],\
+                              PARAMETER[\"False_Easting\",500000],PARAMETER[\"False_Northing\",{}],\
+                              PARAMETER[\"Central_Meridian\",{}],PARAMETER[\"Scale_Factor\",0.9996],\
+                              PARAMETER[\"Latitude_Of_Origin\",0],UNIT[\"Meter\",1]]",
+                             zone, hemisphere, GCS_WGS_1984, false_northing, central_meridian))
+    }
+
+    /// Parses the WKT definition of a coordinate system.
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let wkt = WktNode::parse(text.trim_start_matches('\u{feff}'))?;
+        match wkt.keyword.to_ascii_uppercase().as_str() {
+            "GEOGCS" | "PROJCS" => Ok(Projection {wkt}),
+            _ => Err(Error::new(ErrorKind::Other, format!("PRJ file starts with unsupported `{}`!", wkt.keyword))),
+        }
+    }
+
+    /// Given a file name, parses the PRJ file.
+    pub fn parse_file(path: &Path) -> Result<Self, Error> {
+        let mut text = String::new();
+        File::open(path)?.read_to_string(&mut text)?;
+        Self::parse(&text)
+    }
+
+    /// Writes the WKT into a PRJ file.
+    pub fn write_file(&self, path: &Path) -> Result<(), Error> {
+        let mut file = File::create(path)?;
+        file.write_all(self.to_string().as_bytes())
+    }
+
+    /// The name of the coordinate system.
+    pub fn name(&self) -> Option<&str> {
+        self.wkt.text(0)
+    }
+
+    /// Whether the coordinates are longitude and latitude, rather than projected.
+    pub fn is_geographic(&self) -> bool {
+        self.wkt.keyword.eq_ignore_ascii_case("GEOGCS")
+    }
+
+    /// The semi-major axis in meters and the inverse flattening of the ellipsoid.
+    pub fn spheroid(&self) -> Option<(f64, f64)> {
+        let spheroid = self.wkt.find("SPHEROID").or_else(|| self.wkt.find("ELLIPSOID"))?;
+        Some((spheroid.number(1)?, spheroid.number(2)?))
+    }
+
+    /// The ellipsoid of the coordinate system, if the WKT defines one.
+    pub fn ellipsoid(&self) -> Option<Ellipsoid> {
+        self.spheroid().map(|(a, inverse_flattening)| Ellipsoid::from_inverse_flattening(a, inverse_flattening))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use super::super::{Projection, WktNode, WktValue};
+
+    #[test]
+    fn test_parse_prj() {
+        let projection = Projection::parse_file(Path::new("assets/test.prj")).unwrap();
+        if !projection.is_geographic() || projection.name() != Some("GCS_WGS_1984") {
+            panic!()
+        }
+        if projection.spheroid() != Some((6378137.0, 298.257223563)) {
+            panic!()
+        }
+        if projection.wkt.child("UNIT").and_then(|u| u.number(1)) != Some(0.017453292519943295) {
+            panic!()
+        }
+    }
+
+    #[test]
+    fn test_write_wkt() {
+        let text = r#"GEOGCS["GCS_WGS_1984",DATUM["D_WGS_1984",SPHEROID["WGS_1984",6378137,298.257223563]],PRIMEM["Greenwich",0],UNIT["Degree",0.017453292519943295]]"#;
+        if Projection::wgs84().to_string() != text || Projection::parse(text).unwrap() != Projection::wgs84() {
+            panic!()
+        }
+
+        let utm = Projection::utm(32, false).unwrap();
+        if utm.name() != Some("WGS_1984_UTM_Zone_32S") || Projection::parse(&utm.to_string()).unwrap() != utm {
+            panic!()
+        }
+        if Projection::utm(61, true).is_ok() {
+            panic!()
+        }
+    }
+
+    #[test]
+    fn test_parse_wkt() {
+        let node = WktNode::parse(r#"AXIS["Say ""north""", NORTH]"#).unwrap();
+        let expected = vec![WktValue::Text(String::from("Say \"north\"")), WktValue::Keyword(String::from("NORTH"))];
+        if node.keyword != "AXIS" || node.values != expected {
+            panic!()
+        }
+
+        if WktNode::parse("UNIT[\"Meter\",1").is_ok() || WktNode::parse("UNIT[\"Meter\",1]]").is_ok() {
+            panic!()
+        }
+    }
+}
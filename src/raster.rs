@@ -0,0 +1,402 @@
+//! Module for rendering layers into pictures
+//!
+//! The rasterizer goes through the picture row by row. Every row is crossed by a few scanlines,
+//! and the edges crossing a scanline give the spans inside the shape, by the even-odd rule for
+//! polygons, as in SVG. The spans add up to the coverage of every pixel, counted exactly along the
+//! row, which smooths the edges. Lines and outlines are filled as quads around every segment with
+//! a circle at every vertex, by the nonzero rule, so that the overlaps don't cancel out.
+//!
+//! Like in SVG, north is up, and multipatches aren't drawn. `write_thumbnails` draws every layer
+//! of a directory into a PNG file.
+
+use std::f64::consts::PI;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use super::{Canvas, Shapefile};
+use super::geometry;
+use super::shape::{Shape, BoundingBox, Point};
+use super::style::{self, Color, RenderOptions, Style, StyledShape};
+
+/// The number of scanlines per row of pixels.
+const SCANLINES: usize = 5;
+
+/// An edge of a filled path, going down.
+#[derive(Debug, Clone, Copy)]
+struct Edge {
+    x0: f64,
+    y0: f64,
+    x1: f64,
+    y1: f64,
+    /// 1 if the edge went down originally, -1 if it went up
+    winding: i32,
+}
+
+/// Adds the coverage of the span from a to b to the row.
+fn add_span(coverage: &mut [f32], a: f64, b: f64, weight: f32) {
+    let width = coverage.len() as f64;
+    let (a, b) = (a.max(0f64), b.min(width));
+    if b <= a {
+        return;
+    }
+    let (first, last) = (a.floor() as usize, b.floor() as usize);
+    if first == last {
+        coverage[first] += (b - a) as f32 * weight;
+        return;
+    }
+    coverage[first] += (first as f64 + 1f64 - a) as f32 * weight;
+    for c in &mut coverage[first + 1..last] {
+        *c += weight;
+    }
+    if last < coverage.len() {
+        coverage[last] += (b - last as f64) as f32 * weight;
+    }
+}
+
+/// A ring of points around a circle, clockwise on the screen like the quads of strokes.
+fn circle(center: (f64, f64), radius: f64) -> Vec<(f64, f64)> {
+    let count = ((radius * 4f64).ceil() as usize).clamp(8, 64);
+    (0..count).map(|i| {
+        let angle = -2f64 * PI * i as f64 / count as f64;
+        (center.0 + radius * angle.cos(), center.1 + radius * angle.sin())
+    }).collect()
+}
+
+impl Canvas {
+    /// A picture filled with the given color. Both sides are at least one pixel.
+    pub fn new(width: u32, height: u32, background: Color) -> Self {
+        let (width, height) = (width.max(1), height.max(1));
+        let pixels = [background.r, background.g, background.b, background.a].repeat(width as usize * height as usize);
+        Canvas {width, height, pixels}
+    }
+
+    /// The width in pixels.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// The height in pixels.
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// The RGBA values of all pixels, row by row from the top.
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    /// The color of a pixel, None outside the picture.
+    pub fn pixel(&self, x: u32, y: u32) -> Option<Color> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        let i = (y as usize * self.width as usize + x as usize) * 4;
+        Some(Color::rgba(self.pixels[i], self.pixels[i + 1], self.pixels[i + 2], self.pixels[i + 3]))
+    }
+
+    /// Sets the color of a pixel, without blending. Pixels outside the picture are ignored.
+    pub fn set_pixel(&mut self, x: u32, y: u32, color: Color) {
+        if x < self.width && y < self.height {
+            let i = (y as usize * self.width as usize + x as usize) * 4;
+            self.pixels[i..i + 4].copy_from_slice(&[color.r, color.g, color.b, color.a]);
+        }
+    }
+
+    /// Paints the color over a pixel, with the given share of its opacity.
+    fn blend(&mut self, x: usize, y: usize, color: Color, coverage: f32) {
+        let i = (y * self.width as usize + x) * 4;
+        let alpha = f32::from(color.a) / 255f32 * coverage.min(1f32);
+        let below = f32::from(self.pixels[i + 3]) / 255f32;
+        let result = alpha + below * (1f32 - alpha);
+        if result <= 0f32 {
+            return;
+        }
+        for (c, &source) in [color.r, color.g, color.b].iter().enumerate() {
+            let mixed = (f32::from(source) * alpha + f32::from(self.pixels[i + c]) * below * (1f32 - alpha)) / result;
+            self.pixels[i + c] = mixed.round() as u8;
+        }
+        self.pixels[i + 3] = (result * 255f32).round() as u8;
+    }
+
+    /// Fills the area inside the rings, which are given in pixels. With the even-odd rule, areas
+    /// inside an even number of rings stay empty, otherwise those around which the rings don't
+    /// wind at all.
+    fn fill(&mut self, rings: &[Vec<(f64, f64)>], color: Color, even_odd: bool) {
+        let mut edges: Vec<Edge> = vec![];
+        for ring in rings {
+            for i in 0..ring.len() {
+                let (a, b) = (ring[i], ring[(i + 1) % ring.len()]);
+                if a.1 < b.1 {
+                    edges.push(Edge {x0: a.0, y0: a.1, x1: b.0, y1: b.1, winding: 1});
+                } else if a.1 > b.1 {
+                    edges.push(Edge {x0: b.0, y0: b.1, x1: a.0, y1: a.1, winding: -1});
+                }
+            }
+        }
+        if edges.is_empty() || color.a == 0 {
+            return;
+        }
+        edges.sort_by(|a, b| a.y0.partial_cmp(&b.y0).unwrap_or(::std::cmp::Ordering::Equal));
+
+        let top = edges[0].y0.floor().max(0f64) as usize;
+        let bottom = (edges.iter().fold(f64::NEG_INFINITY, |m, e| m.max(e.y1)).ceil().max(0f64) as usize).min(self.height as usize);
+        let mut next = 0;
+        let mut active: Vec<Edge> = vec![];
+        let mut coverage = vec![0f32; self.width as usize];
+        let mut crossings: Vec<(f64, i32)> = vec![];
+
+        for y in top..bottom {
+            while next < edges.len() && edges[next].y0 < (y + 1) as f64 {
+                active.push(edges[next]);
+                next += 1;
+            }
+            active.retain(|e| e.y1 > y as f64);
+            if active.is_empty() {
+                continue;
+            }
+
+            for c in coverage.iter_mut() {
+                *c = 0f32;
+            }
+            for s in 0..SCANLINES {
+                let scanline = y as f64 + (s as f64 + 0.5f64) / SCANLINES as f64;
+                crossings.clear();
+                crossings.extend(active.iter().filter(|e| e.y0 <= scanline && scanline < e.y1).map(|e| {
+                    (e.x0 + (scanline - e.y0) / (e.y1 - e.y0) * (e.x1 - e.x0), e.winding)
+                }));
+                crossings.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(::std::cmp::Ordering::Equal));
+
+                let mut winding = 0;
+                let mut start = 0f64;
+                for &(x, direction) in &crossings {
+                    let was_inside = if even_odd { winding % 2 == 1 } else { winding != 0 };
+                    winding += if even_odd { 1 } else { direction };
+                    let inside = if even_odd { winding % 2 == 1 } else { winding != 0 };
+                    if inside && !was_inside {
+                        start = x;
+                    } else if was_inside && !inside {
+                        add_span(&mut coverage, start, x, 1f32 / SCANLINES as f32);
+                    }
+                }
+            }
+
+            for (x, &c) in coverage.iter().enumerate() {
+                if c > 0f32 {
+                    self.blend(x, y, color, c);
+                }
+            }
+        }
+    }
+
+    /// Fills the polygon given by its rings in pixels, by the even-odd rule.
+    pub fn fill_polygon(&mut self, rings: &[Vec<(f64, f64)>], color: Color) {
+        self.fill(rings, color, true);
+    }
+
+    /// Draws lines through the points, which are given in pixels, with round joins and ends.
+    pub fn stroke_lines(&mut self, lines: &[Vec<(f64, f64)>], closed: bool, width: f64, color: Color) {
+        let radius = width / 2f64;
+        if radius <= 0f64 {
+            return;
+        }
+
+        let mut shapes: Vec<Vec<(f64, f64)>> = vec![];
+        for line in lines {
+            let count = if closed { line.len() } else { line.len().saturating_sub(1) };
+            for i in 0..count {
+                let (a, b) = (line[i], line[(i + 1) % line.len()]);
+                let length = (b.0 - a.0).hypot(b.1 - a.1);
+                if length == 0f64 {
+                    continue;
+                }
+                let (nx, ny) = (-(b.1 - a.1) / length * radius, (b.0 - a.0) / length * radius);
+                shapes.push(vec![(a.0 + nx, a.1 + ny), (b.0 + nx, b.1 + ny), (b.0 - nx, b.1 - ny), (a.0 - nx, a.1 - ny)]);
+            }
+            shapes.extend(line.iter().map(|&p| circle(p, radius)));
+        }
+        self.fill(&shapes, color, false);
+    }
+
+    /// Draws a disk, which is given in pixels.
+    pub fn fill_circle(&mut self, center: (f64, f64), radius: f64, color: Color) {
+        self.fill(&[circle(center, radius)], color, false);
+    }
+}
+
+/// Draws the shapes into the extent, onto a picture of the given width.
+fn render(shapes: &[StyledShape], extent: &BoundingBox, width: u32, style: &Style, background: Color) -> Canvas {
+    let scale = f64::from(width) / (extent.x_max - extent.x_min);
+    let height = ((extent.y_max - extent.y_min) * scale).round().max(1f64) as u32;
+    let to_pixels = |p: Point| ((p.x - extent.x_min) * scale, (extent.y_max - p.y) * scale);
+    let mut canvas = Canvas::new(width, height, background);
+
+    for s in shapes {
+        match s.shape {
+            Shape::Polygon {..} | Shape::PolygonM {..} | Shape::PolygonZ {..} |
+            Shape::PolyLine {..} | Shape::PolyLineM {..} | Shape::PolyLineZ {..} => {
+                let parts: Vec<Vec<(f64, f64)>> = geometry::parts_of(&s.shape).unwrap_or_default().iter()
+                                                          .map(|p| p.iter().map(|&q| to_pixels(q)).collect())
+                                                          .collect();
+                let polygon = matches!(s.shape, Shape::Polygon {..} | Shape::PolygonM {..} | Shape::PolygonZ {..});
+                if polygon {
+                    canvas.fill_polygon(&parts, s.fill);
+                }
+                canvas.stroke_lines(&parts, polygon, style.stroke_width, s.stroke);
+            },
+            Shape::Point {..} | Shape::PointM {..} | Shape::PointZ {..} |
+            Shape::MultiPoint {..} | Shape::MultiPointM {..} | Shape::MultiPointZ {..} => {
+                for v in geometry::vertex_parts(&s.shape).into_iter().flatten() {
+                    let center = to_pixels(v.point);
+                    canvas.fill_circle(center, style.point_radius, s.fill);
+                    canvas.stroke_lines(&[circle(center, style.point_radius)], true, style.stroke_width, s.stroke);
+                }
+            },
+            Shape::MultiPatch {..} | Shape::NullShape => (),
+        }
+    }
+    canvas
+}
+
+impl Shapefile {
+    /// Draws the layer, or the shapes overlapping the extent of the options, into a picture.
+    pub fn render(&mut self, options: &RenderOptions) -> Result<Canvas, Error> {
+        let extent = style::drawing_extent(self, options.extent.as_ref());
+        let shapes = options.style.apply(self, options.extent.as_ref())?;
+        Ok(render(&shapes, &extent, options.width, &options.style, options.background.unwrap_or(Color::TRANSPARENT)))
+    }
+
+    /// Draws the layer into a PNG file.
+    pub fn write_png(&mut self, path: &Path, options: &RenderOptions) -> Result<(), Error> {
+        self.render(options)?.write_png(path)
+    }
+}
+
+/// A layer of `write_thumbnails`, with the path of its picture or the error which kept it from
+/// being drawn.
+pub type Thumbnail = (PathBuf, Result<PathBuf, Error>);
+
+/// Draws a layer into a PNG file of the same name in the output directory.
+fn write_thumbnail(shp: &Path, output: &Path, size: u32, style: &Style) -> Result<PathBuf, Error> {
+    let mut shapefile = Shapefile::new(shp, &shp.with_extension("shx"), &shp.with_extension("dbf"))?;
+
+    let extent = style::drawing_extent(&shapefile, None);
+    let (width, height) = (extent.x_max - extent.x_min, extent.y_max - extent.y_min);
+    let width = if width >= height { size } else { (f64::from(size) * width / height).round().max(1f64) as u32 };
+
+    let name = shp.file_stem().ok_or_else(|| Error::new(ErrorKind::Other, "Layer without a name!"))?;
+    let path = output.join(format!("{}.png", name.to_string_lossy()));
+    shapefile.write_png(&path, &RenderOptions::new(width).style(style.clone()))?;
+    Ok(path)
+}
+
+/// Draws every layer in the directory into a PNG file of the same name in the output directory,
+/// whose longer side is `size` pixels. Layers without an SHX file are read without it. Returns
+/// every SHP file in the order of the layer names, so that one broken layer doesn't stop the
+/// others.
+pub fn write_thumbnails(directory: &Path, output: &Path, size: u32, style: &Style) -> Result<Vec<Thumbnail>, Error> {
+    let mut layers: Vec<PathBuf> = fs::read_dir(directory)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|e| e.eq_ignore_ascii_case("shp")))
+        .collect();
+    layers.sort();
+    fs::create_dir_all(output)?;
+
+    Ok(layers.into_iter().map(|shp| {
+        let result = write_thumbnail(&shp, output, size, style);
+        (shp, result)
+    }).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::env;
+    use std::fs;
+    use super::{render, write_thumbnails};
+    use super::super::{Canvas, ShapefileWriter};
+    use super::super::shape::{Shape, BoundingBox, Point};
+    use super::super::style::{Color, Style, StyledShape};
+
+    #[test]
+    fn test_fill() {
+        // A square with a hole, with edges halfway through pixels on the left
+        let mut canvas = Canvas::new(20, 20, Color::WHITE);
+        let square = vec![(2.5, 2.), (2.5, 18.), (18., 18.), (18., 2.)];
+        let hole = vec![(8., 8.), (12., 8.), (12., 12.), (8., 12.)];
+        canvas.fill_polygon(&[square, hole], Color::BLACK);
+
+        if canvas.pixel(5, 5) != Some(Color::BLACK) || canvas.pixel(10, 10) != Some(Color::WHITE) || canvas.pixel(1, 10) != Some(Color::WHITE) {
+            panic!("{:?}", canvas.pixel(10, 10))
+        }
+        if canvas.pixel(2, 10) != Some(Color::rgb(128, 128, 128)) || canvas.pixel(20, 0).is_some() {
+            panic!("{:?}", canvas.pixel(2, 10))
+        }
+    }
+
+    #[test]
+    fn test_stroke() {
+        // A line crossing itself, half transparent, is as dark where it crosses as elsewhere
+        let mut canvas = Canvas::new(20, 20, Color::TRANSPARENT);
+        canvas.stroke_lines(&[vec![(0., 10.), (20., 10.), (20., 0.), (10., 0.), (10., 20.)]], false, 2., Color::rgba(0, 0, 255, 128));
+        if canvas.pixel(10, 9) != canvas.pixel(5, 9) || canvas.pixel(5, 9) != Some(Color::rgba(0, 0, 255, 128)) || canvas.pixel(5, 5) != Some(Color::TRANSPARENT) {
+            panic!("{:?} {:?}", canvas.pixel(10, 9), canvas.pixel(5, 9))
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let style = Style::new().stroke(Color::BLACK, 2.);
+        let shapes = vec![
+            StyledShape {shape: Shape::from(BoundingBox {x_min: 0., y_min: 0., x_max: 50., y_max: 50.}), fill: Color::rgb(255, 0, 0), stroke: Color::BLACK},
+            StyledShape {shape: Shape::Point {point: Point {x: 75., y: 25.}}, fill: Color::rgb(0, 255, 0), stroke: Color::BLACK},
+        ];
+        let canvas = render(&shapes, &BoundingBox {x_min: 0., y_min: 0., x_max: 100., y_max: 50.}, 200, &style, Color::WHITE);
+        if canvas.width() != 200 || canvas.height() != 100 || canvas.pixel(50, 50) != Some(Color::rgb(255, 0, 0)) {
+            panic!()
+        }
+        if canvas.pixel(150, 50) != Some(Color::rgb(0, 255, 0)) || canvas.pixel(150, 10) != Some(Color::WHITE) || canvas.pixel(100, 50) != Some(Color::BLACK) {
+            panic!("{:?}", canvas.pixel(100, 50))
+        }
+    }
+
+    #[test]
+    fn test_write_thumbnails() {
+        let directory = env::temp_dir().join("shapefile-utils-test-thumbnails");
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+
+        // A layer twice as tall as wide
+        let mut writer = ShapefileWriter::new(&directory.join("tall.shp"), &directory.join("tall.shx"), &directory.join("tall.dbf")).unwrap();
+        writer.write(&Shape::from(BoundingBox {x_min: 0., y_min: 0., x_max: 10., y_max: 20.}), &HashMap::new()).unwrap();
+        writer.finish().unwrap();
+
+        // Two versions of a layer, whose names only differ after a dot
+        for name in ["roads.v1", "roads.v2"].iter() {
+            let mut writer = ShapefileWriter::new(&directory.join(format!("{}.shp", name)), &directory.join(format!("{}.shx", name)), &directory.join(format!("{}.dbf", name))).unwrap();
+            writer.write(&Shape::from(BoundingBox {x_min: 0., y_min: 0., x_max: 10., y_max: 10.}), &HashMap::new()).unwrap();
+            writer.finish().unwrap();
+        }
+
+        // A layer which isn't one
+        fs::write(directory.join("broken.shp"), b"not a shapefile").unwrap();
+
+        let written = write_thumbnails(&directory, &directory.join("thumbnails"), 64, &Style::new()).unwrap();
+        if written.len() != 4 || written[0].0 != directory.join("broken.shp") || written[0].1.is_ok() || written[3].0 != directory.join("tall.shp") {
+            panic!("{:?}", written)
+        }
+        for name in ["roads.v1", "roads.v2"].iter() {
+            if !directory.join("thumbnails").join(format!("{}.png", name)).exists() {
+                panic!("{}", name)
+            }
+        }
+        let path = written[3].1.as_ref().unwrap();
+        if *path != directory.join("thumbnails").join("tall.png") || directory.join("thumbnails").join("broken.png").exists() {
+            panic!("{:?}", path)
+        }
+        let png = fs::read(path).unwrap();
+        if png[16..24] != [0, 0, 0, 32, 0, 0, 0, 64] {
+            panic!("{:?}", &png[16..24])
+        }
+    }
+}
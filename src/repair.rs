@@ -0,0 +1,289 @@
+//! Module for repairing shapes
+//!
+//! `Shape::repaired` fixes the common problems found by `Shape::validate`: Repeated vertices are
+//! removed, rings are closed, parts with too few points or without area are dropped, rings are
+//! wound the way the spec wants them, and the bounding box and the Z and M ranges are computed
+//! from the points. Self-intersecting rings can optionally be split into simple rings.
+
+use std::io::Error;
+use std::path::Path;
+
+use super::Shapefile;
+use super::geometry::{self, SegmentIntersection, Vertex};
+use super::shape::{Shape, PatchType, Point};
+
+/// The upper limit for splits of a single ring, as a safety net against rings that can't be untangled.
+const MAX_SPLITS: usize = 1000;
+
+/// Options for `Shape::repaired_with`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct RepairOptions {
+    /// Whether rings which intersect themselves are split at the intersections into separate rings.
+    pub split_self_intersections: bool,
+}
+
+impl RepairOptions {
+    /// The default options: Self-intersecting rings are left alone.
+    pub fn new() -> Self {
+        RepairOptions {split_self_intersections: false}
+    }
+
+    /// Sets whether self-intersecting rings are split.
+    pub fn split_self_intersections(mut self, split: bool) -> Self {
+        self.split_self_intersections = split;
+        self
+    }
+}
+
+impl Default for RepairOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shape {
+    /// Returns a repaired copy of the shape, using the default options.
+    pub fn repaired(&self) -> Shape {
+        self.repaired_with(&RepairOptions::new())
+    }
+
+    /// Returns a repaired copy of the shape. A shape with no usable parts left becomes a null shape.
+    pub fn repaired_with(&self, options: &RepairOptions) -> Shape {
+        let parts = geometry::vertex_parts(self);
+
+        let parts = match *self {
+            Shape::NullShape => return Shape::NullShape,
+            Shape::PolyLine {..} | Shape::PolyLineM {..} | Shape::PolyLineZ {..} => {
+                parts.into_iter().map(without_repeated).filter(|p| p.len() >= 2).collect()
+            },
+            Shape::Polygon {..} | Shape::PolygonM {..} | Shape::PolygonZ {..} => {
+                let mut rings: Vec<Vec<Vertex>> = vec![];
+                for part in parts {
+                    let ring = closed(without_repeated(part));
+                    if options.split_self_intersections {
+                        rings.extend(split_ring(ring));
+                    } else {
+                        rings.push(ring);
+                    }
+                }
+                rings.retain(|r| r.len() >= 4 && geometry::signed_area(&points_of(r)) != 0f64);
+                oriented(rings)
+            },
+            Shape::MultiPatch {ref part_types, ..} => {
+                // Parts are kept, so that they still match their patch types
+                parts.into_iter().enumerate().map(|(i, p)| match part_types.get(i) {
+                    Some(&PatchType::TriangleStrip) | Some(&PatchType::TriangleFan) => without_repeated(p),
+                    _ => closed(without_repeated(p)),
+                }).collect()
+            },
+            _ => parts,
+        };
+
+        geometry::from_vertex_parts(self, parts)
+    }
+}
+
+impl Shapefile {
+    /// Writes a repaired copy of the layer into a new shapefile, with all attributes. The files
+    /// must be different from the ones being read. Returns the number of shapes which changed.
+    pub fn repair_into(&mut self, shp_path: &Path, shx_path: &Path, dbf_path: &Path, options: &RepairOptions) -> Result<u64, Error> {
+        let mut changed = 0u64;
+        self.rewrite(shp_path, shx_path, dbf_path, |shape| {
+            let repaired = shape.repaired_with(options);
+            if repaired != shape {
+                changed += 1;
+            }
+            Ok(Some(repaired))
+        })?;
+        Ok(changed)
+    }
+}
+
+fn points_of(vertices: &[Vertex]) -> Vec<Point> {
+    vertices.iter().map(|v| v.point).collect()
+}
+
+/// Removes vertices which are at the same place as their predecessor.
+fn without_repeated(part: Vec<Vertex>) -> Vec<Vertex> {
+    let mut result: Vec<Vertex> = Vec::with_capacity(part.len());
+    for v in part {
+        if result.last().is_none_or(|l| l.point != v.point) {
+            result.push(v);
+        }
+    }
+    result
+}
+
+/// Appends the first vertex if the ring doesn't end there.
+fn closed(mut ring: Vec<Vertex>) -> Vec<Vertex> {
+    if let (Some(&first), Some(last)) = (ring.first(), ring.last()) {
+        if first.point != last.point {
+            ring.push(first);
+        }
+    }
+    ring
+}
+
+/// Winds outer rings clockwise and holes counterclockwise. A ring is a hole if an odd number of
+/// other rings contains it.
+fn oriented(mut rings: Vec<Vec<Vertex>>) -> Vec<Vec<Vertex>> {
+    let points: Vec<Vec<Point>> = rings.iter().map(|r| points_of(r)).collect();
+
+    for i in 0..rings.len() {
+        let depth = (0..points.len()).filter(|&j| j != i && geometry::ring_in_ring(&points[i], &points[j])).count();
+        let clockwise = geometry::signed_area(&points[i]) < 0f64;
+        if depth.is_multiple_of(2) != clockwise {
+            rings[i].reverse();
+        }
+    }
+    rings
+}
+
+/// The vertex where the segment from a to b meets the segment from c to d, with interpolated Z and
+/// M values.
+fn meeting_point(a: Vertex, b: Vertex, c: Point, d: Point, kind: SegmentIntersection) -> Vertex {
+    let t = match kind {
+        SegmentIntersection::Proper => {
+            let da = geometry::cross(c, d, a.point);
+            let db = geometry::cross(c, d, b.point);
+            da / (da - db)
+        },
+        _ => {
+            // One end point lies on the other segment
+            if geometry::on_segment(a.point, c, d) {
+                0f64
+            } else if geometry::on_segment(b.point, c, d) {
+                1f64
+            } else {
+                let p = if geometry::on_segment(c, a.point, b.point) { c } else { d };
+                let length = (b.point.x - a.point.x).hypot(b.point.y - a.point.y);
+                (p.x - a.point.x).hypot(p.y - a.point.y) / length
+            }
+        },
+    };
+
+    geometry::interpolate(a, b, t)
+}
+
+/// Splits a closed ring without repeated vertices at the places where it meets itself, until all
+/// pieces are simple rings.
+fn split_ring(ring: Vec<Vertex>) -> Vec<Vec<Vertex>> {
+    let mut result: Vec<Vec<Vertex>> = vec![];
+    let mut pending: Vec<Vec<Vertex>> = vec![ring];
+    let mut splits = 0usize;
+
+    while let Some(ring) = pending.pop() {
+        let points = points_of(&ring);
+        let mut segments = geometry::segments(&[&points]);
+        let mut found: Option<(usize, usize, SegmentIntersection)> = None;
+
+        geometry::for_each_candidate_pair(&mut segments, |s, t| {
+            if geometry::adjacent(s, t, true) {
+                return;
+            }
+            let kind = geometry::segment_intersection(s.a, s.b, t.a, t.b);
+            if kind != SegmentIntersection::None {
+                let pair = (s.index.min(t.index), s.index.max(t.index), kind);
+                if found.is_none_or(|f| (pair.0, pair.1) < (f.0, f.1)) {
+                    found = Some(pair);
+                }
+            }
+        });
+
+        let (i, j, kind) = match found {
+            Some(f) if splits < MAX_SPLITS && ring.len() > 4 => f,
+            _ => {
+                result.push(ring);
+                continue;
+            },
+        };
+        splits += 1;
+
+        // The ring is cut at x into the loop from segment i + 1 to segment j, and the rest
+        let x = meeting_point(ring[i], ring[i + 1], ring[j].point, ring[j + 1].point, kind);
+
+        let mut outer: Vec<Vertex> = ring[..i + 1].to_vec();
+        outer.push(x);
+        outer.extend_from_slice(&ring[j + 1..]);
+
+        let mut inner: Vec<Vertex> = vec![x];
+        inner.extend_from_slice(&ring[i + 1..j + 1]);
+        inner.push(x);
+
+        for piece in [outer, inner] {
+            let piece = closed(without_repeated(piece));
+            if piece.len() >= 4 && piece.len() < ring.len() + 2 {
+                pending.push(piece);
+            }
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RepairOptions;
+    use super::super::shape::{Shape, BoundingBox, Point};
+    use super::super::validity::ShapeIssue;
+    use super::super::geometry::polygon;
+
+    #[test]
+    fn test_repair_rings() {
+        // Counterclockwise, not closed, with a repeated vertex, plus a degenerate ring
+        let shape = polygon(&[&[(0., 0.), (10., 0.), (10., 0.), (10., 10.), (0., 10.)], &[(0., 0.), (1., 1.), (0., 0.)]]);
+        let repaired = shape.repaired();
+
+        let expected = polygon(&[&[(0., 0.), (0., 10.), (10., 10.), (10., 0.), (0., 0.)]]);
+        if let Shape::Polygon {ref points, ref parts, bounding_box} = repaired {
+            if let Shape::Polygon {points: ref e, parts: ref p, ..} = expected {
+                if points != e || parts != p {
+                    panic!("{:?}", repaired)
+                }
+            }
+            if bounding_box != (BoundingBox {x_min: 0., y_min: 0., x_max: 10., y_max: 10.}) {
+                panic!()
+            }
+        } else {
+            panic!()
+        }
+        if !repaired.is_valid() {
+            panic!()
+        }
+    }
+
+    #[test]
+    fn test_split_self_intersection() {
+        let bowtie = polygon(&[&[(0., 0.), (10., 10.), (10., 0.), (0., 6.), (0., 0.)]]);
+        if !bowtie.repaired().validate().contains(&ShapeIssue::SelfIntersection {part: 0}) {
+            panic!()
+        }
+
+        let repaired = bowtie.repaired_with(&RepairOptions::new().split_self_intersections(true));
+        if let Shape::Polygon {ref parts, ..} = repaired {
+            if parts.len() != 2 {
+                panic!("{:?}", repaired)
+            }
+        }
+        if !repaired.is_valid() {
+            panic!("{:?}", repaired.validate())
+        }
+    }
+
+    #[test]
+    fn test_repair_polyline() {
+        let shape = Shape::PolyLine {
+            bounding_box: BoundingBox::new(),
+            parts: vec![0, 1, 3],
+            points: vec![Point {x: 5., y: 5.}, Point {x: 1., y: 1.}, Point {x: 1., y: 1.}, Point {x: 0., y: 0.}, Point {x: 2., y: 0.}],
+        };
+        let expected = Shape::PolyLine {
+            bounding_box: BoundingBox {x_min: 0., y_min: 0., x_max: 2., y_max: 0.},
+            parts: vec![0],
+            points: vec![Point {x: 0., y: 0.}, Point {x: 2., y: 0.}],
+        };
+        if shape.repaired() != expected {
+            panic!()
+        }
+    }
+}
@@ -0,0 +1,487 @@
+//! Serialization of user defined structs into records.
+//!
+//! This is the mirror of the `de` module: every struct field becomes a DBF column of the same
+//! name, except for the geometry field (`geometry` by default), which holds the `Shape`. Numbers,
+//! strings, `bool`s, `Date`s and `Option`s of those are supported as column values.
+//!
+//! # Example
+//!
+//! ```ignore
+//! #[derive(Serialize)]
+//! struct Road {
+//!     #[serde(rename = "osm_id")]
+//!     id: u64,
+//!     name: Option<String>,
+//!     geometry: Shape,
+//! }
+//!
+//! let mut writer = ShapefileWriter::new(&shp_path, &shx_path, &dbf_path).unwrap();
+//! writer.write_record(&road).unwrap();
+//! writer.finish().unwrap();
+//! ```
+
+use std::error;
+use std::fmt;
+use std::io::Cursor;
+
+use serde::ser::{self, Impossible, Serialize, SerializeMap, SerializeStruct};
+
+use super::{Date, DbfValue};
+use super::shape::Shape;
+
+/// Marker used by `Shape`'s `Serialize` implementation to recognize our serializer.
+const SHAPE_TOKEN: &str = "$shapefile_utils::Shape";
+
+/// Marker used by `Date`'s `Serialize` implementation to recognize our serializer.
+const DATE_TOKEN: &str = "$shapefile_utils::Date";
+
+/// An error that occurred while serializing a record.
+#[derive(Debug, PartialEq)]
+pub struct Error {
+    /// A description of the error
+    message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error {message: msg.to_string()}
+    }
+}
+
+/// Splits a struct or map into its shape and its DBF values, in field order. Records without a
+/// geometry field get the NullShape.
+pub(crate) fn to_values<T: Serialize>(value: &T, geometry_field: &str) -> Result<(Shape, Vec<(String, DbfValue)>), Error> {
+    let mut serializer = RecordSerializer {
+        geometry_field,
+        shape: Shape::NullShape,
+        values: vec![],
+        key: None,
+    };
+
+    value.serialize(&mut serializer)?;
+    Ok((serializer.shape, serializer.values))
+}
+
+/// Collects the fields of a struct or map.
+struct RecordSerializer<'a> {
+    /// The name of the field holding the shape
+    geometry_field: &'a str,
+    /// The shape found so far
+    shape: Shape,
+    /// The DBF values found so far
+    values: Vec<(String, DbfValue)>,
+    /// The most recent key of a map
+    key: Option<String>,
+}
+
+impl<'a> RecordSerializer<'a> {
+    /// Stores the value of a field.
+    fn add_field<T: ?Sized + Serialize>(&mut self, key: &str, value: &T) -> Result<(), Error> {
+        if key == self.geometry_field {
+            self.shape = value.serialize(GeometrySerializer)?;
+        } else {
+            self.values.push((key.to_string(), value.serialize(ValueSerializer)?));
+        }
+        Ok(())
+    }
+
+    /// The error for everything that is not a struct or map.
+    fn unsupported() -> Error {
+        ser::Error::custom("records can only be serialized from structs and maps")
+    }
+}
+
+impl<'a, 'b> ser::Serializer for &'b mut RecordSerializer<'a> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = Impossible<(), Error>;
+    type SerializeTuple = Impossible<(), Error>;
+    type SerializeTupleStruct = Impossible<(), Error>;
+    type SerializeTupleVariant = Impossible<(), Error>;
+    type SerializeMap = Self;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Impossible<(), Error>;
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self, Error> {
+        Ok(self)
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self, Error> {
+        Ok(self)
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(self, _name: &'static str, value: &T) -> Result<(), Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<(), Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_bool(self, _v: bool) -> Result<(), Error> { Err(RecordSerializer::unsupported()) }
+    fn serialize_i8(self, _v: i8) -> Result<(), Error> { Err(RecordSerializer::unsupported()) }
+    fn serialize_i16(self, _v: i16) -> Result<(), Error> { Err(RecordSerializer::unsupported()) }
+    fn serialize_i32(self, _v: i32) -> Result<(), Error> { Err(RecordSerializer::unsupported()) }
+    fn serialize_i64(self, _v: i64) -> Result<(), Error> { Err(RecordSerializer::unsupported()) }
+    fn serialize_u8(self, _v: u8) -> Result<(), Error> { Err(RecordSerializer::unsupported()) }
+    fn serialize_u16(self, _v: u16) -> Result<(), Error> { Err(RecordSerializer::unsupported()) }
+    fn serialize_u32(self, _v: u32) -> Result<(), Error> { Err(RecordSerializer::unsupported()) }
+    fn serialize_u64(self, _v: u64) -> Result<(), Error> { Err(RecordSerializer::unsupported()) }
+    fn serialize_f32(self, _v: f32) -> Result<(), Error> { Err(RecordSerializer::unsupported()) }
+    fn serialize_f64(self, _v: f64) -> Result<(), Error> { Err(RecordSerializer::unsupported()) }
+    fn serialize_char(self, _v: char) -> Result<(), Error> { Err(RecordSerializer::unsupported()) }
+    fn serialize_str(self, _v: &str) -> Result<(), Error> { Err(RecordSerializer::unsupported()) }
+    fn serialize_bytes(self, _v: &[u8]) -> Result<(), Error> { Err(RecordSerializer::unsupported()) }
+    fn serialize_none(self) -> Result<(), Error> { Err(RecordSerializer::unsupported()) }
+    fn serialize_unit(self) -> Result<(), Error> { Err(RecordSerializer::unsupported()) }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), Error> {
+        Err(RecordSerializer::unsupported())
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32, _variant: &'static str) -> Result<(), Error> {
+        Err(RecordSerializer::unsupported())
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(self, _name: &'static str, _index: u32, _variant: &'static str, _value: &T) -> Result<(), Error> {
+        Err(RecordSerializer::unsupported())
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Error> {
+        Err(RecordSerializer::unsupported())
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Error> {
+        Err(RecordSerializer::unsupported())
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeTupleStruct, Error> {
+        Err(RecordSerializer::unsupported())
+    }
+
+    fn serialize_tuple_variant(self, _name: &'static str, _index: u32, _variant: &'static str, _len: usize) -> Result<Self::SerializeTupleVariant, Error> {
+        Err(RecordSerializer::unsupported())
+    }
+
+    fn serialize_struct_variant(self, _name: &'static str, _index: u32, _variant: &'static str, _len: usize) -> Result<Self::SerializeStructVariant, Error> {
+        Err(RecordSerializer::unsupported())
+    }
+}
+
+impl<'a, 'b> SerializeStruct for &'b mut RecordSerializer<'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, key: &'static str, value: &T) -> Result<(), Error> {
+        self.add_field(key, value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl<'a, 'b> SerializeMap for &'b mut RecordSerializer<'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<(), Error> {
+        match key.serialize(ValueSerializer)? {
+            DbfValue::Character(s) => {
+                self.key = Some(s);
+                Ok(())
+            },
+            _ => Err(ser::Error::custom("map keys must be strings")),
+        }
+    }
+
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        match self.key.take() {
+            Some(k) => self.add_field(&k, value),
+            None => Err(ser::Error::custom("value serialized before key")),
+        }
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Turns a field value into a `DbfValue`.
+struct ValueSerializer;
+
+impl ValueSerializer {
+    /// The error for everything that doesn't fit into a DBF column.
+    fn unsupported() -> Error {
+        ser::Error::custom("only numbers, strings, bools and dates can be stored in DBF columns")
+    }
+}
+
+impl ser::Serializer for ValueSerializer {
+    type Ok = DbfValue;
+    type Error = Error;
+    type SerializeSeq = Impossible<DbfValue, Error>;
+    type SerializeTuple = Impossible<DbfValue, Error>;
+    type SerializeTupleStruct = Impossible<DbfValue, Error>;
+    type SerializeTupleVariant = Impossible<DbfValue, Error>;
+    type SerializeMap = Impossible<DbfValue, Error>;
+    type SerializeStruct = Impossible<DbfValue, Error>;
+    type SerializeStructVariant = Impossible<DbfValue, Error>;
+
+    fn serialize_bool(self, v: bool) -> Result<DbfValue, Error> {
+        Ok(DbfValue::Logical(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<DbfValue, Error> {
+        Ok(DbfValue::Integer(v as i64))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<DbfValue, Error> {
+        Ok(DbfValue::Integer(v as i64))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<DbfValue, Error> {
+        Ok(DbfValue::Integer(v as i64))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<DbfValue, Error> {
+        Ok(DbfValue::Integer(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<DbfValue, Error> {
+        Ok(DbfValue::Integer(v as i64))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<DbfValue, Error> {
+        Ok(DbfValue::Integer(v as i64))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<DbfValue, Error> {
+        Ok(DbfValue::Integer(v as i64))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<DbfValue, Error> {
+        if v > i64::MAX as u64 {
+            Ok(DbfValue::Float(v as f64))
+        } else {
+            Ok(DbfValue::Integer(v as i64))
+        }
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<DbfValue, Error> {
+        Ok(DbfValue::Float(v as f64))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<DbfValue, Error> {
+        Ok(DbfValue::Float(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<DbfValue, Error> {
+        Ok(DbfValue::Character(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<DbfValue, Error> {
+        Ok(DbfValue::Character(v.to_string()))
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<DbfValue, Error> {
+        Err(Self::unsupported())
+    }
+
+    fn serialize_none(self) -> Result<DbfValue, Error> {
+        Ok(DbfValue::Null)
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<DbfValue, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<DbfValue, Error> {
+        Ok(DbfValue::Null)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<DbfValue, Error> {
+        Ok(DbfValue::Null)
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32, variant: &'static str) -> Result<DbfValue, Error> {
+        Ok(DbfValue::Character(variant.to_string()))
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(self, name: &'static str, value: &T) -> Result<DbfValue, Error> {
+        match value.serialize(self)? {
+            DbfValue::Character(ref s) if name == DATE_TOKEN => {
+                Date::parse(s).map(DbfValue::Date).ok_or_else(|| ser::Error::custom("invalid date"))
+            },
+            v => Ok(v),
+        }
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(self, _name: &'static str, _index: u32, _variant: &'static str, _value: &T) -> Result<DbfValue, Error> {
+        Err(Self::unsupported())
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Error> {
+        Err(Self::unsupported())
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Error> {
+        Err(Self::unsupported())
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeTupleStruct, Error> {
+        Err(Self::unsupported())
+    }
+
+    fn serialize_tuple_variant(self, _name: &'static str, _index: u32, _variant: &'static str, _len: usize) -> Result<Self::SerializeTupleVariant, Error> {
+        Err(Self::unsupported())
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Error> {
+        Err(Self::unsupported())
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct, Error> {
+        Err(Self::unsupported())
+    }
+
+    fn serialize_struct_variant(self, _name: &'static str, _index: u32, _variant: &'static str, _len: usize) -> Result<Self::SerializeStructVariant, Error> {
+        Err(Self::unsupported())
+    }
+}
+
+/// Turns the geometry field back into a `Shape`.
+struct GeometrySerializer;
+
+impl GeometrySerializer {
+    /// The error for everything that is not a shape.
+    fn unsupported() -> Error {
+        ser::Error::custom("the geometry field must be a `Shape` or an `Option<Shape>`")
+    }
+}
+
+impl ser::Serializer for GeometrySerializer {
+    type Ok = Shape;
+    type Error = Error;
+    type SerializeSeq = Impossible<Shape, Error>;
+    type SerializeTuple = Impossible<Shape, Error>;
+    type SerializeTupleStruct = Impossible<Shape, Error>;
+    type SerializeTupleVariant = Impossible<Shape, Error>;
+    type SerializeMap = Impossible<Shape, Error>;
+    type SerializeStruct = Impossible<Shape, Error>;
+    type SerializeStructVariant = Impossible<Shape, Error>;
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(self, name: &'static str, value: &T) -> Result<Shape, Error> {
+        if name != SHAPE_TOKEN {
+            return Err(Self::unsupported());
+        }
+        value.serialize(self)
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Shape, Error> {
+        match Shape::parse(&mut Cursor::new(v)) {
+            Ok((shape, _)) => Ok(shape),
+            Err(e) => Err(ser::Error::custom(e)),
+        }
+    }
+
+    fn serialize_none(self) -> Result<Shape, Error> {
+        Ok(Shape::NullShape)
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<Shape, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Shape, Error> {
+        Ok(Shape::NullShape)
+    }
+
+    fn serialize_bool(self, _v: bool) -> Result<Shape, Error> { Err(Self::unsupported()) }
+    fn serialize_i8(self, _v: i8) -> Result<Shape, Error> { Err(Self::unsupported()) }
+    fn serialize_i16(self, _v: i16) -> Result<Shape, Error> { Err(Self::unsupported()) }
+    fn serialize_i32(self, _v: i32) -> Result<Shape, Error> { Err(Self::unsupported()) }
+    fn serialize_i64(self, _v: i64) -> Result<Shape, Error> { Err(Self::unsupported()) }
+    fn serialize_u8(self, _v: u8) -> Result<Shape, Error> { Err(Self::unsupported()) }
+    fn serialize_u16(self, _v: u16) -> Result<Shape, Error> { Err(Self::unsupported()) }
+    fn serialize_u32(self, _v: u32) -> Result<Shape, Error> { Err(Self::unsupported()) }
+    fn serialize_u64(self, _v: u64) -> Result<Shape, Error> { Err(Self::unsupported()) }
+    fn serialize_f32(self, _v: f32) -> Result<Shape, Error> { Err(Self::unsupported()) }
+    fn serialize_f64(self, _v: f64) -> Result<Shape, Error> { Err(Self::unsupported()) }
+    fn serialize_char(self, _v: char) -> Result<Shape, Error> { Err(Self::unsupported()) }
+    fn serialize_str(self, _v: &str) -> Result<Shape, Error> { Err(Self::unsupported()) }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Shape, Error> {
+        Err(Self::unsupported())
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32, _variant: &'static str) -> Result<Shape, Error> {
+        Err(Self::unsupported())
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(self, _name: &'static str, _index: u32, _variant: &'static str, _value: &T) -> Result<Shape, Error> {
+        Err(Self::unsupported())
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Error> {
+        Err(Self::unsupported())
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Error> {
+        Err(Self::unsupported())
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeTupleStruct, Error> {
+        Err(Self::unsupported())
+    }
+
+    fn serialize_tuple_variant(self, _name: &'static str, _index: u32, _variant: &'static str, _len: usize) -> Result<Self::SerializeTupleVariant, Error> {
+        Err(Self::unsupported())
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Error> {
+        Err(Self::unsupported())
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct, Error> {
+        Err(Self::unsupported())
+    }
+
+    fn serialize_struct_variant(self, _name: &'static str, _index: u32, _variant: &'static str, _len: usize) -> Result<Self::SerializeStructVariant, Error> {
+        Err(Self::unsupported())
+    }
+}
+
+/// The binary SHP encoding of a shape.
+struct ShapeBytes(Vec<u8>);
+
+impl Serialize for ShapeBytes {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+/// A `Shape` is serialized as its binary encoding in the SHP file, which the writer parses again.
+impl Serialize for Shape {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut bytes: Vec<u8> = vec![];
+        self.write(&mut bytes).map_err(ser::Error::custom)?;
+        serializer.serialize_newtype_struct(SHAPE_TOKEN, &ShapeBytes(bytes))
+    }
+}
+
+/// A `Date` is serialized as a `YYYYMMDD` string, which the writer recognizes as a date.
+impl Serialize for Date {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let text = format!("{:04}{:02}{:02}", self.year, self.month, self.day);
+        serializer.serialize_newtype_struct(DATE_TOKEN, &text)
+    }
+}
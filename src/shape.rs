@@ -1,9 +1,10 @@
 //! The file with all definitions related to the Shape struct.
 
-use std::io::{Error, ErrorKind, Read};
-use byteorder::{LittleEndian, ReadBytesExt};
+use std::io::{Error, ErrorKind, Read, Write};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 
 use super::BoundingBoxZ;
+use super::geometry;
 
 /// A bounding box limited to X and Y axes. For axis definitions, see the BoundinxBoxZ struct.
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -29,7 +30,7 @@ pub struct Point {
 }
 
 /// A generic range from a minimum to maximum value, over a type T.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub struct Range<T> {
     pub min: T,
     pub max: T,
@@ -42,7 +43,7 @@ pub type MRange = Range<f64>;
 pub type ZRange = Range<f64>;
 
 /// A point with latitude, longitude, and a measure.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub struct PointM {
     /// The latitude
     pub x: f64,
@@ -54,7 +55,7 @@ pub struct PointM {
 }
 
 /// A point with latitude, longitude, altitude and an optional measure
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub struct PointZ {
     /// The latitude
     pub x: f64,
@@ -69,7 +70,7 @@ pub struct PointZ {
 
 /// The type of a single patch (see MultiPatch shape type).
 /// Defined on page 20 of the spec.
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
 pub enum PatchType {
     /// Every vertex after the first two spans a triangle with its two predecessors.
     TriangleStrip,
@@ -85,8 +86,27 @@ pub enum PatchType {
     Ring,
 }
 
+/// The type of a shape, as encoded in SHP files. The numbers are defined on page 4 of the spec.
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash)]
+pub enum ShapeType {
+    NullShape,
+    Point,
+    PolyLine,
+    Polygon,
+    MultiPoint,
+    PointZ,
+    PolyLineZ,
+    PolygonZ,
+    MultiPointZ,
+    PointM,
+    PolyLineM,
+    PolygonM,
+    MultiPointM,
+    MultiPatch,
+}
+
 /// A shape record defining a geometric feature in the SHP file.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Shape {
     /// The null shape: Empty info.
     NullShape,
@@ -273,6 +293,16 @@ impl BoundingBox {
 
         Ok(result)
     }
+
+    /// Writes the bounding box as four doubles to the output stream.
+    pub fn write<T: Write>(&self, file: &mut T) -> Result<(), Error> {
+        file.write_f64::<LittleEndian>(self.x_min)?;
+        file.write_f64::<LittleEndian>(self.y_min)?;
+        file.write_f64::<LittleEndian>(self.x_max)?;
+        file.write_f64::<LittleEndian>(self.y_max)?;
+
+        Ok(())
+    }
 }
 
 impl Point {
@@ -290,25 +320,99 @@ impl Point {
 
         Ok(result)
     }
+
+    /// Writes the point as two f64s in little-endian format to the output stream.
+    pub fn write<T: Write>(&self, file: &mut T) -> Result<(), Error> {
+        file.write_f64::<LittleEndian>(self.x)?;
+        file.write_f64::<LittleEndian>(self.y)?;
+
+        Ok(())
+    }
 }
 
-impl Shape {
-    /// Constants for encoding the Shape Type (see enum variants)
-    const STY_NULL_SHAPE: i32 = 0;
-    const STY_POINT: i32 = 1;
-    const STY_POLY_LINE: i32 = 3;
-    const STY_POLYGON: i32 = 5;
-    const STY_MULTI_POINT: i32 = 8;
-    const STY_POINT_Z: i32 = 11;
-    const STY_POLY_LINE_Z: i32 = 13;
-    const STY_POLYGON_Z: i32 = 15;
-    const STY_MULTI_POINT_Z: i32 = 18;
-    const STY_POINT_M: i32 = 21;
-    const STY_POLY_LINE_M: i32 = 23;
-    const STY_POLYGON_M: i32 = 25;
-    const STY_MULTI_POINT_M: i32 = 28;
-    const STY_MULTI_PATCH: i32 = 31;
+impl ShapeType {
+    /// Returns the shape type for the number written to the SHP file, or None if the spec doesn't
+    /// define it.
+    pub fn from_i32(id: i32) -> Option<Self> {
+        match id {
+            0 => Some(ShapeType::NullShape),
+            1 => Some(ShapeType::Point),
+            3 => Some(ShapeType::PolyLine),
+            5 => Some(ShapeType::Polygon),
+            8 => Some(ShapeType::MultiPoint),
+            11 => Some(ShapeType::PointZ),
+            13 => Some(ShapeType::PolyLineZ),
+            15 => Some(ShapeType::PolygonZ),
+            18 => Some(ShapeType::MultiPointZ),
+            21 => Some(ShapeType::PointM),
+            23 => Some(ShapeType::PolyLineM),
+            25 => Some(ShapeType::PolygonM),
+            28 => Some(ShapeType::MultiPointM),
+            31 => Some(ShapeType::MultiPatch),
+            _ => None,
+        }
+    }
 
+    /// Returns the number written to the SHP file for the shape type.
+    pub fn to_i32(self) -> i32 {
+        match self {
+            ShapeType::NullShape => 0,
+            ShapeType::Point => 1,
+            ShapeType::PolyLine => 3,
+            ShapeType::Polygon => 5,
+            ShapeType::MultiPoint => 8,
+            ShapeType::PointZ => 11,
+            ShapeType::PolyLineZ => 13,
+            ShapeType::PolygonZ => 15,
+            ShapeType::MultiPointZ => 18,
+            ShapeType::PointM => 21,
+            ShapeType::PolyLineM => 23,
+            ShapeType::PolygonM => 25,
+            ShapeType::MultiPointM => 28,
+            ShapeType::MultiPatch => 31,
+        }
+    }
+
+    /// Whether shapes of this type have Z values. These always have M values as well, even if they
+    /// are optional.
+    pub fn has_z(self) -> bool {
+        matches!(self, ShapeType::PointZ | ShapeType::PolyLineZ | ShapeType::PolygonZ | ShapeType::MultiPointZ | ShapeType::MultiPatch)
+    }
+
+    /// Whether shapes of this type have M values.
+    pub fn has_m(self) -> bool {
+        match self {
+            ShapeType::PointM | ShapeType::PolyLineM | ShapeType::PolygonM | ShapeType::MultiPointM => true,
+            _ => self.has_z(),
+        }
+    }
+
+    /// Whether shapes of this type can consist of more than one geometry: Multipoints, and
+    /// polylines, polygons and multipatches with their parts.
+    pub fn is_multi(self) -> bool {
+        !matches!(self, ShapeType::NullShape | ShapeType::Point | ShapeType::PointZ | ShapeType::PointM)
+    }
+
+    /// The topological dimension of shapes of this type: 0 for points, 1 for polylines and 2 for
+    /// polygons and multipatches. Returns None for the null shape.
+    pub fn dimension(self) -> Option<u32> {
+        match self {
+            ShapeType::NullShape => None,
+            ShapeType::Point | ShapeType::PointZ | ShapeType::PointM
+            | ShapeType::MultiPoint | ShapeType::MultiPointZ | ShapeType::MultiPointM => Some(0),
+            ShapeType::PolyLine | ShapeType::PolyLineZ | ShapeType::PolyLineM => Some(1),
+            ShapeType::Polygon | ShapeType::PolygonZ | ShapeType::PolygonM | ShapeType::MultiPatch => Some(2),
+        }
+    }
+}
+
+impl From<ShapeType> for i32 {
+    fn from(shape_type: ShapeType) -> i32 {
+        shape_type.to_i32()
+    }
+}
+
+impl Shape {
     /// Constants for encoding the Patch Type (see MultiPatch variant)
     const PTY_TRIANGLE_STRIP: i32 = 0;
     const PTY_TRIANGLE_FAN: i32 = 1;
@@ -349,20 +453,20 @@ impl Shape {
     }
 
     /// Gets called internally for parsing a point.
-    fn parse_point_type<T: Read>(file: &mut T, shape_type: i32) -> Result<(Self, usize), Error> {
+    fn parse_point_type<T: Read>(file: &mut T, shape_type: ShapeType) -> Result<(Self, usize), Error> {
         match shape_type {
             // Points come first
-            Self::STY_POINT => {
+            ShapeType::Point => {
                 // X and Y, both double and little endian
                 let v = (Self::parse_f64_array(file, 2))?;
                 Ok((Shape::Point {point: Point{x: v[0], y: v[1]}}, 16))
             },
-            Self::STY_POINT_M => {
+            ShapeType::PointM => {
                 // X, Y and M, both double and little endian
                 let v = (Self::parse_f64_array(file, 3))?;
                 Ok((Shape::PointM {point: PointM{x: v[0], y: v[1], m: v[2]}}, 24))
             },
-            Self::STY_POINT_Z => {
+            ShapeType::PointZ => {
                 // X, Y, M and Z, both double and little endian
                 let v = (Self::parse_f64_array(file, 4))?;
                 Ok((Shape::PointZ {point: PointZ{x: v[0], y: v[1], z: v[2], m: v[3]}}, 32))
@@ -410,13 +514,13 @@ impl Shape {
 
     /// Given a Shape type ID and the parsed base data, we can already construct a valid shape
     /// object.
-    fn shape_from_base_data(shape_type: i32, base: ShapeBaseData) -> Self {
+    fn shape_from_base_data(shape_type: ShapeType, base: ShapeBaseData) -> Self {
         match shape_type {
             // The poly lines
-            Self::STY_POLY_LINE => {
+            ShapeType::PolyLine => {
                 Shape::PolyLine {bounding_box: base.bounding_box, parts: base.parts, points: base.points}
             },
-            Self::STY_POLY_LINE_M => {
+            ShapeType::PolyLineM => {
                 Shape::PolyLineM {
                     bounding_box: base.bounding_box,
                     parts: base.parts,
@@ -424,7 +528,7 @@ impl Shape {
                     m_range: base.m_range,
                     m: base.m}
             },
-            Self::STY_POLY_LINE_Z => {
+            ShapeType::PolyLineZ => {
                 Shape::PolyLineZ {
                     bounding_box: base.bounding_box,
                     parts: base.parts,
@@ -435,10 +539,10 @@ impl Shape {
                     m: base.m}
             },
             // The polygons
-            Self::STY_POLYGON => {
+            ShapeType::Polygon => {
                 Shape::Polygon {bounding_box: base.bounding_box, parts: base.parts, points: base.points}
             },
-            Self::STY_POLYGON_M => {
+            ShapeType::PolygonM => {
                 Shape::PolygonM {
                     bounding_box: base.bounding_box,
                     parts: base.parts,
@@ -446,7 +550,7 @@ impl Shape {
                     m_range: base.m_range,
                     m: base.m}
             },
-            Self::STY_POLYGON_Z => {
+            ShapeType::PolygonZ => {
                 Shape::PolygonZ {
                     bounding_box: base.bounding_box,
                     parts: base.parts,
@@ -457,17 +561,17 @@ impl Shape {
                     m: base.m}
             },
             // Then the multipoints
-            Self::STY_MULTI_POINT => {
+            ShapeType::MultiPoint => {
                 Shape::MultiPoint {bounding_box: base.bounding_box, points: base.points}
             },
-            Self::STY_MULTI_POINT_M => {
+            ShapeType::MultiPointM => {
                 Shape::MultiPointM {
                     bounding_box: base.bounding_box,
                     points: base.points,
                     m_range: base.m_range,
                     m: base.m}
             },
-            Self::STY_MULTI_POINT_Z => {
+            ShapeType::MultiPointZ => {
                 Shape::MultiPointZ {
                     bounding_box: base.bounding_box,
                     points: base.points,
@@ -476,7 +580,7 @@ impl Shape {
                     m_range: base.m_range,
                     m: base.m}
             },
-            Self::STY_MULTI_PATCH => {
+            ShapeType::MultiPatch => {
                 Shape::MultiPatch {
                     bounding_box: base.bounding_box,
                     parts: base.parts,
@@ -487,7 +591,7 @@ impl Shape {
                     m_range: base.m_range,
                     m: base.m}
             },
-            Self::STY_NULL_SHAPE => {
+            ShapeType::NullShape => {
                 Shape::NullShape
             },
             _ => {
@@ -499,14 +603,20 @@ impl Shape {
 
     /// Parses a shape from the input stream.
     pub fn parse<T: Read>(file: &mut T) -> Result<(Self, usize), Error> {
-        let shape_type = try!(file.read_i32::<LittleEndian>());
+        let shape_type_id = try!(file.read_i32::<LittleEndian>());
         let mut length = 4usize;
 
+        // Unknown shape types are read as null shapes
+        let shape_type = match ShapeType::from_i32(shape_type_id) {
+            Some(t) => t,
+            None => return Ok((Shape::NullShape, length)),
+        };
+
         // Get the points out of here, they're too special
         match shape_type {
-            Self::STY_POINT
-            | Self::STY_POINT_M
-            | Self::STY_POINT_Z => {
+            ShapeType::Point
+            | ShapeType::PointM
+            | ShapeType::PointZ => {
                 let (sh, sz) = try!(Self::parse_point_type(file, shape_type));
                 return Ok((sh, sz + length))
             },
@@ -517,13 +627,13 @@ impl Shape {
         let mut base: ShapeBaseData = ShapeBaseData::new();
 
         match shape_type {
-            Self::STY_POLY_LINE
-            | Self::STY_POLYGON
-            | Self::STY_POLY_LINE_M
-            | Self::STY_POLYGON_M
-            | Self::STY_POLY_LINE_Z
-            | Self::STY_POLYGON_Z
-            | Self::STY_MULTI_PATCH => {
+            ShapeType::PolyLine
+            | ShapeType::Polygon
+            | ShapeType::PolyLineM
+            | ShapeType::PolygonM
+            | ShapeType::PolyLineZ
+            | ShapeType::PolygonZ
+            | ShapeType::MultiPatch => {
                 length += 40usize;
                 base.bounding_box = try!(BoundingBox::parse(file));
                 base.num_parts = try!(file.read_i32::<LittleEndian>());
@@ -531,7 +641,7 @@ impl Shape {
                 length += 4 * base.num_parts as usize;
                 base.parts = try!(Self::parse_i32_array(file, base.num_parts as usize));
 
-                if shape_type == Self::STY_MULTI_PATCH {
+                if shape_type == ShapeType::MultiPatch {
                     let part_types_id = try!(Self::parse_i32_array(file, base.num_parts as usize));
                     length += 4 * base.num_parts as usize;
                     base.part_types = part_types_id.iter()
@@ -542,9 +652,9 @@ impl Shape {
                 length += 16 * base.num_points as usize;
                 base.points = try!(Self::parse_point_array(file, base.num_points as usize));
             },
-            Self::STY_MULTI_POINT
-            | Self::STY_MULTI_POINT_M
-            | Self::STY_MULTI_POINT_Z => {
+            ShapeType::MultiPoint
+            | ShapeType::MultiPointM
+            | ShapeType::MultiPointZ => {
                 length += 36usize;
                 base.bounding_box = try!(BoundingBox::parse(file));
                 base.num_points = try!(file.read_i32::<LittleEndian>());
@@ -555,10 +665,10 @@ impl Shape {
         };
 
         match shape_type {
-            Self::STY_POLY_LINE_Z
-            | Self::STY_POLYGON_Z
-            | Self::STY_MULTI_POINT_Z
-            | Self::STY_MULTI_PATCH => {
+            ShapeType::PolyLineZ
+            | ShapeType::PolygonZ
+            | ShapeType::MultiPointZ
+            | ShapeType::MultiPatch => {
                 let (z_range, z) = try!(Self::parse_f64_range_and_array(file, base.num_points as usize));
                 let (m_range, m) = try!(Self::parse_f64_range_and_array(file, base.num_points as usize));
                 base.z_range = z_range;
@@ -567,9 +677,9 @@ impl Shape {
                 base.m = m;
                 length += 32usize + 16 * base.num_points as usize;
             },
-            Self::STY_POLY_LINE_M
-            | Self::STY_POLYGON_M
-            | Self::STY_MULTI_POINT_M => {
+            ShapeType::PolyLineM
+            | ShapeType::PolygonM
+            | ShapeType::MultiPointM => {
                 let (m_range, m) = try!(Self::parse_f64_range_and_array(file, base.num_points as usize));
                 base.m_range = m_range;
                 base.m = m;
@@ -580,6 +690,266 @@ impl Shape {
 
         Ok((Self::shape_from_base_data(shape_type, base), length))
     }
+
+    /// Returns the type of the shape.
+    pub fn shape_type(&self) -> ShapeType {
+        match *self {
+            Shape::NullShape => ShapeType::NullShape,
+            Shape::Point {..} => ShapeType::Point,
+            Shape::PolyLine {..} => ShapeType::PolyLine,
+            Shape::Polygon {..} => ShapeType::Polygon,
+            Shape::MultiPoint {..} => ShapeType::MultiPoint,
+            Shape::PointZ {..} => ShapeType::PointZ,
+            Shape::PolyLineZ {..} => ShapeType::PolyLineZ,
+            Shape::PolygonZ {..} => ShapeType::PolygonZ,
+            Shape::MultiPointZ {..} => ShapeType::MultiPointZ,
+            Shape::PointM {..} => ShapeType::PointM,
+            Shape::PolyLineM {..} => ShapeType::PolyLineM,
+            Shape::PolygonM {..} => ShapeType::PolygonM,
+            Shape::MultiPointM {..} => ShapeType::MultiPointM,
+            Shape::MultiPatch {..} => ShapeType::MultiPatch,
+        }
+    }
+
+    /// Given a patch type (see MultiPatch), returns its encoded ID.
+    fn get_id_from_patch_type(patch_type: &PatchType) -> i32 {
+        match *patch_type {
+            PatchType::TriangleStrip => Self::PTY_TRIANGLE_STRIP,
+            PatchType::TriangleFan => Self::PTY_TRIANGLE_FAN,
+            PatchType::OuterRing => Self::PTY_OUTER_RING,
+            PatchType::InnerRing => Self::PTY_INNER_RING,
+            PatchType::FirstRing => Self::PTY_FIRST_RING,
+            PatchType::Ring => Self::PTY_RING,
+        }
+    }
+
+    /// Writes an array of f64 values, preceded by its range.
+    fn write_f64_range_and_array<T: Write>(file: &mut T, range: &Range<f64>, values: &[f64]) -> Result<(), Error> {
+        file.write_f64::<LittleEndian>(range.min)?;
+        file.write_f64::<LittleEndian>(range.max)?;
+        for v in values {
+            file.write_f64::<LittleEndian>(*v)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the bounding box, the parts and the points of multi-part shapes.
+    fn write_parts_and_points<T: Write>(file: &mut T, bounding_box: &BoundingBox, parts: &[i32], part_types: Option<&[PatchType]>, points: &[Point]) -> Result<(), Error> {
+        bounding_box.write(file)?;
+        file.write_i32::<LittleEndian>(parts.len() as i32)?;
+        file.write_i32::<LittleEndian>(points.len() as i32)?;
+        for p in parts {
+            file.write_i32::<LittleEndian>(*p)?;
+        }
+        if let Some(types) = part_types {
+            for t in types {
+                file.write_i32::<LittleEndian>(Self::get_id_from_patch_type(t))?;
+            }
+        }
+        for p in points {
+            p.write(file)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the bounding box and the points of multi-point shapes.
+    fn write_points<T: Write>(file: &mut T, bounding_box: &BoundingBox, points: &[Point]) -> Result<(), Error> {
+        bounding_box.write(file)?;
+        file.write_i32::<LittleEndian>(points.len() as i32)?;
+        for p in points {
+            p.write(file)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the shape to the output stream in the format `parse` reads. Returns the number of
+    /// bytes written.
+    pub fn write<T: Write>(&self, file: &mut T) -> Result<usize, Error> {
+        let mut buffer: Vec<u8> = vec![];
+        buffer.write_i32::<LittleEndian>(self.shape_type().to_i32())?;
+
+        match *self {
+            Shape::NullShape => (),
+            Shape::Point {ref point} => point.write(&mut buffer)?,
+            Shape::PointM {ref point} => {
+                for v in [point.x, point.y, point.m].iter() {
+                    buffer.write_f64::<LittleEndian>(*v)?;
+                }
+            },
+            Shape::PointZ {ref point} => {
+                for v in [point.x, point.y, point.z, point.m].iter() {
+                    buffer.write_f64::<LittleEndian>(*v)?;
+                }
+            },
+            Shape::PolyLine {ref bounding_box, ref parts, ref points}
+            | Shape::Polygon {ref bounding_box, ref parts, ref points} => {
+                Self::write_parts_and_points(&mut buffer, bounding_box, parts, None, points)?;
+            },
+            Shape::PolyLineM {ref bounding_box, ref parts, ref points, ref m_range, ref m}
+            | Shape::PolygonM {ref bounding_box, ref parts, ref points, ref m_range, ref m} => {
+                Self::write_parts_and_points(&mut buffer, bounding_box, parts, None, points)?;
+                Self::write_f64_range_and_array(&mut buffer, m_range, m)?;
+            },
+            Shape::PolyLineZ {ref bounding_box, ref parts, ref points, ref z_range, ref z, ref m_range, ref m}
+            | Shape::PolygonZ {ref bounding_box, ref parts, ref points, ref z_range, ref z, ref m_range, ref m} => {
+                Self::write_parts_and_points(&mut buffer, bounding_box, parts, None, points)?;
+                Self::write_f64_range_and_array(&mut buffer, z_range, z)?;
+                Self::write_f64_range_and_array(&mut buffer, m_range, m)?;
+            },
+            Shape::MultiPoint {ref bounding_box, ref points} => {
+                Self::write_points(&mut buffer, bounding_box, points)?;
+            },
+            Shape::MultiPointM {ref bounding_box, ref points, ref m_range, ref m} => {
+                Self::write_points(&mut buffer, bounding_box, points)?;
+                Self::write_f64_range_and_array(&mut buffer, m_range, m)?;
+            },
+            Shape::MultiPointZ {ref bounding_box, ref points, ref z_range, ref z, ref m_range, ref m} => {
+                Self::write_points(&mut buffer, bounding_box, points)?;
+                Self::write_f64_range_and_array(&mut buffer, z_range, z)?;
+                Self::write_f64_range_and_array(&mut buffer, m_range, m)?;
+            },
+            Shape::MultiPatch {ref bounding_box, ref parts, ref part_types, ref points, ref z_range, ref z, ref m_range, ref m} => {
+                Self::write_parts_and_points(&mut buffer, bounding_box, parts, Some(part_types), points)?;
+                Self::write_f64_range_and_array(&mut buffer, z_range, z)?;
+                Self::write_f64_range_and_array(&mut buffer, m_range, m)?;
+            },
+        }
+
+        file.write_all(&buffer)?;
+        Ok(buffer.len())
+    }
+
+    /// The extent of the shape on all four axes, as stored in the shape. The axes the shape doesn't
+    /// have are zero. Returns None for the NullShape.
+    pub(crate) fn stored_extent(&self) -> Option<BoundingBoxZ> {
+        let mut result = BoundingBoxZ::new();
+
+        let (bounding_box, z_range, m_range) = match *self {
+            Shape::NullShape => return None,
+            Shape::Point {ref point} => {
+                (BoundingBox {x_min: point.x, y_min: point.y, x_max: point.x, y_max: point.y}, None, None)
+            },
+            Shape::PointM {ref point} => {
+                (BoundingBox {x_min: point.x, y_min: point.y, x_max: point.x, y_max: point.y}, None, Some(Range {min: point.m, max: point.m}))
+            },
+            Shape::PointZ {ref point} => {
+                (BoundingBox {x_min: point.x, y_min: point.y, x_max: point.x, y_max: point.y},
+                 Some(Range {min: point.z, max: point.z}),
+                 Some(Range {min: point.m, max: point.m}))
+            },
+            Shape::PolyLine {bounding_box, ..}
+            | Shape::Polygon {bounding_box, ..}
+            | Shape::MultiPoint {bounding_box, ..} => (bounding_box, None, None),
+            Shape::PolyLineM {bounding_box, m_range, ..}
+            | Shape::PolygonM {bounding_box, m_range, ..}
+            | Shape::MultiPointM {bounding_box, m_range, ..} => (bounding_box, None, Some(m_range)),
+            Shape::PolyLineZ {bounding_box, z_range, m_range, ..}
+            | Shape::PolygonZ {bounding_box, z_range, m_range, ..}
+            | Shape::MultiPointZ {bounding_box, z_range, m_range, ..}
+            | Shape::MultiPatch {bounding_box, z_range, m_range, ..} => (bounding_box, Some(z_range), Some(m_range)),
+        };
+
+        result.x_min = bounding_box.x_min;
+        result.y_min = bounding_box.y_min;
+        result.x_max = bounding_box.x_max;
+        result.y_max = bounding_box.y_max;
+        if let Some(z) = z_range {
+            result.z_min = z.min;
+            result.z_max = z.max;
+        }
+        if let Some(m) = m_range {
+            result.m_min = m.min;
+            result.m_max = m.max;
+        }
+
+        Some(result)
+    }
+
+    /// The Z and M values of the shape, if it has them. Points have one value each.
+    fn z_and_m(&self) -> (Option<Vec<f64>>, Option<Vec<f64>>) {
+        match *self {
+            Shape::NullShape | Shape::Point {..} | Shape::PolyLine {..} | Shape::Polygon {..} | Shape::MultiPoint {..} => (None, None),
+            Shape::PointM {point} => (None, Some(vec![point.m])),
+            Shape::PointZ {point} => (Some(vec![point.z]), Some(vec![point.m])),
+            Shape::PolyLineM {ref m, ..}
+            | Shape::PolygonM {ref m, ..}
+            | Shape::MultiPointM {ref m, ..} => (None, Some(m.clone())),
+            Shape::PolyLineZ {ref z, ref m, ..}
+            | Shape::PolygonZ {ref z, ref m, ..}
+            | Shape::MultiPointZ {ref z, ref m, ..}
+            | Shape::MultiPatch {ref z, ref m, ..} => (Some(z.clone()), Some(m.clone())),
+        }
+    }
+
+    /// The bounding box of the points of the shape. Returns None for the NullShape.
+    pub fn computed_bounding_box(&self) -> Option<BoundingBox> {
+        match *self {
+            Shape::NullShape => None,
+            Shape::Point {point} => Some(geometry::bounding_box_of(&[point])),
+            Shape::PointM {point} => Some(geometry::bounding_box_of(&[Point {x: point.x, y: point.y}])),
+            Shape::PointZ {point} => Some(geometry::bounding_box_of(&[Point {x: point.x, y: point.y}])),
+            Shape::PolyLine {ref points, ..} | Shape::PolyLineM {ref points, ..} | Shape::PolyLineZ {ref points, ..}
+            | Shape::Polygon {ref points, ..} | Shape::PolygonM {ref points, ..} | Shape::PolygonZ {ref points, ..}
+            | Shape::MultiPoint {ref points, ..} | Shape::MultiPointM {ref points, ..} | Shape::MultiPointZ {ref points, ..}
+            | Shape::MultiPatch {ref points, ..} => Some(geometry::bounding_box_of(points)),
+        }
+    }
+
+    /// The range of the Z values. Returns None for shapes without Z values.
+    pub fn computed_z_range(&self) -> Option<ZRange> {
+        match self.z_and_m() {
+            (Some(ref z), _) if !z.is_empty() => Some(geometry::range_of(z)),
+            _ => None,
+        }
+    }
+
+    /// The range of the M values, leaving out those which mean "no data". Returns None for shapes
+    /// without M values, or if none of them has data.
+    pub fn computed_m_range(&self) -> Option<MRange> {
+        match self.z_and_m() {
+            (_, Some(ref m)) if m.iter().any(|&v| v >= geometry::NO_DATA) => Some(geometry::measure_range_of(m)),
+            _ => None,
+        }
+    }
+
+    /// Whether the bounding box, Z range and M range stored in the shape match its points.
+    pub fn extents_match(&self) -> bool {
+        *self == self.with_computed_extents()
+    }
+
+    /// Returns a copy of the shape with the bounding box, Z range and M range computed from the
+    /// points. Ranges without values are left as they are.
+    pub fn with_computed_extents(&self) -> Shape {
+        let mut result = self.clone();
+        let computed_bounding_box = self.computed_bounding_box();
+        let computed_z_range = self.computed_z_range();
+        let computed_m_range = self.computed_m_range();
+
+        match result {
+            Shape::PolyLine {ref mut bounding_box, ..}
+            | Shape::Polygon {ref mut bounding_box, ..}
+            | Shape::MultiPoint {ref mut bounding_box, ..} => {
+                *bounding_box = computed_bounding_box.unwrap();
+            },
+            Shape::PolyLineM {ref mut bounding_box, ref mut m_range, ..}
+            | Shape::PolygonM {ref mut bounding_box, ref mut m_range, ..}
+            | Shape::MultiPointM {ref mut bounding_box, ref mut m_range, ..} => {
+                *bounding_box = computed_bounding_box.unwrap();
+                *m_range = computed_m_range.unwrap_or(*m_range);
+            },
+            Shape::PolyLineZ {ref mut bounding_box, ref mut z_range, ref mut m_range, ..}
+            | Shape::PolygonZ {ref mut bounding_box, ref mut z_range, ref mut m_range, ..}
+            | Shape::MultiPointZ {ref mut bounding_box, ref mut z_range, ref mut m_range, ..}
+            | Shape::MultiPatch {ref mut bounding_box, ref mut z_range, ref mut m_range, ..} => {
+                *bounding_box = computed_bounding_box.unwrap();
+                *z_range = computed_z_range.unwrap_or(*z_range);
+                *m_range = computed_m_range.unwrap_or(*m_range);
+            },
+            Shape::NullShape | Shape::Point {..} | Shape::PointM {..} | Shape::PointZ {..} => (),
+        }
+
+        result
+    }
 }
 
 impl BoundingBoxZ {
@@ -614,11 +984,32 @@ impl BoundingBoxZ {
         // Return what we've got
         Ok(result)
     }
+
+    /// Writes the BoundingBoxZ to the binary output stream
+    pub fn write<T: Write>(&self, file: &mut T) -> Result<(), Error> {
+        for v in [self.x_min, self.y_min, self.x_max, self.y_max, self.z_min, self.z_max, self.m_min, self.m_max].iter() {
+            file.write_f64::<LittleEndian>(*v)?;
+        }
+
+        Ok(())
+    }
+
+    /// Grows the box so that it also contains the other one.
+    pub fn extend(&mut self, other: &BoundingBoxZ) {
+        self.x_min = self.x_min.min(other.x_min);
+        self.y_min = self.y_min.min(other.y_min);
+        self.x_max = self.x_max.max(other.x_max);
+        self.y_max = self.y_max.max(other.y_max);
+        self.z_min = self.z_min.min(other.z_min);
+        self.z_max = self.z_max.max(other.z_max);
+        self.m_min = self.m_min.min(other.m_min);
+        self.m_max = self.m_max.max(other.m_max);
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{Shape, BoundingBox, Point, PatchType};
+    use super::{Shape, ShapeType, BoundingBox, Point, PointZ, PatchType, Range};
     use std::io::Cursor;
     use byteorder::{LittleEndian, WriteBytesExt};
 
@@ -1023,4 +1414,83 @@ mod tests {
             panic!()
         }
     }
+
+    #[test]
+    fn test_write_parse_roundtrip() {
+        let shapes = vec![
+            Shape::NullShape,
+            Shape::PointZ {point: PointZ {x: 1.0, y: 2.0, z: 3.0, m: 4.0}},
+            Shape::MultiPatch {
+                bounding_box: BoundingBox {x_min: 0.0, y_min: 0.0, x_max: 1.0, y_max: 1.0},
+                parts: vec![0, 3],
+                part_types: vec![PatchType::TriangleFan, PatchType::OuterRing],
+                points: vec![Point {x: 0.0, y: 0.0}, Point {x: 1.0, y: 0.0}, Point {x: 1.0, y: 1.0}, Point {x: 0.0, y: 1.0}],
+                z_range: Range {min: 0.0, max: 2.0},
+                z: vec![0.0, 1.0, 2.0, 0.5],
+                m_range: Range {min: 5.0, max: 5.0},
+                m: vec![5.0, 5.0, 5.0, 5.0],
+            },
+        ];
+
+        for shape in shapes {
+            let mut buffer: Vec<u8> = vec![];
+            let written = shape.write(&mut buffer).unwrap();
+            let (parsed, read) = Shape::parse(&mut Cursor::new(&buffer)).unwrap();
+            if parsed != shape || read != written || written != buffer.len() {
+                panic!()
+            }
+        }
+    }
+
+    #[test]
+    fn test_computed_extents() {
+        let shape = Shape::PolyLineZ {
+            bounding_box: BoundingBox::new(),
+            parts: vec![0],
+            points: vec![Point {x: 1.0, y: 5.0}, Point {x: -1.0, y: 2.0}, Point {x: 3.0, y: 4.0}],
+            z_range: Range {min: 0.0, max: 0.0},
+            z: vec![7.0, 3.0, 5.0],
+            m_range: Range {min: 0.0, max: 0.0},
+            m: vec![-1e39, 2.0, 1.0],
+        };
+
+        if shape.extents_match() {
+            panic!()
+        }
+        if shape.computed_bounding_box() != Some(BoundingBox {x_min: -1.0, y_min: 2.0, x_max: 3.0, y_max: 5.0})
+            || shape.computed_z_range() != Some(Range {min: 3.0, max: 7.0})
+            || shape.computed_m_range() != Some(Range {min: 1.0, max: 2.0}) {
+            panic!()
+        }
+
+        let fixed = shape.with_computed_extents();
+        if !fixed.extents_match() || fixed.computed_bounding_box() != shape.computed_bounding_box() {
+            panic!()
+        }
+        if Shape::NullShape.computed_bounding_box().is_some() || !Shape::NullShape.extents_match() {
+            panic!()
+        }
+    }
+
+    #[test]
+    fn test_shape_type() {
+        for id in -1..40 {
+            match ShapeType::from_i32(id) {
+                Some(t) => if i32::from(t) != id { panic!() },
+                None => if [0, 1, 3, 5, 8, 11, 13, 15, 18, 21, 23, 25, 28, 31].contains(&id) { panic!() },
+            }
+        }
+
+        let t = Shape::PointZ {point: PointZ {x: 1.0, y: 2.0, z: 3.0, m: 4.0}}.shape_type();
+        if t != ShapeType::PointZ || !t.has_z() || !t.has_m() || t.is_multi() || t.dimension() != Some(0) {
+            panic!()
+        }
+        let t = ShapeType::PolygonM;
+        if t.has_z() || !t.has_m() || !t.is_multi() || t.dimension() != Some(2) {
+            panic!()
+        }
+        if ShapeType::NullShape.dimension().is_some() || ShapeType::PolyLine.has_m() {
+            panic!()
+        }
+    }
 }
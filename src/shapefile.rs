@@ -5,29 +5,141 @@
 //!
 
 use std::collections::HashMap;
-use std::io::Error;
+use std::io::{Error, ErrorKind};
 use std::iter::Iterator;
+use std::marker::PhantomData;
 use std::path::Path;
 
-use super::{Shapefile, ShapefileRecord, ShpFile, DbfFile, ShxFile, ShapefileRecordIterator};
-use super::shape::Shape;
+use dbf;
+use serde::de::DeserializeOwned;
+use serde::ser::Serialize;
+
+use super::{Shapefile, ShapefileRecord, ShpFile, DbfFile, ShxFile, ShapefileRecordIterator, ShapefileRecordAsIterator};
+use super::{ShapeIterator, AttributeIterator, ShapefileSequentialIterator};
+use super::{ShapefileWriter, ShpWriter, ShxWriter, DbfWriter, DbfField, DbfValue};
+use super::{de, ser};
+use super::{BoundingBoxZ, Projection};
+use super::shape::{Shape, ShapeType};
 
 impl Shapefile {
     /// Creates a new `Shapefile` instance by taking all three files specified in the spec.
+    ///
+    /// If the SHX file doesn't exist, the index is built in memory by reading through the SHP file,
+    /// like `without_shx` does.
     pub fn new(shp_path: &Path, shx_path: &Path, dbf_path: &Path) -> Result<Self, Error> {
+        let mut shp_file = try!(ShpFile::parse_file(shp_path));
+        let shx_file = match ShxFile::parse_file(shx_path) {
+            Ok(f) => f,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => try!(ShxFile::from_shp(&mut shp_file)),
+            Err(e) => return Err(e),
+        };
+
         Ok(Shapefile {
-            shp_file: try!(ShpFile::parse_file(shp_path)),
-            shx_file: try!(ShxFile::parse_file(shx_path)),
+            shp_file,
+            shx_file,
             dbf_file: try!(DbfFile::parse_file(dbf_path)),
+            projection: Self::find_projection(shp_path),
+            geometry_field: String::from(de::GEOMETRY_FIELD),
+        })
+    }
+
+    /// Creates a new `Shapefile` instance from the SHP and DBF files only. The offsets of the
+    /// records are collected by reading through the SHP file once, so that `record` still works.
+    pub fn without_shx(shp_path: &Path, dbf_path: &Path) -> Result<Self, Error> {
+        let mut shp_file = ShpFile::parse_file(shp_path)?;
+        let shx_file = ShxFile::from_shp(&mut shp_file)?;
+
+        Ok(Shapefile {
+            shp_file,
+            shx_file,
+            dbf_file: DbfFile::parse_file(dbf_path)?,
+            projection: Self::find_projection(shp_path),
+            geometry_field: String::from(de::GEOMETRY_FIELD),
         })
     }
 
+    /// Reads the PRJ file next to the SHP file. A PRJ file which can't be read is treated like a
+    /// missing one, since the records can be read without it.
+    fn find_projection(shp_path: &Path) -> Option<Projection> {
+        ["prj", "PRJ"].iter()
+                      .map(|e| shp_path.with_extension(e))
+                      .find(|p| p.exists())
+                      .and_then(|p| Projection::parse_file(&p).ok())
+    }
+
+    /// The coordinate system of the shapes, from the PRJ file next to the SHP file.
+    pub fn projection(&self) -> Option<&Projection> {
+        self.projection.as_ref()
+    }
+
+    /// Whether the record offsets come from an index built in memory, because the layer was
+    /// opened without an SHX file.
+    pub fn has_shx(&self) -> bool {
+        !self.shx_file.is_in_memory()
+    }
+
     /// Constructs a `ShapefileRecordIterator` that can be used to iterate over the records inside
     /// the Shapefile.
     pub fn iter<'a>(&'a mut self) -> ShapefileRecordIterator<'a> {
         ShapefileRecordIterator {instance: self, id: 1u64}
     }
 
+    /// Constructs an iterator that reads the SHP file from front to back, using the record headers
+    /// to find the next record. This is much faster than `iter` for full scans, since it doesn't
+    /// seek. The SHX file is not used, unless the iterator is told to validate against it.
+    pub fn iter_sequential<'a>(&'a mut self) -> ShapefileSequentialIterator<'a> {
+        ShapefileSequentialIterator {
+            instance: self,
+            id: 1u64,
+            position: ShpFile::FIRST_RECORD_OFFSET,
+            end: 0u64,
+            validate_index: false,
+            failed: false,
+        }
+    }
+
+    /// Constructs an iterator over the shapes only. The DBF file is not read.
+    pub fn shapes<'a>(&'a mut self) -> ShapeIterator<'a> {
+        ShapeIterator {instance: self, id: 1u64}
+    }
+
+    /// Constructs an iterator over the attributes only. The SHP and SHX files are not read.
+    pub fn attributes<'a>(&'a mut self) -> AttributeIterator<'a> {
+        AttributeIterator {instance: self, id: 0u32, columns: None}
+    }
+
+    /// Constructs an iterator over the given attribute columns only. Columns which don't exist in
+    /// the DBF file are left out of the records.
+    pub fn attributes_projected<'a>(&'a mut self, columns: &[&str]) -> AttributeIterator<'a> {
+        let columns = self.dbf_file.column_indices(columns);
+        AttributeIterator {instance: self, id: 0u32, columns: Some(columns)}
+    }
+
+    /// The column definitions of the DBF file.
+    pub fn fields(&self) -> &[DbfField] {
+        &self.dbf_file.fields
+    }
+
+    /// Sets the struct field which receives the shape for `iter_as` and `record_as`. Defaults to
+    /// `geometry`. A DBF column of the same name is left out, so this reads a layer with a
+    /// `geometry` column.
+    pub fn set_geometry_field(&mut self, name: &str) {
+        self.geometry_field = name.to_string();
+    }
+
+    /// Constructs an iterator which deserializes every record into a `T`. See the `de` module for
+    /// how the attributes and the shape are mapped onto the struct.
+    pub fn iter_as<'a, T: DeserializeOwned>(&'a mut self) -> ShapefileRecordAsIterator<'a, T> {
+        let geometry_field = self.geometry_field.clone();
+        ShapefileRecordAsIterator {records: self.iter(), geometry_field, marker: PhantomData}
+    }
+
+    /// Gives the data behind the record number, deserialized into a `T`.
+    pub fn record_as<T: DeserializeOwned>(&mut self, id: u64) -> Option<Result<T, de::Error>> {
+        let geometry_field = self.geometry_field.clone();
+        self.record(id).map(|r| de::from_record_with(r, &geometry_field))
+    }
+
     /// Gives the data behind the record number
     pub fn record(&mut self, id: u64) -> Option<ShapefileRecord> {
         let mut result = ShapefileRecord {shape: Shape::new(), metadata: HashMap::new()};
@@ -45,9 +157,44 @@ impl Shapefile {
         Some(result)
     }
 
-    /// The amount of records in the file.
+    /// The amount of records in the file. If the SHX and the DBF file disagree, this is the smaller
+    /// count, so that every record up to it has a shape and attributes.
     pub fn num_records(&self) -> u64 {
-        self.shx_file.num_records()
+        self.shx_file.num_records().min(self.dbf_file.num_records as u64)
+    }
+
+    /// The type of the shapes in the file, from the SHP header. All shapes are of this type, or
+    /// null shapes.
+    pub fn shape_type(&self) -> ShapeType {
+        self.shp_file.header.shape_type
+    }
+
+    /// The extent of all shapes in the file, from the SHP header. The Z and M ranges are zero if
+    /// the shapes don't have these values.
+    pub fn extent(&self) -> BoundingBoxZ {
+        self.shp_file.header.bounding_box
+    }
+
+    /// Writes all records into a new shapefile with the same columns, after passing each shape
+    /// through `f`. Records for which `f` returns None are left out. Returns the number of records
+    /// written.
+    pub(crate) fn rewrite<F>(&mut self, shp_path: &Path, shx_path: &Path, dbf_path: &Path, mut f: F) -> Result<u64, Error>
+    where F: FnMut(Shape) -> Result<Option<Shape>, Error>
+    {
+        let fields = self.fields().iter().map(DbfField::for_copy).collect();
+        let mut writer = ShapefileWriter::with_schema(shp_path, shx_path, dbf_path, fields)?;
+        let mut count = 0u64;
+
+        for record in self.iter_sequential() {
+            let record = record?;
+            if let Some(shape) = f(record.shape)? {
+                writer.write(&shape, &record.metadata)?;
+                count += 1;
+            }
+        }
+
+        writer.finish()?;
+        Ok(count)
     }
 }
 
@@ -61,3 +208,199 @@ impl<'a> Iterator for ShapefileRecordIterator<'a> {
         result
     }
 }
+
+impl<'a> ShapefileSequentialIterator<'a> {
+    /// Makes the iterator check the offset and the length of every record against the SHX file,
+    /// and the record numbers against their position. A mismatch is returned as an error.
+    pub fn with_index_validation(mut self) -> Self {
+        self.validate_index = true;
+        self
+    }
+
+    /// Positions the files at the first record.
+    fn start(&mut self) -> Result<(), Error> {
+        self.end = self.instance.shp_file.records_end()?;
+        self.instance.shp_file.seek_first_record()?;
+        if self.validate_index {
+            self.instance.shx_file.seek_first_record()?;
+        }
+        Ok(())
+    }
+
+    /// Reads the next record from the SHP file, and its attributes from the DBF file.
+    fn read_next(&mut self) -> Result<Option<ShapefileRecord>, Error> {
+        if self.id == 1u64 {
+            self.start()?;
+        }
+
+        if self.position >= self.end {
+            return Ok(None);
+        }
+
+        let (record, index) = self.instance.shp_file.read_next_record(self.position)?;
+
+        if self.validate_index {
+            if self.id > self.instance.shx_file.num_records() {
+                return Err(Error::new(ErrorKind::Other, format!("SHP record {} is missing in the SHX file!", self.id)));
+            }
+
+            let expected = self.instance.shx_file.read_next_record()?;
+            if expected.offset != index.offset || expected.length != index.length {
+                return Err(Error::new(ErrorKind::Other, format!("SHP record {} doesn't match its SHX entry!", self.id)));
+            }
+
+            if record.record_number as u64 != self.id {
+                return Err(Error::new(ErrorKind::Other, format!("SHP record {} has record number {}!", self.id, record.record_number)));
+            }
+        }
+
+        let metadata = match self.instance.dbf_file.record(self.id as u32 - 1) {
+            Some(r) => r,
+            None => return Err(Error::new(ErrorKind::Other, format!("DBF record {} is missing!", self.id))),
+        };
+
+        self.position += 8u64 + index.length as u64 * 2u64;
+        self.id += 1u64;
+
+        Ok(Some(ShapefileRecord {shape: record.shape, metadata}))
+    }
+}
+
+impl<'a> Iterator for ShapefileSequentialIterator<'a> {
+    type Item = Result<ShapefileRecord, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        match self.read_next() {
+            Ok(r) => r.map(Ok),
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e))
+            },
+        }
+    }
+}
+
+impl<'a> Iterator for ShapeIterator<'a> {
+    type Item = Shape;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let id = self.id;
+        let result = self.instance.shp_file.record(&mut self.instance.shx_file, id);
+        self.id += 1u64;
+        result.map(|r| r.shape)
+    }
+}
+
+impl<'a> Iterator for AttributeIterator<'a> {
+    type Item = dbf::Record;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let id = self.id;
+        let result = self.instance.dbf_file.record_columns(id, self.columns.as_ref().map(|c| &c[..]));
+        self.id += 1u32;
+        result
+    }
+}
+
+impl<'a, T: DeserializeOwned> Iterator for ShapefileRecordAsIterator<'a, T> {
+    type Item = Result<T, de::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let geometry_field = &self.geometry_field;
+        self.records.next().map(|r| de::from_record_with(r, geometry_field))
+    }
+}
+
+impl ShapefileWriter {
+    /// Creates the three files of a new shapefile. The DBF columns are derived from the first
+    /// record: Text becomes character columns and numbers number columns, just wide enough for
+    /// them and without decimal places for whole numbers. A column which is null in the first
+    /// record gets its type from the first value that isn't. Columns get wider if later values
+    /// need more room, up to 254 characters for text, which rewrites the records written so far.
+    pub fn new(shp_path: &Path, shx_path: &Path, dbf_path: &Path) -> Result<Self, Error> {
+        Self::create(shp_path, shx_path, dbf_path, None)
+    }
+
+    /// Creates the three files of a new shapefile, with the given DBF columns. The text of memo
+    /// columns goes into a DBT file next to the DBF file.
+    pub fn with_schema(shp_path: &Path, shx_path: &Path, dbf_path: &Path, fields: Vec<DbfField>) -> Result<Self, Error> {
+        Self::create(shp_path, shx_path, dbf_path, Some(fields))
+    }
+
+    fn create(shp_path: &Path, shx_path: &Path, dbf_path: &Path, fields: Option<Vec<DbfField>>) -> Result<Self, Error> {
+        Ok(ShapefileWriter {
+            shp_file: ShpWriter::create_file(shp_path)?,
+            shx_file: ShxWriter::create_file(shx_path)?,
+            dbf_file: DbfWriter::create_file(dbf_path, fields)?,
+            geometry_field: String::from(de::GEOMETRY_FIELD),
+            finished: false,
+        })
+    }
+
+    /// Sets the struct field which holds the shape for `write_record`. Defaults to `geometry`.
+    pub fn set_geometry_field(&mut self, name: &str) {
+        self.geometry_field = name.to_string();
+    }
+
+    /// Appends a record with the given shape and DBF values. Without a schema, the columns are
+    /// derived in alphabetical order.
+    pub fn write(&mut self, shape: &Shape, metadata: &HashMap<String, dbf::Field>) -> Result<(), Error> {
+        let mut values: Vec<(String, DbfValue)> = metadata.iter()
+                                                          .map(|(k, v)| (k.clone(), DbfValue::from(v)))
+                                                          .collect();
+        values.sort_by(|a, b| a.0.cmp(&b.0));
+
+        self.write_values(shape, &values)
+    }
+
+    /// Appends a record from a struct. See the `ser` module for how the struct is mapped onto the
+    /// shape and the columns.
+    pub fn write_record<T: Serialize>(&mut self, record: &T) -> Result<(), Error> {
+        let (shape, values) = match ser::to_values(record, &self.geometry_field) {
+            Ok(v) => v,
+            Err(e) => return Err(Error::new(ErrorKind::Other, e)),
+        };
+
+        self.write_values(&shape, &values)
+    }
+
+    /// Appends a record to all three files.
+    fn write_values(&mut self, shape: &Shape, values: &[(String, DbfValue)]) -> Result<(), Error> {
+        if self.finished {
+            return Err(Error::new(ErrorKind::Other, "Shapefile is already finished!"));
+        }
+
+        // Encode the attributes first, so that nothing gets written if they don't fit
+        let dbf_record = self.dbf_file.encode_record(values)?;
+        let index = self.shp_file.write_record(shape)?;
+        self.shx_file.write_record(&index)?;
+        self.dbf_file.write_encoded(&dbf_record)
+    }
+
+    /// Writes the headers and flushes all files. This also happens when the writer is dropped,
+    /// but errors are lost there.
+    pub fn finish(mut self) -> Result<(), Error> {
+        self.write_headers()
+    }
+
+    fn write_headers(&mut self) -> Result<(), Error> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+
+        let header = self.shp_file.finish()?;
+        self.shx_file.finish(header)?;
+        self.dbf_file.finish()
+    }
+}
+
+impl Drop for ShapefileWriter {
+    fn drop(&mut self) {
+        let _ = self.write_headers();
+    }
+}
@@ -7,11 +7,11 @@
 //!
 
 use std::fs::File;
-use std::io::{Error, ErrorKind, BufReader, Read, SeekFrom, Seek};
+use std::io::{self, Error, ErrorKind, BufReader, BufWriter, Read, Write, SeekFrom, Seek};
 use std::path::Path;
-use byteorder::{BigEndian, LittleEndian, ReadBytesExt};
+use byteorder::{BigEndian, LittleEndian, ReadBytesExt, WriteBytesExt};
 
-use super::{ShpFile, ShxFile, ShxRecord, FileHeader, BoundingBoxZ};
+use super::{ShpFile, ShpWriter, ShxFile, ShxRecord, FileHeader, BoundingBoxZ};
 use super::shape::*;
 
 /// One of multiple geometric data records in a SHP file.
@@ -20,7 +20,7 @@ pub struct Record {
     /// The ID of the record (starting at 1)
     pub record_number: i32,
     /// Length of the record contents section in 16-bit words
-    content_length: i32,
+    pub(crate) content_length: i32,
     /// The shape
     pub shape: Shape,
 }
@@ -64,7 +64,7 @@ impl FileHeader {
 
     /// Creates a new empty file header
     pub fn new() -> Self {
-        FileHeader {file_length: 0, shape_type: 0, bounding_box: BoundingBoxZ::new()}
+        FileHeader {file_length: 0, shape_type: ShapeType::NullShape, bounding_box: BoundingBoxZ::new()}
     }
 
     /// Reads a file header from the given input stream
@@ -97,7 +97,10 @@ impl FileHeader {
         }
 
         // Read shape type - Little Endian
-        result.shape_type = try!(file.read_i32::<LittleEndian>());
+        result.shape_type = match ShapeType::from_i32(try!(file.read_i32::<LittleEndian>())) {
+            Some(t) => t,
+            None => return Err(Error::new(ErrorKind::Other, "SHP header has an unknown shape type!")),
+        };
 
         // Read bounding box
         result.bounding_box = try!(BoundingBoxZ::parse(file));
@@ -105,6 +108,21 @@ impl FileHeader {
         // Return our result
         Ok(result)
     }
+
+    /// Writes the file header to the given output stream. Always 100 bytes.
+    pub fn write<T: Write>(&self, file: &mut T) -> Result<(), Error> {
+        file.write_i32::<BigEndian>(Self::SHP_MAGIC_NUMBER)?;
+
+        // 20 unused bytes
+        for _ in 0..5 {
+            file.write_i32::<BigEndian>(0)?;
+        }
+
+        file.write_i32::<BigEndian>(self.file_length)?;
+        file.write_i32::<LittleEndian>(Self::SHP_VERSION)?;
+        file.write_i32::<LittleEndian>(self.shape_type.to_i32())?;
+        self.bounding_box.write(file)
+    }
 }
 
 impl ShpFile {
@@ -139,6 +157,43 @@ impl ShpFile {
         return result.parse_header();
     }
 
+    /// Offset of the first record in bytes, right after the header.
+    pub const FIRST_RECORD_OFFSET: u64 = 100;
+
+    /// The offset in bytes where the records end. This is the file length from the header, unless
+    /// the file is shorter than that.
+    pub fn records_end(&self) -> Result<u64, Error> {
+        let actual = self.file.get_ref().metadata()?.len();
+        Ok(actual.min(self.header.file_length as u64 * 2u64))
+    }
+
+    /// Moves to the first record, for reading the records one after the other with `read_next_record`.
+    pub fn seek_first_record(&mut self) -> Result<(), Error> {
+        self.file.seek(SeekFrom::Start(Self::FIRST_RECORD_OFFSET))?;
+        Ok(())
+    }
+
+    /// Reads the record at the current position of the file, without seeking. Any content beyond
+    /// what the shape needs is skipped, so that the file is positioned at the next record.
+    /// Returns the record and its index entry, given the offset of the record in bytes.
+    pub fn read_next_record(&mut self, position: u64) -> Result<(Record, ShxRecord), Error> {
+        let (record, read) = Record::parse(&mut self.file)?;
+
+        // Record header plus content
+        let expected = 8u64 + record.content_length as u64 * 2u64;
+        if record.content_length < 0 || (read as u64) > expected {
+            return Err(Error::new(ErrorKind::Other, "SHP record is longer than its content length!"));
+        }
+
+        let padding = expected - read as u64;
+        if padding > 0 {
+            io::copy(&mut (&mut self.file).take(padding), &mut io::sink())?;
+        }
+
+        let index = ShxRecord {offset: (position / 2u64) as i32, length: record.content_length};
+        Ok((record, index))
+    }
+
     pub fn record(&mut self, shx_file: &mut ShxFile, id: u64) -> Option<Record> {
         let rec: ShxRecord;
         match shx_file.record(id) {
@@ -161,3 +216,71 @@ impl ShpFile {
         }
     }
 }
+
+impl ShpWriter {
+    /// Length of the file header in 16-bit words
+    const HEADER_LENGTH: i32 = 50;
+    /// Length of a record header in 16-bit words
+    const RECORD_HEADER_LENGTH: i32 = 4;
+
+    /// Creates the SHP file. The header is written when finishing.
+    pub fn create_file(path: &Path) -> Result<Self, Error> {
+        let mut file = BufWriter::new(File::create(path)?);
+
+        // Leave room for the header
+        file.write_all(&[0u8; 100])?;
+
+        let mut header = FileHeader::new();
+        header.file_length = Self::HEADER_LENGTH;
+
+        Ok(ShpWriter {header, has_extent: false, num_records: 0, file})
+    }
+
+    /// Appends a record with the given shape. The bounding box and the ranges of the shape are
+    /// computed from its points, whatever the shape says. Returns the entry for the index file.
+    pub fn write_record(&mut self, shape: &Shape) -> Result<ShxRecord, Error> {
+        let shape = &shape.with_computed_extents();
+        let shape_type = shape.shape_type();
+
+        // All shapes must be of the same type, or null
+        if *shape != Shape::NullShape {
+            if self.header.shape_type == ShapeType::NullShape {
+                self.header.shape_type = shape_type;
+            } else if self.header.shape_type != shape_type {
+                return Err(Error::new(ErrorKind::Other, "Shape type differs from the other shapes in the SHP file!"));
+            }
+        }
+
+        let mut content: Vec<u8> = vec![];
+        shape.write(&mut content)?;
+
+        self.num_records += 1;
+        let result = ShxRecord {offset: self.header.file_length, length: content.len() as i32 / 2};
+
+        self.file.write_i32::<BigEndian>(self.num_records)?;
+        self.file.write_i32::<BigEndian>(result.length)?;
+        self.file.write_all(&content)?;
+
+        self.header.file_length += Self::RECORD_HEADER_LENGTH + result.length;
+
+        if let Some(extent) = shape.stored_extent() {
+            if self.has_extent {
+                self.header.bounding_box.extend(&extent);
+            } else {
+                self.header.bounding_box = extent;
+                self.has_extent = true;
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Writes the header and flushes the file. Returns the header, which the SHX file needs.
+    pub fn finish(&mut self) -> Result<&FileHeader, Error> {
+        self.file.seek(SeekFrom::Start(0))?;
+        self.header.write(&mut self.file)?;
+        self.file.flush()?;
+
+        Ok(&self.header)
+    }
+}
@@ -3,11 +3,30 @@
 //! These files are basically index files for the SHP files: They contain, in ascending order, all
 //! the entries that can be found in the SHP file. Just a simple index.
 
-use std::io::{Error, ErrorKind, BufReader, Read, Seek, SeekFrom};
+use std::io::{Error, ErrorKind, BufReader, BufWriter, Read, Write, Seek, SeekFrom};
 use std::path::Path;
 use std::fs::File;
-use byteorder::{BigEndian, ReadBytesExt};
-use super::{FileHeader, ShxFile, ShxRecord};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use super::{FileHeader, ShpFile, ShxFile, ShxRecord, ShxWriter};
+
+/// Writes a fresh SHX file for the given SHP file, with the offsets and content lengths taken from
+/// the SHP records and the header copied from the SHP header. This repairs SHX files that are
+/// missing, truncated or otherwise broken.
+///
+/// Returns the number of indexed records. If the SHP file itself is damaged, only the records in
+/// front of the damage are indexed.
+pub fn rebuild_shx(shp_path: &Path, shx_path: &Path) -> Result<u64, Error> {
+    let mut shp_file = ShpFile::parse_file(shp_path)?;
+    let index = ShxFile::from_shp(&mut shp_file)?;
+
+    let mut writer = ShxWriter::create_file(shx_path)?;
+    for record in &index.records {
+        writer.write_record(record)?;
+    }
+    writer.finish(&shp_file.header)?;
+
+    Ok(index.records.len() as u64)
+}
 
 impl ShxRecord {
     /// Constructs a zero-initialized Record
@@ -31,25 +50,36 @@ impl ShxRecord {
 
         Ok(result)
     }
+
+    /// Writes the record to the binary output stream.
+    /// Produces 8 bytes.
+    pub fn write<T: Write>(&self, file: &mut T) -> Result<(), Error> {
+        file.write_i32::<BigEndian>(self.offset)?;
+        file.write_i32::<BigEndian>(self.length)?;
+
+        Ok(())
+    }
 }
 
 impl ShxFile {
     /// Parses the SHX file from the supplied input stream
-    fn parse_header(mut self) -> Result<Self, Error> {
-        try!(self.file.seek(SeekFrom::Start(0)));
+    fn parse_header(mut self, mut file: BufReader<File>) -> Result<Self, Error> {
+        try!(file.seek(SeekFrom::Start(0)));
 
         // Try parsing the header
-        self.header = try!(FileHeader::parse(&mut self.file));
+        self.header = try!(FileHeader::parse(&mut file));
+        self.file = Some(file);
 
         Ok(self)
     }
 
     /// Given a file name, parses the SHX file and returns the result.
     pub fn parse_file(path: &Path) -> Result<Self, Error> {
-        let result = ShxFile {file: BufReader::new(try!(File::open(path))), header: FileHeader::new()};
+        let file = BufReader::new(try!(File::open(path)));
+        let result = ShxFile {file: None, records: vec![], cursor: 0, header: FileHeader::new()};
 
         // Check file header is actually there before attempting any reads
-        match result.file.get_ref().metadata() {
+        match file.get_ref().metadata() {
             Ok(m) => {
                 if m.len() < 100 {
                     return Err(Error::new(ErrorKind::Other, "SHX file has invalid size!"));
@@ -61,7 +91,38 @@ impl ShxFile {
         }
 
         // Parse the data
-        return result.parse_header();
+        return result.parse_header(file);
+    }
+
+    /// Builds the index in memory by reading the SHP file from front to back. Reading stops at
+    /// the first record which can't be parsed, so a damaged SHP file yields the records before the
+    /// damage.
+    pub fn from_shp(shp_file: &mut ShpFile) -> Result<Self, Error> {
+        let end = shp_file.records_end()?;
+        let mut position = ShpFile::FIRST_RECORD_OFFSET;
+        let mut records: Vec<ShxRecord> = vec![];
+
+        shp_file.seek_first_record()?;
+        while position < end {
+            match shp_file.read_next_record(position) {
+                Ok((_, index)) => {
+                    position += 8u64 + index.length as u64 * 2u64;
+                    if position > end {
+                        break;
+                    }
+                    records.push(index);
+                },
+                Err(_) => break,
+            }
+        }
+
+        let header = FileHeader {
+            file_length: 50 + 4 * records.len() as i32,
+            shape_type: shp_file.header.shape_type,
+            bounding_box: shp_file.header.bounding_box,
+        };
+
+        Ok(ShxFile {file: None, records, cursor: 0, header})
     }
 
     /// Returns a record with the given ID.
@@ -77,9 +138,14 @@ impl ShxFile {
             return None;
         }
 
+        let file = match self.file {
+            Some(ref mut f) => f,
+            None => return self.records.get(id as usize - 1).cloned(),
+        };
+
         let record_pos = header_size + (id - 1u64) * record_size;
 
-        match self.file.seek(SeekFrom::Start(record_pos)) {
+        match file.seek(SeekFrom::Start(record_pos)) {
             Ok(p) => {
                 if p != record_pos {
                     return None;
@@ -88,18 +154,78 @@ impl ShxFile {
             Err(_) => return None,
         }
 
-        match ShxRecord::parse(&mut self.file) {
+        match ShxRecord::parse(file) {
             Ok(v) => return Some(v),
             Err(_) => return None,
         }
     }
 
+    /// Moves to the first record, for reading the records one after the other with `read_next_record`.
+    pub fn seek_first_record(&mut self) -> Result<(), Error> {
+        match self.file {
+            Some(ref mut f) => {
+                f.seek(SeekFrom::Start(100u64))?;
+            },
+            None => self.cursor = 0,
+        }
+        Ok(())
+    }
+
+    /// Reads the record at the current position of the file, without seeking.
+    pub fn read_next_record(&mut self) -> Result<ShxRecord, Error> {
+        match self.file {
+            Some(ref mut f) => ShxRecord::parse(f),
+            None => {
+                let result = self.records.get(self.cursor).cloned();
+                self.cursor += 1;
+                result.ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "No more SHX records!"))
+            },
+        }
+    }
+
+    /// Whether the index was built in memory, because there was no SHX file.
+    pub fn is_in_memory(&self) -> bool {
+        self.file.is_none()
+    }
+
     /// Gets the amount of records listed in the index file.
     pub fn num_records(&self) -> u64 {
-        let file_size = self.header.file_length as u64 * 2u64;
+        let file_size = self.header.file_length.max(0) as u64 * 2u64;
         let header_size = 100u64;
         let record_size = 8u64;
 
-        (file_size - header_size) as u64 / record_size
+        file_size.saturating_sub(header_size) / record_size
+    }
+}
+
+impl ShxWriter {
+    /// Creates the SHX file. The header is written when finishing.
+    pub fn create_file(path: &Path) -> Result<Self, Error> {
+        let mut file = BufWriter::new(File::create(path)?);
+
+        // Leave room for the header
+        file.write_all(&[0u8; 100])?;
+
+        Ok(ShxWriter {num_records: 0, file})
+    }
+
+    /// Appends an index record.
+    pub fn write_record(&mut self, record: &ShxRecord) -> Result<(), Error> {
+        self.num_records += 1;
+        record.write(&mut self.file)
+    }
+
+    /// Writes the header, which is the one of the SHP file with a different file length, and
+    /// flushes the file.
+    pub fn finish(&mut self, shp_header: &FileHeader) -> Result<(), Error> {
+        let header = FileHeader {
+            file_length: 50 + 4 * self.num_records,
+            shape_type: shp_header.shape_type,
+            bounding_box: shp_header.bounding_box,
+        };
+
+        self.file.seek(SeekFrom::Start(0))?;
+        header.write(&mut self.file)?;
+        self.file.flush()
     }
 }
@@ -0,0 +1,529 @@
+//! Module for simplifying shapes
+//!
+//! Polylines and polygons lose the vertices which hardly change their form, by the Douglas-Peucker
+//! or the Visvalingam-Whyatt algorithm. Every part stays, the ends of polylines stay, and rings
+//! stay closed with at least three corners. The remaining vertices keep their Z and M values. If
+//! a simplified shape isn't valid while the original was, it is simplified again with half the
+//! tolerance, and left as it is if that doesn't help.
+//!
+//! When simplifying many shapes, the topology between them can be preserved: The rings and lines
+//! are cut where they stop sharing their path with others, and every such piece is simplified only
+//! once, so that neighboring polygons still share their edges afterwards.
+
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::io::Error;
+use std::path::Path;
+
+use super::Shapefile;
+use super::geometry::{self, key, Key, Vertex};
+use super::shape::{Shape, Point};
+
+/// How many times the tolerance is halved for shapes which became invalid.
+const MAX_RETRIES: usize = 8;
+
+/// The way vertices are chosen for removal.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Algorithm {
+    /// Keeps the vertices farther away from the simplified line than the tolerance.
+    DouglasPeucker,
+    /// Removes vertices as long as the triangle with their neighbors is smaller than the tolerance,
+    /// which is an area.
+    VisvalingamWhyatt,
+}
+
+/// Options for `Shape::simplified_with`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct SimplifyOptions {
+    /// The algorithm
+    pub algorithm: Algorithm,
+    /// The distance for Douglas-Peucker, the area for Visvalingam-Whyatt
+    pub tolerance: f64,
+    /// Whether edges shared between shapes, or between the parts of a shape, are simplified the
+    /// same way everywhere.
+    pub preserve_topology: bool,
+}
+
+impl SimplifyOptions {
+    /// Douglas-Peucker with the given distance, without preserving topology.
+    pub fn new(tolerance: f64) -> Self {
+        SimplifyOptions {algorithm: Algorithm::DouglasPeucker, tolerance, preserve_topology: false}
+    }
+
+    /// Sets the algorithm.
+    pub fn algorithm(mut self, algorithm: Algorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Sets whether shared edges are simplified the same way everywhere.
+    pub fn preserve_topology(mut self, preserve: bool) -> Self {
+        self.preserve_topology = preserve;
+        self
+    }
+}
+
+/// Douglas-Peucker on a line whose ends are kept.
+fn douglas_peucker(points: &[Point], tolerance: f64) -> Vec<bool> {
+    let mut keep = vec![false; points.len()];
+    if points.is_empty() {
+        return keep;
+    }
+    keep[0] = true;
+    keep[points.len() - 1] = true;
+
+    let mut pending: Vec<(usize, usize)> = vec![(0, points.len() - 1)];
+    while let Some((first, last)) = pending.pop() {
+        let mut farthest: Option<(usize, f64)> = None;
+        for i in first + 1..last {
+            let d = geometry::point_segment_distance(points[i], points[first], points[last]);
+            if farthest.is_none_or(|f| d > f.1) {
+                farthest = Some((i, d));
+            }
+        }
+        if let Some((i, d)) = farthest {
+            if d > tolerance {
+                keep[i] = true;
+                pending.push((first, i));
+                pending.push((i, last));
+            }
+        }
+    }
+    keep
+}
+
+/// A vertex in the queue of Visvalingam-Whyatt, ordered so that the smallest area comes first.
+#[derive(PartialEq)]
+struct Candidate {
+    area: f64,
+    index: usize,
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.area.partial_cmp(&self.area).unwrap_or(Ordering::Equal).then(other.index.cmp(&self.index))
+    }
+}
+
+/// Visvalingam-Whyatt on a line whose ends are kept.
+fn visvalingam_whyatt(points: &[Point], tolerance: f64) -> Vec<bool> {
+    let n = points.len();
+    let mut keep = vec![true; n];
+    if n <= 2 {
+        return keep;
+    }
+
+    let area = |a: usize, b: usize, c: usize| geometry::cross(points[a], points[b], points[c]).abs() / 2f64;
+    let mut previous: Vec<usize> = (0..n).map(|i| i.saturating_sub(1)).collect();
+    let mut next: Vec<usize> = (0..n).map(|i| i + 1).collect();
+    let areas_of = |i: usize| if i == 0 || i == n - 1 { f64::INFINITY } else { area(i - 1, i, i + 1) };
+    let mut areas: Vec<f64> = (0..n).map(areas_of).collect();
+    let mut queue: BinaryHeap<Candidate> = (1..n - 1).map(|i| Candidate {area: areas[i], index: i}).collect();
+
+    // Removing the smallest triangle first, until all triangles are big enough
+    while let Some(Candidate {area: a, index: i}) = queue.pop() {
+        if !keep[i] || a != areas[i] {
+            continue;
+        }
+        if a >= tolerance {
+            break;
+        }
+
+        keep[i] = false;
+        let (p, q) = (previous[i], next[i]);
+        next[p] = q;
+        previous[q] = p;
+        for &j in &[p, q] {
+            if j != 0 && j != n - 1 {
+                areas[j] = area(previous[j], j, next[j]);
+                queue.push(Candidate {area: areas[j], index: j});
+            }
+        }
+    }
+    keep
+}
+
+fn simplify_line(points: &[Point], tolerance: f64, algorithm: Algorithm) -> Vec<bool> {
+    match algorithm {
+        Algorithm::DouglasPeucker => douglas_peucker(points, tolerance),
+        Algorithm::VisvalingamWhyatt => visvalingam_whyatt(points, tolerance),
+    }
+}
+
+/// Simplifies a ring given without its closing vertex. The ring is cut at its first vertex and the
+/// vertex farthest from it, and at least three vertices stay.
+fn simplify_loop(points: &[Point], tolerance: f64, algorithm: Algorithm) -> Vec<bool> {
+    let n = points.len();
+    if n <= 3 {
+        return vec![true; n];
+    }
+
+    let distance = |i: usize| (points[i].x - points[0].x).hypot(points[i].y - points[0].y);
+    let far = (1..n).max_by(|&i, &j| distance(i).partial_cmp(&distance(j)).unwrap_or(Ordering::Equal)).unwrap_or(1);
+
+    let mut second_half: Vec<Point> = points[far..].to_vec();
+    second_half.push(points[0]);
+    let mut keep = simplify_line(&points[..far + 1], tolerance, algorithm);
+    keep.resize(n, false);
+    let rest = simplify_line(&second_half, tolerance, algorithm);
+    for (i, &k) in rest[..rest.len() - 1].iter().enumerate() {
+        keep[far + i] = keep[far + i] || k;
+    }
+
+    if keep.iter().filter(|&&k| k).count() < 3 {
+        if let Some(i) = farthest_from_chord(points, 0, far).or_else(|| farthest_from_chord(&second_half, 0, second_half.len() - 1).map(|i| far + i)) {
+            keep[i] = true;
+        }
+    }
+    keep
+}
+
+/// The vertex between first and last which is farthest from the segment between them.
+fn farthest_from_chord(points: &[Point], first: usize, last: usize) -> Option<usize> {
+    (first + 1..last).max_by(|&i, &j| {
+        let di = geometry::point_segment_distance(points[i], points[first], points[last]);
+        let dj = geometry::point_segment_distance(points[j], points[first], points[last]);
+        di.partial_cmp(&dj).unwrap_or(Ordering::Equal)
+    })
+}
+
+/// A piece of a part which is simplified on its own: Either the part between two junctions, or
+/// a whole ring without junctions.
+struct Piece {
+    /// The indices of the vertices in the part, in the order of the canonical form
+    order: Vec<usize>,
+    /// The canonical form, the same for every part sharing the piece
+    key: Vec<Key>,
+    /// Whether the piece is a whole ring
+    closed: bool,
+}
+
+impl Piece {
+    /// Creates a piece from the indices of its vertices, turned and rotated into canonical form.
+    fn new(indices: Vec<usize>, part: &[Point], closed: bool) -> Self {
+        let keys = |order: &[usize]| -> Vec<Key> { order.iter().map(|&i| key(part[i])).collect() };
+
+        let forward = indices;
+        let mut candidates: Vec<Vec<usize>> = vec![];
+        if closed {
+            // Rings start at their smallest vertex, and run the way with the smaller second vertex
+            let start = (0..forward.len()).min_by_key(|&i| key(part[forward[i]])).unwrap_or(0);
+            let mut rotated: Vec<usize> = forward[start..].to_vec();
+            rotated.extend_from_slice(&forward[..start]);
+            let mut backward: Vec<usize> = vec![rotated[0]];
+            backward.extend(rotated[1..].iter().rev());
+            candidates.push(rotated);
+            candidates.push(backward);
+        } else {
+            let backward: Vec<usize> = forward.iter().rev().cloned().collect();
+            candidates.push(forward);
+            candidates.push(backward);
+        }
+
+        let order = candidates.into_iter().min_by_key(|o| keys(o)).unwrap_or_default();
+        let key = keys(&order);
+        Piece {order, key, closed}
+    }
+}
+
+/// The vertices where lines and rings meet or part ways: Those with different neighbors in
+/// different places, and the ends of polylines.
+fn junctions(parts: &[(Vec<Point>, bool)]) -> HashSet<Key> {
+    let mut neighbors: HashMap<Key, (Key, Key)> = HashMap::new();
+    let mut result: HashSet<Key> = HashSet::new();
+
+    for &(ref points, ring) in parts {
+        let n = points.len();
+        for i in 0..n {
+            let (previous, next) = if ring {
+                (points[(i + n - 1) % n], points[(i + 1) % n])
+            } else if i == 0 || i == n - 1 {
+                result.insert(key(points[i]));
+                continue;
+            } else {
+                (points[i - 1], points[i + 1])
+            };
+
+            let pair = if key(previous) < key(next) { (key(previous), key(next)) } else { (key(next), key(previous)) };
+            match neighbors.get(&key(points[i])) {
+                Some(&p) if p != pair => {
+                    result.insert(key(points[i]));
+                },
+                Some(_) => (),
+                None => {
+                    neighbors.insert(key(points[i]), pair);
+                },
+            }
+        }
+    }
+    result
+}
+
+/// Cuts a part into pieces at the junctions.
+fn pieces(points: &[Point], ring: bool, junctions: &HashSet<Key>) -> Vec<Piece> {
+    let n = points.len();
+    if n < 2 || (ring && n <= 3) {
+        return vec![];
+    }
+
+    let mut cuts: Vec<usize> = (0..n).filter(|&i| junctions.contains(&key(points[i]))).collect();
+    if !ring {
+        if cuts.first() != Some(&0) {
+            cuts.insert(0, 0);
+        }
+        if cuts.last() != Some(&(n - 1)) {
+            cuts.push(n - 1);
+        }
+        return cuts.windows(2).map(|w| Piece::new((w[0]..w[1] + 1).collect(), points, false)).collect();
+    }
+
+    if cuts.is_empty() {
+        return vec![Piece::new((0..n).collect(), points, true)];
+    }
+    (0..cuts.len()).map(|c| {
+        let first = cuts[c];
+        let last = if c + 1 < cuts.len() { cuts[c + 1] } else { cuts[0] + n };
+        Piece::new((first..last + 1).map(|i| i % n).collect(), points, false)
+    }).collect()
+}
+
+/// Simplifies the shapes in one go, sharing the simplified pieces if topology is preserved.
+fn simplify_once(shapes: &[&Shape], tolerance: f64, options: &SimplifyOptions) -> Vec<Shape> {
+    // The vertices of every part, rings without their closing vertex
+    let parts: Vec<Option<Vec<Vec<Vertex>>>> = shapes.iter().map(|s| {
+        let ring = match **s {
+            Shape::PolyLine {..} | Shape::PolyLineM {..} | Shape::PolyLineZ {..} => false,
+            Shape::Polygon {..} | Shape::PolygonM {..} | Shape::PolygonZ {..} => true,
+            _ => return None,
+        };
+        Some(geometry::vertex_parts(s).into_iter().map(|mut p| {
+            if ring && p.len() > 1 && p[0].point == p[p.len() - 1].point {
+                p.pop();
+            }
+            p
+        }).collect())
+    }).collect();
+    let is_ring = |s: &Shape| matches!(*s, Shape::Polygon {..} | Shape::PolygonM {..} | Shape::PolygonZ {..});
+
+    let all: Vec<(Vec<Point>, bool)> = parts.iter().zip(shapes).flat_map(|(p, s)| {
+        p.iter().flat_map(|p| p.iter()).map(move |part| (part.iter().map(|v| v.point).collect::<Vec<Point>>(), is_ring(s)))
+    }).collect();
+    let junctions = if options.preserve_topology { junctions(&all) } else { HashSet::new() };
+
+    let mut pieces_of_parts: Vec<Vec<Piece>> = vec![];
+    let mut simplified: HashMap<Vec<Key>, Vec<bool>> = HashMap::new();
+    for &(ref points, ring) in &all {
+        let part_pieces = pieces(points, ring, &junctions);
+        for piece in &part_pieces {
+            if !simplified.contains_key(&piece.key) {
+                let canonical: Vec<Point> = piece.order.iter().map(|&i| points[i]).collect();
+                let keep = if piece.closed {
+                    simplify_loop(&canonical, tolerance, options.algorithm)
+                } else {
+                    simplify_line(&canonical, tolerance, options.algorithm)
+                };
+                simplified.insert(piece.key.clone(), keep);
+            }
+        }
+        pieces_of_parts.push(part_pieces);
+    }
+
+    let kept = |points: &[Point], part_pieces: &[Piece], simplified: &HashMap<Vec<Key>, Vec<bool>>| -> Vec<bool> {
+        if part_pieces.is_empty() {
+            return vec![true; points.len()];
+        }
+        let mut keep = vec![false; points.len()];
+        for piece in part_pieces {
+            for (j, &k) in simplified[&piece.key].iter().enumerate() {
+                if k {
+                    keep[piece.order[j]] = true;
+                }
+            }
+        }
+        keep
+    };
+
+    // Rings which fell below three vertices get back the farthest vertex of each of their pieces
+    for (&(ref points, ring), part_pieces) in all.iter().zip(&pieces_of_parts) {
+        if ring && kept(points, part_pieces, &simplified).iter().filter(|&&k| k).count() < 3 {
+            for piece in part_pieces.iter().filter(|p| !p.closed) {
+                let canonical: Vec<Point> = piece.order.iter().map(|&i| points[i]).collect();
+                if let Some(i) = farthest_from_chord(&canonical, 0, canonical.len() - 1) {
+                    if let Some(keep) = simplified.get_mut(&piece.key) {
+                        keep[i] = true;
+                    }
+                }
+            }
+        }
+    }
+
+    let mut flags = all.iter().zip(&pieces_of_parts).map(|((points, _), part_pieces)| kept(points, part_pieces, &simplified));
+    shapes.iter().zip(parts).map(|(&shape, parts)| {
+        let parts = match parts {
+            Some(p) => p,
+            None => return shape.clone(),
+        };
+        let ring = is_ring(shape);
+        let parts = parts.into_iter().map(|part| {
+            let keep = flags.next().unwrap_or_default();
+            let mut result: Vec<Vertex> = part.into_iter().zip(keep).filter(|&(_, k)| k).map(|(v, _)| v).collect();
+            if ring && !result.is_empty() {
+                let first = result[0];
+                result.push(first);
+            }
+            result
+        }).collect();
+        geometry::from_vertex_parts(shape, parts)
+    }).collect()
+}
+
+/// Simplifies shapes together, so that shared edges stay shared if the options say so.
+pub fn simplify_shapes(shapes: &[Shape], options: &SimplifyOptions) -> Vec<Shape> {
+    let all: Vec<&Shape> = shapes.iter().collect();
+    let valid: Vec<bool> = shapes.iter().map(|s| s.is_valid()).collect();
+    let broken = |result: &[Shape]| -> Vec<usize> {
+        (0..shapes.len()).filter(|&i| valid[i] && !result[i].is_valid()).collect()
+    };
+
+    let mut tolerance = options.tolerance;
+    let mut result = simplify_once(&all, tolerance, options);
+    for _ in 0..MAX_RETRIES {
+        let indices = broken(&result);
+        if indices.is_empty() {
+            return result;
+        }
+
+        tolerance /= 2f64;
+        if options.preserve_topology {
+            result = simplify_once(&all, tolerance, options);
+        } else {
+            let again = simplify_once(&indices.iter().map(|&i| &shapes[i]).collect::<Vec<&Shape>>(), tolerance, options);
+            for (i, shape) in indices.into_iter().zip(again) {
+                result[i] = shape;
+            }
+        }
+    }
+
+    for i in broken(&result) {
+        result[i] = shapes[i].clone();
+    }
+    result
+}
+
+impl Shape {
+    /// Returns the shape simplified by Douglas-Peucker with the given distance.
+    pub fn simplified(&self, tolerance: f64) -> Shape {
+        self.simplified_with(&SimplifyOptions::new(tolerance))
+    }
+
+    /// Returns the shape simplified with the given options. Preserving topology keeps the edges
+    /// which the parts of the shape share.
+    pub fn simplified_with(&self, options: &SimplifyOptions) -> Shape {
+        simplify_shapes(::std::slice::from_ref(self), options).pop().unwrap_or(Shape::NullShape)
+    }
+}
+
+impl Shapefile {
+    /// Writes a simplified copy of the layer into a new shapefile, with all attributes. With
+    /// topology preserved, all shapes are read into memory first. Returns the number of records
+    /// written.
+    pub fn simplify_into(&mut self, shp_path: &Path, shx_path: &Path, dbf_path: &Path, options: &SimplifyOptions) -> Result<u64, Error> {
+        if !options.preserve_topology {
+            return self.rewrite(shp_path, shx_path, dbf_path, |shape| Ok(Some(shape.simplified_with(options))));
+        }
+
+        let mut shapes: Vec<Shape> = vec![];
+        for record in self.iter_sequential() {
+            shapes.push(record?.shape);
+        }
+        let mut simplified = simplify_shapes(&shapes, options).into_iter();
+        self.rewrite(shp_path, shx_path, dbf_path, |_| Ok(simplified.next()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Algorithm, SimplifyOptions, simplify_shapes};
+    use super::super::shape::{Shape, BoundingBox, Point, ZRange, MRange};
+    use super::super::geometry::polygon;
+
+    fn points_of(shape: &Shape) -> Vec<(f64, f64)> {
+        match *shape {
+            Shape::Polygon {ref points, ..} | Shape::PolyLineZ {ref points, ..} => points.iter().map(|p| (p.x, p.y)).collect(),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn test_simplify_line() {
+        let xs = [0., 1., 2., 3., 4.];
+        let ys = [0., 1.7, 3.3, 5., 0.];
+        let line = Shape::PolyLineZ {
+            bounding_box: BoundingBox::new(),
+            parts: vec![0],
+            points: xs.iter().zip(&ys).map(|(&x, &y)| Point {x, y}).collect(),
+            z_range: ZRange {min: 0., max: 0.},
+            z: vec![10., 11., 12., 13., 14.],
+            m_range: MRange {min: 0., max: 0.},
+            m: vec![0., 1., 2., 3., 4.],
+        };
+
+        for &algorithm in &[Algorithm::DouglasPeucker, Algorithm::VisvalingamWhyatt] {
+            let simplified = line.simplified_with(&SimplifyOptions::new(0.5).algorithm(algorithm));
+            if points_of(&simplified) != vec![(0., 0.), (3., 5.), (4., 0.)] {
+                panic!("{:?}", simplified)
+            }
+            if let Shape::PolyLineZ {ref z, ref m, ..} = simplified {
+                if *z != vec![10., 13., 14.] || *m != vec![0., 3., 4.] {
+                    panic!()
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_simplify_ring() {
+        // A square with wiggly edges, and a tiny island which must not vanish
+        let square = polygon(&[
+            &[(0., 0.), (0., 5.), (0.1, 7.), (0., 10.), (5., 10.1), (10., 10.), (10., 0.), (5., -0.1), (0., 0.)],
+            &[(20., 20.), (20., 20.1), (20.1, 20.1), (20.1, 20.), (20., 20.)],
+        ]);
+        let simplified = square.simplified(1.0);
+        if points_of(&simplified) != vec![(0., 0.), (0., 10.), (10., 10.), (10., 0.), (0., 0.), (20., 20.), (20., 20.1), (20.1, 20.1), (20., 20.)] {
+            panic!("{:?}", simplified)
+        }
+        if !simplified.is_valid() {
+            panic!("{:?}", simplified.validate())
+        }
+    }
+
+    #[test]
+    fn test_preserve_topology() {
+        // Two neighbors sharing a wiggly edge, which runs the other way in the second ring
+        let left = polygon(&[&[(0., 0.), (0., 10.), (5., 10.), (5.2, 7.), (4.8, 5.), (5.2, 3.), (5., 0.), (0., 0.)]]);
+        let right = polygon(&[&[(5., 0.), (5.2, 3.), (4.8, 5.), (5.2, 7.), (5., 10.), (10., 10.), (10., 0.), (5., 0.)]]);
+        let options = SimplifyOptions::new(0.5).preserve_topology(true);
+        let simplified = simplify_shapes(&[left, right], &options);
+
+        let shared = |shape: &Shape| -> Vec<(f64, f64)> {
+            let mut points: Vec<(f64, f64)> = points_of(shape).into_iter().filter(|p| p.0 > 4. && p.0 < 6.).collect();
+            points.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            points.dedup();
+            points
+        };
+        if shared(&simplified[0]) != shared(&simplified[1]) || shared(&simplified[0]).len() != 2 {
+            panic!("{:?}", simplified)
+        }
+        if simplified.iter().any(|s| !s.is_valid()) {
+            panic!()
+        }
+    }
+}
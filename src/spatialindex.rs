@@ -0,0 +1,158 @@
+//! Module for the spatial index
+//!
+//! The index is a static R-tree: The items are sorted into tiles of neighboring boxes, which are
+//! grouped into nodes, which are grouped into nodes again, up to a single root. A query only
+//! descends into the nodes whose box overlaps the query box.
+
+use std::cmp::Ordering;
+
+use super::{Shapefile, SpatialIndex};
+use super::geometry;
+use super::shape::{BoundingBox, Point};
+
+/// The default number of children of a node.
+const DEFAULT_NODE_SIZE: usize = 16;
+
+fn union(boxes: &[BoundingBox]) -> BoundingBox {
+    let mut result = boxes[0];
+    for b in &boxes[1..] {
+        result.x_min = result.x_min.min(b.x_min);
+        result.y_min = result.y_min.min(b.y_min);
+        result.x_max = result.x_max.max(b.x_max);
+        result.y_max = result.y_max.max(b.y_max);
+    }
+    result
+}
+
+fn compare_centers(a: f64, b: f64) -> Ordering {
+    a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+}
+
+impl SpatialIndex {
+    /// Builds the index over items given by their id and bounding box.
+    pub fn new(items: Vec<(u64, BoundingBox)>) -> Self {
+        Self::with_node_size(items, DEFAULT_NODE_SIZE)
+    }
+
+    /// Builds the index with the given number of children per node. Smaller nodes make queries
+    /// look at fewer boxes, but the tree deeper.
+    pub fn with_node_size(mut items: Vec<(u64, BoundingBox)>, node_size: usize) -> Self {
+        let node_size = node_size.max(2);
+
+        // Cut the items into vertical slices by the x of their center, then sort every slice by y,
+        // so that consecutive runs of node_size items are close to each other
+        let leaves = items.len().div_ceil(node_size);
+        let slices = (leaves as f64).sqrt().ceil().max(1f64) as usize;
+        let slice_size = slices * node_size;
+
+        items.sort_by(|a, b| compare_centers(a.1.x_min + a.1.x_max, b.1.x_min + b.1.x_max));
+        for slice in items.chunks_mut(slice_size) {
+            slice.sort_by(|a, b| compare_centers(a.1.y_min + a.1.y_max, b.1.y_min + b.1.y_max));
+        }
+
+        let ids = items.iter().map(|i| i.0).collect();
+        let mut levels: Vec<Vec<BoundingBox>> = vec![items.into_iter().map(|i| i.1).collect()];
+        while levels[levels.len() - 1].len() > 1 {
+            let parents = levels[levels.len() - 1].chunks(node_size).map(union).collect();
+            levels.push(parents);
+        }
+
+        SpatialIndex {node_size, levels, ids}
+    }
+
+    /// The number of items.
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// Whether there are no items.
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// The box around all items, None if there are none.
+    pub fn bounding_box(&self) -> Option<BoundingBox> {
+        self.levels.last().and_then(|l| l.first()).cloned()
+    }
+
+    /// The ids of the items whose bounding box overlaps the given one, in no particular order.
+    pub fn query(&self, bounding_box: &BoundingBox) -> Vec<u64> {
+        let mut result: Vec<u64> = vec![];
+        if self.is_empty() {
+            return result;
+        }
+
+        let mut pending: Vec<(usize, usize)> = vec![(self.levels.len() - 1, 0)];
+        while let Some((level, index)) = pending.pop() {
+            if !geometry::boxes_overlap(&self.levels[level][index], bounding_box) {
+                continue;
+            }
+            if level == 0 {
+                result.push(self.ids[index]);
+                continue;
+            }
+
+            let first = index * self.node_size;
+            let last = (first + self.node_size).min(self.levels[level - 1].len());
+            pending.extend((first..last).map(|child| (level - 1, child)));
+        }
+        result
+    }
+
+    /// The ids of the items whose bounding box contains the point, in no particular order.
+    pub fn query_point(&self, point: Point) -> Vec<u64> {
+        self.query(&BoundingBox {x_min: point.x, y_min: point.y, x_max: point.x, y_max: point.y})
+    }
+}
+
+impl Shapefile {
+    /// Builds a spatial index over the bounding boxes of all shapes, with the record ids as item
+    /// ids. Null shapes are left out.
+    pub fn spatial_index(&mut self) -> SpatialIndex {
+        let mut items: Vec<(u64, BoundingBox)> = vec![];
+        for (i, shape) in self.shapes().enumerate() {
+            if let Some(b) = shape.computed_bounding_box() {
+                items.push((i as u64 + 1, b));
+            }
+        }
+        SpatialIndex::new(items)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::SpatialIndex;
+    use super::super::shape::{BoundingBox, Point};
+
+    #[test]
+    fn test_query() {
+        // A grid of unit squares, with ids counting along the rows
+        let mut items: Vec<(u64, BoundingBox)> = vec![];
+        for y in 0..30 {
+            for x in 0..30 {
+                let (x, y) = (x as f64, y as f64);
+                items.push((items.len() as u64, BoundingBox {x_min: x, y_min: y, x_max: x + 1., y_max: y + 1.}));
+            }
+        }
+        let index = SpatialIndex::with_node_size(items, 4);
+        if index.len() != 900 || index.bounding_box() != Some(BoundingBox {x_min: 0., y_min: 0., x_max: 30., y_max: 30.}) {
+            panic!()
+        }
+
+        let mut found = index.query_point(Point {x: 10.5, y: 20.5});
+        if found != vec![610] {
+            panic!("{:?}", found)
+        }
+
+        // Corners are shared by four squares
+        found = index.query(&BoundingBox {x_min: 2., y_min: 3., x_max: 2., y_max: 3.});
+        found.sort();
+        if found != vec![61, 62, 91, 92] {
+            panic!("{:?}", found)
+        }
+
+        if !SpatialIndex::new(vec![]).query_point(Point {x: 0., y: 0.}).is_empty() {
+            panic!()
+        }
+    }
+}
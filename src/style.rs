@@ -0,0 +1,350 @@
+//! Module for the styles of rendered layers
+//!
+//! A style gives the colors of polygons, lines and points. With a theme, the color of every shape
+//! comes from an attribute instead: a color per distinct value, or a color from a ramp by numeric
+//! value. The theme colors polygons and points inside, and lines along their stroke. Themes look
+//! at all records of a layer, so that a part of it gets the same colors as the whole.
+//!
+//! `RenderOptions` are the same for all renderers, SVG and raster alike.
+
+use std::collections::{BTreeSet, HashMap};
+use std::io::{Error, ErrorKind};
+
+use dbf;
+
+use super::Shapefile;
+use super::geometry;
+use super::shape::{Shape, BoundingBox};
+
+/// A color with opacity, 255 being opaque.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Color {
+    /// The red channel
+    pub r: u8,
+    /// The green channel
+    pub g: u8,
+    /// The blue channel
+    pub b: u8,
+    /// The opacity, 0 being fully transparent
+    pub a: u8,
+}
+
+impl Color {
+    /// Opaque black.
+    pub const BLACK: Color = Color {r: 0, g: 0, b: 0, a: 255};
+    /// Opaque white.
+    pub const WHITE: Color = Color {r: 255, g: 255, b: 255, a: 255};
+    /// No color at all, for leaving out fills or strokes.
+    pub const TRANSPARENT: Color = Color {r: 0, g: 0, b: 0, a: 0};
+
+    /// An opaque color.
+    pub fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Color {r, g, b, a: 255}
+    }
+
+    /// A color with the given opacity.
+    pub fn rgba(r: u8, g: u8, b: u8, a: u8) -> Self {
+        Color {r, g, b, a}
+    }
+
+    /// The color as #rrggbb, without the opacity.
+    pub fn to_hex(&self) -> String {
+        format!("#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+
+    /// The color at t between this one, at 0, and the other one, at 1.
+    pub fn mix(&self, other: Color, t: f64) -> Color {
+        let channel = |a: u8, b: u8| (f64::from(a) + (f64::from(b) - f64::from(a)) * t.clamp(0f64, 1f64)).round() as u8;
+        Color {r: channel(self.r, other.r), g: channel(self.g, other.g), b: channel(self.b, other.b), a: channel(self.a, other.a)}
+    }
+}
+
+/// The colors given to categories, one after the other.
+const PALETTE: [Color; 10] = [
+    Color {r: 0x4e, g: 0x79, b: 0xa7, a: 255},
+    Color {r: 0xf2, g: 0x8e, b: 0x2b, a: 255},
+    Color {r: 0xe1, g: 0x57, b: 0x59, a: 255},
+    Color {r: 0x76, g: 0xb7, b: 0xb2, a: 255},
+    Color {r: 0x59, g: 0xa1, b: 0x4f, a: 255},
+    Color {r: 0xed, g: 0xc9, b: 0x48, a: 255},
+    Color {r: 0xb0, g: 0x7a, b: 0xa1, a: 255},
+    Color {r: 0xff, g: 0x9d, b: 0xa7, a: 255},
+    Color {r: 0x9c, g: 0x75, b: 0x5f, a: 255},
+    Color {r: 0xba, g: 0xb0, b: 0xac, a: 255},
+];
+
+/// How the colors of shapes follow an attribute.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Theme {
+    /// The distinct values of the field, in sorted order, get the colors of the palette one after
+    /// the other, starting over when there are more values than colors.
+    Categorical {
+        /// The name of the DBF field
+        field: String,
+        /// The colors for the values
+        palette: Vec<Color>,
+    },
+    /// The range of the numeric field is cut into classes of equal width, which get colors evenly
+    /// spaced from low to high.
+    Graduated {
+        /// The name of the numeric DBF field
+        field: String,
+        /// The color of the lowest class
+        low: Color,
+        /// The color of the highest class
+        high: Color,
+        /// The number of classes, at least one
+        classes: usize,
+    },
+}
+
+impl Theme {
+    /// The name of the DBF field the colors follow.
+    fn field(&self) -> &str {
+        match *self {
+            Theme::Categorical {ref field, ..} | Theme::Graduated {ref field, ..} => field,
+        }
+    }
+}
+
+/// A shape ready for drawing, with its colors.
+pub(crate) struct StyledShape {
+    pub shape: Shape,
+    pub fill: Color,
+    pub stroke: Color,
+}
+
+/// The colors and sizes used for drawing a layer.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Style {
+    /// The color inside polygons and points
+    pub fill: Color,
+    /// The color of lines, and of the outlines of polygons and points
+    pub stroke: Color,
+    /// The width of lines and outlines, in pixels
+    pub stroke_width: f64,
+    /// The radius of points, in pixels
+    pub point_radius: f64,
+    /// The theme, if the colors follow an attribute
+    pub theme: Option<Theme>,
+}
+
+impl Style {
+    /// Blue polygons and points with dark gray outlines, and dark gray lines, one pixel wide.
+    pub fn new() -> Self {
+        Style {
+            fill: Color::rgb(0xa6, 0xc8, 0xe0),
+            stroke: Color::rgb(0x33, 0x33, 0x33),
+            stroke_width: 1f64,
+            point_radius: 3f64,
+            theme: None,
+        }
+    }
+
+    /// Sets the fill color.
+    pub fn fill(mut self, color: Color) -> Self {
+        self.fill = color;
+        self
+    }
+
+    /// Sets the stroke color and width.
+    pub fn stroke(mut self, color: Color, width: f64) -> Self {
+        self.stroke = color;
+        self.stroke_width = width;
+        self
+    }
+
+    /// Sets the radius of points.
+    pub fn point_radius(mut self, radius: f64) -> Self {
+        self.point_radius = radius;
+        self
+    }
+
+    /// Colors the shapes by the values of a field, with the built-in palette.
+    pub fn categorical(mut self, field: &str) -> Self {
+        self.theme = Some(Theme::Categorical {field: String::from(field), palette: PALETTE.to_vec()});
+        self
+    }
+
+    /// Colors the shapes by the value of a numeric field, in classes from low to high.
+    pub fn graduated(mut self, field: &str, low: Color, high: Color, classes: usize) -> Self {
+        self.theme = Some(Theme::Graduated {field: String::from(field), low, high, classes});
+        self
+    }
+
+    /// The themed color of every record, None for records without a usable value, or for all of
+    /// them without a theme.
+    pub fn theme_colors(&self, records: &[HashMap<String, dbf::Field>]) -> Vec<Option<Color>> {
+        match self.theme {
+            None => vec![None; records.len()],
+            Some(Theme::Categorical {ref field, ref palette}) => {
+                let label = |r: &HashMap<String, dbf::Field>| match r.get(field) {
+                    Some(dbf::Field::Character(s)) => Some(s.trim().to_string()),
+                    Some(&dbf::Field::Numeric(n)) => Some(n.to_string()),
+                    _ => None,
+                };
+                let categories: Vec<String> = records.iter().filter_map(&label).collect::<BTreeSet<String>>().into_iter().collect();
+                records.iter().map(|r| {
+                    let i = categories.binary_search(&label(r)?).ok()?;
+                    palette.get(i % palette.len().max(1)).cloned()
+                }).collect()
+            },
+            Some(Theme::Graduated {ref field, low, high, classes}) => {
+                let value = |r: &HashMap<String, dbf::Field>| match r.get(field) {
+                    Some(&dbf::Field::Numeric(n)) if n.is_finite() => Some(n),
+                    _ => None,
+                };
+                let (min, max) = records.iter().filter_map(&value).fold((f64::INFINITY, f64::NEG_INFINITY), |(a, b), v| (a.min(v), b.max(v)));
+                let classes = classes.max(1);
+                records.iter().map(|r| {
+                    let v = value(r)?;
+                    let class = if max > min { (((v - min) / (max - min) * classes as f64) as usize).min(classes - 1) } else { 0 };
+                    Some(if classes == 1 { low } else { low.mix(high, class as f64 / (classes - 1) as f64) })
+                }).collect()
+            },
+        }
+    }
+
+    /// Reads the shapes of a layer which overlap the extent, or all of them, with their colors.
+    /// The theme only reads its field from the DBF file, and the shapes outside of the extent are
+    /// dropped as they are read.
+    pub(crate) fn apply(&self, shapefile: &mut Shapefile, extent: Option<&BoundingBox>) -> Result<Vec<StyledShape>, Error> {
+        let colors = match self.theme {
+            Some(ref theme) => {
+                let attributes: Vec<dbf::Record> = shapefile.attributes_projected(&[theme.field()]).collect();
+                self.theme_colors(&attributes)
+            },
+            None => vec![],
+        };
+
+        let num_records = shapefile.num_records();
+        let mut result: Vec<StyledShape> = vec![];
+        let mut count = 0usize;
+        for shape in shapefile.shapes() {
+            let color = colors.get(count).cloned().unwrap_or(None);
+            count += 1;
+            let b = match shape.computed_bounding_box() {
+                Some(b) => b,
+                None => continue,
+            };
+            if extent.is_some_and(|e| !geometry::boxes_overlap(&b, e)) {
+                continue;
+            }
+            let (fill, stroke) = match (&shape, color) {
+                (_, None) => (self.fill, self.stroke),
+                (&Shape::PolyLine {..}, Some(c)) | (&Shape::PolyLineM {..}, Some(c)) | (&Shape::PolyLineZ {..}, Some(c)) => (self.fill, c),
+                (_, Some(c)) => (c, self.stroke),
+            };
+            result.push(StyledShape {shape, fill, stroke});
+        }
+
+        if (count as u64) < num_records {
+            return Err(Error::new(ErrorKind::Other, format!("SHP record {} can't be read!", count + 1)));
+        }
+        Ok(result)
+    }
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Options for drawing a layer, with `Shapefile::to_svg` or `Shapefile::render`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct RenderOptions {
+    /// The width of the picture in pixels. The height follows from the extent.
+    pub width: u32,
+    /// The area to draw, the extent of the layer if None
+    pub extent: Option<BoundingBox>,
+    /// The colors and sizes
+    pub style: Style,
+    /// The color behind the shapes, transparent if None
+    pub background: Option<Color>,
+}
+
+impl RenderOptions {
+    /// The whole layer with the default style, without background.
+    pub fn new(width: u32) -> Self {
+        RenderOptions {width, extent: None, style: Style::new(), background: None}
+    }
+
+    /// Draws only the given area.
+    pub fn extent(mut self, extent: BoundingBox) -> Self {
+        self.extent = Some(extent);
+        self
+    }
+
+    /// Sets the style.
+    pub fn style(mut self, style: Style) -> Self {
+        self.style = style;
+        self
+    }
+
+    /// Sets the background color.
+    pub fn background(mut self, color: Color) -> Self {
+        self.background = Some(color);
+        self
+    }
+}
+
+/// The extent to draw: the given one, or else the one of the layer. Extents without width or
+/// height are widened, so that single points can be drawn.
+pub(crate) fn drawing_extent(shapefile: &Shapefile, extent: Option<&BoundingBox>) -> BoundingBox {
+    let mut result = match extent {
+        Some(e) => *e,
+        None => {
+            let e = shapefile.extent();
+            BoundingBox {x_min: e.x_min, y_min: e.y_min, x_max: e.x_max, y_max: e.y_max}
+        },
+    };
+    let margin = (result.x_max - result.x_min).max(result.y_max - result.y_min).max(1f64) / 2f64;
+    if result.x_max <= result.x_min {
+        result.x_min -= margin;
+        result.x_max += margin;
+    }
+    if result.y_max <= result.y_min {
+        result.y_min -= margin;
+        result.y_max += margin;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use dbf;
+    use super::{Color, Style};
+
+    fn record(field: dbf::Field) -> HashMap<String, dbf::Field> {
+        let mut result = HashMap::new();
+        result.insert(String::from("value"), field);
+        result
+    }
+
+    #[test]
+    fn test_theme_colors() {
+        let records = vec![
+            record(dbf::Field::Numeric(10.)),
+            record(dbf::Field::Numeric(0.)),
+            record(dbf::Field::Null),
+            record(dbf::Field::Numeric(4.)),
+            record(dbf::Field::Numeric(10.)),
+        ];
+
+        let colors = Style::new().categorical("value").theme_colors(&records);
+        if colors[0] != colors[4] || colors[0] == colors[1] || colors[2].is_some() || colors[3] == colors[0] {
+            panic!("{:?}", colors)
+        }
+
+        let colors = Style::new().graduated("value", Color::BLACK, Color::WHITE, 3).theme_colors(&records);
+        if colors != vec![Some(Color::WHITE), Some(Color::BLACK), None, Some(Color::rgb(128, 128, 128)), Some(Color::WHITE)] {
+            panic!("{:?}", colors)
+        }
+
+        if Style::new().theme_colors(&records) != vec![None; 5] {
+            panic!()
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! Module for rendering layers as SVG
+//!
+//! Every shape becomes one element: a path for polygons, filled by the even-odd rule so that
+//! holes stay empty whichever way their rings run, a path for polylines, and circles for points.
+//! Multipatches aren't drawn. The y axis is flipped, so that north is up.
+
+use std::fmt::Write;
+use std::fs;
+use std::io::Error;
+use std::path::Path;
+
+use super::Shapefile;
+use super::geometry;
+use super::shape::{Shape, BoundingBox, Point};
+use super::style::{self, Color, RenderOptions, Style, StyledShape};
+
+/// The attributes for a fill or stroke color, with its opacity if it isn't opaque.
+fn paint(name: &str, color: Color) -> String {
+    if color.a == 255 {
+        format!("{}=\"{}\"", name, color.to_hex())
+    } else {
+        format!("{}=\"{}\" {}-opacity=\"{:.3}\"", name, color.to_hex(), name, f64::from(color.a) / 255f64)
+    }
+}
+
+/// Path data for the parts of a shape, closed for polygons.
+fn path_data<F>(shape: &Shape, to_pixels: F, closed: bool) -> String
+where F: Fn(Point) -> (f64, f64)
+{
+    let mut result = String::new();
+    for part in geometry::parts_of(shape).unwrap_or_default() {
+        for (i, &p) in part.iter().enumerate() {
+            let (x, y) = to_pixels(p);
+            let _ = write!(result, "{}{:.2} {:.2}", if i == 0 { "M" } else { "L" }, x, y);
+        }
+        if closed && !part.is_empty() {
+            result.push('Z');
+        }
+    }
+    result
+}
+
+/// Draws the shapes into the extent, onto a picture of the given width.
+fn render(shapes: &[StyledShape], extent: &BoundingBox, width: u32, style: &Style, background: Option<Color>) -> String {
+    let scale = f64::from(width) / (extent.x_max - extent.x_min);
+    let height = ((extent.y_max - extent.y_min) * scale).round().max(1f64);
+    let to_pixels = |p: Point| ((p.x - extent.x_min) * scale, (extent.y_max - p.y) * scale);
+
+    let mut result = format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                              <svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\">\n",
+                             width, height, width, height);
+    if let Some(color) = background {
+        let _ = writeln!(result, "<rect width=\"100%\" height=\"100%\" {}/>", paint("fill", color));
+    }
+    let _ = writeln!(result, "<g stroke-width=\"{}\" stroke-linejoin=\"round\" stroke-linecap=\"round\">", style.stroke_width);
+
+    for s in shapes {
+        match s.shape {
+            Shape::Polygon {..} | Shape::PolygonM {..} | Shape::PolygonZ {..} => {
+                let _ = writeln!(result, "<path d=\"{}\" fill-rule=\"evenodd\" {} {}/>", path_data(&s.shape, to_pixels, true), paint("fill", s.fill), paint("stroke", s.stroke));
+            },
+            Shape::PolyLine {..} | Shape::PolyLineM {..} | Shape::PolyLineZ {..} => {
+                let _ = writeln!(result, "<path d=\"{}\" fill=\"none\" {}/>", path_data(&s.shape, to_pixels, false), paint("stroke", s.stroke));
+            },
+            Shape::Point {..} | Shape::PointM {..} | Shape::PointZ {..} |
+            Shape::MultiPoint {..} | Shape::MultiPointM {..} | Shape::MultiPointZ {..} => {
+                for part in geometry::vertex_parts(&s.shape) {
+                    for v in part {
+                        let (x, y) = to_pixels(v.point);
+                        let _ = writeln!(result, "<circle cx=\"{:.2}\" cy=\"{:.2}\" r=\"{}\" {} {}/>", x, y, style.point_radius, paint("fill", s.fill), paint("stroke", s.stroke));
+                    }
+                }
+            },
+            Shape::MultiPatch {..} | Shape::NullShape => (),
+        }
+    }
+
+    result.push_str("</g>\n</svg>\n");
+    result
+}
+
+impl Shapefile {
+    /// Draws the layer, or the shapes overlapping the extent of the options, as an SVG document.
+    pub fn to_svg(&mut self, options: &RenderOptions) -> Result<String, Error> {
+        let extent = style::drawing_extent(self, options.extent.as_ref());
+        let shapes = options.style.apply(self, options.extent.as_ref())?;
+        Ok(render(&shapes, &extent, options.width, &options.style, options.background))
+    }
+
+    /// Draws the layer into an SVG file.
+    pub fn write_svg(&mut self, path: &Path, options: &RenderOptions) -> Result<(), Error> {
+        let svg = self.to_svg(options)?;
+        fs::write(path, svg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::render;
+    use super::super::shape::{Shape, BoundingBox, Point};
+    use super::super::style::{Color, Style, StyledShape};
+
+    #[test]
+    fn test_render() {
+        // A square with a hole, a line across it and a point in the hole
+        let mut square = Shape::from(BoundingBox {x_min: 0., y_min: 0., x_max: 10., y_max: 10.});
+        if let Shape::Polygon {ref mut parts, ref mut points, ..} = square {
+            parts.push(points.len() as i32);
+            points.extend([(4., 4.), (6., 4.), (6., 6.), (4., 6.), (4., 4.)].iter().map(|&(x, y)| Point {x, y}));
+        }
+        let line = Shape::PolyLine {bounding_box: BoundingBox::new(), parts: vec![0], points: vec![Point {x: 0., y: 5.}, Point {x: 20., y: 5.}]};
+        let style = Style::new();
+        let shapes = vec![
+            StyledShape {shape: square, fill: Color::rgba(255, 0, 0, 51), stroke: Color::BLACK},
+            StyledShape {shape: line, fill: style.fill, stroke: Color::rgb(0, 0, 255)},
+            StyledShape {shape: Shape::Point {point: Point {x: 5., y: 5.}}, fill: style.fill, stroke: style.stroke},
+        ];
+
+        let svg = render(&shapes, &BoundingBox {x_min: 0., y_min: 0., x_max: 20., y_max: 10.}, 200, &style, Some(Color::WHITE));
+        if !svg.contains("width=\"200\" height=\"100\"") || !svg.contains("<rect width=\"100%\" height=\"100%\" fill=\"#ffffff\"/>") {
+            panic!("{}", svg)
+        }
+        if !svg.contains("fill-rule=\"evenodd\" fill=\"#ff0000\" fill-opacity=\"0.200\" stroke=\"#000000\"") {
+            panic!("{}", svg)
+        }
+        // North is up: the hole's lower left corner is at y = 60
+        if !svg.contains("M0.00 100.00L0.00 0.00L100.00 0.00L100.00 100.00L0.00 100.00ZM40.00 60.00") {
+            panic!("{}", svg)
+        }
+        if !svg.contains("<path d=\"M0.00 50.00L200.00 50.00\" fill=\"none\" stroke=\"#0000ff\"/>") || !svg.contains("<circle cx=\"50.00\" cy=\"50.00\" r=\"3\"") {
+            panic!("{}", svg)
+        }
+    }
+}
@@ -0,0 +1,607 @@
+//! Module for coordinate transformations
+//!
+//! A `CoordinateSystem` is read from the WKT of a PRJ file. A `Transformer` between two of them
+//! takes a point through three steps:
+//!
+//! 1. The inverse projection of the source system gives longitude and latitude on its datum.
+//! 2. If the datums differ, the point moves through earth-centered coordinates and the Helmert
+//!    transformations of both datums to WGS84.
+//! 3. The projection of the target system gives the new coordinates.
+//!
+//! Supported are geographic coordinates, Transverse Mercator (which includes UTM and Gauss-Krüger),
+//! Mercator, Web Mercator and Lambert Conformal Conic. Z values are heights above the ground and
+//! stay as they are, M values as well.
+
+use std::f64::consts::{FRAC_PI_2, FRAC_PI_4, PI};
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+use super::{Projection, Shapefile, WktNode};
+use super::geometry::{self, Vertex};
+use super::measure::Ellipsoid;
+use super::shape::{Shape, Point};
+
+/// The latitude beyond which Web Mercator maps are cut off, so that the map is square.
+const WEB_MERCATOR_MAX_LATITUDE: f64 = 85.051_128_779_806_59;
+
+/// The Helmert parameters of well-known datums, in the position vector convention, from the EPSG
+/// registry. Datums not listed here and without `TOWGS84` are taken to be the same as WGS84.
+const KNOWN_DATUMS: &[(&[&str], Helmert)] = &[
+    (&["OSGB_1936"], Helmert {tx: 446.448, ty: -125.157, tz: 542.06, rx: 0.15, ry: 0.247, rz: 0.842, scale: -20.489}),
+    (&["Deutsches_Hauptdreiecksnetz"], Helmert {tx: 598.1, ty: 73.7, tz: 418.2, rx: 0.202, ry: 0.045, rz: -2.455, scale: 6.7}),
+    (&["European_1950", "European_Datum_1950"], Helmert {tx: -87.0, ty: -98.0, tz: -121.0, rx: 0.0, ry: 0.0, rz: 0.0, scale: 0.0}),
+];
+
+/// A seven parameter Helmert transformation from a datum to WGS84, in the position vector
+/// convention used by `TOWGS84` in WKT.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Helmert {
+    /// The translation along the X axis in meters
+    pub tx: f64,
+    /// The translation along the Y axis in meters
+    pub ty: f64,
+    /// The translation along the Z axis in meters
+    pub tz: f64,
+    /// The rotation around the X axis in arc seconds
+    pub rx: f64,
+    /// The rotation around the Y axis in arc seconds
+    pub ry: f64,
+    /// The rotation around the Z axis in arc seconds
+    pub rz: f64,
+    /// The scale difference in parts per million
+    pub scale: f64,
+}
+
+impl Helmert {
+    /// The transformation which changes nothing, for datums which are the same as WGS84.
+    pub const IDENTITY: Helmert = Helmert {tx: 0.0, ty: 0.0, tz: 0.0, rx: 0.0, ry: 0.0, rz: 0.0, scale: 0.0};
+
+    /// Takes earth-centered coordinates in meters on the datum to WGS84.
+    pub fn to_wgs84(&self, p: [f64; 3]) -> [f64; 3] {
+        let (rx, ry, rz) = self.rotations();
+        let s = 1f64 + self.scale * 1e-6;
+        [
+            self.tx + s * (p[0] - rz * p[1] + ry * p[2]),
+            self.ty + s * (rz * p[0] + p[1] - rx * p[2]),
+            self.tz + s * (-ry * p[0] + rx * p[1] + p[2]),
+        ]
+    }
+
+    /// Takes earth-centered coordinates in meters on WGS84 to the datum. The rotations are small,
+    /// so the inverse of the rotation matrix is its transpose.
+    pub fn from_wgs84(&self, p: [f64; 3]) -> [f64; 3] {
+        let (rx, ry, rz) = self.rotations();
+        let s = 1f64 + self.scale * 1e-6;
+        let (x, y, z) = ((p[0] - self.tx) / s, (p[1] - self.ty) / s, (p[2] - self.tz) / s);
+        [x + rz * y - ry * z, -rz * x + y + rx * z, ry * x - rx * y + z]
+    }
+
+    fn rotations(&self) -> (f64, f64, f64) {
+        let arc_second = PI / (180f64 * 3600f64);
+        (self.rx * arc_second, self.ry * arc_second, self.rz * arc_second)
+    }
+}
+
+/// A map projection.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Method {
+    /// Longitude and latitude, not projected
+    Geographic,
+    /// Transverse Mercator, like UTM
+    TransverseMercator,
+    /// Mercator on the ellipsoid
+    Mercator,
+    /// Mercator on a sphere with the semi-major axis of the ellipsoid, as web maps use it
+    WebMercator,
+    /// Lambert Conformal Conic with one or two standard parallels
+    LambertConformalConic,
+}
+
+/// A coordinate system, with everything needed to transform coordinates from and to it. Angles
+/// are in degrees, lengths in the linear unit.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct CoordinateSystem {
+    /// The projection
+    pub method: Method,
+    /// The ellipsoid of the datum
+    pub ellipsoid: Ellipsoid,
+    /// The transformation of the datum to WGS84
+    pub to_wgs84: Helmert,
+    /// The longitude of the prime meridian east of Greenwich
+    pub prime_meridian: f64,
+    /// The size of the angular unit of geographic coordinates in radians
+    pub angular_unit: f64,
+    /// The size of the linear unit of projected coordinates in meters
+    pub linear_unit: f64,
+    /// The easting of the origin
+    pub false_easting: f64,
+    /// The northing of the origin
+    pub false_northing: f64,
+    /// The longitude of the origin
+    pub central_meridian: f64,
+    /// The latitude of the origin
+    pub latitude_of_origin: f64,
+    /// The first latitude where the scale is true, for Mercator and Lambert Conformal Conic
+    pub standard_parallel_1: f64,
+    /// The second latitude where the scale is true, for Lambert Conformal Conic
+    pub standard_parallel_2: f64,
+    /// The scale factor at the origin
+    pub scale_factor: f64,
+}
+
+impl CoordinateSystem {
+    /// Longitude and latitude in degrees on WGS84.
+    pub fn wgs84() -> Self {
+        CoordinateSystem {
+            method: Method::Geographic,
+            ellipsoid: Ellipsoid::WGS84,
+            to_wgs84: Helmert::IDENTITY,
+            prime_meridian: 0f64,
+            angular_unit: PI / 180f64,
+            linear_unit: 1f64,
+            false_easting: 0f64,
+            false_northing: 0f64,
+            central_meridian: 0f64,
+            latitude_of_origin: 0f64,
+            standard_parallel_1: 0f64,
+            standard_parallel_2: 0f64,
+            scale_factor: 1f64,
+        }
+    }
+
+    /// Reads the coordinate system from the WKT of a PRJ file.
+    pub fn from_projection(projection: &Projection) -> Result<Self, Error> {
+        let root = &projection.wkt;
+        let geogcs = if projection.is_geographic() { Some(root) } else { root.child("GEOGCS") };
+        let geogcs = geogcs.ok_or_else(|| Error::new(ErrorKind::Other, "PRJ file has no GEOGCS!"))?;
+        let datum = geogcs.child("DATUM").ok_or_else(|| Error::new(ErrorKind::Other, "PRJ file has no DATUM!"))?;
+
+        let mut result = Self::wgs84();
+        result.ellipsoid = projection.ellipsoid().ok_or_else(|| Error::new(ErrorKind::Other, "PRJ file has no SPHEROID!"))?;
+        result.to_wgs84 = datum_shift(datum);
+        result.prime_meridian = geogcs.child("PRIMEM").and_then(|p| p.number(1)).unwrap_or(0f64);
+        result.angular_unit = geogcs.child("UNIT").and_then(|u| u.number(1)).unwrap_or(PI / 180f64);
+        if projection.is_geographic() {
+            return Ok(result);
+        }
+
+        result.linear_unit = root.child("UNIT").and_then(|u| u.number(1)).unwrap_or(1f64);
+        let name = root.child("PROJECTION").and_then(|p| p.text(0)).unwrap_or("");
+        let system_name = projection.name().unwrap_or("").to_ascii_lowercase();
+        let pseudo_mercator = system_name.contains("pseudo") || system_name.contains("web_mercator");
+
+        result.method = match name.to_ascii_lowercase().as_str() {
+            "transverse_mercator" | "gauss_kruger" => Method::TransverseMercator,
+            "mercator_auxiliary_sphere" | "popular_visualisation_pseudo_mercator" => Method::WebMercator,
+            "mercator" | "mercator_1sp" | "mercator_2sp" if pseudo_mercator => Method::WebMercator,
+            "mercator" | "mercator_1sp" | "mercator_2sp" => Method::Mercator,
+            "lambert_conformal_conic" | "lambert_conformal_conic_1sp" | "lambert_conformal_conic_2sp" => Method::LambertConformalConic,
+            _ => return Err(Error::new(ErrorKind::Other, format!("Unsupported projection `{}`!", name))),
+        };
+
+        let parameter = |names: &[&str]| {
+            root.children().into_iter()
+                .filter(|c| c.keyword.eq_ignore_ascii_case("PARAMETER"))
+                .find(|c| c.text(0).is_some_and(|n| names.iter().any(|m| n.eq_ignore_ascii_case(m))))
+                .and_then(|c| c.number(1))
+        };
+        result.false_easting = parameter(&["False_Easting"]).unwrap_or(0f64);
+        result.false_northing = parameter(&["False_Northing"]).unwrap_or(0f64);
+        result.central_meridian = parameter(&["Central_Meridian", "Longitude_Of_Origin", "Longitude_Of_Center"]).unwrap_or(0f64);
+        result.latitude_of_origin = parameter(&["Latitude_Of_Origin", "Latitude_Of_Center"]).unwrap_or(0f64);
+        result.scale_factor = parameter(&["Scale_Factor"]).unwrap_or(1f64);
+        if result.method == Method::LambertConformalConic {
+            result.standard_parallel_1 = parameter(&["Standard_Parallel_1"]).unwrap_or(result.latitude_of_origin);
+        } else {
+            result.standard_parallel_1 = parameter(&["Standard_Parallel_1"]).unwrap_or(0f64);
+        }
+        result.standard_parallel_2 = parameter(&["Standard_Parallel_2"]).unwrap_or(result.standard_parallel_1);
+
+        Ok(result)
+    }
+}
+
+/// The Helmert transformation of a datum, from `TOWGS84` or from the well-known datums.
+fn datum_shift(datum: &WktNode) -> Helmert {
+    if let Some(t) = datum.child("TOWGS84") {
+        let v = |i: usize| t.number(i).unwrap_or(0f64);
+        return Helmert {tx: v(0), ty: v(1), tz: v(2), rx: v(3), ry: v(4), rz: v(5), scale: v(6)};
+    }
+
+    let name = datum.text(0).unwrap_or("");
+    let name = if name.len() > 2 && name[..2].eq_ignore_ascii_case("D_") { &name[2..] } else { name };
+    KNOWN_DATUMS.iter()
+                .find(|d| d.0.iter().any(|n| n.eq_ignore_ascii_case(name)))
+                .map(|d| d.1)
+                .unwrap_or(Helmert::IDENTITY)
+}
+
+/// Brings a longitude difference in radians into the range from -π to π.
+fn normalized(lambda: f64) -> f64 {
+    let mut result = lambda % (2f64 * PI);
+    if result > PI {
+        result -= 2f64 * PI;
+    } else if result < -PI {
+        result += 2f64 * PI;
+    }
+    result
+}
+
+/// The latitude from the isometric function t = tan(π/4 - φ/2) / ((1 - e sin φ) / (1 + e sin φ))^(e/2),
+/// found by iteration.
+fn latitude_from_t(t: f64, e: f64) -> f64 {
+    let mut phi = FRAC_PI_2 - 2f64 * t.atan();
+    for _ in 0..15 {
+        let es = e * phi.sin();
+        let next = FRAC_PI_2 - 2f64 * (t * ((1f64 - es) / (1f64 + es)).powf(e / 2f64)).atan();
+        if (next - phi).abs() < 1e-14 {
+            return next;
+        }
+        phi = next;
+    }
+    phi
+}
+
+fn t_of(phi: f64, e: f64) -> f64 {
+    let es = e * phi.sin();
+    (FRAC_PI_4 - phi / 2f64).tan() / ((1f64 - es) / (1f64 + es)).powf(e / 2f64)
+}
+
+fn m_of(phi: f64, e2: f64) -> f64 {
+    phi.cos() / (1f64 - e2 * phi.sin().powi(2)).sqrt()
+}
+
+/// The constants of a projection which only depend on its parameters.
+#[derive(Debug, Clone, Copy)]
+enum Projector {
+    Geographic,
+    /// Krüger's series to the sixth order in n, as given by Karney
+    TransverseMercator {k0: f64, a: f64, alpha: [f64; 6], beta: [f64; 6], m0: f64},
+    Mercator {a_k0: f64},
+    WebMercator {a: f64},
+    LambertConformalConic {n: f64, af: f64, r0: f64},
+}
+
+impl Projector {
+    fn new(system: &CoordinateSystem) -> Self {
+        let (a, f) = (system.ellipsoid.a, system.ellipsoid.f);
+        let e2 = system.ellipsoid.e2();
+        let e = e2.sqrt();
+
+        match system.method {
+            Method::Geographic => Projector::Geographic,
+            Method::WebMercator => Projector::WebMercator {a},
+            Method::Mercator => {
+                Projector::Mercator {a_k0: a * system.scale_factor * m_of(system.standard_parallel_1.to_radians(), e2)}
+            },
+            Method::TransverseMercator => {
+                let n = f / (2f64 - f);
+                let (n2, n3, n4, n5, n6) = (n * n, n.powi(3), n.powi(4), n.powi(5), n.powi(6));
+                let alpha = [
+                    n / 2f64 - 2f64 * n2 / 3f64 + 5f64 * n3 / 16f64 + 41f64 * n4 / 180f64 - 127f64 * n5 / 288f64 + 7891f64 * n6 / 37800f64,
+                    13f64 * n2 / 48f64 - 3f64 * n3 / 5f64 + 557f64 * n4 / 1440f64 + 281f64 * n5 / 630f64 - 1983433f64 * n6 / 1935360f64,
+                    61f64 * n3 / 240f64 - 103f64 * n4 / 140f64 + 15061f64 * n5 / 26880f64 + 167603f64 * n6 / 181440f64,
+                    49561f64 * n4 / 161280f64 - 179f64 * n5 / 168f64 + 6601661f64 * n6 / 7257600f64,
+                    34729f64 * n5 / 80640f64 - 3418889f64 * n6 / 1995840f64,
+                    212378941f64 * n6 / 319334400f64,
+                ];
+                let beta = [
+                    n / 2f64 - 2f64 * n2 / 3f64 + 37f64 * n3 / 96f64 - n4 / 360f64 - 81f64 * n5 / 512f64 + 96199f64 * n6 / 604800f64,
+                    n2 / 48f64 + n3 / 15f64 - 437f64 * n4 / 1440f64 + 46f64 * n5 / 105f64 - 1118711f64 * n6 / 3870720f64,
+                    17f64 * n3 / 480f64 - 37f64 * n4 / 840f64 - 209f64 * n5 / 4480f64 + 5569f64 * n6 / 90720f64,
+                    4397f64 * n4 / 161280f64 - 11f64 * n5 / 504f64 - 830251f64 * n6 / 7257600f64,
+                    4583f64 * n5 / 161280f64 - 108847f64 * n6 / 3991680f64,
+                    20648693f64 * n6 / 638668800f64,
+                ];
+                let rectifying_radius = a / (1f64 + n) * (1f64 + n2 / 4f64 + n4 / 64f64 + n6 / 256f64);
+
+                let mut result = Projector::TransverseMercator {k0: system.scale_factor, a: rectifying_radius, alpha, beta, m0: 0f64};
+                // The northing of the origin on the central meridian, which the false northing replaces
+                let (_, m0) = result.forward(0f64, system.latitude_of_origin.to_radians(), e);
+                if let Projector::TransverseMercator {m0: ref mut m, ..} = result {
+                    *m = m0;
+                }
+                result
+            },
+            Method::LambertConformalConic => {
+                let (phi0, phi1, phi2) = (system.latitude_of_origin.to_radians(),
+                                          system.standard_parallel_1.to_radians(),
+                                          system.standard_parallel_2.to_radians());
+                let (m1, m2) = (m_of(phi1, e2), m_of(phi2, e2));
+                let (t0, t1, t2) = (t_of(phi0, e), t_of(phi1, e), t_of(phi2, e));
+                let n = if (phi1 - phi2).abs() < 1e-12 {
+                    phi1.sin()
+                } else {
+                    (m1.ln() - m2.ln()) / (t1.ln() - t2.ln())
+                };
+                let af = a * system.scale_factor * m1 / (n * t1.powf(n));
+                Projector::LambertConformalConic {n, af, r0: af * t0.powf(n)}
+            },
+        }
+    }
+
+    /// Projects a longitude relative to the central meridian and a latitude in radians to meters
+    /// relative to the origin.
+    fn forward(&self, lambda: f64, phi: f64, e: f64) -> (f64, f64) {
+        let lambda = normalized(lambda);
+        match *self {
+            Projector::Geographic => (lambda, phi),
+            Projector::TransverseMercator {k0, a, alpha, m0, ..} => {
+                let tau = (phi.sin().atanh() - e * (e * phi.sin()).atanh()).sinh();
+                let xi_prime = tau.atan2(lambda.cos());
+                let eta_prime = (lambda.sin() / (1f64 + tau * tau).sqrt()).atanh();
+
+                let (mut xi, mut eta) = (xi_prime, eta_prime);
+                for (j, alpha_j) in alpha.iter().enumerate() {
+                    let k = 2f64 * (j + 1) as f64;
+                    xi += alpha_j * (k * xi_prime).sin() * (k * eta_prime).cosh();
+                    eta += alpha_j * (k * xi_prime).cos() * (k * eta_prime).sinh();
+                }
+                (k0 * a * eta, k0 * a * xi - m0)
+            },
+            Projector::Mercator {a_k0} => (a_k0 * lambda, -a_k0 * t_of(phi, e).ln()),
+            Projector::WebMercator {a} => {
+                let phi = phi.clamp(-WEB_MERCATOR_MAX_LATITUDE.to_radians(), WEB_MERCATOR_MAX_LATITUDE.to_radians());
+                (a * lambda, a * (FRAC_PI_4 + phi / 2f64).tan().ln())
+            },
+            Projector::LambertConformalConic {n, af, r0} => {
+                let r = af * t_of(phi, e).powf(n);
+                let theta = n * lambda;
+                (r * theta.sin(), r0 - r * theta.cos())
+            },
+        }
+    }
+
+    /// The inverse of `forward`.
+    fn inverse(&self, x: f64, y: f64, e: f64) -> (f64, f64) {
+        match *self {
+            Projector::Geographic => (x, y),
+            Projector::TransverseMercator {k0, a, beta, m0, ..} => {
+                let xi = (y + m0) / (k0 * a);
+                let eta = x / (k0 * a);
+                let (mut xi_prime, mut eta_prime) = (xi, eta);
+                for (j, beta_j) in beta.iter().enumerate() {
+                    let k = 2f64 * (j + 1) as f64;
+                    xi_prime -= beta_j * (k * xi).sin() * (k * eta).cosh();
+                    eta_prime -= beta_j * (k * xi).cos() * (k * eta).sinh();
+                }
+
+                // The conformal latitude, turned into the geodetic one
+                let chi = (xi_prime.sin() / eta_prime.cosh()).asin();
+                let lambda = eta_prime.sinh().atan2(xi_prime.cos());
+                (lambda, latitude_from_t((FRAC_PI_4 - chi / 2f64).tan(), e))
+            },
+            Projector::Mercator {a_k0} => (x / a_k0, latitude_from_t((-y / a_k0).exp(), e)),
+            Projector::WebMercator {a} => (x / a, 2f64 * (y / a).exp().atan() - FRAC_PI_2),
+            Projector::LambertConformalConic {n, af, r0} => {
+                let sign = n.signum();
+                let r = sign * (x * x + (r0 - y).powi(2)).sqrt();
+                let theta = (sign * x).atan2(sign * (r0 - y));
+                let t = (r / af).powf(1f64 / n);
+                (theta / n, latitude_from_t(t, e))
+            },
+        }
+    }
+}
+
+/// Transforms coordinates from one coordinate system into another.
+#[derive(Debug, Clone, Copy)]
+pub struct Transformer {
+    source: CoordinateSystem,
+    target: CoordinateSystem,
+    source_projector: Projector,
+    target_projector: Projector,
+    /// Whether the datums differ
+    shift: bool,
+}
+
+impl Transformer {
+    /// Creates a transformer between two coordinate systems.
+    pub fn new(source: &CoordinateSystem, target: &CoordinateSystem) -> Self {
+        Transformer {
+            source: *source,
+            target: *target,
+            source_projector: Projector::new(source),
+            target_projector: Projector::new(target),
+            shift: source.to_wgs84 != target.to_wgs84 || source.ellipsoid != target.ellipsoid,
+        }
+    }
+
+    /// Creates a transformer between the coordinate systems of two PRJ files.
+    pub fn between(source: &Projection, target: &Projection) -> Result<Self, Error> {
+        Ok(Self::new(&CoordinateSystem::from_projection(source)?, &CoordinateSystem::from_projection(target)?))
+    }
+
+    /// Transforms a point.
+    pub fn transform(&self, point: Point) -> Point {
+        let (lambda, phi) = to_geographic(&self.source, &self.source_projector, point);
+        let (lambda, phi) = if self.shift {
+            let p = to_earth_centered(&self.source.ellipsoid, lambda, phi);
+            let p = self.target.to_wgs84.from_wgs84(self.source.to_wgs84.to_wgs84(p));
+            from_earth_centered(&self.target.ellipsoid, p)
+        } else {
+            (lambda, phi)
+        };
+        from_geographic(&self.target, &self.target_projector, lambda, phi)
+    }
+}
+
+/// The longitude east of Greenwich and the latitude in radians.
+fn to_geographic(system: &CoordinateSystem, projector: &Projector, point: Point) -> (f64, f64) {
+    let e = system.ellipsoid.e2().sqrt();
+    let prime_meridian = system.prime_meridian.to_radians();
+    match *projector {
+        Projector::Geographic => (point.x * system.angular_unit + prime_meridian, point.y * system.angular_unit),
+        _ => {
+            let x = (point.x - system.false_easting) * system.linear_unit;
+            let y = (point.y - system.false_northing) * system.linear_unit;
+            let (lambda, phi) = projector.inverse(x, y, e);
+            (lambda + system.central_meridian.to_radians() + prime_meridian, phi)
+        },
+    }
+}
+
+fn from_geographic(system: &CoordinateSystem, projector: &Projector, lambda: f64, phi: f64) -> Point {
+    let e = system.ellipsoid.e2().sqrt();
+    let lambda = lambda - system.prime_meridian.to_radians();
+    match *projector {
+        Projector::Geographic => Point {x: lambda / system.angular_unit, y: phi / system.angular_unit},
+        _ => {
+            let (x, y) = projector.forward(lambda - system.central_meridian.to_radians(), phi, e);
+            Point {x: system.false_easting + x / system.linear_unit, y: system.false_northing + y / system.linear_unit}
+        },
+    }
+}
+
+fn to_earth_centered(ellipsoid: &Ellipsoid, lambda: f64, phi: f64) -> [f64; 3] {
+    let e2 = ellipsoid.e2();
+    let n = ellipsoid.a / (1f64 - e2 * phi.sin().powi(2)).sqrt();
+    [n * phi.cos() * lambda.cos(), n * phi.cos() * lambda.sin(), n * (1f64 - e2) * phi.sin()]
+}
+
+fn from_earth_centered(ellipsoid: &Ellipsoid, p: [f64; 3]) -> (f64, f64) {
+    let e2 = ellipsoid.e2();
+    let r = p[0].hypot(p[1]);
+    let lambda = p[1].atan2(p[0]);
+    let mut phi = p[2].atan2(r * (1f64 - e2));
+    for _ in 0..10 {
+        let n = ellipsoid.a / (1f64 - e2 * phi.sin().powi(2)).sqrt();
+        let h = if phi.cos().abs() > 1e-12 { r / phi.cos() - n } else { p[2].abs() - n * (1f64 - e2) };
+        phi = p[2].atan2(r * (1f64 - e2 * n / (n + h)));
+    }
+    (lambda, phi)
+}
+
+impl Shape {
+    /// Returns the shape with all points transformed, and the bounding box computed anew. Z and M
+    /// values stay as they are.
+    pub fn transformed(&self, transformer: &Transformer) -> Shape {
+        if *self == Shape::NullShape {
+            return Shape::NullShape;
+        }
+
+        let parts = geometry::vertex_parts(self).into_iter().map(|part| {
+            part.into_iter().map(|v| Vertex {point: transformer.transform(v.point), ..v}).collect()
+        }).collect();
+        geometry::from_vertex_parts(self, parts)
+    }
+}
+
+impl Shapefile {
+    /// The transformer into the fixed coordinate system of an output format, like WGS84 for KML
+    /// and GPX or Web Mercator for vector tiles. Layers without a PRJ file are taken to be in that
+    /// system already, since there is nothing to reproject them from, and get None.
+    pub fn transformer_to(&self, target: &Projection) -> Result<Option<Transformer>, Error> {
+        self.projection().map(|p| Transformer::between(p, target)).transpose()
+    }
+
+    /// Writes a copy of the layer in another coordinate system into a new shapefile, with all
+    /// attributes and a PRJ file next to the SHP file. The layer needs a PRJ file itself. Returns
+    /// the number of records written.
+    pub fn reproject_into(&mut self, shp_path: &Path, shx_path: &Path, dbf_path: &Path, target: &Projection) -> Result<u64, Error> {
+        let source = self.projection().ok_or_else(|| Error::new(ErrorKind::Other, "Layer has no PRJ file to reproject from!"))?;
+        let transformer = Transformer::between(source, target)?;
+
+        let count = self.rewrite(shp_path, shx_path, dbf_path, |shape| {
+            let shape = shape.transformed(&transformer);
+            match shape.computed_bounding_box() {
+                Some(b) if !(b.x_min.is_finite() && b.y_min.is_finite() && b.x_max.is_finite() && b.y_max.is_finite()) => {
+                    Err(Error::new(ErrorKind::Other, "Shape can't be shown in the target coordinate system!"))
+                },
+                _ => Ok(Some(shape)),
+            }
+        })?;
+
+        target.write_file(&shp_path.with_extension("prj"))?;
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CoordinateSystem, Helmert, Transformer};
+    use super::super::Projection;
+    use super::super::shape::Point;
+
+    const GCS_OSGB_1936: &str = r#"GEOGCS["GCS_OSGB_1936",DATUM["D_OSGB_1936",SPHEROID["Airy_1830",6377563.396,299.3249646]],PRIMEM["Greenwich",0.0],UNIT["Degree",0.0174532925199433]]"#;
+
+    fn transformer(source: &str, target: &str) -> Transformer {
+        Transformer::between(&Projection::parse(source).unwrap(), &Projection::parse(target).unwrap()).unwrap()
+    }
+
+    fn close(p: Point, x: f64, y: f64, tolerance: f64) -> bool {
+        (p.x - x).abs() <= tolerance && (p.y - y).abs() <= tolerance
+    }
+
+    #[test]
+    fn test_transverse_mercator() {
+        // The example of the EPSG guidance note 7-2 for the British National Grid
+        let bng = format!("PROJCS[\"British_National_Grid\",{},PROJECTION[\"Transverse_Mercator\"],\
+                           PARAMETER[\"False_Easting\",400000.0],PARAMETER[\"False_Northing\",-100000.0],\
+                           PARAMETER[\"Central_Meridian\",-2.0],PARAMETER[\"Scale_Factor\",0.9996012717],\
+                           PARAMETER[\"Latitude_Of_Origin\",49.0],UNIT[\"Meter\",1.0]]", GCS_OSGB_1936);
+        let forward = transformer(GCS_OSGB_1936, &bng);
+        let p = forward.transform(Point {x: 0.5, y: 50.5});
+        if !close(p, 577274.99, 69740.50, 0.01) {
+            panic!("{:?}", p)
+        }
+        let back = transformer(&bng, GCS_OSGB_1936).transform(p);
+        if !close(back, 0.5, 50.5, 1e-9) {
+            panic!("{:?}", back)
+        }
+
+        // The central meridian of a UTM zone at the equator
+        let utm = Transformer::between(&Projection::wgs84(), &Projection::utm(32, true).unwrap()).unwrap();
+        if !close(utm.transform(Point {x: 9.0, y: 0.0}), 500000.0, 0.0, 1e-6) {
+            panic!()
+        }
+    }
+
+    #[test]
+    fn test_lambert_and_mercator() {
+        // The examples of the EPSG guidance note 7-2, NAD27 / Texas South Central in US feet
+        let nad27 = r#"GEOGCS["NAD27",DATUM["North_American_Datum_1927",SPHEROID["Clarke 1866",6378206.4,294.9786982]],PRIMEM["Greenwich",0],UNIT["degree",0.0174532925199433]]"#;
+        let texas = format!("PROJCS[\"NAD27 / Texas South Central\",{},PROJECTION[\"Lambert_Conformal_Conic_2SP\"],\
+                             PARAMETER[\"standard_parallel_1\",28.383333333333],PARAMETER[\"standard_parallel_2\",30.283333333333],\
+                             PARAMETER[\"latitude_of_origin\",27.833333333333],PARAMETER[\"central_meridian\",-99],\
+                             PARAMETER[\"false_easting\",2000000],PARAMETER[\"false_northing\",0],UNIT[\"US survey foot\",0.3048006096012192]]", nad27);
+        let p = transformer(nad27, &texas).transform(Point {x: -96.0, y: 28.5});
+        if !close(p, 2963503.91, 254759.80, 0.01) {
+            panic!("{:?}", p)
+        }
+        let back = transformer(&texas, nad27).transform(p);
+        if !close(back, -96.0, 28.5, 1e-9) {
+            panic!("{:?}", back)
+        }
+
+        let web = Transformer::between(&Projection::wgs84(), &Projection::web_mercator()).unwrap();
+        let p = web.transform(Point {x: -100.333333333333, y: 24.381786944444});
+        if !close(p, -11169055.58, 2800000.00, 0.01) {
+            panic!("{:?}", p)
+        }
+    }
+
+    #[test]
+    fn test_datum_shift() {
+        // The example of the EPSG guidance note 7-2, WGS 72 to WGS 84
+        let shift = Helmert {tx: 0.0, ty: 0.0, tz: 4.5, rx: 0.0, ry: 0.0, rz: 0.554, scale: 0.219};
+        let p = shift.to_wgs84([3657660.66, 255768.55, 5201382.11]);
+        if (p[0] - 3657660.78).abs() > 0.01 || (p[1] - 255778.43).abs() > 0.01 || (p[2] - 5201387.75).abs() > 0.01 {
+            panic!("{:?}", p)
+        }
+        let q = shift.from_wgs84(p);
+        if (q[0] - 3657660.66).abs() > 1e-3 || (q[1] - 255768.55).abs() > 1e-3 || (q[2] - 5201382.11).abs() > 1e-3 {
+            panic!("{:?}", q)
+        }
+
+        // OSGB36 is known, and lies about a hundred meters off WGS84 in Britain
+        let osgb = CoordinateSystem::from_projection(&Projection::parse(GCS_OSGB_1936).unwrap()).unwrap();
+        if osgb.to_wgs84 == Helmert::IDENTITY {
+            panic!()
+        }
+        let p = Transformer::new(&osgb, &CoordinateSystem::wgs84()).transform(Point {x: -1.0, y: 52.0});
+        if !close(p, -1.00153, 52.00044, 0.00002) {
+            panic!("{:?}", p)
+        }
+    }
+}
@@ -0,0 +1,347 @@
+//! Module for checking the validity of shapes
+//!
+//! A shape can be perfectly readable and still be useless for any computation, for example a ring
+//! which isn't closed or a polygon whose hole is outside of it. `Shape::validate` checks the rules
+//! of the spec, page 8 onwards, and reports all violations with the index of the part or point.
+
+use std::fmt;
+use std::slice;
+
+use super::geometry::{self, SegmentIntersection};
+use super::shape::{Shape, PatchType, Point};
+
+/// A violation of the rules for shapes found by `Shape::validate`. Parts and points are given by
+/// their index, starting at 0.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ShapeIssue {
+    /// A coordinate is NaN or infinite.
+    NonFiniteCoordinate {point: usize},
+    /// The shape has no parts. A shape without any points should be a null shape instead.
+    NoParts,
+    /// The part offset doesn't point into the points, or isn't in ascending order. The first part
+    /// has to start at 0.
+    PartOffsetOutOfRange {part: usize, offset: i32},
+    /// The part has fewer points than needed: Two for polylines, three for triangles and four for
+    /// rings.
+    TooFewPoints {part: usize, count: usize, minimum: usize},
+    /// The last point of the ring isn't the first one.
+    RingNotClosed {part: usize},
+    /// The ring has no area.
+    DegenerateRing {part: usize},
+    /// The ring is wound the wrong way: Outer rings must be clockwise, holes counterclockwise.
+    WrongOrientation {part: usize},
+    /// The ring is counterclockwise, so it's a hole, but there's no outer ring around it.
+    HoleOutsideShell {part: usize},
+    /// The ring crosses or touches itself.
+    SelfIntersection {part: usize},
+    /// Two rings cross each other.
+    RingsIntersect {part: usize, other: usize},
+    /// There isn't exactly one Z value per point.
+    ZLengthMismatch {expected: usize, found: usize},
+    /// There isn't exactly one M value per point. The M values are optional for shapes with Z values.
+    MLengthMismatch {expected: usize, found: usize},
+    /// There isn't exactly one patch type per part.
+    PartTypesLengthMismatch {expected: usize, found: usize},
+}
+
+impl fmt::Display for ShapeIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ShapeIssue::NonFiniteCoordinate {point} => write!(f, "Point {} has a coordinate which isn't finite", point),
+            ShapeIssue::NoParts => write!(f, "Shape has no parts"),
+            ShapeIssue::PartOffsetOutOfRange {part, offset} => write!(f, "Part {} has invalid offset {}", part, offset),
+            ShapeIssue::TooFewPoints {part, count, minimum} => {
+                write!(f, "Part {} has {} points, but needs at least {}", part, count, minimum)
+            },
+            ShapeIssue::RingNotClosed {part} => write!(f, "Ring {} isn't closed", part),
+            ShapeIssue::DegenerateRing {part} => write!(f, "Ring {} has no area", part),
+            ShapeIssue::WrongOrientation {part} => write!(f, "Ring {} has the wrong orientation", part),
+            ShapeIssue::HoleOutsideShell {part} => write!(f, "Hole {} isn't inside an outer ring", part),
+            ShapeIssue::SelfIntersection {part} => write!(f, "Ring {} intersects itself", part),
+            ShapeIssue::RingsIntersect {part, other} => write!(f, "Rings {} and {} intersect", part, other),
+            ShapeIssue::ZLengthMismatch {expected, found} => write!(f, "Expected {} Z values, found {}", expected, found),
+            ShapeIssue::MLengthMismatch {expected, found} => write!(f, "Expected {} M values, found {}", expected, found),
+            ShapeIssue::PartTypesLengthMismatch {expected, found} => {
+                write!(f, "Expected {} patch types, found {}", expected, found)
+            },
+        }
+    }
+}
+
+/// How the points of a part are interpreted.
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum PartKind {
+    Line,
+    Ring,
+    Triangles,
+}
+
+impl Shape {
+    /// Checks the shape against the rules of the spec and returns all violations. An empty list
+    /// means the shape is valid.
+    pub fn validate(&self) -> Vec<ShapeIssue> {
+        let mut issues: Vec<ShapeIssue> = vec![];
+
+        // Z values are required where present, M values are optional for shapes with Z values
+        let (points, z, m, m_optional) = match *self {
+            Shape::NullShape => return issues,
+            Shape::Point {point} => (vec![point], None, None::<&[f64]>, false),
+            Shape::PointM {point} => (vec![Point {x: point.x, y: point.y}], None, None, false),
+            Shape::PointZ {ref point} => (vec![Point {x: point.x, y: point.y}], Some(slice::from_ref(&point.z)), None, false),
+            Shape::MultiPoint {ref points, ..}
+            | Shape::PolyLine {ref points, ..}
+            | Shape::Polygon {ref points, ..} => (points.clone(), None::<&[f64]>, None, false),
+            Shape::MultiPointM {ref points, ref m, ..}
+            | Shape::PolyLineM {ref points, ref m, ..}
+            | Shape::PolygonM {ref points, ref m, ..} => (points.clone(), None, Some(&m[..]), false),
+            Shape::MultiPointZ {ref points, ref z, ref m, ..}
+            | Shape::PolyLineZ {ref points, ref z, ref m, ..}
+            | Shape::PolygonZ {ref points, ref z, ref m, ..}
+            | Shape::MultiPatch {ref points, ref z, ref m, ..} => (points.clone(), Some(&z[..]), Some(&m[..]), true),
+        };
+
+        if let Some(z) = z {
+            if z.len() != points.len() {
+                issues.push(ShapeIssue::ZLengthMismatch {expected: points.len(), found: z.len()});
+            }
+        }
+        if let Some(m) = m {
+            if m.len() != points.len() && !(m_optional && m.is_empty()) {
+                issues.push(ShapeIssue::MLengthMismatch {expected: points.len(), found: m.len()});
+            }
+        }
+
+        let mut finite = true;
+        for (i, p) in points.iter().enumerate() {
+            let z_finite = z.and_then(|z| z.get(i)).is_none_or(|v| v.is_finite());
+            if !p.x.is_finite() || !p.y.is_finite() || !z_finite {
+                issues.push(ShapeIssue::NonFiniteCoordinate {point: i});
+                finite = false;
+            }
+        }
+
+        let (parts, _) = match geometry::parts_and_points(self) {
+            Some(v) => v,
+            None => return issues,
+        };
+
+        // Parts must start at 0 and be ascending
+        if parts.is_empty() {
+            issues.push(ShapeIssue::NoParts);
+            return issues;
+        }
+        let mut offsets_valid = true;
+        for (i, &offset) in parts.iter().enumerate() {
+            let previous = if i == 0 { None } else { Some(parts[i - 1]) };
+            let in_order = match previous {
+                None => offset == 0,
+                Some(p) => offset >= p,
+            };
+            if !in_order || offset < 0 || offset as usize >= points.len() {
+                issues.push(ShapeIssue::PartOffsetOutOfRange {part: i, offset});
+                offsets_valid = false;
+            }
+        }
+        if !offsets_valid {
+            return issues;
+        }
+
+        let kinds: Vec<PartKind> = match *self {
+            Shape::PolyLine {..} | Shape::PolyLineM {..} | Shape::PolyLineZ {..} => vec![PartKind::Line; parts.len()],
+            Shape::MultiPatch {ref part_types, ..} => {
+                if part_types.len() != parts.len() {
+                    issues.push(ShapeIssue::PartTypesLengthMismatch {expected: parts.len(), found: part_types.len()});
+                }
+                (0..parts.len()).map(|i| match part_types.get(i) {
+                    Some(&PatchType::TriangleStrip) | Some(&PatchType::TriangleFan) => PartKind::Triangles,
+                    _ => PartKind::Ring,
+                }).collect()
+            },
+            _ => vec![PartKind::Ring; parts.len()],
+        };
+
+        // Rings which are fit for the checks of orientation and topology
+        let mut rings: Vec<(usize, &[Point])> = vec![];
+        for (i, range) in geometry::part_ranges(parts, points.len()).into_iter().enumerate() {
+            let part = &points[range];
+            let minimum = match kinds[i] {
+                PartKind::Line => 2,
+                PartKind::Triangles => 3,
+                PartKind::Ring => 4,
+            };
+            if part.len() < minimum {
+                issues.push(ShapeIssue::TooFewPoints {part: i, count: part.len(), minimum});
+                continue;
+            }
+
+            if kinds[i] == PartKind::Ring {
+                if part.first() != part.last() {
+                    issues.push(ShapeIssue::RingNotClosed {part: i});
+                } else if finite && geometry::signed_area(part) == 0f64 {
+                    issues.push(ShapeIssue::DegenerateRing {part: i});
+                } else if finite {
+                    rings.push((i, part));
+                }
+            }
+        }
+
+        if let Shape::MultiPatch {..} = *self {
+            // Patches are surfaces in 3D space, so their planar projection says nothing
+            return issues;
+        }
+
+        // The orientation of a ring which intersects itself is meaningless
+        let crossings = intersections(&rings);
+        rings.retain(|&(i, _)| !crossings.contains(&ShapeIssue::SelfIntersection {part: i}));
+
+        issues.extend(crossings);
+        issues.extend(orientations(&rings));
+        issues
+    }
+
+    /// Whether `validate` finds no issues.
+    pub fn is_valid(&self) -> bool {
+        self.validate().is_empty()
+    }
+}
+
+/// Finds rings which intersect themselves or cross each other.
+fn intersections(rings: &[(usize, &[Point])]) -> Vec<ShapeIssue> {
+    let parts: Vec<&[Point]> = rings.iter().map(|&(_, r)| r).collect();
+    let mut segments = geometry::segments(&parts);
+    let mut self_intersecting: Vec<usize> = vec![];
+    let mut crossing: Vec<(usize, usize)> = vec![];
+
+    geometry::for_each_candidate_pair(&mut segments, |s, t| {
+        let intersection = geometry::segment_intersection(s.a, s.b, t.a, t.b);
+        if s.part == t.part {
+            if intersection == SegmentIntersection::None {
+                return;
+            }
+            if geometry::adjacent(s, t, true) {
+                // Neighbors share a vertex, but must not fold back onto each other
+                let (shared, u, v) = if s.b == t.a { (s.b, s.a, t.b) } else { (s.a, s.b, t.a) };
+                let folds_back = geometry::cross(u, shared, v) == 0f64
+                    && (u.x - shared.x) * (v.x - shared.x) + (u.y - shared.y) * (v.y - shared.y) > 0f64;
+                if !folds_back {
+                    return;
+                }
+            }
+            self_intersecting.push(s.part);
+        } else if intersection == SegmentIntersection::Proper {
+            crossing.push((s.part.min(t.part), s.part.max(t.part)));
+        }
+    });
+
+    self_intersecting.sort();
+    self_intersecting.dedup();
+    crossing.sort();
+    crossing.dedup();
+
+    let mut issues: Vec<ShapeIssue> = self_intersecting.into_iter()
+        .map(|i| ShapeIssue::SelfIntersection {part: rings[i].0})
+        .collect();
+    issues.extend(crossing.into_iter().map(|(i, j)| ShapeIssue::RingsIntersect {part: rings[i].0, other: rings[j].0}));
+    issues
+}
+
+/// Checks that outer rings are clockwise and holes counterclockwise, where a ring is a hole if an
+/// odd number of other rings contains it.
+fn orientations(rings: &[(usize, &[Point])]) -> Vec<ShapeIssue> {
+    let mut issues: Vec<ShapeIssue> = vec![];
+    let clockwise: Vec<bool> = rings.iter().map(|&(_, r)| geometry::signed_area(r) < 0f64).collect();
+    let any_shell = clockwise.iter().any(|&c| c);
+
+    for (i, &(part, ring)) in rings.iter().enumerate() {
+        let containing: Vec<usize> = (0..rings.len())
+            .filter(|&j| j != i && geometry::ring_in_ring(ring, rings[j].1))
+            .collect();
+
+        if !clockwise[i] && any_shell && !containing.iter().any(|&j| clockwise[j]) {
+            issues.push(ShapeIssue::HoleOutsideShell {part});
+        } else if containing.len().is_multiple_of(2) != clockwise[i] {
+            issues.push(ShapeIssue::WrongOrientation {part});
+        }
+    }
+
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ShapeIssue;
+    use super::super::shape::{Shape, BoundingBox, Point, PointZ};
+    use super::super::geometry::polygon;
+
+    const SHELL: &[(f64, f64)] = &[(0., 0.), (0., 10.), (10., 10.), (10., 0.), (0., 0.)];
+    const HOLE: &[(f64, f64)] = &[(2., 2.), (4., 2.), (4., 4.), (2., 4.), (2., 2.)];
+
+    #[test]
+    fn test_valid_polygon() {
+        if !polygon(&[SHELL, HOLE]).is_valid() {
+            panic!()
+        }
+    }
+
+    #[test]
+    fn test_ring_issues() {
+        let open = &[(0., 0.), (0., 10.), (10., 10.), (10., 0.)];
+        let bowtie = &[(20., 0.), (30., 10.), (30., 0.), (20., 6.), (20., 0.)];
+        let outside = &[(42., 2.), (44., 2.), (44., 4.), (42., 4.), (42., 2.)];
+        let issues = polygon(&[SHELL, open, bowtie, outside, &[(0., 0.), (1., 1.)]]).validate();
+
+        let expected = vec![
+            ShapeIssue::RingNotClosed {part: 1},
+            ShapeIssue::TooFewPoints {part: 4, count: 2, minimum: 4},
+            ShapeIssue::SelfIntersection {part: 2},
+            ShapeIssue::HoleOutsideShell {part: 3},
+        ];
+        if issues != expected {
+            panic!("{:?}", issues)
+        }
+    }
+
+    #[test]
+    fn test_orientation_and_crossing() {
+        let reversed: Vec<(f64, f64)> = SHELL.iter().rev().cloned().collect();
+        if polygon(&[&reversed]).validate() != vec![ShapeIssue::WrongOrientation {part: 0}] {
+            panic!()
+        }
+
+        let crossing = &[(5., 5.), (15., 5.), (15., 15.), (5., 15.), (5., 5.)];
+        let issues = polygon(&[SHELL, crossing]).validate();
+        if !issues.contains(&ShapeIssue::RingsIntersect {part: 0, other: 1}) {
+            panic!()
+        }
+    }
+
+    #[test]
+    fn test_parts_and_values() {
+        let line = Shape::PolyLineM {
+            bounding_box: BoundingBox::new(),
+            parts: vec![0, 3],
+            points: vec![Point {x: 0., y: 0.}, Point {x: f64::NAN, y: 1.}],
+            m_range: super::super::shape::Range {min: 0., max: 0.},
+            m: vec![0.],
+        };
+        let expected = vec![
+            ShapeIssue::MLengthMismatch {expected: 2, found: 1},
+            ShapeIssue::NonFiniteCoordinate {point: 1},
+            ShapeIssue::PartOffsetOutOfRange {part: 1, offset: 3},
+        ];
+        if line.validate() != expected {
+            panic!()
+        }
+
+        // A point with several coordinates which aren't finite is reported once
+        let point = Shape::PointZ {point: PointZ {x: f64::NAN, y: 0., z: f64::INFINITY, m: 0.}};
+        if point.validate() != vec![ShapeIssue::NonFiniteCoordinate {point: 0}] {
+            panic!("{:?}", point.validate())
+        }
+
+        let empty = Shape::PolyLine {bounding_box: BoundingBox::new(), parts: vec![], points: vec![]};
+        if empty.validate() != vec![ShapeIssue::NoParts] {
+            panic!()
+        }
+    }
+}
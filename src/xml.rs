@@ -0,0 +1,312 @@
+//! Module for reading and writing the XML of KML and GPX files
+//!
+//! The reader builds a tree of elements from a whole document. It knows just enough XML for the
+//! files of GPS devices and mapping programs: elements, attributes, text, CDATA sections and the
+//! predefined and numeric entities. Declarations, comments, processing instructions and DOCTYPEs
+//! are skipped. Namespace prefixes are dropped from the names of elements.
+
+use std::io::{Error, ErrorKind};
+
+use dbf;
+
+/// An element with its attributes, the elements inside it and all its own text.
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) struct Element {
+    pub name: String,
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<Element>,
+    pub text: String,
+}
+
+impl Element {
+    /// The value of an attribute.
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.iter().find(|a| a.0 == name).map(|a| a.1.as_str())
+    }
+
+    /// The first element directly inside this one with the given name.
+    pub fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|e| e.name == name)
+    }
+
+    /// The trimmed text of the first element directly inside this one with the given name.
+    pub fn child_text(&self, name: &str) -> Option<&str> {
+        self.child(name).map(|e| e.text.trim())
+    }
+
+    /// All elements with the given name, searching everything inside this one, but not inside the
+    /// elements found.
+    pub fn descendants(&self, name: &str) -> Vec<&Element> {
+        let mut result = vec![];
+        for child in &self.children {
+            if child.name == name {
+                result.push(child);
+            } else {
+                result.extend(child.descendants(name));
+            }
+        }
+        result
+    }
+}
+
+/// Escapes text for use in elements and quoted attributes.
+pub(crate) fn escape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&apos;"),
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+/// The text of an attribute for an element, None for null values.
+pub(crate) fn text_of(value: &dbf::Field) -> Option<String> {
+    match *value {
+        dbf::Field::Character(ref s) => Some(s.trim().to_string()),
+        dbf::Field::Numeric(n) => Some(n.to_string()),
+        dbf::Field::Null => None,
+    }
+}
+
+/// Reads a document, one element after the other.
+struct XmlParser<'a> {
+    text: &'a str,
+    position: usize,
+}
+
+impl<'a> XmlParser<'a> {
+    fn error(&self, message: &str) -> Error {
+        Error::new(ErrorKind::Other, format!("{} at position {} of the XML!", message, self.position))
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.text[self.position..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    /// Skips past the given end, like that of a comment.
+    fn skip_past(&mut self, end: &str) -> Result<(), Error> {
+        match self.rest().find(end) {
+            Some(i) => {
+                self.position += i + end.len();
+                Ok(())
+            },
+            None => Err(self.error("Unterminated markup")),
+        }
+    }
+
+    /// Skips declarations, comments, processing instructions and DOCTYPEs. Returns whether there
+    /// was anything to skip.
+    fn skip_markup(&mut self) -> Result<bool, Error> {
+        if self.rest().starts_with("<?") {
+            self.skip_past("?>")?;
+        } else if self.rest().starts_with("<!--") {
+            self.skip_past("-->")?;
+        } else if self.rest().starts_with("<!") && !self.rest().starts_with("<![CDATA[") {
+            self.skip_past(">")?;
+        } else {
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    fn name(&mut self) -> Result<String, Error> {
+        let rest = self.rest();
+        let length = rest.find(|c: char| c.is_whitespace() || c == '>' || c == '/' || c == '=').unwrap_or(rest.len());
+        if length == 0 {
+            return Err(self.error("Expected a name"));
+        }
+        self.position += length;
+        Ok(rest[..length].to_string())
+    }
+
+    /// The name of an element, without its namespace prefix.
+    fn element_name(&mut self) -> Result<String, Error> {
+        let name = self.name()?;
+        Ok(name.rsplit(':').next().unwrap_or(&name).to_string())
+    }
+
+    fn quoted(&mut self) -> Result<String, Error> {
+        let quote = match self.rest().chars().next() {
+            Some(c) if c == '"' || c == '\'' => c,
+            _ => return Err(self.error("Expected a quoted value")),
+        };
+        self.position += 1;
+        match self.rest().find(quote) {
+            Some(i) => {
+                let value = unescape(&self.rest()[..i]);
+                self.position += i + 1;
+                Ok(value)
+            },
+            None => Err(self.error("Unterminated value")),
+        }
+    }
+
+    /// Reads an element, starting at its opening bracket.
+    fn element(&mut self) -> Result<Element, Error> {
+        self.position += 1;
+        let name = self.element_name()?;
+        let mut element = Element {name, attributes: vec![], children: vec![], text: String::new()};
+
+        loop {
+            self.skip_whitespace();
+            if self.rest().starts_with("/>") {
+                self.position += 2;
+                return Ok(element);
+            }
+            if self.rest().starts_with('>') {
+                self.position += 1;
+                break;
+            }
+            let attribute = self.name()?;
+            self.skip_whitespace();
+            if !self.rest().starts_with('=') {
+                return Err(self.error("Expected an equals sign"));
+            }
+            self.position += 1;
+            self.skip_whitespace();
+            let value = self.quoted()?;
+            element.attributes.push((attribute, value));
+        }
+
+        loop {
+            if self.rest().is_empty() {
+                return Err(self.error(&format!("Unterminated element `{}`", element.name)));
+            }
+            if self.skip_markup()? {
+                continue;
+            }
+            if self.rest().starts_with("<![CDATA[") {
+                self.position += 9;
+                let start = self.position;
+                self.skip_past("]]>")?;
+                element.text.push_str(&self.text[start..self.position - 3]);
+            } else if self.rest().starts_with("</") {
+                self.position += 2;
+                let end = self.element_name()?;
+                if end != element.name {
+                    return Err(self.error(&format!("Expected the end of `{}`", element.name)));
+                }
+                self.skip_whitespace();
+                if !self.rest().starts_with('>') {
+                    return Err(self.error("Expected a closing bracket"));
+                }
+                self.position += 1;
+                return Ok(element);
+            } else if self.rest().starts_with('<') {
+                let child = self.element()?;
+                element.children.push(child);
+            } else {
+                let rest = self.rest();
+                let length = rest.find('<').unwrap_or(rest.len());
+                element.text.push_str(&unescape(&rest[..length]));
+                self.position += length;
+            }
+        }
+    }
+}
+
+/// Replaces the predefined and numeric entities. Unknown entities are kept as they are.
+fn unescape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(i) = rest.find('&') {
+        result.push_str(&rest[..i]);
+        rest = &rest[i..];
+        let end = match rest.find(';') {
+            Some(end) => end,
+            None => break,
+        };
+        let entity = &rest[1..end];
+        let c = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ if entity.starts_with("#x") => u32::from_str_radix(&entity[2..], 16).ok().and_then(::std::char::from_u32),
+            _ if entity.starts_with('#') => entity[1..].parse::<u32>().ok().and_then(::std::char::from_u32),
+            _ => None,
+        };
+        match c {
+            Some(c) => {
+                result.push(c);
+                rest = &rest[end + 1..];
+            },
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+            },
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Parses a document into its root element.
+pub(crate) fn parse(text: &str) -> Result<Element, Error> {
+    let mut parser = XmlParser {text: text.trim_start_matches('\u{feff}'), position: 0};
+    loop {
+        parser.skip_whitespace();
+        if !parser.skip_markup()? {
+            break;
+        }
+    }
+    if !parser.rest().starts_with('<') {
+        return Err(parser.error("Expected an element"));
+    }
+
+    let root = parser.element()?;
+    loop {
+        parser.skip_whitespace();
+        if !parser.skip_markup()? {
+            break;
+        }
+    }
+    if !parser.rest().is_empty() {
+        return Err(parser.error("Unexpected text after the end"));
+    }
+    Ok(root)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{escape, parse};
+
+    #[test]
+    fn test_parse() {
+        let text = "<?xml version=\"1.0\"?>\n<!-- A comment -->\n\
+                    <kml:kml xmlns:kml=\"http://www.opengis.net/kml/2.2\">\
+                    <Document><name>A &amp; B &#x263A;</name><Folder><Placemark id='p1'><name><![CDATA[<b>1</b>]]></name></Placemark></Folder>\
+                    <Placemark id=\"p2\"/></Document></kml:kml>";
+        let root = parse(text).unwrap();
+        if root.name != "kml" || root.attribute("xmlns:kml") != Some("http://www.opengis.net/kml/2.2") {
+            panic!("{:?}", root)
+        }
+        let document = root.child("Document").unwrap();
+        if document.child_text("name") != Some("A & B \u{263a}") {
+            panic!("{:?}", document)
+        }
+        let placemarks = root.descendants("Placemark");
+        if placemarks.len() != 2 || placemarks[0].attribute("id") != Some("p1") || placemarks[0].child_text("name") != Some("<b>1</b>") {
+            panic!("{:?}", placemarks)
+        }
+
+        if parse("<a><b></a>").is_ok() || parse("<a>").is_ok() || parse("<a/><b/>").is_ok() {
+            panic!()
+        }
+        if escape("<a href=\"x\">&</a>") != "&lt;a href=&quot;x&quot;&gt;&amp;&lt;/a&gt;" {
+            panic!()
+        }
+    }
+}